@@ -1,6 +1,17 @@
 pub const BGP_PORT: u16 = 179;
 pub const BGP_HEADER_LEN: usize = 19;
+/// Maximum message length, and with the Extended Message capability (RFC
+/// 8654).
+pub const BGP_MAX_LEN: usize = 4096;
+pub const BGP_EXTENDED_MAX_LEN: usize = 65535;
 
+pub use advertise_map::CONDITIONAL_ADVERTISEMENT_TIMER_DEFAULT;
+pub use advertise_map::{AdvertiseCondition, AdvertiseMap, ConditionalAdvertisement};
+pub use aggregate::{Aggregate, Aggregated, Aggregates};
+pub use as_path_list::{AsPathList, AsPathListEntry};
+pub use aspa::{AspaError, AspaTable};
+pub use aspath::{AsPath, AsPathError, AsSegment, AsSegmentType};
+pub use attr::{Attr, AttrError, AttrHeader, Origin};
 pub use capability::*;
 pub use client::Client;
 pub use client::Event;
@@ -8,17 +19,86 @@ pub use client::Message;
 pub use client::MessageOpen;
 pub use client::Peer;
 pub use client::State;
+pub use collision::{collision_check, collision_notification, collision_winner};
+pub use collision::{ConnectMode, Initiator};
 pub use communities::Communities;
+pub use community_list::{CommunityList, CommunityListEntry, CommunityMatch};
+pub use dampening::{DampKey, Dampening, DampeningCommand, DampeningError, DampeningParams};
+pub use dampening::{Dampenings, DAMP_REUSE_TICK};
+pub use ext_communities::{ExtAdmin, ExtCommunities, ExtCommunity};
+pub use fib::{Fib, FibChange, NextHop};
+pub use intern::{AttrSet, AttrStore, HeapSize, InternStats, Interner};
+pub use large_communities::{LargeCommunities, LargeCommunity};
+pub use large_community_list::{LargeCommunityList, LargeCommunityListEntry, LargeCommunityMatch};
+pub use listen_range::{ListenCommand, ListenRange, ListenRanges};
+pub use max_prefix::{MaxPrefix, MaxPrefixEvent, PrefixCounter};
 pub use message::MessageHeader;
 pub use neighbor::Neighbor;
 pub use neighbor::NeighborVec;
+pub use neighbor::PeerType;
+pub use neighbor_config::{
+    family_name, is_link_local, parse_family, AllowasIn, LocalAs, NeighborCommand, NeighborError,
+};
+pub use neighbor_config::{RemoteAs, SendCommunity, Timers};
 pub use neighbor_map::NeighborMap;
+pub use network::{Network, Networks};
+pub use notification::*;
 pub use packet::*;
+pub use peer_group::{NeighborConfig, PeerGroups, Source};
+pub use policy::{Action, Direction, Policy, PolicyContext};
+pub use prefix::{Prefix, PrefixError};
+pub use prefix_list::{PrefixList, PrefixListEntry};
+pub use redistribute::{Redistribute, RedistributeType};
+pub use rib::{Rib, RouteSource, LOCAL_PREF_DEFAULT};
+pub use role::{role_check, Role};
+pub use route::{AspaState, Route, RpkiState};
+pub use route_map::{OnMatch, RouteMap, RouteMapEntry, RouteMapMatch, RouteMapSet};
+pub use rpki::{RpkiTable, RtrClient, RtrCodec, RtrError, RtrPdu, RtrSession, Vrp, VrpTable};
+pub use session::{BgpConfig, Bgpd, BgpdHandle, ConfigTarget};
+pub use unnumbered::{discover, RaSocket, RA_INTERVAL};
+pub use update::{ErrorHandling, MessageUpdate, MpReach, MpUnreach, UpdateContext, UpdateError};
+pub use update_group::{AdjOut, GroupUpdate, UpdateGroup, UpdateGroupKey, UpdateGroups};
+pub use update_pack::{Mrai, PackError, UpdatePacker, MRAI_EBGP_DEFAULT, MRAI_IBGP_DEFAULT};
 
+mod advertise_map;
+mod aggregate;
+mod as_path_list;
+mod aspa;
+mod aspath;
+mod attr;
 mod capability;
 pub mod client;
+mod collision;
 mod communities;
+mod community_list;
+mod dampening;
+mod ext_communities;
+mod fib;
+mod intern;
+mod large_communities;
+mod large_community_list;
+mod listen_range;
+mod max_prefix;
 mod message;
 mod neighbor;
+mod neighbor_config;
 mod neighbor_map;
+mod network;
+mod notification;
 mod packet;
+mod peer_group;
+mod policy;
+mod prefix;
+mod prefix_list;
+mod redistribute;
+mod rib;
+mod role;
+mod route;
+mod route_map;
+mod rpki;
+mod session;
+mod tcp;
+mod unnumbered;
+mod update;
+mod update_group;
+mod update_pack;
@@ -0,0 +1,217 @@
+#![allow(dead_code)]
+
+use super::{Action, Policy, PolicyContext, Prefix, Route};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Interval the conditions are re-evaluated at, besides on Loc-RIB changes.
+pub const CONDITIONAL_ADVERTISEMENT_TIMER_DEFAULT: Duration = Duration::from_secs(60);
+
+/// Routes the advertisement depends on, by the route-map selecting them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AdvertiseCondition {
+    /// Advertise while the Loc-RIB has a route the route-map permits.
+    Exist(String),
+    /// Advertise while the Loc-RIB has none.
+    NonExist(String),
+}
+
+/// `neighbor <addr> advertise-map <name> exist-map|non-exist-map <name>`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AdvertiseMap {
+    /// Route-map selecting the routes advertised conditionally.
+    pub advertise_map: String,
+    pub condition: AdvertiseCondition,
+}
+
+impl AdvertiseMap {
+    pub fn new(advertise_map: &str, condition: AdvertiseCondition) -> Self {
+        AdvertiseMap {
+            advertise_map: advertise_map.to_string(),
+            condition,
+        }
+    }
+
+    /// Whether `route` is advertised conditionally. Set clauses of the
+    /// route-map are not applied.
+    pub fn matches(&self, policy: &Policy, route: &Route) -> bool {
+        policy.check(&self.advertise_map, route, &PolicyContext::local()) == Action::Permit
+    }
+
+    /// Whether the exist-map or non-exist-map permits `route`.
+    pub fn tracks(&self, policy: &Policy, route: &Route) -> bool {
+        let name = match &self.condition {
+            AdvertiseCondition::Exist(name) | AdvertiseCondition::NonExist(name) => name,
+        };
+        policy.check(name, route, &PolicyContext::local()) == Action::Permit
+    }
+
+    /// Whether the condition holds when the Loc-RIB has a route the
+    /// exist-map or non-exist-map permits or not.
+    fn holds(&self, found: bool) -> bool {
+        found == matches!(self.condition, AdvertiseCondition::Exist(_))
+    }
+
+    /// Whether the condition holds for the Loc-RIB `rib`.
+    pub fn condition(&self, policy: &Policy, rib: &[&Route]) -> bool {
+        self.holds(rib.iter().any(|route| self.tracks(policy, route)))
+    }
+}
+
+impl fmt::Display for AdvertiseMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.condition {
+            AdvertiseCondition::Exist(name) => {
+                write!(f, "advertise-map {} exist-map {}", self.advertise_map, name)
+            }
+            AdvertiseCondition::NonExist(name) => {
+                write!(
+                    f,
+                    "advertise-map {} non-exist-map {}",
+                    self.advertise_map, name
+                )
+            }
+        }
+    }
+}
+
+/// Conditional advertisement state of a neighbor. The conditionally
+/// advertised routes are held back until the condition was evaluated.
+#[derive(Clone, Debug)]
+pub struct ConditionalAdvertisement {
+    pub map: AdvertiseMap,
+    interval: Duration,
+    advertising: bool,
+    /// When the condition was last evaluated.
+    last: Option<Instant>,
+    /// Prefixes of the Loc-RIB the condition route-map permits.
+    tracked: BTreeSet<Prefix>,
+}
+
+impl ConditionalAdvertisement {
+    pub fn new(map: AdvertiseMap, interval: Duration) -> Self {
+        ConditionalAdvertisement {
+            map,
+            interval,
+            advertising: false,
+            last: None,
+            tracked: BTreeSet::new(),
+        }
+    }
+
+    /// Whether the routes of the advertise-map are advertised.
+    pub fn advertising(&self) -> bool {
+        self.advertising
+    }
+
+    /// Whether `route` may be advertised to the neighbor now.
+    pub fn permits(&self, policy: &Policy, route: &Route) -> bool {
+        self.advertising || !self.map.matches(policy, route)
+    }
+
+    /// When the condition is evaluated next.
+    pub fn deadline(&self) -> Instant {
+        match self.last {
+            Some(last) => last + self.interval,
+            None => Instant::now(),
+        }
+    }
+
+    /// Evaluate the condition against the Loc-RIB `rib`, when its timer
+    /// expires or the Loc-RIB changed. Returns whether it changed, the
+    /// routes the advertise-map matches are then announced or withdrawn.
+    pub fn evaluate(&mut self, now: Instant, policy: &Policy, rib: &[&Route]) -> bool {
+        self.last = Some(now);
+        self.tracked = rib
+            .iter()
+            .filter(|route| self.map.tracks(policy, route))
+            .map(|route| route.prefix)
+            .collect();
+        self.update()
+    }
+
+    /// Account for the best path to `prefix` changing to `best`, without
+    /// evaluating the whole Loc-RIB. Returns whether the condition changed.
+    /// Nothing is tracked before the first evaluation.
+    pub fn track(&mut self, policy: &Policy, prefix: Prefix, best: Option<&Route>) -> bool {
+        if self.last.is_none() {
+            return false;
+        }
+        let changed = if best.is_some_and(|route| self.map.tracks(policy, route)) {
+            self.tracked.insert(prefix)
+        } else {
+            self.tracked.remove(&prefix)
+        };
+        changed && self.update()
+    }
+
+    fn update(&mut self) -> bool {
+        let advertising = self.map.holds(!self.tracked.is_empty());
+        let changed = advertising != self.advertising;
+        self.advertising = advertising;
+        changed
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bgp::policy::fixture::permit;
+    use crate::bgp::Attr;
+
+    fn route(prefix: &str) -> Route {
+        Route::new(prefix.parse().unwrap(), Attr::new())
+    }
+
+    #[test]
+    fn backup_transit() {
+        // Announce 192.0.2.0/24 only while the default route of the primary
+        // transit is gone.
+        let mut policy = Policy::new();
+        permit(&mut policy, "OURS", "192.0.2.0/24");
+        permit(&mut policy, "PRIMARY", "0.0.0.0/0");
+        let map = AdvertiseMap::new("OURS", AdvertiseCondition::NonExist("PRIMARY".to_string()));
+        assert_eq!(map.to_string(), "advertise-map OURS non-exist-map PRIMARY");
+        let interval = Duration::from_secs(60);
+        let mut cond = ConditionalAdvertisement::new(map, interval);
+        let ours = route("192.0.2.0/24");
+        let other = route("198.51.100.0/24");
+        assert!(!cond.permits(&policy, &ours));
+        assert!(cond.permits(&policy, &other));
+
+        let now = Instant::now();
+        let primary = route("0.0.0.0/0");
+        let mut rib = vec![&ours, &other, &primary];
+        assert!(!cond.evaluate(now, &policy, &rib));
+        assert_eq!(cond.deadline(), now + interval);
+
+        // The primary is gone.
+        rib.pop();
+        assert!(cond.evaluate(now, &policy, &rib));
+        assert!(cond.advertising());
+        assert!(cond.permits(&policy, &ours));
+        assert!(!cond.evaluate(now, &policy, &rib));
+
+        // And back, tracked without evaluating the Loc-RIB.
+        assert!(!cond.track(&policy, other.prefix, Some(&other)));
+        assert!(cond.track(&policy, primary.prefix, Some(&primary)));
+        assert!(!cond.permits(&policy, &ours));
+        assert!(cond.track(&policy, primary.prefix, None));
+        assert!(cond.advertising());
+        rib.push(&primary);
+        assert!(cond.evaluate(now, &policy, &rib));
+        assert!(!cond.permits(&policy, &ours));
+    }
+
+    #[test]
+    fn exist() {
+        let mut policy = Policy::new();
+        permit(&mut policy, "OURS", "192.0.2.0/24");
+        permit(&mut policy, "TRACK", "203.0.113.0/24");
+        let map = AdvertiseMap::new("OURS", AdvertiseCondition::Exist("TRACK".to_string()));
+        assert!(!map.condition(&policy, &[&route("192.0.2.0/24")]));
+        assert!(map.condition(&policy, &[&route("203.0.113.0/24")]));
+    }
+}
@@ -0,0 +1,348 @@
+#![allow(dead_code)]
+
+use super::redistribute::LOCAL_ROUTE_WEIGHT;
+use super::{Action, AsPath, AsSegment, AsSegmentType, Attr, Communities, LargeCommunities};
+use super::{NeighborError, Origin, Policy, PolicyContext, Prefix, Route};
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::Ipv4Addr;
+
+/// `aggregate-address <prefix> [as-set] [summary-only] [matching-MED-only]
+/// [suppress-map <name>] [attribute-map <name>]`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Aggregate {
+    pub prefix: Prefix,
+    /// Keep the ASNs of the contributing routes in an AS_SET, with their
+    /// communities.
+    pub as_set: bool,
+    /// Suppress every contributing route.
+    pub summary_only: bool,
+    /// Only aggregate when every contributing route has the same MED.
+    pub matching_med_only: bool,
+    /// Suppress the contributing routes the route-map permits.
+    pub suppress_map: Option<String>,
+    /// Route-map setting the attributes of the aggregate.
+    pub attribute_map: Option<String>,
+}
+
+/// The aggregate route and the more specific routes it suppresses.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Aggregated {
+    pub route: Route,
+    pub suppressed: BTreeSet<Prefix>,
+}
+
+/// AS_PATH of an aggregate with AS_SET, the AS_SEQUENCE the paths start
+/// with followed by every other ASN in an AS_SET (RFC 4271 9.2.2.2).
+fn as_set_path<'a>(paths: impl Iterator<Item = &'a AsPath> + Clone) -> AsPath {
+    let leading = |path: &'a AsPath| match path.segments().first() {
+        Some(s) if s.typ == AsSegmentType::Sequence => s.asns.as_slice(),
+        _ => &[],
+    };
+    let mut common: Vec<u32> = paths.clone().next().map(leading).unwrap_or(&[]).to_vec();
+    for path in paths.clone() {
+        let n = common
+            .iter()
+            .zip(leading(path))
+            .take_while(|(a, b)| a == b)
+            .count();
+        common.truncate(n);
+    }
+    let set: BTreeSet<u32> = paths
+        .flat_map(|path| path.segments().iter())
+        .flat_map(|s| s.asns.iter().copied())
+        .filter(|asn| !common.contains(asn))
+        .collect();
+
+    let mut segments = Vec::new();
+    if !common.is_empty() {
+        segments.push(AsSegment::new(AsSegmentType::Sequence, common));
+    }
+    if !set.is_empty() {
+        segments.push(AsSegment::new(
+            AsSegmentType::Set,
+            set.into_iter().collect(),
+        ));
+    }
+    AsPath::from_segments(segments)
+}
+
+impl Aggregate {
+    pub fn new(prefix: Prefix) -> Self {
+        Aggregate {
+            prefix,
+            as_set: false,
+            summary_only: false,
+            matching_med_only: false,
+            suppress_map: None,
+            attribute_map: None,
+        }
+    }
+
+    /// Parse an `aggregate-address ...` configuration line.
+    pub fn parse(line: &str) -> Result<Aggregate, NeighborError> {
+        let syntax = || NeighborError::Syntax(line.to_string());
+        let mut words = line.split_whitespace();
+        if words.next() != Some("aggregate-address") {
+            return Err(syntax());
+        }
+        let prefix = words.next().ok_or_else(syntax)?;
+        let mut aggregate = Aggregate::new(prefix.parse().map_err(|_| syntax())?);
+        while let Some(word) = words.next() {
+            match word {
+                "as-set" => aggregate.as_set = true,
+                "summary-only" => aggregate.summary_only = true,
+                "matching-MED-only" => aggregate.matching_med_only = true,
+                "suppress-map" => {
+                    aggregate.suppress_map = Some(words.next().ok_or_else(syntax)?.to_string())
+                }
+                "attribute-map" => {
+                    aggregate.attribute_map = Some(words.next().ok_or_else(syntax)?.to_string())
+                }
+                _ => return Err(syntax()),
+            }
+        }
+        Ok(aggregate)
+    }
+
+    /// Whether `route` is more specific than the aggregate.
+    pub fn contributes(&self, route: &Route) -> bool {
+        route.prefix.prefixlen() > self.prefix.prefixlen() && self.prefix.contains(&route.prefix)
+    }
+
+    /// Build the aggregate from the best paths `routes`. `None` when no
+    /// route contributes, when their MEDs differ with matching-MED-only or
+    /// when the attribute-map denies it. `asn` and `router_id` make the
+    /// AGGREGATOR.
+    pub fn aggregate<'a>(
+        &self,
+        policy: &Policy,
+        asn: u32,
+        router_id: Ipv4Addr,
+        routes: impl IntoIterator<Item = &'a Route>,
+    ) -> Option<Aggregated> {
+        let contributors: Vec<&Route> = routes
+            .into_iter()
+            .filter(|route| self.contributes(route))
+            .collect();
+        let first = contributors.first()?;
+        if self.matching_med_only && contributors.iter().any(|r| r.attr.med != first.attr.med) {
+            return None;
+        }
+
+        let mut attr = Attr::new();
+        attr.origin = contributors
+            .iter()
+            .map(|r| r.attr.origin)
+            .max_by_key(|origin| *origin as u8)
+            .unwrap_or(Origin::Igp);
+        attr.aggregator = Some((asn, router_id));
+        let atomic = contributors.iter().any(|r| r.attr.atomic_aggregate);
+        if self.as_set {
+            attr.as_path = as_set_path(contributors.iter().map(|r| &*r.attr.as_path));
+            attr.atomic_aggregate = atomic;
+            for route in &contributors {
+                if let Some(coms) = &route.attr.communities {
+                    attr.communities
+                        .get_or_insert_with(Communities::new)
+                        .merge(coms);
+                }
+                if let Some(lcoms) = &route.attr.large_communities {
+                    attr.large_communities
+                        .get_or_insert_with(LargeCommunities::new)
+                        .merge(lcoms);
+                }
+            }
+        } else {
+            // The AS paths of the contributing routes are lost.
+            attr.atomic_aggregate =
+                atomic || contributors.iter().any(|r| !r.attr.as_path.is_empty());
+        }
+        if self.matching_med_only {
+            attr.med = first.attr.med;
+        }
+
+        let mut route = Route::new(self.prefix, attr);
+        route.weight = LOCAL_ROUTE_WEIGHT;
+        if let Some(name) = &self.attribute_map {
+            if policy.apply(name, &mut route, &PolicyContext::local()) == Action::Deny {
+                return None;
+            }
+        }
+
+        let suppressed = contributors
+            .iter()
+            .filter(|r| self.suppresses(policy, r))
+            .map(|r| r.prefix)
+            .collect();
+        Some(Aggregated { route, suppressed })
+    }
+
+    fn suppresses(&self, policy: &Policy, route: &Route) -> bool {
+        if self.summary_only {
+            return true;
+        }
+        match &self.suppress_map {
+            Some(name) => {
+                let mut route = route.clone();
+                policy.apply(name, &mut route, &PolicyContext::local()) == Action::Permit
+            }
+            None => false,
+        }
+    }
+}
+
+/// The aggregates of a BGP instance.
+#[derive(Clone, Debug, Default)]
+pub struct Aggregates {
+    aggregates: BTreeMap<Prefix, Aggregate>,
+}
+
+impl Aggregates {
+    pub fn new() -> Self {
+        Aggregates::default()
+    }
+
+    /// Apply a `[no] aggregate-address ...` configuration line. An
+    /// aggregate replaces the one of the same prefix. Returns the prefix of
+    /// the aggregate.
+    pub fn configure(&mut self, line: &str) -> Result<Prefix, NeighborError> {
+        match line.strip_prefix("no ") {
+            Some(rest) => {
+                let aggregate = Aggregate::parse(rest)?;
+                self.aggregates.remove(&aggregate.prefix);
+                Ok(aggregate.prefix)
+            }
+            None => {
+                let aggregate = Aggregate::parse(line)?;
+                let prefix = aggregate.prefix;
+                self.aggregates.insert(prefix, aggregate);
+                Ok(prefix)
+            }
+        }
+    }
+
+    pub fn get(&self, prefix: &Prefix) -> Option<&Aggregate> {
+        self.aggregates.get(prefix)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Aggregate> {
+        self.aggregates.values()
+    }
+
+    /// The aggregates a route to `prefix` contributes to.
+    pub fn covering<'a>(&'a self, prefix: &'a Prefix) -> impl Iterator<Item = &'a Aggregate> {
+        self.aggregates
+            .values()
+            .filter(move |a| prefix.prefixlen() > a.prefix.prefixlen() && a.prefix.contains(prefix))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bgp::policy::fixture::permit;
+
+    fn route(prefix: &str, path: &str, origin: Origin, med: Option<u32>) -> Route {
+        let mut attr = Attr::new();
+        attr.as_path = path.parse().unwrap();
+        attr.origin = origin;
+        attr.med = med;
+        attr.communities = Communities::from_str(&format!("65000:{}", path.len()));
+        Route::new(prefix.parse().unwrap(), attr)
+    }
+
+    fn routes() -> Vec<Route> {
+        vec![
+            route("10.1.0.0/24", "65001 65010", Origin::Igp, Some(0)),
+            route("10.1.1.0/24", "65001 65020 65021", Origin::Egp, Some(0)),
+            route("10.1.0.0/16", "65001", Origin::Igp, Some(10)),
+            route("10.2.0.0/24", "65002", Origin::Incomplete, Some(0)),
+        ]
+    }
+
+    #[test]
+    fn parse() {
+        let a = Aggregate::parse(
+            "aggregate-address 10.1.0.0/16 as-set summary-only suppress-map S attribute-map A",
+        )
+        .unwrap();
+        assert!(a.as_set && a.summary_only && !a.matching_med_only);
+        assert_eq!(a.suppress_map.as_deref(), Some("S"));
+        assert_eq!(a.attribute_map.as_deref(), Some("A"));
+        assert!(Aggregate::parse("aggregate-address 10.1.0.0/16 suppress-map").is_err());
+        assert!(Aggregate::parse("aggregate-address 2001:db8::/32 matching-MED-only").is_ok());
+
+        let mut aggregates = Aggregates::new();
+        aggregates
+            .configure("aggregate-address 10.1.0.0/16")
+            .unwrap();
+        aggregates
+            .configure("no aggregate-address 10.1.0.0/16")
+            .unwrap();
+        assert_eq!(aggregates.iter().count(), 0);
+    }
+
+    #[test]
+    fn aggregate() {
+        let policy = Policy::new();
+        let router_id: Ipv4Addr = "10.0.0.1".parse().unwrap();
+        let a = Aggregate::new("10.1.0.0/16".parse().unwrap());
+        let result = a.aggregate(&policy, 65000, router_id, &routes()).unwrap();
+        let attr = &result.route.attr;
+        assert_eq!(attr.origin, Origin::Egp);
+        assert!(attr.as_path.is_empty());
+        assert!(attr.atomic_aggregate);
+        assert_eq!(attr.aggregator, Some((65000, router_id)));
+        assert_eq!(attr.communities, None);
+        assert!(result.suppressed.is_empty());
+
+        // The /16 itself does not contribute, no contributors no aggregate.
+        let a = Aggregate::new("10.3.0.0/16".parse().unwrap());
+        assert!(a.aggregate(&policy, 65000, router_id, &routes()).is_none());
+    }
+
+    #[test]
+    fn as_set() {
+        let policy = Policy::new();
+        let mut a = Aggregate::parse("aggregate-address 10.1.0.0/16 as-set summary-only").unwrap();
+        let result = a
+            .aggregate(&policy, 65000, "10.0.0.1".parse().unwrap(), &routes())
+            .unwrap();
+        let attr = &result.route.attr;
+        assert_eq!(attr.as_path.to_string(), "65001 {65010,65020,65021}");
+        assert!(!attr.atomic_aggregate);
+        assert_eq!(attr.communities.as_ref().unwrap().len(), 2);
+        assert_eq!(result.suppressed.len(), 2);
+
+        // MEDs 0 and 0 match, 10 is the aggregate's own prefix.
+        a.matching_med_only = true;
+        let result = a
+            .aggregate(&policy, 65000, "10.0.0.1".parse().unwrap(), &routes())
+            .unwrap();
+        assert_eq!(result.route.attr.med, Some(0));
+        let mut differ = routes();
+        differ[1].attr_mut().med = Some(5);
+        assert!(a
+            .aggregate(&policy, 65000, "10.0.0.1".parse().unwrap(), &differ)
+            .is_none());
+    }
+
+    #[test]
+    fn route_maps() {
+        let mut policy = Policy::new();
+        permit(&mut policy, "SUPPRESS", "10.1.1.0/24");
+
+        let a = Aggregate::parse("aggregate-address 10.1.0.0/16 suppress-map SUPPRESS").unwrap();
+        let result = a
+            .aggregate(&policy, 65000, "10.0.0.1".parse().unwrap(), &routes())
+            .unwrap();
+        let expected: BTreeSet<Prefix> = vec!["10.1.1.0/24".parse().unwrap()].into_iter().collect();
+        assert_eq!(result.suppressed, expected);
+
+        // An attribute-map which does not exist denies the aggregate.
+        let a = Aggregate::parse("aggregate-address 10.1.0.0/16 attribute-map NONE").unwrap();
+        assert!(a
+            .aggregate(&policy, 65000, "10.0.0.1".parse().unwrap(), &routes())
+            .is_none());
+    }
+}
@@ -0,0 +1,85 @@
+#![allow(dead_code)]
+
+use super::{Action, AsPath};
+use regex::Regex;
+
+/// Translate the Cisco style `_` delimiter into a regular expression which
+/// matches the beginning or end of the path or any separator between ASNs.
+pub(crate) fn bgp_regex(s: &str) -> Result<Regex, regex::Error> {
+    Regex::new(&s.replace('_', "(^|[,{}()\\[\\] ]|$)"))
+}
+
+#[derive(Clone, Debug)]
+pub struct AsPathListEntry {
+    pub action: Action,
+    pub regex: Regex,
+}
+
+impl AsPathListEntry {
+    pub fn new(action: Action, s: &str) -> Result<Self, regex::Error> {
+        Ok(AsPathListEntry {
+            action,
+            regex: bgp_regex(s)?,
+        })
+    }
+}
+
+/// AS path access list. Entries are evaluated in insertion order against the
+/// string form of the AS path.
+#[derive(Clone, Debug, Default)]
+pub struct AsPathList {
+    entries: Vec<AsPathListEntry>,
+}
+
+impl AsPathList {
+    pub fn new() -> Self {
+        AsPathList {
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, entry: AsPathListEntry) {
+        self.entries.push(entry)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn apply(&self, path: &AsPath) -> Action {
+        let s = path.to_string();
+        for entry in self.entries.iter() {
+            if entry.regex.is_match(&s) {
+                return entry.action;
+            }
+        }
+        Action::Deny
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn apply() {
+        let mut list = AsPathList::new();
+        list.push(AsPathListEntry::new(Action::Deny, "_65000_").unwrap());
+        list.push(AsPathListEntry::new(Action::Permit, "^100_").unwrap());
+        list.push(AsPathListEntry::new(Action::Permit, "^$").unwrap());
+
+        let apply = |s: &str| list.apply(&s.parse().unwrap());
+        assert_eq!(apply("100 200"), Action::Permit);
+        assert_eq!(apply("100"), Action::Permit);
+        assert_eq!(apply("1000 200"), Action::Deny);
+        assert_eq!(apply("100 65000 200"), Action::Deny);
+        assert_eq!(apply("100 {65000,1}"), Action::Deny);
+        assert_eq!(apply("100 650001"), Action::Permit);
+        assert_eq!(apply(""), Action::Permit);
+        assert_eq!(apply("200"), Action::Deny);
+    }
+}
@@ -0,0 +1,262 @@
+#![allow(dead_code)]
+
+use super::{AsPath, AsSegmentType, AspaState, Route};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use std::path::Path;
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum AspaError {
+    #[error("malformed ASPA JSON: {0}")]
+    Json(String),
+}
+
+/// Result of checking one hop of an AS path against the ASPA of the
+/// customer side.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Hop {
+    NoAttestation,
+    ProviderPlus,
+    NotProviderPlus,
+}
+
+/// Provider sets of customer ASes. An empty set is an ASPA attesting that
+/// the customer has no providers at all, the AS0 ASPA.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AspaTable {
+    aspas: BTreeMap<u32, BTreeSet<u32>>,
+}
+
+fn parse_asn(v: &Value) -> Option<u32> {
+    match v {
+        Value::Number(n) => n.as_u64().and_then(|n| u32::try_from(n).ok()),
+        Value::String(s) => s
+            .strip_prefix("AS")
+            .or_else(|| s.strip_prefix("as"))
+            .unwrap_or(s)
+            .parse()
+            .ok(),
+        _ => None,
+    }
+}
+
+impl AspaTable {
+    pub fn new() -> Self {
+        AspaTable::default()
+    }
+
+    /// Set the providers of `customer`, replacing any earlier ASPA. AS0 is
+    /// not a provider.
+    pub fn insert(&mut self, customer: u32, providers: &[u32]) {
+        let providers = providers.iter().copied().filter(|&p| p != 0).collect();
+        self.aspas.insert(customer, providers);
+    }
+
+    pub fn remove(&mut self, customer: u32) -> bool {
+        self.aspas.remove(&customer).is_some()
+    }
+
+    pub fn get(&self, customer: u32) -> Option<&BTreeSet<u32>> {
+        self.aspas.get(&customer)
+    }
+
+    pub fn len(&self) -> usize {
+        self.aspas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.aspas.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.aspas.clear();
+    }
+
+    /// Parse validator JSON output, an `aspas` array of objects with a
+    /// `customer_asid` (or `customer`) and `providers`. ASNs may be numbers
+    /// or `AS<n>` strings.
+    pub fn from_json(s: &str) -> Result<AspaTable, AspaError> {
+        let json: Value = serde_json::from_str(s).map_err(|e| AspaError::Json(e.to_string()))?;
+        let aspas = json
+            .get("aspas")
+            .and_then(|v| v.as_array())
+            .ok_or_else(|| AspaError::Json("no aspas array".to_string()))?;
+
+        let mut table = AspaTable::new();
+        for aspa in aspas {
+            let customer = aspa
+                .get("customer_asid")
+                .or_else(|| aspa.get("customer"))
+                .and_then(parse_asn)
+                .ok_or_else(|| AspaError::Json(format!("bad customer in {}", aspa)))?;
+            let providers = aspa
+                .get("providers")
+                .and_then(|v| v.as_array())
+                .ok_or_else(|| AspaError::Json(format!("bad providers in {}", aspa)))?
+                .iter()
+                .map(parse_asn)
+                .collect::<Option<Vec<u32>>>()
+                .ok_or_else(|| AspaError::Json(format!("bad provider in {}", aspa)))?;
+            table.insert(customer, &providers);
+        }
+        Ok(table)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<AspaTable, anyhow::Error> {
+        let s = std::fs::read_to_string(path)?;
+        Ok(AspaTable::from_json(&s)?)
+    }
+
+    fn hop(&self, customer: u32, provider: u32) -> Hop {
+        match self.aspas.get(&customer) {
+            Some(providers) if providers.contains(&provider) => Hop::ProviderPlus,
+            Some(_) => Hop::NotProviderPlus,
+            None => Hop::NoAttestation,
+        }
+    }
+
+    /// AS path verification (draft-ietf-sidrops-aspa-verification 6).
+    /// `downstream` is set for routes received from a provider, where the
+    /// path may legitimately go up and then down once. Routes from
+    /// customers, lateral peers and route servers must only go up.
+    pub fn verify(&self, as_path: &AsPath, downstream: bool) -> AspaState {
+        // Unique ASNs, origin first. Confederation segments are internal to
+        // the neighbor and an AS_SET makes the path unverifiable.
+        let mut path: Vec<u32> = Vec::new();
+        for s in as_path.segments().iter().rev() {
+            match s.typ {
+                AsSegmentType::Sequence => {
+                    for asn in s.asns.iter().rev() {
+                        if path.last() != Some(asn) {
+                            path.push(*asn);
+                        }
+                    }
+                }
+                AsSegmentType::Set => return AspaState::Invalid,
+                _ => {}
+            }
+        }
+        let n = path.len();
+        if n == 0 {
+            return AspaState::Invalid;
+        }
+
+        // Up-ramp: hops from the origin towards the neighbor which are
+        // customer to provider.
+        let up = |stop: &dyn Fn(Hop) -> bool| {
+            (0..n - 1)
+                .find(|&i| stop(self.hop(path[i], path[i + 1])))
+                .map_or(n, |i| i + 1)
+        };
+        // Down-ramp: hops from the neighbor towards the origin which are
+        // customer to provider when read in that direction.
+        let down = |stop: &dyn Fn(Hop) -> bool| {
+            (1..n)
+                .rev()
+                .find(|&j| stop(self.hop(path[j], path[j - 1])))
+                .map_or(n, |j| n - j)
+        };
+        let not_provider = |h: Hop| h == Hop::NotProviderPlus;
+        let not_attested = |h: Hop| h != Hop::ProviderPlus;
+
+        let max_up = up(&not_provider);
+        let min_up = up(&not_attested);
+        if !downstream {
+            return if max_up < n {
+                AspaState::Invalid
+            } else if min_up < n {
+                AspaState::Unknown
+            } else {
+                AspaState::Valid
+            };
+        }
+
+        if n <= 2 {
+            return AspaState::Valid;
+        }
+        let max_down = down(&not_provider);
+        let min_down = down(&not_attested);
+        if max_up + max_down < n {
+            AspaState::Invalid
+        } else if min_up + min_down < n {
+            AspaState::Unknown
+        } else {
+            AspaState::Valid
+        }
+    }
+
+    pub fn verify_route(&self, route: &mut Route, downstream: bool) {
+        route.aspa = self.verify(&route.attr.as_path, downstream);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn table() -> AspaTable {
+        // 100 and 200 are customers of 300, 300 of 400 and 500 of 400.
+        let mut table = AspaTable::new();
+        table.insert(100, &[300]);
+        table.insert(200, &[300]);
+        table.insert(300, &[400]);
+        table.insert(500, &[400]);
+        table
+    }
+
+    fn verify(table: &AspaTable, path: &str, downstream: bool) -> AspaState {
+        table.verify(&path.parse().unwrap(), downstream)
+    }
+
+    #[test]
+    fn upstream() {
+        let table = table();
+        assert_eq!(verify(&table, "100", false), AspaState::Valid);
+        assert_eq!(verify(&table, "300 100", false), AspaState::Valid);
+        assert_eq!(verify(&table, "400 300 300 100", false), AspaState::Valid);
+        // 100 leaked a route from its provider 300 to 200.
+        assert_eq!(verify(&table, "100 300 200", false), AspaState::Invalid);
+        // 300 only attests 400 as its provider.
+        assert_eq!(verify(&table, "600 300 100", false), AspaState::Invalid);
+        assert_eq!(verify(&table, "300 600", false), AspaState::Unknown);
+        assert_eq!(verify(&table, "{100,200}", false), AspaState::Invalid);
+    }
+
+    #[test]
+    fn downstream() {
+        let table = table();
+        // Up from 100 to 400, down to 500.
+        assert_eq!(verify(&table, "500 400 300 100", true), AspaState::Valid);
+        assert_eq!(verify(&table, "300 100", true), AspaState::Valid);
+        // Down 300 to 200, up again to 300 is a valley.
+        assert_eq!(
+            verify(&table, "500 400 300 200 300 100", true),
+            AspaState::Invalid
+        );
+        // 600 has no ASPA so the down-ramp can not be verified.
+        assert_eq!(
+            verify(&table, "600 700 400 300 100", true),
+            AspaState::Unknown
+        );
+    }
+
+    #[test]
+    fn from_json() {
+        let json = r#"{
+            "metadata": {"generated": 1700000000},
+            "aspas": [
+                {"customer_asid": 64496, "providers": [64497, 64498]},
+                {"customer": "AS64499", "providers": ["AS0"]}
+            ]
+        }"#;
+        let table = AspaTable::from_json(json).unwrap();
+        assert_eq!(table.len(), 2);
+        assert!(table.get(64496).unwrap().contains(&64498));
+        assert!(table.get(64499).unwrap().is_empty());
+        assert_eq!(verify(&table, "64497 64499", false), AspaState::Invalid);
+
+        assert!(AspaTable::from_json("{}").is_err());
+        assert!(AspaTable::from_json(r#"{"aspas": [{"customer": "x"}]}"#).is_err());
+    }
+}
@@ -0,0 +1,366 @@
+#![allow(dead_code)]
+
+use super::attr::{AttrHeader, ATTR_FLAG_TRANSITIVE, ATTR_TYPE_AS_PATH};
+use byteorder::{NetworkEndian, ReadBytesExt, WriteBytesExt};
+use std::fmt;
+use std::io::Cursor;
+use std::str::FromStr;
+
+/// Placeholder for four-octet ASNs on two-octet sessions (RFC 6793).
+pub const AS_TRANS: u32 = 23456;
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum AsPathError {
+    #[error("malformed AS path")]
+    Malformed,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AsSegmentType {
+    Set = 1,
+    Sequence = 2,
+    ConfedSequence = 3,
+    ConfedSet = 4,
+}
+
+impl AsSegmentType {
+    pub fn from_u8(val: u8) -> Option<Self> {
+        match val {
+            1 => Some(AsSegmentType::Set),
+            2 => Some(AsSegmentType::Sequence),
+            3 => Some(AsSegmentType::ConfedSequence),
+            4 => Some(AsSegmentType::ConfedSet),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct AsSegment {
+    pub typ: AsSegmentType,
+    pub asns: Vec<u32>,
+}
+
+impl AsSegment {
+    /// Maximum number of ASNs a single segment can carry on the wire.
+    pub const MAX_LEN: usize = 255;
+
+    pub fn new(typ: AsSegmentType, asns: Vec<u32>) -> Self {
+        AsSegment { typ, asns }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct AsPath {
+    segments: Vec<AsSegment>,
+}
+
+impl AsPath {
+    pub fn new() -> Self {
+        AsPath {
+            segments: Vec::new(),
+        }
+    }
+
+    pub fn from_segments(segments: Vec<AsSegment>) -> Self {
+        AsPath { segments }
+    }
+
+    pub fn segments(&self) -> &Vec<AsSegment> {
+        &self.segments
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.segments.iter().all(|s| s.asns.is_empty())
+    }
+
+    /// Path length used by best path selection (RFC 4271 9.1.2.2). An AS_SET
+    /// counts as one regardless of its size and confederation segments are
+    /// not counted.
+    pub fn length(&self) -> usize {
+        self.segments
+            .iter()
+            .map(|s| match s.typ {
+                AsSegmentType::Sequence => s.asns.len(),
+                AsSegmentType::Set => 1,
+                _ => 0,
+            })
+            .sum()
+    }
+
+    /// Originating AS, the last ASN of a trailing AS_SEQUENCE.
+    pub fn origin_as(&self) -> Option<u32> {
+        match self.segments.last() {
+            Some(s) if s.typ == AsSegmentType::Sequence => s.asns.last().copied(),
+            _ => None,
+        }
+    }
+
+    /// Leftmost ASN of the path, the AS of the neighbor which sent the route.
+    pub fn first_as(&self) -> Option<u32> {
+        match self.segments.first() {
+            Some(s) if s.typ == AsSegmentType::Sequence => s.asns.first().copied(),
+            _ => None,
+        }
+    }
+
+    pub fn contains(&self, asn: u32) -> bool {
+        self.segments.iter().any(|s| s.asns.contains(&asn))
+    }
+
+    pub fn count(&self, asn: u32) -> usize {
+        self.segments
+            .iter()
+            .map(|s| s.asns.iter().filter(|&&a| a == asn).count())
+            .sum()
+    }
+
+    /// Prepend `asn` `count` times, extending the leading AS_SEQUENCE when it
+    /// has room.
+    pub fn prepend(&mut self, asn: u32, count: usize) {
+        for _ in 0..count {
+            match self.segments.first_mut() {
+                Some(s)
+                    if s.typ == AsSegmentType::Sequence && s.asns.len() < AsSegment::MAX_LEN =>
+                {
+                    s.asns.insert(0, asn);
+                }
+                _ => {
+                    self.segments
+                        .insert(0, AsSegment::new(AsSegmentType::Sequence, vec![asn]));
+                }
+            }
+        }
+    }
+
+    /// Remove every occurrence of `asn`, dropping segments which become empty.
+    pub fn exclude(&mut self, asn: u32) {
+        for s in self.segments.iter_mut() {
+            s.asns.retain(|&a| a != asn);
+        }
+        self.segments.retain(|s| !s.asns.is_empty());
+    }
+    /// Encode as an AS_PATH path attribute with two or four octet ASNs.
+    /// Segments longer than `AsSegment::MAX_LEN` are split.
+    pub fn to_bytes(&self, buf: &mut Vec<u8>, as4: bool) -> Result<usize, anyhow::Error> {
+        let mut value = Vec::new();
+        for s in self.segments.iter() {
+            for asns in s.asns.chunks(AsSegment::MAX_LEN) {
+                value.write_u8(s.typ as u8)?;
+                value.write_u8(asns.len() as u8)?;
+                for asn in asns {
+                    if as4 {
+                        value.write_u32::<NetworkEndian>(*asn)?;
+                    } else if *asn > u16::MAX as u32 {
+                        value.write_u16::<NetworkEndian>(AS_TRANS as u16)?;
+                    } else {
+                        value.write_u16::<NetworkEndian>(*asn as u16)?;
+                    }
+                }
+            }
+        }
+        let header = AttrHeader::new(ATTR_FLAG_TRANSITIVE, ATTR_TYPE_AS_PATH, value.len());
+        let len = header.to_bytes(buf)?;
+        buf.extend_from_slice(&value);
+        Ok(len + value.len())
+    }
+
+    /// Decode the `len` octets value of an AS_PATH path attribute. Unknown
+    /// segment types, empty segments and segments overrunning the attribute
+    /// are malformed (RFC 7606 7.2).
+    pub fn from_bytes(c: &mut Cursor<&[u8]>, len: usize, as4: bool) -> Result<AsPath, AsPathError> {
+        let size = if as4 { 4 } else { 2 };
+        let mut path = AsPath::new();
+        let mut remain = len;
+        while remain > 0 {
+            if remain < 2 {
+                return Err(AsPathError::Malformed);
+            }
+            let typ = c.read_u8().map_err(|_| AsPathError::Malformed)?;
+            let typ = AsSegmentType::from_u8(typ).ok_or(AsPathError::Malformed)?;
+            let count = c.read_u8().map_err(|_| AsPathError::Malformed)? as usize;
+            if count == 0 || 2 + count * size > remain {
+                return Err(AsPathError::Malformed);
+            }
+            let mut asns = Vec::with_capacity(count);
+            for _ in 0..count {
+                let asn = if as4 {
+                    c.read_u32::<NetworkEndian>()
+                } else {
+                    c.read_u16::<NetworkEndian>().map(|v| v as u32)
+                };
+                asns.push(asn.map_err(|_| AsPathError::Malformed)?);
+            }
+            path.segments.push(AsSegment::new(typ, asns));
+            remain -= 2 + count * size;
+        }
+        Ok(path)
+    }
+}
+
+impl fmt::Display for AsSegment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (open, close, sep) = match self.typ {
+            AsSegmentType::Sequence => ("", "", " "),
+            AsSegmentType::Set => ("{", "}", ","),
+            AsSegmentType::ConfedSequence => ("(", ")", " "),
+            AsSegmentType::ConfedSet => ("[", "]", ","),
+        };
+        let asns: Vec<String> = self.asns.iter().map(|a| a.to_string()).collect();
+        write!(f, "{}{}{}", open, asns.join(sep), close)
+    }
+}
+
+impl fmt::Display for AsPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let segs: Vec<String> = self.segments.iter().map(|s| s.to_string()).collect();
+        write!(f, "{}", segs.join(" "))
+    }
+}
+
+impl FromStr for AsPath {
+    type Err = AsPathError;
+
+    /// Parse the `Display` format, e.g. `100 200 {300,400} (65001 65002)`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut path = AsPath::new();
+        let mut chars = s.chars().peekable();
+        let mut seq: Vec<u32> = Vec::new();
+
+        let parse_asn = |s: &str| s.parse::<u32>().map_err(|_| AsPathError::Malformed);
+
+        while let Some(&ch) = chars.peek() {
+            let (typ, close) = match ch {
+                '{' => (AsSegmentType::Set, '}'),
+                '(' => (AsSegmentType::ConfedSequence, ')'),
+                '[' => (AsSegmentType::ConfedSet, ']'),
+                ' ' => {
+                    chars.next();
+                    continue;
+                }
+                _ => {
+                    let mut word = String::new();
+                    while let Some(&c) = chars.peek() {
+                        if c == ' ' || c == '{' || c == '(' || c == '[' {
+                            break;
+                        }
+                        word.push(c);
+                        chars.next();
+                    }
+                    seq.push(parse_asn(&word)?);
+                    continue;
+                }
+            };
+            chars.next();
+            if !seq.is_empty() {
+                path.segments.push(AsSegment::new(
+                    AsSegmentType::Sequence,
+                    std::mem::take(&mut seq),
+                ));
+            }
+            let mut body = String::new();
+            loop {
+                match chars.next() {
+                    Some(c) if c == close => break,
+                    Some(c) => body.push(c),
+                    None => return Err(AsPathError::Malformed),
+                }
+            }
+            let asns = body
+                .split([',', ' '])
+                .filter(|s| !s.is_empty())
+                .map(parse_asn)
+                .collect::<Result<Vec<u32>, AsPathError>>()?;
+            if asns.is_empty() {
+                return Err(AsPathError::Malformed);
+            }
+            path.segments.push(AsSegment::new(typ, asns));
+        }
+        if !seq.is_empty() {
+            path.segments
+                .push(AsSegment::new(AsSegmentType::Sequence, seq));
+        }
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn from_str() {
+        let path: AsPath = "100 200 {300,400} 500".parse().unwrap();
+        assert_eq!(path.segments().len(), 3);
+        assert_eq!(format!("{}", path), "100 200 {300,400} 500");
+        assert_eq!(path.length(), 4);
+        assert_eq!(path.origin_as(), Some(500));
+        assert_eq!(path.first_as(), Some(100));
+
+        let path: AsPath = "(65001 65002) 100 [1,2]".parse().unwrap();
+        assert_eq!(format!("{}", path), "(65001 65002) 100 [1,2]");
+        assert_eq!(path.length(), 1);
+        assert_eq!(path.origin_as(), None);
+        assert_eq!(path.first_as(), None);
+
+        let path: AsPath = "".parse().unwrap();
+        assert!(path.is_empty());
+        assert_eq!(format!("{}", path), "");
+
+        assert!("100 {200".parse::<AsPath>().is_err());
+        assert!("100 abc".parse::<AsPath>().is_err());
+        assert!("100 {}".parse::<AsPath>().is_err());
+    }
+
+    #[test]
+    fn prepend() {
+        let mut path: AsPath = "100 200".parse().unwrap();
+        path.prepend(65000, 2);
+        assert_eq!(format!("{}", path), "65000 65000 100 200");
+        assert_eq!(path.count(65000), 2);
+
+        let mut path: AsPath = "{100,200}".parse().unwrap();
+        path.prepend(65000, 1);
+        assert_eq!(format!("{}", path), "65000 {100,200}");
+
+        let mut path = AsPath::new();
+        path.prepend(1, 300);
+        assert_eq!(path.segments().len(), 2);
+        assert_eq!(path.length(), 300);
+    }
+
+    #[test]
+    fn exclude() {
+        let mut path: AsPath = "100 200 {200,300} 200".parse().unwrap();
+        path.exclude(200);
+        assert_eq!(format!("{}", path), "100 {300}");
+
+        let mut path: AsPath = "{200}".parse().unwrap();
+        path.exclude(200);
+        assert!(path.segments().is_empty());
+    }
+
+    #[test]
+    fn to_bytes() {
+        let path: AsPath = "100 70000 {300,400}".parse().unwrap();
+        let mut buf = Vec::new();
+        assert_eq!(path.to_bytes(&mut buf, true).unwrap(), 23);
+        assert_eq!(buf[..3], [0x40, 2, 20]);
+        let mut c = Cursor::new(&buf[3..]);
+        assert_eq!(AsPath::from_bytes(&mut c, 20, true).unwrap(), path);
+
+        let mut buf = Vec::new();
+        assert_eq!(path.to_bytes(&mut buf, false).unwrap(), 15);
+        let mut c = Cursor::new(&buf[3..]);
+        assert_eq!(
+            AsPath::from_bytes(&mut c, 12, false).unwrap().to_string(),
+            "100 23456 {300,400}"
+        );
+
+        // Zero length segment, unknown type and overrun.
+        for value in [&[2u8, 0][..], &[5, 1, 0, 1], &[2, 2, 0, 1]] {
+            let mut c = Cursor::new(value);
+            assert!(AsPath::from_bytes(&mut c, value.len(), false).is_err());
+        }
+    }
+}
@@ -0,0 +1,287 @@
+#![allow(dead_code)]
+
+use super::aspath::AS_TRANS;
+use super::communities::{
+    COMMUNITY_BLACKHOLE, COMMUNITY_GSHUT, COMMUNITY_NO_ADVERTISE, COMMUNITY_NO_EXPORT,
+};
+use super::{AsPath, Communities, ExtCommunities, LargeCommunities, UpdateContext};
+use byteorder::{NetworkEndian, ReadBytesExt, WriteBytesExt};
+use std::fmt;
+use std::io::Cursor;
+use std::net::{IpAddr, Ipv4Addr};
+use std::str::FromStr;
+
+pub const ATTR_FLAG_OPTIONAL: u8 = 0x80;
+pub const ATTR_FLAG_TRANSITIVE: u8 = 0x40;
+pub const ATTR_FLAG_PARTIAL: u8 = 0x20;
+pub const ATTR_FLAG_EXTENDED_LENGTH: u8 = 0x10;
+
+pub const ATTR_TYPE_ORIGIN: u8 = 1;
+pub const ATTR_TYPE_AS_PATH: u8 = 2;
+pub const ATTR_TYPE_NEXT_HOP: u8 = 3;
+pub const ATTR_TYPE_MED: u8 = 4;
+pub const ATTR_TYPE_LOCAL_PREF: u8 = 5;
+pub const ATTR_TYPE_ATOMIC_AGGREGATE: u8 = 6;
+pub const ATTR_TYPE_AGGREGATOR: u8 = 7;
+pub const ATTR_TYPE_COMMUNITIES: u8 = 8;
+pub const ATTR_TYPE_MP_REACH_NLRI: u8 = 14;
+pub const ATTR_TYPE_MP_UNREACH_NLRI: u8 = 15;
+pub const ATTR_TYPE_EXT_COMMUNITIES: u8 = 16;
+pub const ATTR_TYPE_IPV6_EXT_COMMUNITIES: u8 = 25;
+pub const ATTR_TYPE_LARGE_COMMUNITIES: u8 = 32;
+pub const ATTR_TYPE_OTC: u8 = 35;
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum AttrError {
+    #[error("malformed attribute type {0}")]
+    Malformed(u8),
+    #[error("attribute type {typ} length {len} error")]
+    Length { typ: u8, len: usize },
+}
+
+/// Attribute flags, type code and length. The extended length flag is set on
+/// encode whenever the value does not fit in one octet.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AttrHeader {
+    pub flags: u8,
+    pub typ: u8,
+    pub len: usize,
+}
+
+impl AttrHeader {
+    pub fn new(flags: u8, typ: u8, len: usize) -> Self {
+        AttrHeader { flags, typ, len }
+    }
+
+    pub fn from_bytes(c: &mut Cursor<&[u8]>) -> Result<Self, anyhow::Error> {
+        let flags = c.read_u8()?;
+        let typ = c.read_u8()?;
+        let len = if flags & ATTR_FLAG_EXTENDED_LENGTH != 0 {
+            c.read_u16::<NetworkEndian>()? as usize
+        } else {
+            c.read_u8()? as usize
+        };
+        Ok(AttrHeader { flags, typ, len })
+    }
+
+    pub fn to_bytes(&self, buf: &mut Vec<u8>) -> Result<usize, anyhow::Error> {
+        if self.len > u16::MAX as usize {
+            return Err(AttrError::Length {
+                typ: self.typ,
+                len: self.len,
+            }
+            .into());
+        }
+        if self.len > u8::MAX as usize {
+            buf.write_u8(self.flags | ATTR_FLAG_EXTENDED_LENGTH)?;
+            buf.write_u8(self.typ)?;
+            buf.write_u16::<NetworkEndian>(self.len as u16)?;
+            Ok(4)
+        } else {
+            buf.write_u8(self.flags & !ATTR_FLAG_EXTENDED_LENGTH)?;
+            buf.write_u8(self.typ)?;
+            buf.write_u8(self.len as u8)?;
+            Ok(3)
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Origin {
+    #[default]
+    Igp = 0,
+    Egp = 1,
+    Incomplete = 2,
+}
+
+impl Origin {
+    pub fn from_u8(val: u8) -> Option<Self> {
+        match val {
+            0 => Some(Origin::Igp),
+            1 => Some(Origin::Egp),
+            2 => Some(Origin::Incomplete),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Origin::Igp => "igp",
+            Origin::Egp => "egp",
+            Origin::Incomplete => "incomplete",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl FromStr for Origin {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "igp" => Ok(Origin::Igp),
+            "egp" => Ok(Origin::Egp),
+            "incomplete" => Ok(Origin::Incomplete),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Path attributes of a route.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Attr {
+    pub origin: Origin,
+    pub as_path: AsPath,
+    pub next_hop: Option<IpAddr>,
+    pub med: Option<u32>,
+    pub local_pref: Option<u32>,
+    pub atomic_aggregate: bool,
+    /// AS and BGP identifier of the speaker which aggregated the route.
+    pub aggregator: Option<(u32, Ipv4Addr)>,
+    pub communities: Option<Communities>,
+    pub ext_communities: Option<ExtCommunities>,
+    pub large_communities: Option<LargeCommunities>,
+    /// Only-to-Customer, the AS which restricted the route to customers
+    /// (RFC 9234 5).
+    pub otc: Option<u32>,
+}
+
+impl Attr {
+    pub fn new() -> Self {
+        Attr::default()
+    }
+
+    /// Encode the path attributes other than NEXT_HOP, which depends on the
+    /// NLRI they are sent with. LOCAL_PREF is not sent to an external
+    /// neighbor.
+    pub fn to_bytes(&self, buf: &mut Vec<u8>, ctx: &UpdateContext) -> Result<usize, anyhow::Error> {
+        fn attr(
+            buf: &mut Vec<u8>,
+            flags: u8,
+            typ: u8,
+            value: &[u8],
+        ) -> Result<usize, anyhow::Error> {
+            let len = AttrHeader::new(flags, typ, value.len()).to_bytes(buf)?;
+            buf.extend_from_slice(value);
+            Ok(len + value.len())
+        }
+        let mut len = attr(
+            buf,
+            ATTR_FLAG_TRANSITIVE,
+            ATTR_TYPE_ORIGIN,
+            &[self.origin as u8],
+        )?;
+        len += self.as_path.to_bytes(buf, ctx.as4)?;
+        if let Some(med) = self.med {
+            len += attr(buf, ATTR_FLAG_OPTIONAL, ATTR_TYPE_MED, &med.to_be_bytes())?;
+        }
+        if let (Some(local_pref), false) = (self.local_pref, ctx.external) {
+            len += attr(
+                buf,
+                ATTR_FLAG_TRANSITIVE,
+                ATTR_TYPE_LOCAL_PREF,
+                &local_pref.to_be_bytes(),
+            )?;
+        }
+        if self.atomic_aggregate {
+            len += attr(buf, ATTR_FLAG_TRANSITIVE, ATTR_TYPE_ATOMIC_AGGREGATE, &[])?;
+        }
+        if let Some((asn, addr)) = self.aggregator {
+            let mut value = Vec::new();
+            if ctx.as4 {
+                value.write_u32::<NetworkEndian>(asn)?;
+            } else if asn > u16::MAX as u32 {
+                value.write_u16::<NetworkEndian>(AS_TRANS as u16)?;
+            } else {
+                value.write_u16::<NetworkEndian>(asn as u16)?;
+            }
+            value.extend_from_slice(&addr.octets());
+            let flags = ATTR_FLAG_OPTIONAL | ATTR_FLAG_TRANSITIVE;
+            len += attr(buf, flags, ATTR_TYPE_AGGREGATOR, &value)?;
+        }
+        if let Some(coms) = &self.communities {
+            len += coms.to_bytes(buf)?;
+        }
+        if let Some(ecoms) = &self.ext_communities {
+            len += ecoms.to_bytes(buf)?;
+        }
+        if let Some(lcoms) = &self.large_communities {
+            len += lcoms.to_bytes(buf)?;
+        }
+        if let Some(otc) = self.otc {
+            let flags = ATTR_FLAG_OPTIONAL | ATTR_FLAG_TRANSITIVE;
+            len += attr(buf, flags, ATTR_TYPE_OTC, &otc.to_be_bytes())?;
+        }
+        Ok(len)
+    }
+
+    /// Inbound handling of well-known communities. A route tagged BLACKHOLE
+    /// is kept within the local AS by adding NO_EXPORT unless it is already
+    /// scoped (RFC 7999 3.2), and a route tagged GRACEFUL_SHUTDOWN is
+    /// depreferenced to local preference 0 (RFC 8326 4.2).
+    pub fn apply_well_known_communities(&mut self) {
+        let coms = match self.communities.as_mut() {
+            Some(coms) => coms,
+            None => return,
+        };
+        if coms.contains(&COMMUNITY_BLACKHOLE)
+            && !coms.contains(&COMMUNITY_NO_EXPORT)
+            && !coms.contains(&COMMUNITY_NO_ADVERTISE)
+        {
+            coms.push(COMMUNITY_NO_EXPORT);
+        }
+        if coms.contains(&COMMUNITY_GSHUT) {
+            self.local_pref = Some(0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn header() {
+        let mut buf = Vec::new();
+        let h = AttrHeader::new(ATTR_FLAG_OPTIONAL | ATTR_FLAG_TRANSITIVE, 8, 4);
+        assert_eq!(h.to_bytes(&mut buf).unwrap(), 3);
+        assert_eq!(buf, vec![0xc0, 8, 4]);
+        let mut c = Cursor::new(buf.as_slice());
+        assert_eq!(AttrHeader::from_bytes(&mut c).unwrap(), h);
+
+        let mut buf = Vec::new();
+        let h = AttrHeader::new(ATTR_FLAG_OPTIONAL | ATTR_FLAG_TRANSITIVE, 8, 300);
+        assert_eq!(h.to_bytes(&mut buf).unwrap(), 4);
+        assert_eq!(buf, vec![0xd0, 8, 1, 44]);
+        let mut c = Cursor::new(buf.as_slice());
+        let d = AttrHeader::from_bytes(&mut c).unwrap();
+        assert_eq!(d.len, 300);
+        assert_eq!(d.flags, 0xd0);
+
+        let h = AttrHeader::new(0, 8, 65536);
+        assert!(h.to_bytes(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn well_known_communities() {
+        let mut attr = Attr::new();
+        attr.local_pref = Some(100);
+        attr.communities = Communities::from_str("blackhole graceful-shutdown");
+        attr.apply_well_known_communities();
+        assert_eq!(attr.local_pref, Some(0));
+        assert_eq!(
+            format!("{}", attr.communities.unwrap()),
+            "blackhole graceful-shutdown no-export"
+        );
+
+        let mut attr = Attr::new();
+        attr.communities = Communities::from_str("blackhole no-advertise");
+        attr.apply_well_known_communities();
+        assert_eq!(
+            format!("{}", attr.communities.unwrap()),
+            "blackhole no-advertise"
+        );
+    }
+}
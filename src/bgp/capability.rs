@@ -2,19 +2,19 @@
 
 use byteorder::WriteBytesExt;
 use byteorder::{NetworkEndian, ReadBytesExt};
-use std::io::Cursor;
+use std::io::{Cursor, Read};
 
 pub const AFI_IP: u16 = 1;
-const AFI_IP6: u16 = 2;
-const AFI_L2VPN: u16 = 25;
+pub const AFI_IP6: u16 = 2;
+pub const AFI_L2VPN: u16 = 25;
 const AFI_OPAQUE: u16 = 16397;
 
-const SAFI_UNICAST: u8 = 1;
-const SAFI_MULTICAST: u8 = 2;
+pub const SAFI_UNICAST: u8 = 1;
+pub const SAFI_MULTICAST: u8 = 2;
 const SAFI_MPLS_LABEL: u8 = 4;
 const SAFI_ENCAPSULATION: u8 = 7;
 const SAFI_VPLS: u8 = 65;
-const SAFI_EVPN: u8 = 70;
+pub const SAFI_EVPN: u8 = 70;
 pub const SAFI_MPLS_VPN: u8 = 128;
 const SAFI_MPLS_VPN_MULTICAST: u8 = 129;
 const SAFI_ROUTE_TARGET_CONSTRAINTS: u8 = 132;
@@ -22,11 +22,14 @@ const SAFI_FLOW_SPEC_UNICAST: u8 = 133;
 const SAFI_FLOW_SPEC_VPN: u8 = 134;
 const SAFI_KEY_VALUE: u8 = 241;
 
-#[derive(Debug)]
+/// Optional parameter type of the extended format marker (RFC 9072 2).
+pub const OPT_PARAM_EXTENDED: u8 = 255;
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct Capabilities(Vec<Capability>);
 
 impl Capabilities {
-    const OPT_PARAM_CODE: u8 = 2;
+    pub const OPT_PARAM_CODE: u8 = 2;
 
     pub fn new() -> Self {
         Capabilities(Vec::<Capability>::new())
@@ -44,25 +47,52 @@ impl Capabilities {
         self.0.len()
     }
 
-    pub fn to_bytes(&self, buf: &mut [u8]) -> Result<usize, anyhow::Error> {
+    /// Encode the capabilities as one Capabilities optional parameter. The
+    /// parameter length is two octets in the `extended` format (RFC 9072).
+    pub fn to_bytes(&self, buf: &mut Vec<u8>, extended: bool) -> Result<usize, anyhow::Error> {
         if self.len() == 0 {
             return Ok(0);
         }
 
-        let mut c = Cursor::new(buf);
-        let offset: usize = 2;
-        c.set_position(offset as u64);
-
-        let mut len: usize = 0;
+        let mut value = Vec::new();
         for cap in &self.0 {
-            len += cap.to_bytes(&mut c)?;
+            cap.to_bytes(&mut value)?;
         }
 
-        c.set_position(0);
-        c.write_u8(Capabilities::OPT_PARAM_CODE)?;
-        c.write_u8(len as u8)?;
+        buf.write_u8(Capabilities::OPT_PARAM_CODE)?;
+        if extended {
+            if value.len() > u16::MAX as usize {
+                return Err(Error::Length(value.len()).into());
+            }
+            buf.write_u16::<NetworkEndian>(value.len() as u16)?;
+        } else {
+            if value.len() > u8::MAX as usize {
+                return Err(Error::Length(value.len()).into());
+            }
+            buf.write_u8(value.len() as u8)?;
+        }
+        buf.extend_from_slice(&value);
 
-        Ok(offset + len)
+        Ok(if extended { 3 } else { 2 } + value.len())
+    }
+
+    /// Decode the capabilities in the value of one Capabilities optional
+    /// parameter.
+    pub fn from_bytes(value: &[u8]) -> Result<Capabilities, anyhow::Error> {
+        let mut caps = Capabilities::new();
+        let mut c = Cursor::new(value);
+        while (c.position() as usize) < value.len() {
+            let cap = Capability::from_bytes(&mut c)?;
+            if c.position() as usize > value.len() {
+                return Err(Error::Malformed.into());
+            }
+            caps.push(cap);
+        }
+        Ok(caps)
+    }
+
+    pub fn append(&mut self, other: Capabilities) {
+        self.0.extend(other.0)
     }
 }
 
@@ -70,9 +100,13 @@ impl Capabilities {
 pub enum Error {
     #[error("malformed packet")]
     Malformed,
+    #[error("capabilities length {0} does not fit the optional parameter")]
+    Length(usize),
+    #[error("unsupported optional parameter {0}")]
+    UnsupportedParam(u8),
 }
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Family {
     pub afi: u16,
     pub safi: u8,
@@ -86,7 +120,7 @@ pub struct RestartFlags {
     forwarding_state: u8,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Capability {
     MultiProtocol(Family),
     RouteRefresh,
@@ -99,6 +133,15 @@ pub enum Capability {
     DynamicCapability,
     LongLived(Vec<(Family, u8, u32)>),
     AddPath(Vec<(Family, u8)>),
+    ExtendedMessage,
+    /// Extended Next Hop Encoding (RFC 8950), NLRI families with the AFI of
+    /// the next hop they may be advertised with.
+    ExtendedNextHop(Vec<(Family, u16)>),
+    /// BGP Role (RFC 9234). The raw value is kept so that unknown roles are
+    /// reported as a mismatch.
+    Role(u8),
+    /// A capability which is not supported, with its code and value.
+    Unknown(u8, Vec<u8>),
 }
 
 impl Capability {
@@ -114,6 +157,8 @@ impl Capability {
     const CAPABILITY_CODE_ORF: u8 = 3; /* Cooperative Route Filtering Capability */
     const CAPABILITY_CODE_LABEL_INFO: u8 = 4; /* Carrying Label Information */
     const CAPABILITY_CODE_ENHE: u8 = 5; /* Extended Next Hop Encoding */
+    const EXTENDED_MESSAGE: u8 = 6; /* Extended Message */
+    const ROLE: u8 = 9; /* BGP Role */
     const CAPABILITY_CODE_ENH_REFRESH: u8 = 70; /* Enhanced Route Refresh */
     const CAPABILITY_CODE_FQDN: u8 = 73; /* Advertise hostname capability */
     const CAPABILITY_CODE_ORF_OLD: u8 = 130; /* Cooperative Route Filtering Capability(Cisco) */
@@ -207,26 +252,126 @@ impl Capability {
                 }
                 return Ok(Capability::LongLived(v));
             }
-            _ => {
-                // Unknown capability.
+            Capability::EXTENDED_MESSAGE => {
+                if len != 0 {
+                    return Err(Error::Malformed.into());
+                }
+                return Ok(Capability::ExtendedMessage);
             }
+            Capability::CAPABILITY_CODE_ENHE => {
+                if len == 0 || len % 6 != 0 {
+                    return Err(Error::Malformed.into());
+                }
+                let mut v = Vec::new();
+                for _ in 0..len / 6 {
+                    let afi = c.read_u16::<NetworkEndian>()?;
+                    // The NLRI SAFI is two octets here.
+                    let safi = c.read_u16::<NetworkEndian>()?;
+                    let nexthop_afi = c.read_u16::<NetworkEndian>()?;
+                    if safi > u8::MAX as u16 {
+                        continue;
+                    }
+                    v.push((
+                        Family {
+                            afi,
+                            safi: safi as u8,
+                        },
+                        nexthop_afi,
+                    ));
+                }
+                return Ok(Capability::ExtendedNextHop(v));
+            }
+            Capability::ROLE => {
+                if len != 1 {
+                    return Err(Error::Malformed.into());
+                }
+                return Ok(Capability::Role(c.read_u8()?));
+            }
+            _ => {}
         }
-        Ok(Capability::RouteRefresh)
+        // Unknown capability, kept with its value.
+        let mut value = vec![0; len as usize];
+        c.read_exact(&mut value)?;
+        Ok(Capability::Unknown(code, value))
     }
 
-    pub fn to_bytes(&self, c: &mut Cursor<&mut [u8]>) -> Result<usize, anyhow::Error> {
-        let sp = c.position();
+    pub fn to_bytes(&self, buf: &mut Vec<u8>) -> Result<usize, anyhow::Error> {
+        let sp = buf.len();
+        // Code and a length which is filled in when the value is written.
+        let code = match self {
+            Capability::MultiProtocol(_) => Capability::MULTI_PROTOCOL,
+            Capability::RouteRefresh => Capability::ROUTE_REFRESH,
+            Capability::GracefulRestart { .. } => Capability::GRACEFUL_RESTART,
+            Capability::FourOctetAs(_) => Capability::FOUR_OCTET_AS,
+            Capability::DynamicCapability => Capability::DYNAMIC_CAPABILITY,
+            Capability::LongLived(_) => Capability::LONG_LIVED_GRACEFUL_RESTART,
+            Capability::AddPath(_) => Capability::ADD_PATH,
+            Capability::ExtendedMessage => Capability::EXTENDED_MESSAGE,
+            Capability::ExtendedNextHop(_) => Capability::CAPABILITY_CODE_ENHE,
+            Capability::Role(_) => Capability::ROLE,
+            Capability::Unknown(code, _) => *code,
+        };
+        buf.write_u8(code)?;
+        buf.write_u8(0)?;
+
         match self {
             Capability::MultiProtocol(family) => {
-                c.write_u8(Capability::MULTI_PROTOCOL)?;
-                c.write_u8(4)?;
-                c.write_u16::<NetworkEndian>(family.afi)?;
-                c.write_u8(0)?;
-                c.write_u8(family.safi)?;
-                return Ok((c.position() - sp) as usize);
+                buf.write_u16::<NetworkEndian>(family.afi)?;
+                buf.write_u8(0)?;
+                buf.write_u8(family.safi)?;
             }
-            _ => {}
+            Capability::RouteRefresh
+            | Capability::DynamicCapability
+            | Capability::ExtendedMessage => {}
+            Capability::GracefulRestart {
+                flags,
+                time,
+                families,
+            } => {
+                buf.write_u16::<NetworkEndian>((*flags as u16) << 12 | (*time & 0xfff))?;
+                for (family, flags) in families {
+                    buf.write_u16::<NetworkEndian>(family.afi)?;
+                    buf.write_u8(family.safi)?;
+                    buf.write_u8(*flags)?;
+                }
+            }
+            Capability::FourOctetAs(asn) => {
+                buf.write_u32::<NetworkEndian>(*asn)?;
+            }
+            Capability::LongLived(v) => {
+                for (family, flags, time) in v {
+                    buf.write_u16::<NetworkEndian>(family.afi)?;
+                    buf.write_u8(family.safi)?;
+                    buf.write_u8(*flags)?;
+                    buf.write_u8((*time >> 16) as u8)?;
+                    buf.write_u16::<NetworkEndian>(*time as u16)?;
+                }
+            }
+            Capability::AddPath(v) => {
+                for (family, flags) in v {
+                    buf.write_u16::<NetworkEndian>(family.afi)?;
+                    buf.write_u8(family.safi)?;
+                    buf.write_u8(*flags)?;
+                }
+            }
+            Capability::ExtendedNextHop(v) => {
+                for (family, nexthop_afi) in v {
+                    buf.write_u16::<NetworkEndian>(family.afi)?;
+                    buf.write_u16::<NetworkEndian>(family.safi as u16)?;
+                    buf.write_u16::<NetworkEndian>(*nexthop_afi)?;
+                }
+            }
+            Capability::Role(role) => {
+                buf.write_u8(*role)?;
+            }
+            Capability::Unknown(_, value) => buf.extend_from_slice(value),
+        }
+
+        let len = buf.len() - sp - 2;
+        if len > u8::MAX as usize {
+            return Err(Error::Length(len).into());
         }
-        Ok(0)
+        buf[sp + 1] = len as u8;
+        Ok(buf.len() - sp)
     }
 }
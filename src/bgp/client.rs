@@ -1,10 +1,21 @@
+use crate::bgp::aspath::AS_TRANS;
 use crate::bgp::packet::MutableBgpHeaderPacket;
 use crate::bgp::packet::MutableBgpOpenPacket;
-use crate::bgp::packet::{BgpHeaderPacket, BgpOpenOptPacket, BgpOpenPacket, BgpTypes};
-use crate::bgp::{Capabilities, Capability, Family, AFI_IP, BGP_HEADER_LEN, SAFI_MPLS_VPN};
+use crate::bgp::packet::{BgpHeaderPacket, BgpOpenPacket, BgpType, BgpTypes};
+use crate::bgp::{AdjOut, ErrorHandling, MessageUpdate, PeerType, UpdateContext, UpdateError};
+use crate::bgp::{
+    Capabilities, Capability, Family, AFI_IP, AFI_IP6, BGP_HEADER_LEN, SAFI_MPLS_VPN,
+};
+use crate::bgp::{Error as CapabilityError, BGP_EXTENDED_MAX_LEN, BGP_MAX_LEN, OPT_PARAM_EXTENDED};
+use crate::bgp::{MaxPrefixEvent, MessageNotification, Neighbor, PrefixCounter, Role};
+use crate::bgp::{NOTIFY_HEADER_BAD_MESTYPE, NOTIFY_HEADER_ERR, NOTIFY_HEADER_NOT_SYNC};
+use crate::bgp::{NOTIFY_OPEN_BAD_PEER_AS, NOTIFY_OPEN_ERR, NOTIFY_OPEN_UNSUP_PARAM};
+use crate::bgp::{NOTIFY_OPEN_UNACEP_HOLDTIME, NOTIFY_OPEN_UNSUP_VERSION};
+use byteorder::{NetworkEndian, ReadBytesExt};
 use bytes::BytesMut;
 use pnet::packet::Packet;
-use std::io::{Error, ErrorKind};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{Cursor, Error, ErrorKind};
 use std::net::{Ipv4Addr, SocketAddr};
 use tokio::net::TcpStream;
 use tokio_util::codec::{Decoder, Encoder};
@@ -17,11 +28,21 @@ pub enum Event {
     Connect(SocketAddr),
     TimerExpired,
     Packet(Message),
+    /// Close the session with a Cease NOTIFICATION and end its task.
+    Stop,
+    /// The neighbor's configuration changed.
+    Config(Box<Neighbor>),
+    /// `clear bgp`, reset the session.
+    Clear,
+    /// Changes of the Adj-RIB-Out to send on the connection with the id.
+    Adj(u64, Vec<AdjOut>),
 }
 
 #[derive(Debug)]
 pub enum Message {
     Open(MessageOpen),
+    Update(MessageUpdate),
+    Notification(MessageNotification),
     KeepAlive,
     None,
 }
@@ -54,54 +75,94 @@ impl MessageOpen {
         }
     }
 
-    pub fn from_bytes(buf: &[u8]) -> Result<Self, anyhow::Error> {
-        let open = BgpOpenPacket::new(buf).ok_or(Error::from(ErrorKind::UnexpectedEof))?;
-        let opt_len = open.get_opt_param_len() as usize;
-        println!("opt_len {}", opt_len);
-        println!("opt_payloadlen {}", open.payload().len());
-
-        if opt_len > open.payload().len() {
-            return Err(Error::from(ErrorKind::UnexpectedEof).into());
+    /// OPEN announcing the local speaker. Four-octet AS numbers are always
+    /// announced, AS_TRANS takes the place of an AS which does not fit two
+    /// octets (RFC 6793 4.1).
+    pub fn local(asn: u32, hold_time: u16, router_id: Ipv4Addr, caps: Vec<Capability>) -> Self {
+        let mut open = MessageOpen {
+            version: 4,
+            asn: if asn > u16::MAX as u32 {
+                AS_TRANS as u16
+            } else {
+                asn as u16
+            },
+            hold_time,
+            router_id,
+            caps: Capabilities::new(),
+        };
+        open.push_cap(Capability::FourOctetAs(asn));
+        for cap in caps {
+            open.push_cap(cap);
         }
+        open
+    }
+
+    /// AS of the speaker, from the four-octet AS capability when present.
+    pub fn asn(&self) -> u32 {
+        self.caps
+            .get_ref()
+            .iter()
+            .find_map(|cap| match cap {
+                Capability::FourOctetAs(asn) => Some(*asn),
+                _ => None,
+            })
+            .unwrap_or(self.asn as u32)
+    }
 
-        println!("before cap parse");
+    pub fn hold_time(&self) -> u16 {
+        self.hold_time
+    }
 
-        let mut caps = Capabilities::new();
-        if opt_len > 0 {
-            println!("opt_len {}", opt_len);
+    pub fn router_id(&self) -> Ipv4Addr {
+        self.router_id
+    }
+
+    pub fn caps(&self) -> &Capabilities {
+        &self.caps
+    }
+
+    pub fn push_cap(&mut self, cap: Capability) {
+        self.caps.push(cap);
+    }
 
-            let opt = BgpOpenOptPacket::new(open.payload())
-                .ok_or(Error::from(ErrorKind::UnexpectedEof))?;
+    pub fn from_bytes(buf: &[u8]) -> Result<Self, anyhow::Error> {
+        let open = BgpOpenPacket::new(buf).ok_or(Error::from(ErrorKind::UnexpectedEof))?;
+        let payload = open.payload();
+        let mut opt_len = open.get_opt_param_len() as usize;
+        if opt_len > payload.len() {
+            return Err(Error::from(ErrorKind::UnexpectedEof).into());
+        }
 
-            // When Open opt message is not capability(2) return here.
-            if opt.get_typ() != 2 {
-                println!("Opt type is not 2");
+        // Extended format, the real length follows the marker type (RFC
+        // 9072 2).
+        let mut c = Cursor::new(payload);
+        let extended = opt_len > 0 && payload[0] == OPT_PARAM_EXTENDED;
+        if extended {
+            c.read_u8()?;
+            opt_len = c.read_u16::<NetworkEndian>()? as usize;
+            if 3 + opt_len > payload.len() {
                 return Err(Error::from(ErrorKind::UnexpectedEof).into());
             }
-            let mut len = opt.get_length() as usize;
-            if len > opt.payload().len() {
-                println!("len is smaller than payload");
+        }
+        let end = c.position() as usize + opt_len;
+
+        let mut caps = Capabilities::new();
+        while (c.position() as usize) < end {
+            let typ = c.read_u8()?;
+            let len = if extended {
+                c.read_u16::<NetworkEndian>()? as usize
+            } else {
+                c.read_u8()? as usize
+            };
+            let pos = c.position() as usize;
+            if pos + len > end {
                 return Err(Error::from(ErrorKind::UnexpectedEof).into());
             }
-
-            // Parse Open capability message.
-            let mut c = std::io::Cursor::new(opt.payload());
-
-            while len > 0 {
-                let pos = c.position();
-                match Capability::from_bytes(&mut c) {
-                    Ok(cap) => caps.push(cap),
-                    Err(e) => {
-                        println!("XXX error {}", e);
-                        return Err(e);
-                    }
-                }
-                let diff = (c.position() - pos) as usize;
-                if diff > len {
-                    return Err(Error::from(ErrorKind::UnexpectedEof).into());
-                }
-                len -= diff;
+            if typ != Capabilities::OPT_PARAM_CODE {
+                return Err(CapabilityError::UnsupportedParam(typ).into());
             }
+            caps.append(Capabilities::from_bytes(&payload[pos..pos + len])?);
+            c.set_position((pos + len) as u64);
         }
 
         Ok(MessageOpen {
@@ -114,21 +175,42 @@ impl MessageOpen {
         })
     }
 
-    pub fn to_bytes(&self, buf: &mut [u8]) -> Result<usize, anyhow::Error> {
+    pub fn to_bytes(&self, buf: &mut Vec<u8>) -> Result<usize, anyhow::Error> {
+        // Optional parameters use the extended format only when they do
+        // not fit the one octet length.
+        let mut params = Vec::new();
+        let mut extended = false;
+        if self.caps.to_bytes(&mut params, false).is_err() || params.len() > u8::MAX as usize {
+            params.clear();
+            self.caps.to_bytes(&mut params, true)?;
+            extended = true;
+        }
+
+        let start = buf.len();
         let offset = MutableBgpOpenPacket::minimum_packet_size();
-        let len = self.caps.to_bytes(&mut buf[offset..])?;
+        buf.resize(start + offset, 0);
+        if extended {
+            buf.push(OPT_PARAM_EXTENDED);
+            buf.extend_from_slice(&(params.len() as u16).to_be_bytes());
+        }
+        buf.extend_from_slice(&params);
 
-        let mut open = MutableBgpOpenPacket::new(buf).unwrap();
+        let mut open = MutableBgpOpenPacket::new(&mut buf[start..]).unwrap();
         open.set_version(self.version);
         open.set_asn(self.asn as u16);
         open.set_hold_time(self.hold_time);
         open.set_router_id(self.router_id);
-        open.set_opt_param_len(len as u8);
+        open.set_opt_param_len(if extended {
+            OPT_PARAM_EXTENDED
+        } else {
+            params.len() as u8
+        });
 
-        Ok(offset + len)
+        Ok(buf.len() - start)
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum State {
     Idle,
     Connect,
@@ -140,34 +222,211 @@ pub enum State {
 
 pub struct Peer {
     pub state: State,
+    pub prefix_count: BTreeMap<Family, PrefixCounter>,
+    pub peer_type: PeerType,
+    /// AS of the neighbor from its OPEN.
+    pub remote_as: u32,
+    /// BGP Identifier of the neighbor from its OPEN.
+    pub router_id: Ipv4Addr,
+    /// Four-octet AS numbers were negotiated.
+    pub as4: bool,
+    /// Families disabled by a malformed MP_REACH_NLRI or MP_UNREACH_NLRI
+    /// until the session is reset (RFC 7606 2).
+    pub disabled: BTreeSet<Family>,
+    /// Role announced by the neighbor (RFC 9234).
+    pub role: Option<Role>,
+    /// Extended Message capability was negotiated (RFC 8654).
+    pub extended_message: bool,
+    /// IPv4 routes are exchanged with IPv6 next hops (RFC 8950).
+    pub extended_nexthop: bool,
 }
 
-impl Message {
-    pub fn to_bytes(self) -> Result<Vec<u8>, anyhow::Error> {
-        let mut buf = [0u8; 4096];
-        let mut len: usize = BGP_HEADER_LEN;
-        let mut typ = BgpTypes::OPEN;
+impl Peer {
+    pub fn new(state: State) -> Self {
+        Peer {
+            state,
+            prefix_count: BTreeMap::new(),
+            peer_type: PeerType::External,
+            remote_as: 0,
+            router_id: Ipv4Addr::UNSPECIFIED,
+            as4: false,
+            disabled: BTreeSet::new(),
+            role: None,
+            extended_message: false,
+            extended_nexthop: false,
+        }
+    }
 
-        match self {
-            Message::Open(m) => {
-                typ = BgpTypes::OPEN;
-                len += m.to_bytes(&mut buf[len..])?;
+    pub fn update_context(&self) -> UpdateContext {
+        UpdateContext {
+            as4: self.as4,
+            external: self.peer_type == PeerType::External,
+            extended_nexthop: self.extended_nexthop,
+        }
+    }
+
+    /// Validate the neighbor's OPEN, `local_as` is the AS of our side of the
+    /// session. Returns the NOTIFICATION to send when the session must not
+    /// come up.
+    pub fn open_received(
+        &mut self,
+        neighbor: &Neighbor,
+        local_as: u32,
+        open: &MessageOpen,
+    ) -> Result<(), MessageNotification> {
+        if open.version != 4 {
+            return Err(MessageNotification::new(
+                NOTIFY_OPEN_ERR,
+                NOTIFY_OPEN_UNSUP_VERSION,
+                vec![0, 4],
+            ));
+        }
+        if open.hold_time == 1 || open.hold_time == 2 {
+            return Err(MessageNotification::new(
+                NOTIFY_OPEN_ERR,
+                NOTIFY_OPEN_UNACEP_HOLDTIME,
+                Vec::new(),
+            ));
+        }
+        if let Some(remote_as) = neighbor.remote_as {
+            if !remote_as.matches(open.asn(), local_as) {
+                return Err(MessageNotification::new(
+                    NOTIFY_OPEN_ERR,
+                    NOTIFY_OPEN_BAD_PEER_AS,
+                    Vec::new(),
+                ));
+            }
+        }
+        self.remote_as = open.asn();
+        self.router_id = open.router_id();
+        self.peer_type = if open.asn() == local_as {
+            PeerType::Internal
+        } else {
+            PeerType::External
+        };
+        self.role = neighbor.role_check(open.caps())?;
+        self.as4 = open
+            .caps()
+            .get_ref()
+            .iter()
+            .any(|cap| matches!(cap, Capability::FourOctetAs(_)));
+        self.extended_message = neighbor.extended_message
+            && open.caps().get_ref().contains(&Capability::ExtendedMessage);
+        self.extended_nexthop = neighbor.extended_nexthop_enabled()
+            && open.caps().get_ref().iter().any(|cap| match cap {
+                Capability::ExtendedNextHop(v) => v
+                    .iter()
+                    .any(|(family, afi)| family.afi == AFI_IP && *afi == AFI_IP6),
+                _ => false,
+            });
+        Ok(())
+    }
+
+    /// Disable families with a malformed MP_REACH_NLRI or MP_UNREACH_NLRI
+    /// and drop routes of disabled families. The errors handled stay in
+    /// `update`.
+    pub fn update_received(&mut self, mut update: MessageUpdate) -> MessageUpdate {
+        for e in update.errors.iter() {
+            if let (ErrorHandling::AfiSafiDisable, Some(family)) = (e.handling, e.family) {
+                self.disabled.insert(family);
+            }
+        }
+        if let Some(reach) = &update.mp_reach {
+            if self.disabled.contains(&reach.family) {
+                update.mp_reach = None;
             }
-            Message::KeepAlive => {
-                typ = BgpTypes::KEEPALIVE;
+        }
+        if let Some(unreach) = &update.mp_unreach {
+            if self.disabled.contains(&unreach.family) {
+                update.mp_unreach = None;
             }
-            _ => {}
         }
+        update
+    }
+
+    /// Account for a prefix accepted from `neighbor`. Returns the
+    /// maximum-prefix event the count reached.
+    pub fn prefix_accepted(
+        &mut self,
+        neighbor: &Neighbor,
+        family: Family,
+    ) -> Option<MaxPrefixEvent> {
+        let max = neighbor.max_prefix.get(&family);
+        self.prefix_count.entry(family).or_default().increment(max)
+    }
+
+    pub fn prefix_withdrawn(&mut self, neighbor: &Neighbor, family: Family) {
+        if let Some(counter) = self.prefix_count.get_mut(&family) {
+            counter.decrement(neighbor.max_prefix.get(&family));
+        }
+    }
+}
+
+/// Maximum length of a message of type `typ`. OPEN and KEEPALIVE are never
+/// extended (RFC 8654 4).
+pub fn max_message_len(typ: BgpType, extended: bool) -> usize {
+    if extended && typ != BgpTypes::OPEN && typ != BgpTypes::KEEPALIVE {
+        BGP_EXTENDED_MAX_LEN
+    } else {
+        BGP_MAX_LEN
+    }
+}
 
-        // BGP Header.
-        for i in 0..16 {
-            buf[i] = 0xff;
+/// Minimum length of a message of type `typ` (RFC 4271 4).
+fn min_message_len(typ: BgpType) -> usize {
+    match typ {
+        BgpTypes::OPEN => 29,
+        BgpTypes::UPDATE => 23,
+        BgpTypes::NOTIFICATION => 21,
+        _ => BGP_HEADER_LEN,
+    }
+}
+
+impl Message {
+    /// Encode the message, `extended` when the Extended Message capability
+    /// was negotiated. UPDATEs are encoded with four-octet ASNs.
+    pub fn to_bytes(self, extended: bool) -> Result<Vec<u8>, anyhow::Error> {
+        let ctx = UpdateContext {
+            as4: true,
+            ..Default::default()
+        };
+        self.encode(extended, &ctx)
+    }
+
+    /// Encode the message, UPDATEs for a session with properties `ctx`.
+    pub fn encode(self, extended: bool, ctx: &UpdateContext) -> Result<Vec<u8>, anyhow::Error> {
+        let mut buf = vec![0xff; 16];
+        buf.extend_from_slice(&[0, 0, 0]);
+        let typ = match self {
+            Message::Open(m) => {
+                m.to_bytes(&mut buf)?;
+                BgpTypes::OPEN
+            }
+            Message::Update(m) => {
+                m.to_bytes(&mut buf, ctx)?;
+                BgpTypes::UPDATE
+            }
+            Message::Notification(m) => {
+                m.to_bytes(&mut buf)?;
+                BgpTypes::NOTIFICATION
+            }
+            _ => BgpTypes::KEEPALIVE,
+        };
+
+        let len = buf.len();
+        let max = max_message_len(typ, extended);
+        if len > max {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("message length {} exceeds {}", len, max),
+            )
+            .into());
         }
-        let mut header = MutableBgpHeaderPacket::new(&mut buf[..len]).unwrap();
+        let mut header = MutableBgpHeaderPacket::new(&mut buf).unwrap();
         header.set_bgp_type(typ);
         header.set_length(len as u16);
 
-        Ok((&buf[..len]).to_vec())
+        Ok(buf)
     }
 }
 
@@ -176,100 +435,299 @@ impl Encoder<Message> for Peer {
     type Error = anyhow::Error;
 
     fn encode(&mut self, msg: Message, dst: &mut BytesMut) -> Result<(), anyhow::Error> {
-        let buf = msg.to_bytes()?;
+        let buf = msg.encode(self.extended_message, &self.update_context())?;
         dst.extend_from_slice(&buf);
         Ok(())
     }
 }
 
-pub fn from_bytes(buf: &mut BytesMut) -> Result<Message, anyhow::Error> {
-    println!("--------------------");
-    println!("RECV: Buffer length {}", buf.len());
-    let n = buf.len();
+/// NOTIFICATION for an OPEN which cannot be decoded: an optional parameter
+/// other than Capabilities, or a malformed one (RFC 4271 6.2).
+fn open_notification(e: anyhow::Error) -> MessageNotification {
+    let subcode = match e.downcast_ref::<CapabilityError>() {
+        Some(CapabilityError::UnsupportedParam(_)) => NOTIFY_OPEN_UNSUP_PARAM,
+        _ => 0,
+    };
+    MessageNotification::new(NOTIFY_OPEN_ERR, subcode, Vec::new())
+}
 
-    if n == 0 {
-        println!("XXX read length is zero");
-        //std::process::exit(1);
-        return Ok(Message::None);
+/// Take one message off `buf`. Returns `Ok(None)` until the whole message
+/// has been received. Errors which close the session carry the
+/// NOTIFICATION to send, see `Peer::notification`.
+pub fn from_bytes(
+    buf: &mut BytesMut,
+    ctx: &UpdateContext,
+    extended: bool,
+) -> Result<Option<Message>, anyhow::Error> {
+    if buf.len() < BGP_HEADER_LEN {
+        return Ok(None);
     }
-
-    if n < 19 {
-        // Need to read more.
-        println!("BGP packet length is smaller than minimum length (19).");
-        return Err(std::io::Error::from(std::io::ErrorKind::BrokenPipe).into());
+    if buf[..16].iter().any(|&b| b != 0xff) {
+        return Err(MessageNotification::new(
+            NOTIFY_HEADER_ERR,
+            NOTIFY_HEADER_NOT_SYNC,
+            Vec::new(),
+        )
+        .into());
+    }
+    let header = BgpHeaderPacket::new(&buf[..BGP_HEADER_LEN])
+        .ok_or(Error::from(ErrorKind::UnexpectedEof))?;
+    let typ = header.get_bgp_type();
+    let len = header.get_length();
+
+    if (len as usize) < min_message_len(typ)
+        || (len as usize) > max_message_len(typ, extended)
+        || (typ == BgpTypes::KEEPALIVE && len as usize != BGP_HEADER_LEN)
+    {
+        return Err(MessageNotification::bad_message_length(len).into());
+    }
+    if buf.len() < len as usize {
+        buf.reserve(len as usize - buf.len());
+        return Ok(None);
     }
-    let packet = BgpHeaderPacket::new(&buf).unwrap();
-    let typ = packet.get_bgp_type();
-    let len = packet.get_length();
-
-    println!("RECV: Header Type {:?}", typ);
-    println!("RECV: Header length {:?}", len);
 
+    let frame = buf.split_to(len as usize);
+    let payload = &frame[BGP_HEADER_LEN..];
     let msg = match typ {
         BgpTypes::OPEN => {
-            let msg = MessageOpen::from_bytes(packet.payload())?;
-            Message::Open(msg)
-        }
-        BgpTypes::UPDATE => {
-            println!("Update message!");
-            Message::KeepAlive
-        }
-        BgpTypes::NOTIFICATION => {
-            println!("Notification message!");
-            Message::KeepAlive
-        }
-        BgpTypes::KEEPALIVE => {
-            println!("Keepalive message!");
-            Message::KeepAlive
+            Message::Open(MessageOpen::from_bytes(payload).map_err(open_notification)?)
         }
+        BgpTypes::UPDATE => Message::Update(MessageUpdate::from_bytes(payload, ctx)?),
+        BgpTypes::NOTIFICATION => Message::Notification(MessageNotification::from_bytes(payload)?),
+        BgpTypes::KEEPALIVE => Message::KeepAlive,
         unknown => {
-            println!("Unknown message type {:?}", unknown);
-            Message::KeepAlive
+            return Err(MessageNotification::new(
+                NOTIFY_HEADER_ERR,
+                NOTIFY_HEADER_BAD_MESTYPE,
+                vec![unknown.0],
+            )
+            .into());
         }
     };
-    println!("{:?}", msg);
-    println!("Packet Forward Length {:?}", len);
-
-    let _ = buf.split_to(len as usize);
+    Ok(Some(msg))
+}
 
-    Ok(msg)
+impl Peer {
+    /// NOTIFICATION to send for a decode error, `None` when the connection
+    /// failed.
+    pub fn notification(e: &anyhow::Error) -> Option<MessageNotification> {
+        if let Some(e) = e.downcast_ref::<UpdateError>() {
+            return Some(e.notification());
+        }
+        e.downcast_ref::<MessageNotification>().cloned()
+    }
 }
 
 impl Decoder for Peer {
     type Item = Message;
-    type Error = std::io::Error;
-
-    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Message>, Error> {
-        if src.is_empty() {
-            println!("XXXX Empty");
-            return Ok(None);
-        }
-
-        println!("YYY: decode is called");
-        match from_bytes(src) {
-            Ok(Message::None) => {
-                println!("XXX Message::None");
-                Ok(Some(Message::None))
-            }
+    type Error = anyhow::Error;
 
-            Ok(Message::Open(m)) => {
-                match self.state {
-                    State::OpenSent => {
-                        println!("OpenSent -> OpenConfirm");
-                        self.state = State::OpenConfirm;
-                    }
-                    _ => {}
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Message>, anyhow::Error> {
+        let ctx = self.update_context();
+        match from_bytes(src, &ctx, self.extended_message)? {
+            Some(Message::Open(m)) => {
+                if let State::OpenSent = self.state {
+                    self.state = State::OpenConfirm;
                 }
                 Ok(Some(Message::Open(m)))
             }
-            Ok(m) => {
-                println!("XXX Other messages:");
-                Ok(Some(m))
+            Some(Message::Update(m)) => Ok(Some(Message::Update(self.update_received(m)))),
+            msg => Ok(msg),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn open(caps: Capabilities) -> MessageOpen {
+        MessageOpen {
+            version: 4,
+            asn: 65000,
+            hold_time: 90,
+            router_id: "10.0.0.1".parse().unwrap(),
+            caps,
+        }
+    }
+
+    #[test]
+    fn open_extended_params() {
+        let mut buf = Vec::new();
+        let m = MessageOpen::new();
+        let len = m.to_bytes(&mut buf).unwrap();
+        assert_eq!(buf[9], 8);
+        assert_eq!(len, 10 + 8);
+        let d = MessageOpen::from_bytes(&buf[..len]).unwrap();
+        assert_eq!(d.caps, m.caps);
+
+        // Add-path for enough families to overflow the one octet length.
+        let mut caps = Capabilities::new();
+        for safi in 0..80 {
+            let family = Family { afi: AFI_IP, safi };
+            caps.push(Capability::MultiProtocol(family));
+            caps.push(Capability::AddPath(vec![(family, 3)]));
+        }
+        let m = open(caps);
+        buf.clear();
+        let len = m.to_bytes(&mut buf).unwrap();
+        assert_eq!(buf[9], OPT_PARAM_EXTENDED);
+        assert_eq!(buf[10], OPT_PARAM_EXTENDED);
+        assert_eq!(len, 10 + 3 + 3 + 80 * 12);
+        let d = MessageOpen::from_bytes(&buf[..len]).unwrap();
+        assert_eq!(d.caps, m.caps);
+    }
+
+    #[test]
+    fn open_multiple_params() {
+        // Two Capabilities parameters, then an unsupported parameter type.
+        let mut buf = vec![4, 0xfd, 0xe8, 0, 90, 10, 0, 0, 1, 13];
+        buf.extend_from_slice(&[2, 6, 1, 4, 0, 1, 0, 1]);
+        buf.extend_from_slice(&[2, 3, 9, 1, 3]);
+        let d = MessageOpen::from_bytes(&buf).unwrap();
+        assert_eq!(d.caps.len(), 2);
+        assert_eq!(d.caps.get_ref()[1], Capability::Role(3));
+
+        buf[9] += 3;
+        buf.extend_from_slice(&[1, 1, 0]);
+        assert!(MessageOpen::from_bytes(&buf).is_err());
+
+        // Parameter overruns the optional parameters.
+        buf[9] = 7;
+        assert!(MessageOpen::from_bytes(&buf).is_err());
+    }
+
+    #[test]
+    fn open_errors() {
+        let ctx = UpdateContext::default();
+        let decode = |open: &[u8]| {
+            let mut buf = vec![0xff; 16];
+            buf.extend_from_slice(&((BGP_HEADER_LEN + open.len()) as u16).to_be_bytes());
+            buf.push(1);
+            buf.extend_from_slice(open);
+            let e = from_bytes(&mut BytesMut::from(&buf[..]), &ctx, false).unwrap_err();
+            let n = Peer::notification(&e).unwrap();
+            assert_eq!(n.code, NOTIFY_OPEN_ERR);
+            n.subcode
+        };
+        let mut open = vec![4, 0xfd, 0xe8, 0, 90, 10, 0, 0, 1, 3, 1, 1, 0];
+        assert_eq!(decode(&open), NOTIFY_OPEN_UNSUP_PARAM);
+
+        // Extended length beyond the message.
+        open[9..].copy_from_slice(&[255, 255, 0, 9]);
+        assert_eq!(decode(&open), 0);
+
+        // Capability value shorter than its length.
+        let open = [4, 0xfd, 0xe8, 0, 90, 10, 0, 0, 1, 4, 2, 2, 65, 4];
+        assert_eq!(decode(&open), 0);
+    }
+
+    #[test]
+    fn unknown_capability() {
+        let mut caps = Capabilities::new();
+        caps.push(Capability::Unknown(73, vec![3, b'r', b't', b'r', 0]));
+        caps.push(Capability::FourOctetAs(65000));
+        let mut buf = Vec::new();
+        caps.to_bytes(&mut buf, false).unwrap();
+        assert_eq!(&buf[2..4], &[73, 5]);
+        assert_eq!(Capabilities::from_bytes(&buf[2..]).unwrap(), caps);
+    }
+
+    #[test]
+    fn extended_message() {
+        let ctx = UpdateContext::default();
+        let mut caps = Capabilities::new();
+        for afi in 1..4 {
+            for safi in 0..255 {
+                caps.push(Capability::AddPath(vec![(Family { afi, safi }, 3)]));
             }
-            Err(_) => {
-                println!("XXXXXXXXXXX");
-                Err(std::io::Error::from(std::io::ErrorKind::BrokenPipe))
+        }
+        // Such an OPEN does not fit even with extended messages.
+        assert!(Message::Open(open(caps)).to_bytes(true).is_err());
+
+        let data = vec![0u8; 5000];
+        let m = Message::Notification(MessageNotification::new(6, 0, data.clone()));
+        assert!(m.to_bytes(false).is_err());
+        let m = Message::Notification(MessageNotification::new(6, 0, data));
+        let buf = m.to_bytes(true).unwrap();
+        assert_eq!(buf.len(), 5021);
+        assert_eq!(&buf[16..18], &[0x13, 0x9d]);
+
+        // Accepted only once extended messages are negotiated.
+        let mut src = BytesMut::from(&buf[..]);
+        let e = from_bytes(&mut src, &ctx, false).unwrap_err();
+        assert_eq!(
+            e.downcast_ref::<MessageNotification>(),
+            Some(&MessageNotification::bad_message_length(5021))
+        );
+        assert!(matches!(
+            from_bytes(&mut src, &ctx, true).unwrap(),
+            Some(Message::Notification(_))
+        ));
+        assert!(src.is_empty());
+
+        // KEEPALIVE with a body.
+        let mut buf = Message::KeepAlive.to_bytes(false).unwrap();
+        assert_eq!(buf.len(), BGP_HEADER_LEN);
+        buf[17] = 20;
+        buf.push(0);
+        let mut src = BytesMut::from(&buf[..]);
+        assert!(from_bytes(&mut src, &ctx, true).is_err());
+    }
+
+    #[test]
+    fn decoder() {
+        let mut buf = Message::Open(MessageOpen::new()).to_bytes(false).unwrap();
+        buf.extend(Message::KeepAlive.to_bytes(false).unwrap());
+
+        // One octet at a time, as TCP may deliver it.
+        let mut peer = Peer::new(State::OpenSent);
+        let mut src = BytesMut::new();
+        let mut msgs = Vec::new();
+        for b in buf {
+            src.extend_from_slice(&[b]);
+            if let Some(msg) = peer.decode(&mut src).unwrap() {
+                msgs.push(msg);
             }
         }
+        assert!(src.is_empty());
+        assert!(matches!(msgs[..], [Message::Open(_), Message::KeepAlive]));
+        assert!(matches!(peer.state, State::OpenConfirm));
+
+        // Broken marker.
+        let mut src = BytesMut::from(&[0u8; 19][..]);
+        let e = peer.decode(&mut src).unwrap_err();
+        assert_eq!(
+            Peer::notification(&e),
+            Some(MessageNotification::new(
+                NOTIFY_HEADER_ERR,
+                NOTIFY_HEADER_NOT_SYNC,
+                Vec::new()
+            ))
+        );
+
+        // Unknown type.
+        let mut buf = vec![0xff; 16];
+        buf.extend_from_slice(&[0, 19, 9]);
+        let e = peer.decode(&mut BytesMut::from(&buf[..])).unwrap_err();
+        assert_eq!(Peer::notification(&e).unwrap().data, vec![9]);
+
+        // Header length shorter than the header.
+        buf[17] = 18;
+        buf[18] = 4;
+        let e = peer.decode(&mut BytesMut::from(&buf[..])).unwrap_err();
+        assert_eq!(
+            Peer::notification(&e),
+            Some(MessageNotification::bad_message_length(18))
+        );
+
+        // UPDATE error which resets the session.
+        let mut buf = vec![0xff; 16];
+        buf.extend_from_slice(&[0, 27, 2, 0, 0, 0, 4, 0, 99, 1, 0]);
+        let e = peer.decode(&mut BytesMut::from(&buf[..])).unwrap_err();
+        assert_eq!(
+            Peer::notification(&e).unwrap().code,
+            crate::bgp::NOTIFY_UPDATE_ERR
+        );
     }
 }
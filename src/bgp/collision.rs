@@ -0,0 +1,173 @@
+#![allow(dead_code)]
+
+use super::{MessageNotification, State};
+use super::{NOTIFY_CEASE, NOTIFY_CEASE_COLLISION_RESOLUTION};
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+
+/// Which side opened a TCP connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Initiator {
+    /// We connected to the neighbor.
+    Local,
+    /// The neighbor connected to us and the connection was accepted.
+    Remote,
+}
+
+/// Which connections are made to a neighbor. `neighbor <addr> passive` only
+/// accepts connections and `neighbor <addr> active` never accepts them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ConnectMode {
+    #[default]
+    Both,
+    Passive,
+    Active,
+}
+
+impl ConnectMode {
+    pub fn allows(&self, initiator: Initiator) -> bool {
+        !matches!(
+            (self, initiator),
+            (ConnectMode::Passive, Initiator::Local) | (ConnectMode::Active, Initiator::Remote)
+        )
+    }
+}
+
+impl fmt::Display for ConnectMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            ConnectMode::Both => "both",
+            ConnectMode::Passive => "passive",
+            ConnectMode::Active => "active",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl FromStr for ConnectMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "both" => Ok(ConnectMode::Both),
+            "passive" => Ok(ConnectMode::Passive),
+            "active" => Ok(ConnectMode::Active),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Connection which is kept when both sides connected. The speaker with the
+/// higher BGP Identifier keeps the connection it initiated (RFC 4271 6.8),
+/// on equal identifiers the one with the larger AS does (RFC 6286 2.3).
+pub fn collision_winner(
+    local_id: Ipv4Addr,
+    local_as: u32,
+    remote_id: Ipv4Addr,
+    remote_as: u32,
+) -> Initiator {
+    let local = (u32::from(local_id), local_as);
+    let remote = (u32::from(remote_id), remote_as);
+    if local > remote {
+        Initiator::Local
+    } else {
+        Initiator::Remote
+    }
+}
+
+/// Check for a collision when the OPEN of `remote_id` arrives on the
+/// connection opened by `initiator` while the other connection to the same
+/// neighbor is in `other`. Returns the connection to close, `None` when
+/// there is no collision. An established session is always kept.
+pub fn collision_check(
+    initiator: Initiator,
+    other: State,
+    local_id: Ipv4Addr,
+    local_as: u32,
+    remote_id: Ipv4Addr,
+    remote_as: u32,
+) -> Option<Initiator> {
+    match other {
+        State::OpenSent | State::OpenConfirm => {
+            match collision_winner(local_id, local_as, remote_id, remote_as) {
+                Initiator::Local => Some(Initiator::Remote),
+                Initiator::Remote => Some(Initiator::Local),
+            }
+        }
+        State::Established => Some(initiator),
+        _ => None,
+    }
+}
+
+/// Cease NOTIFICATION sent on the connection closed by collision
+/// resolution.
+pub fn collision_notification() -> MessageNotification {
+    MessageNotification::new(NOTIFY_CEASE, NOTIFY_CEASE_COLLISION_RESOLUTION, Vec::new())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn id(s: &str) -> Ipv4Addr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn winner() {
+        assert_eq!(
+            collision_winner(id("10.0.0.2"), 1, id("10.0.0.1"), 2),
+            Initiator::Local
+        );
+        assert_eq!(
+            collision_winner(id("9.0.0.1"), 1, id("10.0.0.1"), 2),
+            Initiator::Remote
+        );
+        // Identifiers compare as numbers, not octet by octet strings.
+        assert_eq!(
+            collision_winner(id("10.0.0.10"), 1, id("10.0.0.9"), 2),
+            Initiator::Local
+        );
+        assert_eq!(
+            collision_winner(id("10.0.0.1"), 65001, id("10.0.0.1"), 65000),
+            Initiator::Local
+        );
+    }
+
+    #[test]
+    fn check() {
+        let (local, remote) = (id("10.0.0.2"), id("10.0.0.1"));
+        assert_eq!(
+            collision_check(Initiator::Remote, State::OpenConfirm, local, 1, remote, 2),
+            Some(Initiator::Remote)
+        );
+        assert_eq!(
+            collision_check(Initiator::Local, State::OpenSent, remote, 2, local, 1),
+            Some(Initiator::Local)
+        );
+        assert_eq!(
+            collision_check(Initiator::Local, State::Established, local, 1, remote, 2),
+            Some(Initiator::Local)
+        );
+        assert_eq!(
+            collision_check(Initiator::Remote, State::Connect, local, 1, remote, 2),
+            None
+        );
+        assert_eq!(
+            format!("{}", collision_notification()),
+            "Cease/Connection Collision Resolution"
+        );
+    }
+
+    #[test]
+    fn connect_mode() {
+        assert!(ConnectMode::Both.allows(Initiator::Local));
+        assert!(ConnectMode::Both.allows(Initiator::Remote));
+        assert!(!ConnectMode::Passive.allows(Initiator::Local));
+        assert!(ConnectMode::Passive.allows(Initiator::Remote));
+        assert!(ConnectMode::Active.allows(Initiator::Local));
+        assert!(!ConnectMode::Active.allows(Initiator::Remote));
+        assert_eq!("passive".parse(), Ok(ConnectMode::Passive));
+    }
+}
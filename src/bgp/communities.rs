@@ -1,11 +1,16 @@
 #![allow(dead_code)]
 
+use super::attr::ATTR_TYPE_COMMUNITIES;
+use super::attr::{AttrError, AttrHeader, ATTR_FLAG_OPTIONAL, ATTR_FLAG_TRANSITIVE};
+use super::PeerType;
+use byteorder::{NetworkEndian, ReadBytesExt, WriteBytesExt};
 use lazy_static::lazy_static;
 use std::collections::HashMap;
+use std::io::Cursor;
 use std::sync::Mutex;
 
 const COMMUNITY_INTERNET: u32 = 0x0;
-const COMMUNITY_GSHUT: u32 = 0xFFFF0000;
+pub const COMMUNITY_GSHUT: u32 = 0xFFFF0000;
 const COMMUNITY_ACCEPT_OWN: u32 = 0xFFFF0001;
 const COMMUNITY_FILTER_TRANSLATED_V4: u32 = 0xFFFF0002;
 const COMMUNITY_FILTER_V4: u32 = 0xFFFF0003;
@@ -14,12 +19,12 @@ const COMMUNITY_FILTER_V6: u32 = 0xFFFF0005;
 const COMMUNITY_LLGR_STALE: u32 = 0xFFFF0006;
 const COMMUNITY_NO_LLGR: u32 = 0xFFFF0007;
 const COMMUNITY_ACCEPT_OWN_NEXTHOP: u32 = 0xFFFF0008;
-const COMMUNITY_BLACKHOLE: u32 = 0xFFFF029A;
-const COMMUNITY_NO_EXPORT: u32 = 0xFFFFFF01;
-const COMMUNITY_NO_ADVERTISE: u32 = 0xFFFFFF02;
-const COMMUNITY_NO_EXPORT_SUBCONFED: u32 = 0xFFFFFF03;
-const COMMUNITY_LOCAL_AS: u32 = 0xFFFFFF03;
-const COMMUNITY_NO_PEER: u32 = 0xFFFFFF04;
+pub const COMMUNITY_BLACKHOLE: u32 = 0xFFFF029A;
+pub const COMMUNITY_NO_EXPORT: u32 = 0xFFFFFF01;
+pub const COMMUNITY_NO_ADVERTISE: u32 = 0xFFFFFF02;
+pub const COMMUNITY_NO_EXPORT_SUBCONFED: u32 = 0xFFFFFF03;
+pub const COMMUNITY_LOCAL_AS: u32 = 0xFFFFFF03;
+pub const COMMUNITY_NO_PEER: u32 = 0xFFFFFF04;
 
 lazy_static! {
     static ref COMMUNITY_STR_MAP: Mutex<HashMap<&'static str, u32>> = {
@@ -65,6 +70,7 @@ lazy_static! {
     };
 }
 
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
 pub struct Communities(Vec<u32>);
 
 impl Communities {
@@ -116,6 +122,38 @@ impl Communities {
         self.0.contains(x)
     }
 
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, u32> {
+        self.0.iter()
+    }
+
+    pub fn remove(&mut self, x: &u32) {
+        self.0.retain(|v| v != x)
+    }
+
+    pub fn retain<F>(&mut self, f: F)
+    where
+        F: FnMut(&u32) -> bool,
+    {
+        self.0.retain(f)
+    }
+
+    /// Append communities from `other` which are not already present.
+    pub fn merge(&mut self, other: &Communities) {
+        for v in other.iter() {
+            if !self.contains(v) {
+                self.push(*v);
+            }
+        }
+    }
+
     pub fn parse_community(s: &str) -> Option<u32> {
         let com_strs: Vec<&str> = s.split(':').collect();
         match com_strs.len() {
@@ -171,6 +209,70 @@ impl Communities {
         // format!(r#"community: {}"#, self)
         format!(r#"{{"community": "{}"}}"#, self)
     }
+
+    /// Whether these communities ask for inbound handling, see
+    /// `Attr::apply_well_known_communities()`.
+    pub fn has_inbound_action(&self) -> bool {
+        self.contains(&COMMUNITY_BLACKHOLE) || self.contains(&COMMUNITY_GSHUT)
+    }
+
+    /// Whether a route carrying these communities may be advertised to a
+    /// neighbor of `peer_type`. `bilateral` is true when the neighbor is a
+    /// lateral peer rather than a customer or provider, NO_PEER (RFC 3765)
+    /// only restricts advertisement to such neighbors.
+    pub fn advertise_allowed(&self, peer_type: PeerType, bilateral: bool) -> bool {
+        if self.contains(&COMMUNITY_NO_ADVERTISE) {
+            return false;
+        }
+        match peer_type {
+            PeerType::Internal => true,
+            PeerType::ConfedExternal => !self.contains(&COMMUNITY_NO_EXPORT_SUBCONFED),
+            PeerType::External => {
+                !(self.contains(&COMMUNITY_NO_EXPORT)
+                    || self.contains(&COMMUNITY_NO_EXPORT_SUBCONFED)
+                    || bilateral && self.contains(&COMMUNITY_NO_PEER))
+            }
+        }
+    }
+}
+
+impl Communities {
+    const ATTR_FLAGS: u8 = ATTR_FLAG_OPTIONAL | ATTR_FLAG_TRANSITIVE;
+
+    /// Encode as a COMMUNITIES path attribute (RFC 1997). Nothing is written
+    /// when there are no communities.
+    pub fn to_bytes(&self, buf: &mut Vec<u8>) -> Result<usize, anyhow::Error> {
+        if self.is_empty() {
+            return Ok(0);
+        }
+        let header = AttrHeader::new(
+            Communities::ATTR_FLAGS,
+            ATTR_TYPE_COMMUNITIES,
+            self.len() * 4,
+        );
+        let mut len = header.to_bytes(buf)?;
+        for val in self.iter() {
+            buf.write_u32::<NetworkEndian>(*val)?;
+            len += 4;
+        }
+        Ok(len)
+    }
+
+    /// Decode the `len` octets value of a COMMUNITIES path attribute.
+    pub fn from_bytes(c: &mut Cursor<&[u8]>, len: usize) -> Result<Communities, anyhow::Error> {
+        if len == 0 || !len.is_multiple_of(4) {
+            return Err(AttrError::Length {
+                typ: ATTR_TYPE_COMMUNITIES,
+                len,
+            }
+            .into());
+        }
+        let mut coms = Communities::new();
+        for _ in 0..len / 4 {
+            coms.push(c.read_u32::<NetworkEndian>()?);
+        }
+        Ok(coms)
+    }
 }
 
 #[cfg(test)]
@@ -276,4 +378,61 @@ mod test {
 
     #[test]
     fn to_string() {}
+
+    #[test]
+    fn to_bytes() {
+        let com = Communities::from_str("no-export 100:10").unwrap();
+        let mut buf = Vec::new();
+        assert_eq!(com.to_bytes(&mut buf).unwrap(), 11);
+        assert_eq!(
+            buf,
+            vec![0xc0, 8, 8, 0xff, 0xff, 0xff, 0x01, 0x00, 0x64, 0x00, 0x0a]
+        );
+
+        let mut c = Cursor::new(&buf[3..]);
+        let decoded = Communities::from_bytes(&mut c, 8).unwrap();
+        assert_eq!(decoded, com);
+
+        let mut c = Cursor::new(&buf[3..]);
+        assert!(Communities::from_bytes(&mut c, 6).is_err());
+        let mut c = Cursor::new(&buf[3..]);
+        assert!(Communities::from_bytes(&mut c, 0).is_err());
+        let mut c = Cursor::new(&buf[3..]);
+        assert!(Communities::from_bytes(&mut c, 12).is_err());
+
+        let mut buf = Vec::new();
+        assert_eq!(Communities::new().to_bytes(&mut buf).unwrap(), 0);
+        assert!(buf.is_empty());
+
+        let mut com = Communities::new();
+        for i in 0..100 {
+            com.push(i);
+        }
+        let mut buf = Vec::new();
+        assert_eq!(com.to_bytes(&mut buf).unwrap(), 404);
+        assert_eq!(buf[0], 0xd0);
+    }
+
+    #[test]
+    fn advertise_allowed() {
+        let com = Communities::from_str("100:1").unwrap();
+        assert!(com.advertise_allowed(PeerType::External, true));
+
+        let com = Communities::from_str("no-advertise").unwrap();
+        assert!(!com.advertise_allowed(PeerType::Internal, false));
+
+        let com = Communities::from_str("no-export").unwrap();
+        assert!(com.advertise_allowed(PeerType::Internal, false));
+        assert!(com.advertise_allowed(PeerType::ConfedExternal, false));
+        assert!(!com.advertise_allowed(PeerType::External, false));
+
+        let com = Communities::from_str("local-AS").unwrap();
+        assert!(com.advertise_allowed(PeerType::Internal, false));
+        assert!(!com.advertise_allowed(PeerType::ConfedExternal, false));
+        assert!(!com.advertise_allowed(PeerType::External, false));
+
+        let com = Communities::from_str("no-peer").unwrap();
+        assert!(com.advertise_allowed(PeerType::External, false));
+        assert!(!com.advertise_allowed(PeerType::External, true));
+    }
 }
@@ -0,0 +1,149 @@
+#![allow(dead_code)]
+
+use super::as_path_list::bgp_regex;
+use super::{Action, Communities};
+use regex::Regex;
+
+#[derive(Clone, Debug)]
+pub enum CommunityMatch {
+    /// Matches when every listed community is present.
+    Standard(Communities),
+    /// Regular expression against the string form of the communities.
+    Expanded(Regex),
+}
+
+#[derive(Clone, Debug)]
+pub struct CommunityListEntry {
+    pub action: Action,
+    pub val: CommunityMatch,
+}
+
+impl CommunityListEntry {
+    pub fn standard(action: Action, coms: Communities) -> Self {
+        CommunityListEntry {
+            action,
+            val: CommunityMatch::Standard(coms),
+        }
+    }
+
+    pub fn expanded(action: Action, s: &str) -> Result<Self, regex::Error> {
+        Ok(CommunityListEntry {
+            action,
+            val: CommunityMatch::Expanded(bgp_regex(s)?),
+        })
+    }
+
+    fn matches(&self, coms: &Communities) -> bool {
+        match &self.val {
+            CommunityMatch::Standard(list) => list.iter().all(|c| coms.contains(c)),
+            CommunityMatch::Expanded(regex) => regex.is_match(&coms.to_string()),
+        }
+    }
+
+    fn matches_exact(&self, coms: &Communities) -> bool {
+        match &self.val {
+            CommunityMatch::Standard(list) => {
+                list.iter().all(|c| coms.contains(c)) && coms.iter().all(|c| list.contains(c))
+            }
+            CommunityMatch::Expanded(_) => self.matches(coms),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CommunityList {
+    entries: Vec<CommunityListEntry>,
+}
+
+impl CommunityList {
+    pub fn new() -> Self {
+        CommunityList {
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, entry: CommunityListEntry) {
+        self.entries.push(entry)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn apply(&self, coms: &Communities) -> Action {
+        for entry in self.entries.iter() {
+            if entry.matches(coms) {
+                return entry.action;
+            }
+        }
+        Action::Deny
+    }
+
+    /// Like `apply` but standard entries must list exactly the communities of
+    /// the route.
+    pub fn apply_exact(&self, coms: &Communities) -> Action {
+        for entry in self.entries.iter() {
+            if entry.matches_exact(coms) {
+                return entry.action;
+            }
+        }
+        Action::Deny
+    }
+
+    /// Remove each community which, on its own, is permitted by this list.
+    pub fn delete(&self, coms: &mut Communities) {
+        coms.retain(|c| {
+            let mut single = Communities::new();
+            single.push(*c);
+            self.apply(&single) != Action::Permit
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn apply() {
+        let mut list = CommunityList::new();
+        list.push(CommunityListEntry::standard(
+            Action::Deny,
+            Communities::from_str("no-export").unwrap(),
+        ));
+        list.push(CommunityListEntry::standard(
+            Action::Permit,
+            Communities::from_str("100:1 100:2").unwrap(),
+        ));
+        list.push(CommunityListEntry::expanded(Action::Permit, "^200:").unwrap());
+
+        let apply = |s: &str| list.apply(&Communities::from_str(s).unwrap());
+        assert_eq!(apply("100:1 100:2 100:3"), Action::Permit);
+        assert_eq!(apply("100:1"), Action::Deny);
+        assert_eq!(apply("100:1 100:2 no-export"), Action::Deny);
+        assert_eq!(apply("200:5 100:1"), Action::Permit);
+        assert_eq!(apply("100:1 200:5"), Action::Deny);
+
+        let exact = |s: &str| list.apply_exact(&Communities::from_str(s).unwrap());
+        assert_eq!(exact("100:2 100:1"), Action::Permit);
+        assert_eq!(exact("100:1 100:2 100:3"), Action::Deny);
+    }
+
+    #[test]
+    fn delete() {
+        let mut list = CommunityList::new();
+        list.push(CommunityListEntry::expanded(Action::Permit, "^100:").unwrap());
+        list.push(CommunityListEntry::standard(
+            Action::Permit,
+            Communities::from_str("no-export").unwrap(),
+        ));
+
+        let mut coms = Communities::from_str("100:1 200:1 no-export 100:2").unwrap();
+        list.delete(&mut coms);
+        assert_eq!(format!("{}", coms), "200:1");
+    }
+}
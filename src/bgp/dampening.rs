@@ -0,0 +1,749 @@
+#![allow(dead_code)]
+
+use super::{parse_family, Action, AsPath, Family, Origin, Policy, PolicyContext, Prefix, Route};
+use super::{AFI_IP, AFI_IP6, SAFI_UNICAST};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Penalty added for each withdrawal.
+const DAMP_PENALTY: f64 = 1000.0;
+/// Penalty added when a path is re-advertised with changed attributes.
+const DAMP_PENALTY_ATTR_CHANGE: f64 = 500.0;
+/// Granularity of the reuse list.
+pub const DAMP_REUSE_TICK: Duration = Duration::from_secs(10);
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum DampeningError {
+    #[error("unknown dampening command: {0}")]
+    Syntax(String),
+    #[error("half-life must not be 0")]
+    HalfLife,
+    #[error("reuse {reuse} must be above 0 and below suppress {suppress}")]
+    Reuse { reuse: u32, suppress: u32 },
+    #[error("max-suppress must not be shorter than the half-life")]
+    MaxSuppress,
+}
+
+/// `bgp dampening <half-life> <reuse> <suppress> <max-suppress>`, valid by
+/// construction: the penalty computations divide by the half-life and the
+/// reuse threshold.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DampeningParams {
+    half_life: Duration,
+    reuse: u32,
+    suppress: u32,
+    max_suppress: Duration,
+}
+
+impl Default for DampeningParams {
+    fn default() -> Self {
+        DampeningParams {
+            half_life: Duration::from_secs(15 * 60),
+            reuse: 750,
+            suppress: 2000,
+            max_suppress: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+impl DampeningParams {
+    pub fn new(
+        half_life: Duration,
+        reuse: u32,
+        suppress: u32,
+        max_suppress: Duration,
+    ) -> Result<Self, DampeningError> {
+        if half_life.is_zero() {
+            return Err(DampeningError::HalfLife);
+        }
+        if reuse == 0 || reuse >= suppress {
+            return Err(DampeningError::Reuse { reuse, suppress });
+        }
+        if max_suppress < half_life {
+            return Err(DampeningError::MaxSuppress);
+        }
+        Ok(DampeningParams {
+            half_life,
+            reuse,
+            suppress,
+            max_suppress,
+        })
+    }
+
+    pub fn half_life(&self) -> Duration {
+        self.half_life
+    }
+
+    pub fn reuse(&self) -> u32 {
+        self.reuse
+    }
+
+    pub fn suppress(&self) -> u32 {
+        self.suppress
+    }
+
+    pub fn max_suppress(&self) -> Duration {
+        self.max_suppress
+    }
+
+    /// Highest penalty a path can accumulate, the value which decays to the
+    /// reuse threshold in exactly `max_suppress`.
+    fn ceiling(&self) -> f64 {
+        let exp = self.max_suppress.as_secs_f64() / self.half_life.as_secs_f64();
+        self.reuse as f64 * 2f64.powf(exp)
+    }
+
+    fn decay(&self, penalty: f64, elapsed: Duration) -> f64 {
+        penalty * 0.5f64.powf(elapsed.as_secs_f64() / self.half_life.as_secs_f64())
+    }
+
+    /// Time for `penalty` to decay to `target`.
+    fn time_to(&self, penalty: f64, target: f64) -> Duration {
+        if penalty <= target {
+            return Duration::from_secs(0);
+        }
+        Duration::from_secs_f64(self.half_life.as_secs_f64() * (penalty / target).log2())
+    }
+}
+
+/// Path identity, the prefix and the neighbor it was learned from.
+pub type DampKey = (Prefix, IpAddr);
+
+#[derive(Clone, Debug)]
+struct DampInfo {
+    params: DampeningParams,
+    penalty: f64,
+    updated: Instant,
+    flaps: u32,
+    start: Instant,
+    suppressed: Option<Instant>,
+    /// Reuse list tick the entry is due at. Entries found in other slots
+    /// are stale and ignored.
+    due: u64,
+    as_path: Arc<AsPath>,
+    origin: Origin,
+}
+
+impl DampInfo {
+    fn penalty(&self, now: Instant) -> f64 {
+        self.params
+            .decay(self.penalty, now.saturating_duration_since(self.updated))
+    }
+
+    fn charge(&mut self, penalty: f64, now: Instant) {
+        let current = self.penalty(now) + penalty;
+        self.penalty = current.min(self.params.ceiling());
+        self.updated = now;
+    }
+
+    /// Time until the entry needs attention: a suppressed path may be reused
+    /// and an unsuppressed one forgotten once its penalty fell below half the
+    /// reuse threshold.
+    fn next_event(&self, now: Instant) -> Duration {
+        let penalty = self.penalty(now);
+        match self.suppressed {
+            Some(since) => {
+                let limit = self
+                    .params
+                    .max_suppress
+                    .checked_sub(now.saturating_duration_since(since))
+                    .unwrap_or_default();
+                self.params
+                    .time_to(penalty, self.params.reuse as f64)
+                    .min(limit)
+            }
+            None => self.params.time_to(penalty, self.params.reuse as f64 / 2.0),
+        }
+    }
+}
+
+/// Route flap dampening state of one address family (RFC 2439). Suppressed
+/// paths wait on a timer wheel of `DAMP_REUSE_TICK` slots.
+#[derive(Debug)]
+pub struct Dampening {
+    pub params: DampeningParams,
+    /// Only paths permitted by this route-map are dampened. Its `set
+    /// dampening` clauses override `params` per path.
+    pub route_map: Option<String>,
+    paths: HashMap<DampKey, DampInfo>,
+    reuse_list: Vec<Vec<DampKey>>,
+    start: Instant,
+    tick: u64,
+}
+
+impl Dampening {
+    pub fn new(params: DampeningParams, now: Instant) -> Self {
+        let span = params.max_suppress + params.half_life;
+        let slots = (span.as_secs() / DAMP_REUSE_TICK.as_secs()) as usize + 2;
+        Dampening {
+            params,
+            route_map: None,
+            paths: HashMap::new(),
+            reuse_list: vec![Vec::new(); slots],
+            start: now,
+            tick: 0,
+        }
+    }
+
+    fn key(route: &Route) -> DampKey {
+        let peer = route.peer.unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        (route.prefix, peer)
+    }
+
+    /// Dampening parameters for `route`, `None` when the route-map excludes
+    /// it from dampening.
+    fn route_params(&self, route: &Route, policy: &Policy) -> Option<DampeningParams> {
+        match &self.route_map {
+            Some(name) => {
+                let mut route = route.clone();
+                if policy.apply(name, &mut route, &PolicyContext::local()) == Action::Deny {
+                    return None;
+                }
+                Some(route.dampening.unwrap_or(self.params))
+            }
+            None => Some(route.dampening.unwrap_or(self.params)),
+        }
+    }
+
+    fn schedule(&mut self, key: DampKey, now: Instant) {
+        let info = match self.paths.get_mut(&key) {
+            Some(info) => info,
+            None => return,
+        };
+        let ticks = info
+            .next_event(now)
+            .as_secs()
+            .div_ceil(DAMP_REUSE_TICK.as_secs());
+        let ticks = ticks.max(1).min(self.reuse_list.len() as u64 - 1);
+        info.due = self.tick + ticks;
+        let slot = (info.due % self.reuse_list.len() as u64) as usize;
+        self.reuse_list[slot].push(key);
+    }
+
+    fn flap(&mut self, route: &Route, penalty: f64, policy: &Policy, now: Instant) -> bool {
+        let params = match self.route_params(route, policy) {
+            Some(params) => params,
+            None => return false,
+        };
+        let key = Dampening::key(route);
+        let info = self.paths.entry(key).or_insert_with(|| DampInfo {
+            params,
+            penalty: 0.0,
+            updated: now,
+            flaps: 0,
+            start: now,
+            suppressed: None,
+            due: 0,
+            as_path: route.attr.as_path.clone(),
+            origin: route.attr.origin,
+        });
+        info.params = params;
+        info.as_path = route.attr.as_path.clone();
+        info.origin = route.attr.origin;
+        info.charge(penalty, now);
+        if penalty >= DAMP_PENALTY {
+            info.flaps += 1;
+        }
+        if info.suppressed.is_none() && info.penalty >= params.suppress as f64 {
+            info.suppressed = Some(now);
+        }
+        let suppressed = info.suppressed.is_some();
+        self.schedule(key, now);
+        suppressed
+    }
+
+    /// Record the withdrawal of `route`. Returns true when the path is now
+    /// suppressed.
+    pub fn withdraw(&mut self, route: &Route, policy: &Policy, now: Instant) -> bool {
+        self.flap(route, DAMP_PENALTY, policy, now)
+    }
+
+    /// Record an advertisement of `route`. Returns true when the path is
+    /// suppressed and must not be used for best path selection.
+    pub fn update(
+        &mut self,
+        route: &Route,
+        attr_changed: bool,
+        policy: &Policy,
+        now: Instant,
+    ) -> bool {
+        let key = Dampening::key(route);
+        if !attr_changed {
+            return self.is_suppressed(&key);
+        }
+        self.flap(route, DAMP_PENALTY_ATTR_CHANGE, policy, now)
+    }
+
+    pub fn is_suppressed(&self, key: &DampKey) -> bool {
+        match self.paths.get(key) {
+            Some(info) => info.suppressed.is_some(),
+            None => false,
+        }
+    }
+
+    /// Advance the reuse list to `now`. Returns paths whose suppression
+    /// ended, which must be run through best path selection again.
+    pub fn tick(&mut self, now: Instant) -> Vec<DampKey> {
+        let target =
+            now.saturating_duration_since(self.start).as_secs() / DAMP_REUSE_TICK.as_secs();
+        let mut reused = Vec::new();
+
+        while self.tick < target {
+            self.tick += 1;
+            let slot = (self.tick % self.reuse_list.len() as u64) as usize;
+            let keys = std::mem::take(&mut self.reuse_list[slot]);
+
+            for key in keys {
+                let info = match self.paths.get_mut(&key) {
+                    Some(info) if info.due == self.tick => info,
+                    _ => continue,
+                };
+                let penalty = info.penalty(now);
+                match info.suppressed {
+                    Some(since) => {
+                        if penalty < info.params.reuse as f64
+                            || now.saturating_duration_since(since) >= info.params.max_suppress
+                        {
+                            info.suppressed = None;
+                            reused.push(key);
+                        }
+                    }
+                    None => {
+                        if penalty < info.params.reuse as f64 / 2.0 {
+                            self.paths.remove(&key);
+                            continue;
+                        }
+                    }
+                }
+                self.schedule(key, now);
+            }
+        }
+        reused
+    }
+
+    /// Forget everything learned from `peer`, e.g. after `clear bgp
+    /// dampening`.
+    pub fn clear(&mut self, peer: Option<IpAddr>) {
+        match peer {
+            Some(peer) => self.paths.retain(|k, _| k.1 != peer),
+            None => self.paths.clear(),
+        }
+    }
+
+    fn sorted(&self) -> Vec<(&DampKey, &DampInfo)> {
+        let mut paths: Vec<(&DampKey, &DampInfo)> = self.paths.iter().collect();
+        paths.sort_by(|a, b| a.0.cmp(b.0));
+        paths
+    }
+
+    /// `show bgp dampening dampened-paths`
+    pub fn show_dampened(&self, now: Instant) -> String {
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "   {:<18} {:<16} {:<9} Path",
+            "Network", "From", "Reuse"
+        );
+        for (key, info) in self.sorted() {
+            if info.suppressed.is_none() {
+                continue;
+            }
+            let _ = writeln!(
+                out,
+                "*d {:<18} {:<16} {:<9} {}",
+                key.0.to_string(),
+                key.1.to_string(),
+                fmt_duration(info.next_event(now)),
+                fmt_path(info)
+            );
+        }
+        out
+    }
+
+    /// `show bgp dampening flap-statistics`
+    pub fn show_flap_statistics(&self, now: Instant) -> String {
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "   {:<18} {:<16} {:>5} {:<9} {:<9} Path",
+            "Network", "From", "Flaps", "Duration", "Reuse"
+        );
+        for (key, info) in self.sorted() {
+            let (status, reuse) = match info.suppressed {
+                Some(_) => ("*d", fmt_duration(info.next_event(now))),
+                None => ("h ", String::new()),
+            };
+            let _ = writeln!(
+                out,
+                "{} {:<18} {:<16} {:>5} {:<9} {:<9} {}",
+                status,
+                key.0.to_string(),
+                key.1.to_string(),
+                info.flaps,
+                fmt_duration(now.saturating_duration_since(info.start)),
+                reuse,
+                fmt_path(info)
+            );
+        }
+        out
+    }
+}
+
+/// `[no] [<afi> <safi>] bgp dampening [<half-life> [<reuse> <suppress>
+/// <max-suppress>]] [route-map <name>]`, times in minutes. IPv4 unicast when
+/// the family is omitted.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DampeningCommand {
+    Enable {
+        family: Family,
+        params: DampeningParams,
+        route_map: Option<String>,
+    },
+    Disable(Family),
+}
+
+impl DampeningCommand {
+    pub fn parse(line: &str) -> Result<DampeningCommand, DampeningError> {
+        let syntax = || DampeningError::Syntax(line.to_string());
+        let mut words: Vec<&str> = line.split_whitespace().collect();
+        let no = words.first() == Some(&"no");
+        if no {
+            words.remove(0);
+        }
+        let family = match words.as_slice() {
+            [afi, safi, ..] => parse_family(afi, safi),
+            _ => None,
+        };
+        if family.is_some() {
+            words.drain(..2);
+        }
+        let family = family.unwrap_or(Family {
+            afi: AFI_IP,
+            safi: SAFI_UNICAST,
+        });
+        let args = match words.as_slice() {
+            ["bgp", "dampening", args @ ..] => args,
+            _ => return Err(syntax()),
+        };
+        if no {
+            return Ok(DampeningCommand::Disable(family));
+        }
+        let (args, route_map) = match args {
+            [args @ .., "route-map", name] => (args, Some(name.to_string())),
+            args => (args, None),
+        };
+        let mut values = Vec::new();
+        for arg in args {
+            values.push(arg.parse::<u32>().map_err(|_| syntax())?);
+        }
+        let minutes = |m: u32| Duration::from_secs(m as u64 * 60);
+        let default = DampeningParams::default();
+        let params = match values[..] {
+            [] => default,
+            // The maximum suppression keeps its default ratio to the
+            // half-life.
+            [half_life] => DampeningParams::new(
+                minutes(half_life),
+                default.reuse,
+                default.suppress,
+                minutes(half_life * 4),
+            )?,
+            [half_life, reuse, suppress, max_suppress] => {
+                DampeningParams::new(minutes(half_life), reuse, suppress, minutes(max_suppress))?
+            }
+            _ => return Err(syntax()),
+        };
+        Ok(DampeningCommand::Enable {
+            family,
+            params,
+            route_map,
+        })
+    }
+}
+
+/// Route flap dampening of the address families it is enabled in.
+#[derive(Debug, Default)]
+pub struct Dampenings {
+    families: BTreeMap<Family, Dampening>,
+}
+
+impl Dampenings {
+    pub fn new() -> Self {
+        Dampenings::default()
+    }
+
+    /// Enable or disable dampening of a family. Changed parameters start
+    /// over, forgetting the penalties.
+    pub fn configure(&mut self, cmd: DampeningCommand, now: Instant) {
+        match cmd {
+            DampeningCommand::Enable {
+                family,
+                params,
+                route_map,
+            } => {
+                if let Some(damp) = self.families.get_mut(&family) {
+                    if damp.params == params {
+                        damp.route_map = route_map;
+                        return;
+                    }
+                }
+                let mut damp = Dampening::new(params, now);
+                damp.route_map = route_map;
+                self.families.insert(family, damp);
+            }
+            DampeningCommand::Disable(family) => {
+                self.families.remove(&family);
+            }
+        }
+    }
+
+    pub fn get(&self, family: &Family) -> Option<&Dampening> {
+        self.families.get(family)
+    }
+
+    pub fn get_mut(&mut self, family: &Family) -> Option<&mut Dampening> {
+        self.families.get_mut(family)
+    }
+
+    pub fn is_enabled(&self, family: &Family) -> bool {
+        self.families.contains_key(family)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.families.is_empty()
+    }
+
+    fn family(prefix: &Prefix) -> Family {
+        Family {
+            afi: if prefix.is_ipv4() { AFI_IP } else { AFI_IP6 },
+            safi: SAFI_UNICAST,
+        }
+    }
+
+    /// Record the withdrawal of `route` in its family. Returns true when
+    /// the path is now suppressed.
+    pub fn withdraw(&mut self, route: &Route, policy: &Policy, now: Instant) -> bool {
+        match self.families.get_mut(&Dampenings::family(&route.prefix)) {
+            Some(damp) => damp.withdraw(route, policy, now),
+            None => false,
+        }
+    }
+
+    /// Record an advertisement of `route` in its family, see
+    /// `Dampening::update()`.
+    pub fn update(
+        &mut self,
+        route: &Route,
+        attr_changed: bool,
+        policy: &Policy,
+        now: Instant,
+    ) -> bool {
+        match self.families.get_mut(&Dampenings::family(&route.prefix)) {
+            Some(damp) => damp.update(route, attr_changed, policy, now),
+            None => false,
+        }
+    }
+
+    pub fn is_suppressed(&self, key: &DampKey) -> bool {
+        match self.families.get(&Dampenings::family(&key.0)) {
+            Some(damp) => damp.is_suppressed(key),
+            None => false,
+        }
+    }
+
+    /// Advance the reuse lists of all families, see `Dampening::tick()`.
+    pub fn tick(&mut self, now: Instant) -> Vec<DampKey> {
+        self.families
+            .values_mut()
+            .flat_map(|damp| damp.tick(now))
+            .collect()
+    }
+}
+
+fn fmt_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    format!(
+        "{:02}:{:02}:{:02}",
+        secs / 3600,
+        (secs / 60) % 60,
+        secs % 60
+    )
+}
+
+fn fmt_path(info: &DampInfo) -> String {
+    let origin = match info.origin {
+        Origin::Igp => "i",
+        Origin::Egp => "e",
+        Origin::Incomplete => "?",
+    };
+    if info.as_path.is_empty() {
+        origin.to_string()
+    } else {
+        format!("{} {}", info.as_path, origin)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bgp::policy::fixture::permit;
+    use crate::bgp::{Attr, RouteMapSet};
+
+    fn route(prefix: &str) -> Route {
+        let mut attr = Attr::new();
+        attr.as_path = "100 200".parse().unwrap();
+        let mut route = Route::new(prefix.parse().unwrap(), attr);
+        route.peer = Some("192.168.0.2".parse().unwrap());
+        route
+    }
+
+    #[test]
+    fn suppress_and_reuse() {
+        let policy = Policy::new();
+        let now = Instant::now();
+        let mut damp = Dampening::new(DampeningParams::default(), now);
+        let r = route("10.0.0.0/8");
+        let key = Dampening::key(&r);
+
+        assert!(!damp.withdraw(&r, &policy, now));
+        assert!(!damp.update(&r, false, &policy, now));
+        assert!(!damp.update(&r, true, &policy, now));
+        assert!(damp.withdraw(&r, &policy, now));
+        assert!(damp.is_suppressed(&key));
+        assert!(damp.update(&r, false, &policy, now));
+
+        let out = damp.show_dampened(now);
+        assert!(out.contains("*d 10.0.0.0/8"));
+        assert!(out.contains("100 200 i"));
+        let out = damp.show_flap_statistics(now);
+        assert!(out.contains("    2 "));
+
+        // 2500 decays below the reuse threshold of 750 after 1.74 half-lives.
+        let later = now + Duration::from_secs(25 * 60);
+        assert!(damp.tick(later).is_empty());
+        assert!(damp.is_suppressed(&key));
+
+        let later = now + Duration::from_secs(27 * 60);
+        assert_eq!(damp.tick(later), vec![key]);
+        assert!(!damp.is_suppressed(&key));
+
+        // Forgotten once the penalty is below half the reuse threshold.
+        let later = now + Duration::from_secs(42 * 60);
+        assert!(damp.tick(later).is_empty());
+        assert!(damp.paths.is_empty());
+    }
+
+    #[test]
+    fn max_suppress() {
+        let policy = Policy::new();
+        let now = Instant::now();
+        let mut damp = Dampening::new(DampeningParams::default(), now);
+        let r = route("10.0.0.0/8");
+        let key = Dampening::key(&r);
+
+        for _ in 0..20 {
+            damp.withdraw(&r, &policy, now);
+        }
+        assert!(damp.paths[&key].penalty <= damp.params.ceiling());
+
+        let later = now + Duration::from_secs(59 * 60);
+        assert!(damp.tick(later).is_empty());
+        let later = now + Duration::from_secs(61 * 60);
+        assert_eq!(damp.tick(later), vec![key]);
+    }
+
+    #[test]
+    fn route_map() {
+        let mut policy = Policy::new();
+        let minutes = |m: u64| Duration::from_secs(m * 60);
+        let params = DampeningParams::new(minutes(15), 750, 1500, minutes(60)).unwrap();
+        permit(&mut policy, "DAMP", "10.0.0.0/8")
+            .sets
+            .push(RouteMapSet::Dampening(params));
+
+        let now = Instant::now();
+        let mut damp = Dampening::new(DampeningParams::default(), now);
+        damp.route_map = Some("DAMP".to_string());
+
+        let r = route("10.0.0.0/8");
+        assert!(!damp.withdraw(&r, &policy, now));
+        assert!(damp.withdraw(&r, &policy, now));
+
+        let r = route("172.16.0.0/12");
+        for _ in 0..5 {
+            assert!(!damp.withdraw(&r, &policy, now));
+        }
+        assert_eq!(damp.paths.len(), 1);
+    }
+
+    #[test]
+    fn params() {
+        let minutes = |m: u64| Duration::from_secs(m * 60);
+        assert_eq!(
+            DampeningParams::new(minutes(0), 750, 2000, minutes(60)),
+            Err(DampeningError::HalfLife)
+        );
+        assert_eq!(
+            DampeningParams::new(minutes(15), 0, 2000, minutes(60)),
+            Err(DampeningError::Reuse {
+                reuse: 0,
+                suppress: 2000
+            })
+        );
+        assert!(DampeningParams::new(minutes(15), 2000, 2000, minutes(60)).is_err());
+        assert_eq!(
+            DampeningParams::new(minutes(15), 750, 2000, minutes(10)),
+            Err(DampeningError::MaxSuppress)
+        );
+    }
+
+    #[test]
+    fn configure() {
+        let ipv6 = Family {
+            afi: crate::bgp::AFI_IP6,
+            safi: SAFI_UNICAST,
+        };
+        let cmd = DampeningCommand::parse("ipv6 unicast bgp dampening 10 route-map DAMP").unwrap();
+        match &cmd {
+            DampeningCommand::Enable {
+                family,
+                params,
+                route_map,
+            } => {
+                assert_eq!(*family, ipv6);
+                assert_eq!(params.half_life(), Duration::from_secs(600));
+                assert_eq!(params.max_suppress(), Duration::from_secs(2400));
+                assert_eq!(route_map.as_deref(), Some("DAMP"));
+            }
+            cmd => panic!("unexpected {:?}", cmd),
+        }
+        assert!(matches!(
+            DampeningCommand::parse("bgp dampening 15 750 500 60"),
+            Err(DampeningError::Reuse { .. })
+        ));
+        assert!(DampeningCommand::parse("bgp dampening 15 750").is_err());
+        assert!(DampeningCommand::parse("bgp dampening 0").is_err());
+
+        let now = Instant::now();
+        let mut dampenings = Dampenings::new();
+        dampenings.configure(cmd, now);
+        dampenings.configure(DampeningCommand::parse("bgp dampening").unwrap(), now);
+        assert!(dampenings.is_enabled(&ipv6));
+        assert_eq!(
+            dampenings.get(&ipv6).unwrap().route_map.as_deref(),
+            Some("DAMP")
+        );
+        dampenings.configure(
+            DampeningCommand::parse("no ipv6 unicast bgp dampening").unwrap(),
+            now,
+        );
+        assert!(!dampenings.is_enabled(&ipv6));
+        assert!(dampenings.is_enabled(&Family {
+            afi: AFI_IP,
+            safi: SAFI_UNICAST
+        }));
+    }
+}
@@ -0,0 +1,572 @@
+#![allow(dead_code)]
+
+use super::attr::{AttrError, AttrHeader, ATTR_FLAG_OPTIONAL, ATTR_FLAG_TRANSITIVE};
+use super::attr::{ATTR_TYPE_EXT_COMMUNITIES, ATTR_TYPE_IPV6_EXT_COMMUNITIES};
+use byteorder::{NetworkEndian, ReadBytesExt, WriteBytesExt};
+use std::fmt;
+use std::io::{Cursor, Read, Write};
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+// Type high octet (RFC 7153).
+const EXT_TYPE_AS: u8 = 0x00;
+const EXT_TYPE_IPV4: u8 = 0x01;
+const EXT_TYPE_AS4: u8 = 0x02;
+const EXT_TYPE_OPAQUE: u8 = 0x03;
+const EXT_TYPE_NON_TRANSITIVE_AS: u8 = 0x40;
+
+// Sub-types.
+const EXT_SUBTYPE_ROUTE_TARGET: u8 = 0x02;
+const EXT_SUBTYPE_ROUTE_ORIGIN: u8 = 0x03;
+const EXT_SUBTYPE_LINK_BANDWIDTH: u8 = 0x04;
+const EXT_SUBTYPE_COLOR: u8 = 0x0b;
+const EXT_SUBTYPE_ENCAPSULATION: u8 = 0x0c;
+
+/// Global and local administrator of a route target or route origin.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ExtAdmin {
+    As(u16, u32),
+    Ipv4(Ipv4Addr, u16),
+    As4(u32, u16),
+    /// Only carried in the IPv6 Address Specific attribute (RFC 5701).
+    Ipv6(Ipv6Addr, u16),
+}
+
+impl ExtAdmin {
+    /// Parse `ASN:NN`, `A.B.C.D:NN` or `X:X::X:X:NN`. An ASN above 65535
+    /// selects the four-octet AS encoding with a two octet local value.
+    pub fn parse(s: &str) -> Option<ExtAdmin> {
+        let pos = s.rfind(':')?;
+        let (global, local) = (&s[..pos], &s[pos + 1..]);
+        if let Ok(addr) = global.parse::<Ipv4Addr>() {
+            return Some(ExtAdmin::Ipv4(addr, local.parse().ok()?));
+        }
+        if let Ok(addr) = global.parse::<Ipv6Addr>() {
+            return Some(ExtAdmin::Ipv6(addr, local.parse().ok()?));
+        }
+        let asn = global.parse::<u32>().ok()?;
+        if asn > u16::MAX as u32 {
+            Some(ExtAdmin::As4(asn, local.parse().ok()?))
+        } else {
+            Some(ExtAdmin::As(asn as u16, local.parse().ok()?))
+        }
+    }
+}
+
+impl fmt::Display for ExtAdmin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExtAdmin::As(asn, val) => write!(f, "{}:{}", asn, val),
+            ExtAdmin::Ipv4(addr, val) => write!(f, "{}:{}", addr, val),
+            ExtAdmin::As4(asn, val) => write!(f, "{}:{}", asn, val),
+            ExtAdmin::Ipv6(addr, val) => write!(f, "{}:{}", addr, val),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ExtCommunity {
+    RouteTarget(ExtAdmin),
+    RouteOrigin(ExtAdmin),
+    /// Bandwidth of the link to the neighbor AS in bytes per second, an
+    /// IEEE floating point number kept as received with its type octet,
+    /// transitive or not.
+    LinkBandwidth {
+        typ: u8,
+        asn: u16,
+        bandwidth: [u8; 4],
+    },
+    /// BGP tunnel encapsulation type (RFC 9012).
+    Encapsulation(u16),
+    Color {
+        flags: u16,
+        color: u32,
+    },
+    /// Any other eight octet extended community, kept as received.
+    Opaque {
+        typ: u8,
+        subtype: u8,
+        value: [u8; 6],
+    },
+    /// Any other IPv6 Address Specific extended community.
+    Ipv6Opaque {
+        typ: u8,
+        subtype: u8,
+        value: [u8; 18],
+    },
+}
+
+impl ExtCommunity {
+    /// True for communities encoded in the IPv6 Address Specific Extended
+    /// Community attribute instead of the Extended Communities attribute.
+    pub fn is_ipv6(&self) -> bool {
+        matches!(
+            self,
+            ExtCommunity::RouteTarget(ExtAdmin::Ipv6(..))
+                | ExtCommunity::RouteOrigin(ExtAdmin::Ipv6(..))
+                | ExtCommunity::Ipv6Opaque { .. }
+        )
+    }
+
+    /// Parse the value which follows keyword `typ` in the string form.
+    pub fn parse(typ: &str, s: &str) -> Option<ExtCommunity> {
+        match typ {
+            "rt" => Some(ExtCommunity::RouteTarget(ExtAdmin::parse(s)?)),
+            "soo" => Some(ExtCommunity::RouteOrigin(ExtAdmin::parse(s)?)),
+            "lb" => {
+                let strs: Vec<&str> = s.split(':').collect();
+                if strs.len() != 2 {
+                    return None;
+                }
+                Some(ExtCommunity::LinkBandwidth {
+                    typ: EXT_TYPE_NON_TRANSITIVE_AS,
+                    asn: strs[0].parse().ok()?,
+                    bandwidth: strs[1].parse::<f32>().ok()?.to_be_bytes(),
+                })
+            }
+            "encap" => Some(ExtCommunity::Encapsulation(s.parse().ok()?)),
+            "color" => {
+                let strs: Vec<&str> = s.split(':').collect();
+                match strs.len() {
+                    1 => Some(ExtCommunity::Color {
+                        flags: 0,
+                        color: strs[0].parse().ok()?,
+                    }),
+                    2 => Some(ExtCommunity::Color {
+                        flags: strs[0].parse().ok()?,
+                        color: strs[1].parse().ok()?,
+                    }),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn encode(&self, buf: &mut Vec<u8>) -> Result<(), anyhow::Error> {
+        let admin = |buf: &mut Vec<u8>, subtype: u8, admin: &ExtAdmin| -> std::io::Result<()> {
+            match admin {
+                ExtAdmin::As(asn, val) => {
+                    buf.write_u8(EXT_TYPE_AS)?;
+                    buf.write_u8(subtype)?;
+                    buf.write_u16::<NetworkEndian>(*asn)?;
+                    buf.write_u32::<NetworkEndian>(*val)
+                }
+                ExtAdmin::Ipv4(addr, val) => {
+                    buf.write_u8(EXT_TYPE_IPV4)?;
+                    buf.write_u8(subtype)?;
+                    buf.write_all(&addr.octets())?;
+                    buf.write_u16::<NetworkEndian>(*val)
+                }
+                ExtAdmin::As4(asn, val) => {
+                    buf.write_u8(EXT_TYPE_AS4)?;
+                    buf.write_u8(subtype)?;
+                    buf.write_u32::<NetworkEndian>(*asn)?;
+                    buf.write_u16::<NetworkEndian>(*val)
+                }
+                ExtAdmin::Ipv6(addr, val) => {
+                    buf.write_u8(EXT_TYPE_AS)?;
+                    buf.write_u8(subtype)?;
+                    buf.write_all(&addr.octets())?;
+                    buf.write_u16::<NetworkEndian>(*val)
+                }
+            }
+        };
+        match self {
+            ExtCommunity::RouteTarget(a) => admin(buf, EXT_SUBTYPE_ROUTE_TARGET, a)?,
+            ExtCommunity::RouteOrigin(a) => admin(buf, EXT_SUBTYPE_ROUTE_ORIGIN, a)?,
+            ExtCommunity::LinkBandwidth {
+                typ,
+                asn,
+                bandwidth,
+            } => {
+                buf.write_u8(*typ)?;
+                buf.write_u8(EXT_SUBTYPE_LINK_BANDWIDTH)?;
+                buf.write_u16::<NetworkEndian>(*asn)?;
+                buf.write_all(bandwidth)?;
+            }
+            ExtCommunity::Encapsulation(tunnel) => {
+                buf.write_u8(EXT_TYPE_OPAQUE)?;
+                buf.write_u8(EXT_SUBTYPE_ENCAPSULATION)?;
+                buf.write_u32::<NetworkEndian>(0)?;
+                buf.write_u16::<NetworkEndian>(*tunnel)?;
+            }
+            ExtCommunity::Color { flags, color } => {
+                buf.write_u8(EXT_TYPE_OPAQUE)?;
+                buf.write_u8(EXT_SUBTYPE_COLOR)?;
+                buf.write_u16::<NetworkEndian>(*flags)?;
+                buf.write_u32::<NetworkEndian>(*color)?;
+            }
+            ExtCommunity::Opaque {
+                typ,
+                subtype,
+                value,
+            } => {
+                buf.write_u8(*typ)?;
+                buf.write_u8(*subtype)?;
+                buf.write_all(value)?;
+            }
+            ExtCommunity::Ipv6Opaque {
+                typ,
+                subtype,
+                value,
+            } => {
+                buf.write_u8(*typ)?;
+                buf.write_u8(*subtype)?;
+                buf.write_all(value)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn from_bytes(c: &mut Cursor<&[u8]>) -> Result<ExtCommunity, anyhow::Error> {
+        let typ = c.read_u8()?;
+        let subtype = c.read_u8()?;
+        let mut value = [0u8; 6];
+        c.read_exact(&mut value)?;
+        let mut v = Cursor::new(&value[..]);
+
+        let admin = match typ {
+            EXT_TYPE_AS => Some(ExtAdmin::As(
+                v.read_u16::<NetworkEndian>()?,
+                v.read_u32::<NetworkEndian>()?,
+            )),
+            EXT_TYPE_IPV4 => Some(ExtAdmin::Ipv4(
+                Ipv4Addr::from(v.read_u32::<NetworkEndian>()?),
+                v.read_u16::<NetworkEndian>()?,
+            )),
+            EXT_TYPE_AS4 => Some(ExtAdmin::As4(
+                v.read_u32::<NetworkEndian>()?,
+                v.read_u16::<NetworkEndian>()?,
+            )),
+            _ => None,
+        };
+        v.set_position(0);
+
+        let com = match (typ, subtype, admin) {
+            (_, EXT_SUBTYPE_ROUTE_TARGET, Some(a)) => ExtCommunity::RouteTarget(a),
+            (_, EXT_SUBTYPE_ROUTE_ORIGIN, Some(a)) => ExtCommunity::RouteOrigin(a),
+            (EXT_TYPE_AS, EXT_SUBTYPE_LINK_BANDWIDTH, _)
+            | (EXT_TYPE_NON_TRANSITIVE_AS, EXT_SUBTYPE_LINK_BANDWIDTH, _) => {
+                ExtCommunity::LinkBandwidth {
+                    typ,
+                    asn: v.read_u16::<NetworkEndian>()?,
+                    bandwidth: [value[2], value[3], value[4], value[5]],
+                }
+            }
+            (EXT_TYPE_OPAQUE, EXT_SUBTYPE_ENCAPSULATION, _) => {
+                v.set_position(4);
+                ExtCommunity::Encapsulation(v.read_u16::<NetworkEndian>()?)
+            }
+            (EXT_TYPE_OPAQUE, EXT_SUBTYPE_COLOR, _) => ExtCommunity::Color {
+                flags: v.read_u16::<NetworkEndian>()?,
+                color: v.read_u32::<NetworkEndian>()?,
+            },
+            _ => ExtCommunity::Opaque {
+                typ,
+                subtype,
+                value,
+            },
+        };
+        Ok(com)
+    }
+
+    fn from_ipv6_bytes(c: &mut Cursor<&[u8]>) -> Result<ExtCommunity, anyhow::Error> {
+        let typ = c.read_u8()?;
+        let subtype = c.read_u8()?;
+        let mut value = [0u8; 18];
+        c.read_exact(&mut value)?;
+
+        let mut octets = [0u8; 16];
+        octets.copy_from_slice(&value[..16]);
+        let admin = ExtAdmin::Ipv6(
+            Ipv6Addr::from(octets),
+            u16::from_be_bytes([value[16], value[17]]),
+        );
+
+        let com = match (typ, subtype) {
+            (EXT_TYPE_AS, EXT_SUBTYPE_ROUTE_TARGET) => ExtCommunity::RouteTarget(admin),
+            (EXT_TYPE_AS, EXT_SUBTYPE_ROUTE_ORIGIN) => ExtCommunity::RouteOrigin(admin),
+            _ => ExtCommunity::Ipv6Opaque {
+                typ,
+                subtype,
+                value,
+            },
+        };
+        Ok(com)
+    }
+}
+
+impl fmt::Display for ExtCommunity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExtCommunity::RouteTarget(a) => write!(f, "RT:{}", a),
+            ExtCommunity::RouteOrigin(a) => write!(f, "SoO:{}", a),
+            ExtCommunity::LinkBandwidth { asn, bandwidth, .. } => {
+                write!(f, "LB:{}:{}", asn, f32::from_be_bytes(*bandwidth))
+            }
+            ExtCommunity::Encapsulation(tunnel) => write!(f, "ET:{}", tunnel),
+            ExtCommunity::Color { flags, color } => {
+                if *flags == 0 {
+                    write!(f, "Color:{}", color)
+                } else {
+                    write!(f, "Color:{}:{}", flags, color)
+                }
+            }
+            ExtCommunity::Opaque {
+                typ,
+                subtype,
+                value,
+            } => {
+                write!(f, "0x{:02x}{:02x}:", typ, subtype)?;
+                for v in value.iter() {
+                    write!(f, "{:02x}", v)?;
+                }
+                Ok(())
+            }
+            ExtCommunity::Ipv6Opaque {
+                typ,
+                subtype,
+                value,
+            } => {
+                write!(f, "0x{:02x}{:02x}:", typ, subtype)?;
+                for v in value.iter() {
+                    write!(f, "{:02x}", v)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Extended communities (RFC 4360) including IPv6 Address Specific ones
+/// (RFC 5701), which are encoded in a separate attribute.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ExtCommunities(Vec<ExtCommunity>);
+
+impl ExtCommunities {
+    const ATTR_FLAGS: u8 = ATTR_FLAG_OPTIONAL | ATTR_FLAG_TRANSITIVE;
+
+    pub fn new() -> Self {
+        ExtCommunities(Vec::<ExtCommunity>::new())
+    }
+
+    pub fn push(&mut self, value: ExtCommunity) {
+        self.0.push(value)
+    }
+
+    pub fn contains(&self, x: &ExtCommunity) -> bool {
+        self.0.contains(x)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, ExtCommunity> {
+        self.0.iter()
+    }
+
+    pub fn remove(&mut self, x: &ExtCommunity) {
+        self.0.retain(|v| v != x)
+    }
+
+    /// Append communities from `other` which are not already present.
+    pub fn merge(&mut self, other: &ExtCommunities) {
+        for v in other.iter() {
+            if !self.contains(v) {
+                self.push(*v);
+            }
+        }
+    }
+
+    /// Parse keyword and value pairs such as `rt 65000:100 soo 10.0.0.1:5`.
+    /// A keyword applies to every following value until the next keyword,
+    /// so `rt 65000:100 65000:200` holds two route targets.
+    pub fn from_str(s: &str) -> Option<ExtCommunities> {
+        let mut coms = ExtCommunities::new();
+        let mut typ: Option<&str> = None;
+
+        for word in s.split(' ').filter(|s| !s.is_empty()) {
+            match word {
+                "rt" | "soo" | "lb" | "encap" | "color" => typ = Some(word),
+                _ => coms.push(ExtCommunity::parse(typ?, word)?),
+            }
+        }
+        if coms.is_empty() {
+            return None;
+        }
+        Some(coms)
+    }
+
+    pub fn to_json(&self) -> String {
+        format!(r#"{{"extendedCommunity": "{}"}}"#, self)
+    }
+
+    /// Encode as the Extended Communities attribute followed by the IPv6
+    /// Address Specific Extended Community attribute, omitting either one
+    /// when it would be empty.
+    pub fn to_bytes(&self, buf: &mut Vec<u8>) -> Result<usize, anyhow::Error> {
+        let mut len = 0;
+        let (v6, v4): (Vec<&ExtCommunity>, Vec<&ExtCommunity>) =
+            self.iter().partition(|c| c.is_ipv6());
+
+        for (coms, typ, size) in [
+            (v4, ATTR_TYPE_EXT_COMMUNITIES, 8),
+            (v6, ATTR_TYPE_IPV6_EXT_COMMUNITIES, 20),
+        ] {
+            if coms.is_empty() {
+                continue;
+            }
+            let header = AttrHeader::new(ExtCommunities::ATTR_FLAGS, typ, coms.len() * size);
+            len += header.to_bytes(buf)?;
+            for com in coms {
+                com.encode(buf)?;
+                len += size;
+            }
+        }
+        Ok(len)
+    }
+
+    /// Decode the `len` octets value of an Extended Communities attribute.
+    pub fn from_bytes(c: &mut Cursor<&[u8]>, len: usize) -> Result<ExtCommunities, anyhow::Error> {
+        if len == 0 || !len.is_multiple_of(8) {
+            return Err(AttrError::Length {
+                typ: ATTR_TYPE_EXT_COMMUNITIES,
+                len,
+            }
+            .into());
+        }
+        let mut coms = ExtCommunities::new();
+        for _ in 0..len / 8 {
+            coms.push(ExtCommunity::from_bytes(c)?);
+        }
+        Ok(coms)
+    }
+
+    /// Decode the `len` octets value of an IPv6 Address Specific Extended
+    /// Community attribute.
+    pub fn from_ipv6_bytes(
+        c: &mut Cursor<&[u8]>,
+        len: usize,
+    ) -> Result<ExtCommunities, anyhow::Error> {
+        if len == 0 || !len.is_multiple_of(20) {
+            return Err(AttrError::Length {
+                typ: ATTR_TYPE_IPV6_EXT_COMMUNITIES,
+                len,
+            }
+            .into());
+        }
+        let mut coms = ExtCommunities::new();
+        for _ in 0..len / 20 {
+            coms.push(ExtCommunity::from_ipv6_bytes(c)?);
+        }
+        Ok(coms)
+    }
+}
+
+impl fmt::Display for ExtCommunities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let strs: Vec<String> = self.iter().map(|c| c.to_string()).collect();
+        write!(f, "{}", strs.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn from_str() {
+        let ecom = ExtCommunities::from_str("rt 65000:100 soo 10.0.0.1:5").unwrap();
+        assert_eq!(format!("{}", ecom), "RT:65000:100 SoO:10.0.0.1:5");
+
+        let ecom = ExtCommunities::from_str("rt 65000:100 4200000000:1 2001:db8::1:7").unwrap();
+        assert_eq!(ecom.len(), 3);
+        assert_eq!(
+            format!("{}", ecom),
+            "RT:65000:100 RT:4200000000:1 RT:2001:db8::1:7"
+        );
+        assert!(ecom.iter().nth(2).unwrap().is_ipv6());
+
+        let ecom =
+            ExtCommunities::from_str("lb 65000:125000000 encap 8 color 100 color 1:200").unwrap();
+        assert_eq!(
+            format!("{}", ecom),
+            "LB:65000:125000000 ET:8 Color:100 Color:1:200"
+        );
+
+        assert!(ExtCommunities::from_str("").is_none());
+        assert!(ExtCommunities::from_str("65000:100").is_none());
+        assert!(ExtCommunities::from_str("rt 65000").is_none());
+        assert!(ExtCommunities::from_str("rt 4200000000:65536").is_none());
+        assert!(ExtCommunities::from_str("soo 10.0.0.1:abc").is_none());
+        assert!(ExtCommunities::from_str("unknown 1:1").is_none());
+    }
+
+    #[test]
+    fn to_json() {
+        let ecom = ExtCommunities::from_str("rt 65000:100").unwrap();
+        assert_eq!(
+            ecom.to_json(),
+            String::from(r#"{"extendedCommunity": "RT:65000:100"}"#)
+        );
+    }
+
+    #[test]
+    fn to_bytes() {
+        let ecom = ExtCommunities::from_str("rt 65000:100 soo 10.0.0.1:5").unwrap();
+        let mut buf = Vec::new();
+        assert_eq!(ecom.to_bytes(&mut buf).unwrap(), 19);
+        assert_eq!(
+            buf,
+            vec![
+                0xc0, 16, 16, 0x00, 0x02, 0xfd, 0xe8, 0x00, 0x00, 0x00, 0x64, 0x01, 0x03, 10, 0, 0,
+                1, 0x00, 0x05
+            ]
+        );
+        let mut c = Cursor::new(&buf[3..]);
+        assert_eq!(ExtCommunities::from_bytes(&mut c, 16).unwrap(), ecom);
+
+        let ecom = ExtCommunities::from_str(
+            "rt 4200000000:1 2001:db8::1:7 lb 65000:125000000 encap 8 color 3:100",
+        )
+        .unwrap();
+        let mut buf = Vec::new();
+        assert_eq!(ecom.to_bytes(&mut buf).unwrap(), 3 + 32 + 3 + 20);
+        let mut c = Cursor::new(&buf[3..]);
+        let mut decoded = ExtCommunities::from_bytes(&mut c, 32).unwrap();
+        c.set_position(c.position() + 3);
+        decoded.merge(&ExtCommunities::from_ipv6_bytes(&mut c, 20).unwrap());
+        assert_eq!(
+            format!("{}", decoded),
+            "RT:4200000000:1 LB:65000:125000000 ET:8 Color:3:100 RT:2001:db8::1:7"
+        );
+
+        let unknown = [0x80, 0x06, 0, 0, 0x47, 0x9c, 0x40, 0x00];
+        let mut c = Cursor::new(&unknown[..]);
+        let decoded = ExtCommunities::from_bytes(&mut c, 8).unwrap();
+        assert_eq!(format!("{}", decoded), "0x8006:0000479c4000");
+        let mut buf = Vec::new();
+        decoded.to_bytes(&mut buf).unwrap();
+        assert_eq!(&buf[3..], &unknown[..]);
+
+        // Link bandwidth goes out byte for byte as received, transitive or
+        // not.
+        let lb = [0x00, 0x04, 0xfd, 0xe8, 0x4b, 0x3e, 0xbc, 0x21];
+        let mut c = Cursor::new(&lb[..]);
+        let decoded = ExtCommunities::from_bytes(&mut c, 8).unwrap();
+        assert_eq!(format!("{}", decoded), "LB:65000:12500001");
+        let mut buf = Vec::new();
+        decoded.to_bytes(&mut buf).unwrap();
+        assert_eq!(&buf[3..], &lb[..]);
+
+        let mut c = Cursor::new(&unknown[..]);
+        assert!(ExtCommunities::from_bytes(&mut c, 7).is_err());
+        let mut c = Cursor::new(&unknown[..]);
+        assert!(ExtCommunities::from_ipv6_bytes(&mut c, 20).is_err());
+    }
+}
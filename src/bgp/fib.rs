@@ -0,0 +1,326 @@
+#![allow(dead_code)]
+
+use super::{MpReach, Prefix};
+use std::io;
+use std::mem;
+use std::net::IpAddr;
+use std::os::unix::io::RawFd;
+use tokio::sync::mpsc;
+
+const RTM_NEWROUTE: u16 = 24;
+const RTM_DELROUTE: u16 = 25;
+const NLMSG_ERROR: u16 = 2;
+
+const NLM_F_REQUEST: u16 = 0x1;
+const NLM_F_ACK: u16 = 0x4;
+const NLM_F_REPLACE: u16 = 0x100;
+const NLM_F_CREATE: u16 = 0x400;
+
+const RTA_DST: u16 = 1;
+const RTA_OIF: u16 = 4;
+const RTA_GATEWAY: u16 = 5;
+/// Gateway of another address family, IPv6 next hops of IPv4 routes.
+const RTA_VIA: u16 = 18;
+
+const RT_TABLE_MAIN: u8 = 254;
+const RTPROT_BGP: u8 = 186;
+const RT_SCOPE_UNIVERSE: u8 = 0;
+const RTN_UNICAST: u8 = 1;
+
+const NLMSG_HDR_LEN: usize = 16;
+const RTMSG_LEN: usize = 12;
+
+/// Where the kernel forwards packets of an installed route.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NextHop {
+    pub addr: IpAddr,
+    /// Outgoing interface, needed for link-local addresses.
+    pub ifindex: Option<u32>,
+}
+
+impl NextHop {
+    /// Next hop of routes in `reach` received over the session on
+    /// interface `ifindex`. The link-local address is preferred, the
+    /// global one may only be reachable through a route of its own.
+    pub fn from_reach(reach: &MpReach, ifindex: Option<u32>) -> NextHop {
+        match reach.link_local {
+            Some(link_local) if ifindex.is_some() => NextHop {
+                addr: IpAddr::V6(link_local),
+                ifindex,
+            },
+            _ => NextHop {
+                addr: reach.next_hop,
+                ifindex,
+            },
+        }
+    }
+}
+
+/// A change of the routes installed, the best path of a prefix changed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FibChange {
+    /// Install the route, replacing the one installed for the prefix.
+    Install(Prefix, NextHop),
+    Remove(Prefix, NextHop),
+}
+
+fn family(addr: &IpAddr) -> u8 {
+    match addr {
+        IpAddr::V4(_) => libc::AF_INET as u8,
+        IpAddr::V6(_) => libc::AF_INET6 as u8,
+    }
+}
+
+fn octets(addr: &IpAddr) -> Vec<u8> {
+    match addr {
+        IpAddr::V4(addr) => addr.octets().to_vec(),
+        IpAddr::V6(addr) => addr.octets().to_vec(),
+    }
+}
+
+fn push_attr(buf: &mut Vec<u8>, typ: u16, value: &[u8]) {
+    buf.extend_from_slice(&((4 + value.len()) as u16).to_ne_bytes());
+    buf.extend_from_slice(&typ.to_ne_bytes());
+    buf.extend_from_slice(value);
+    buf.resize((buf.len() + 3) & !3, 0);
+}
+
+/// rtnetlink request to add, or replace, the route to `prefix` through
+/// `nexthop`, or to delete it. Only the best path of a prefix is installed,
+/// a new one replaces it. An IPv6 next hop of an IPv4 prefix (RFC
+/// 8950) is given as RTA_VIA.
+pub fn route_message(prefix: &Prefix, nexthop: &NextHop, add: bool, seq: u32) -> Vec<u8> {
+    let (typ, flags) = if add {
+        (RTM_NEWROUTE, NLM_F_CREATE | NLM_F_REPLACE)
+    } else {
+        (RTM_DELROUTE, 0)
+    };
+    let mut buf = Vec::new();
+    // Length is filled in at the end.
+    buf.extend_from_slice(&0u32.to_ne_bytes());
+    buf.extend_from_slice(&typ.to_ne_bytes());
+    buf.extend_from_slice(&(flags | NLM_F_REQUEST | NLM_F_ACK).to_ne_bytes());
+    buf.extend_from_slice(&seq.to_ne_bytes());
+    buf.extend_from_slice(&0u32.to_ne_bytes());
+
+    let addr = prefix.addr();
+    buf.extend_from_slice(&[
+        family(&addr),
+        prefix.prefixlen(),
+        0,
+        0,
+        RT_TABLE_MAIN,
+        RTPROT_BGP,
+        RT_SCOPE_UNIVERSE,
+        RTN_UNICAST,
+    ]);
+    buf.extend_from_slice(&0u32.to_ne_bytes());
+
+    push_attr(&mut buf, RTA_DST, &octets(&addr));
+    if family(&nexthop.addr) == family(&addr) {
+        push_attr(&mut buf, RTA_GATEWAY, &octets(&nexthop.addr));
+    } else {
+        let mut via = (family(&nexthop.addr) as u16).to_ne_bytes().to_vec();
+        via.extend_from_slice(&octets(&nexthop.addr));
+        push_attr(&mut buf, RTA_VIA, &via);
+    }
+    if let Some(ifindex) = nexthop.ifindex {
+        push_attr(&mut buf, RTA_OIF, &ifindex.to_ne_bytes());
+    }
+
+    let len = buf.len() as u32;
+    buf[..4].copy_from_slice(&len.to_ne_bytes());
+    buf
+}
+
+/// Routes installed in the kernel's main table. Needs CAP_NET_ADMIN.
+pub struct Fib {
+    fd: RawFd,
+    seq: u32,
+}
+
+impl Fib {
+    pub fn open() -> io::Result<Fib> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::NETLINK_ROUTE,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fib = Fib { fd, seq: 0 };
+        let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        let ret = unsafe {
+            libc::bind(
+                fd,
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(fib)
+    }
+
+    pub fn install(&mut self, prefix: &Prefix, nexthop: &NextHop) -> io::Result<()> {
+        self.request(prefix, nexthop, true)
+    }
+
+    pub fn remove(&mut self, prefix: &Prefix, nexthop: &NextHop) -> io::Result<()> {
+        self.request(prefix, nexthop, false)
+    }
+
+    /// Apply the changes received on `rx` until it is closed. Blocks on the
+    /// netlink socket, runs on a thread of its own like
+    /// `tokio::task::spawn_blocking()`.
+    pub fn run(mut self, mut rx: mpsc::UnboundedReceiver<FibChange>) {
+        while let Some(change) = rx.blocking_recv() {
+            let (prefix, op, res) = match change {
+                FibChange::Install(prefix, nexthop) => {
+                    (prefix, "install", self.install(&prefix, &nexthop))
+                }
+                FibChange::Remove(prefix, nexthop) => {
+                    (prefix, "remove", self.remove(&prefix, &nexthop))
+                }
+            };
+            if let Err(e) = res {
+                println!("route {} {} error {}", prefix, op, e);
+            }
+        }
+    }
+
+    /// Send the request and wait for the kernel's acknowledgement.
+    fn request(&mut self, prefix: &Prefix, nexthop: &NextHop, add: bool) -> io::Result<()> {
+        self.seq = self.seq.wrapping_add(1);
+        let msg = route_message(prefix, nexthop, add, self.seq);
+        let ret = unsafe { libc::send(self.fd, msg.as_ptr() as *const libc::c_void, msg.len(), 0) };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut buf = [0u8; 1024];
+        loop {
+            let len =
+                unsafe { libc::recv(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0) };
+            if len < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if let Some(errno) = ack(&buf[..len as usize], self.seq) {
+                return match errno {
+                    0 => Ok(()),
+                    errno => Err(io::Error::from_raw_os_error(errno)),
+                };
+            }
+        }
+    }
+}
+
+impl Drop for Fib {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+/// Error code of the acknowledgement of request `seq` in `buf`, 0 for
+/// success.
+fn ack(buf: &[u8], seq: u32) -> Option<i32> {
+    let u16_at = |i: usize| u16::from_ne_bytes([buf[i], buf[i + 1]]);
+    let u32_at = |i: usize| u32::from_ne_bytes([buf[i], buf[i + 1], buf[i + 2], buf[i + 3]]);
+    let mut pos = 0;
+    while pos + NLMSG_HDR_LEN + 4 <= buf.len() {
+        let len = u32_at(pos) as usize;
+        if len < NLMSG_HDR_LEN || pos + len > buf.len() {
+            return None;
+        }
+        if u16_at(pos + 4) == NLMSG_ERROR && u32_at(pos + 8) == seq {
+            return Some(-(u32_at(pos + NLMSG_HDR_LEN) as i32));
+        }
+        pos += (len + 3) & !3;
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bgp::{Family, AFI_IP, SAFI_UNICAST};
+    use std::net::Ipv6Addr;
+
+    #[test]
+    fn ipv4_via_ipv6() {
+        let prefix: Prefix = "10.0.1.0/24".parse().unwrap();
+        let nexthop = NextHop {
+            addr: "fe80::1".parse().unwrap(),
+            ifindex: Some(3),
+        };
+        let msg = route_message(&prefix, &nexthop, true, 7);
+        // Header, rtmsg, RTA_DST, RTA_VIA and RTA_OIF.
+        assert_eq!(msg.len(), NLMSG_HDR_LEN + RTMSG_LEN + 8 + 24 + 8);
+        assert_eq!(u32::from_ne_bytes([msg[0], msg[1], msg[2], msg[3]]), 68);
+        assert_eq!(u16::from_ne_bytes([msg[4], msg[5]]), RTM_NEWROUTE);
+        assert_eq!(&msg[16..20], &[libc::AF_INET as u8, 24, 0, 0]);
+        let dst = NLMSG_HDR_LEN + RTMSG_LEN;
+        assert_eq!(&msg[dst + 4..dst + 8], &[10, 0, 1, 0]);
+        let via = dst + 8;
+        assert_eq!(u16::from_ne_bytes([msg[via], msg[via + 1]]), 22);
+        assert_eq!(u16::from_ne_bytes([msg[via + 2], msg[via + 3]]), RTA_VIA);
+        assert_eq!(
+            u16::from_ne_bytes([msg[via + 4], msg[via + 5]]),
+            libc::AF_INET6 as u16
+        );
+        assert_eq!(
+            &msg[via + 6..via + 22],
+            &"fe80::1".parse::<Ipv6Addr>().unwrap().octets()
+        );
+
+        // Same family next hops are gateways.
+        let nexthop = NextHop {
+            addr: "192.168.0.1".parse().unwrap(),
+            ifindex: None,
+        };
+        let msg = route_message(&prefix, &nexthop, false, 8);
+        assert_eq!(u16::from_ne_bytes([msg[4], msg[5]]), RTM_DELROUTE);
+        assert_eq!(
+            u16::from_ne_bytes([msg[dst + 10], msg[dst + 11]]),
+            RTA_GATEWAY
+        );
+    }
+
+    #[test]
+    fn from_reach() {
+        let mut reach = MpReach {
+            family: Family {
+                afi: AFI_IP,
+                safi: SAFI_UNICAST,
+            },
+            next_hop: "2001:db8::1".parse().unwrap(),
+            link_local: Some("fe80::1".parse().unwrap()),
+            nlri: Vec::new(),
+        };
+        let nexthop = NextHop::from_reach(&reach, Some(3));
+        assert_eq!(nexthop.addr, "fe80::1".parse::<IpAddr>().unwrap());
+        assert_eq!(NextHop::from_reach(&reach, None).addr, reach.next_hop);
+        reach.link_local = None;
+        assert_eq!(NextHop::from_reach(&reach, Some(3)).addr, reach.next_hop);
+    }
+
+    #[test]
+    fn ack_error() {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&36u32.to_ne_bytes());
+        buf.extend_from_slice(&NLMSG_ERROR.to_ne_bytes());
+        buf.extend_from_slice(&0u16.to_ne_bytes());
+        buf.extend_from_slice(&5u32.to_ne_bytes());
+        buf.extend_from_slice(&0u32.to_ne_bytes());
+        buf.extend_from_slice(&(-libc::EPERM).to_ne_bytes());
+        buf.extend_from_slice(&[0u8; 16]);
+        assert_eq!(ack(&buf, 5), Some(libc::EPERM));
+        assert_eq!(ack(&buf, 6), None);
+    }
+}
@@ -0,0 +1,362 @@
+#![allow(dead_code)]
+
+use super::{AsPath, Attr, Communities, ExtCommunities, LargeCommunities, Origin};
+use super::{ExtCommunity, LargeCommunity};
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::Hash;
+use std::mem::size_of;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Arc;
+
+/// Memory owned by a value outside of itself.
+pub trait HeapSize {
+    fn heap_size(&self) -> usize;
+}
+
+impl HeapSize for AsPath {
+    fn heap_size(&self) -> usize {
+        self.segments().capacity() * size_of::<super::AsSegment>()
+            + self
+                .segments()
+                .iter()
+                .map(|s| s.asns.capacity() * size_of::<u32>())
+                .sum::<usize>()
+    }
+}
+
+impl HeapSize for Communities {
+    fn heap_size(&self) -> usize {
+        self.len() * size_of::<u32>()
+    }
+}
+
+impl HeapSize for ExtCommunities {
+    fn heap_size(&self) -> usize {
+        self.len() * size_of::<ExtCommunity>()
+    }
+}
+
+impl HeapSize for LargeCommunities {
+    fn heap_size(&self) -> usize {
+        self.len() * size_of::<LargeCommunity>()
+    }
+}
+
+/// Hash-consed values, equal values share one allocation.
+#[derive(Debug)]
+pub struct Interner<T: Hash + Eq> {
+    values: HashSet<Arc<T>>,
+}
+
+impl<T: Hash + Eq + HeapSize> Interner<T> {
+    pub fn new() -> Self {
+        Interner {
+            values: HashSet::new(),
+        }
+    }
+
+    /// The shared copy of `value`.
+    pub fn intern(&mut self, value: T) -> Arc<T> {
+        if let Some(shared) = self.values.get(&value) {
+            return shared.clone();
+        }
+        let shared = Arc::new(value);
+        self.values.insert(shared.clone());
+        shared
+    }
+
+    /// The shared copy of `value`, which becomes it when there is none.
+    pub fn intern_arc(&mut self, value: Arc<T>) -> Arc<T> {
+        if let Some(shared) = self.values.get(&value) {
+            return shared.clone();
+        }
+        self.values.insert(value.clone());
+        value
+    }
+
+    /// Drop the values nothing refers to anymore.
+    pub fn collect(&mut self) {
+        self.values.retain(|value| Arc::strong_count(value) > 1);
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn stats(&self) -> InternStats {
+        let mut stats = InternStats::default();
+        for value in &self.values {
+            stats.entries += 1;
+            // The interner's own reference is not counted.
+            stats.refs += Arc::strong_count(value) - 1;
+            stats.bytes += size_of::<T>() + value.heap_size();
+        }
+        stats
+    }
+}
+
+impl<T: Hash + Eq + HeapSize> Default for Interner<T> {
+    fn default() -> Self {
+        Interner::new()
+    }
+}
+
+/// Memory usage of the values of one attribute type.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct InternStats {
+    /// Distinct values.
+    pub entries: usize,
+    /// References to them.
+    pub refs: usize,
+    /// Memory the values use, without the interner's bookkeeping.
+    pub bytes: usize,
+}
+
+impl fmt::Display for InternStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} entries, {} references, {} bytes",
+            self.entries, self.refs, self.bytes
+        )
+    }
+}
+
+/// Path attributes as stored with routes, the variable length ones shared
+/// with every other route carrying the same.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct AttrSet {
+    pub origin: Origin,
+    pub as_path: Arc<AsPath>,
+    pub next_hop: Option<IpAddr>,
+    pub med: Option<u32>,
+    pub local_pref: Option<u32>,
+    pub atomic_aggregate: bool,
+    pub aggregator: Option<(u32, Ipv4Addr)>,
+    pub communities: Option<Arc<Communities>>,
+    pub ext_communities: Option<Arc<ExtCommunities>>,
+    pub large_communities: Option<Arc<LargeCommunities>>,
+    pub otc: Option<u32>,
+}
+
+impl AttrSet {
+    /// The AS path to modify, copied when shared.
+    pub fn as_path_mut(&mut self) -> &mut AsPath {
+        Arc::make_mut(&mut self.as_path)
+    }
+
+    pub fn communities_mut(&mut self) -> Option<&mut Communities> {
+        self.communities.as_mut().map(Arc::make_mut)
+    }
+
+    pub fn large_communities_mut(&mut self) -> Option<&mut LargeCommunities> {
+        self.large_communities.as_mut().map(Arc::make_mut)
+    }
+
+    /// A copy in the form UPDATEs carry.
+    pub fn attr(&self) -> Attr {
+        Attr {
+            origin: self.origin,
+            as_path: (*self.as_path).clone(),
+            next_hop: self.next_hop,
+            med: self.med,
+            local_pref: self.local_pref,
+            atomic_aggregate: self.atomic_aggregate,
+            aggregator: self.aggregator,
+            communities: self.communities.as_deref().cloned(),
+            ext_communities: self.ext_communities.as_deref().cloned(),
+            large_communities: self.large_communities.as_deref().cloned(),
+            otc: self.otc,
+        }
+    }
+}
+
+/// Attributes shared with nothing else.
+impl From<Attr> for AttrSet {
+    fn from(attr: Attr) -> Self {
+        AttrSet {
+            origin: attr.origin,
+            as_path: Arc::new(attr.as_path),
+            next_hop: attr.next_hop,
+            med: attr.med,
+            local_pref: attr.local_pref,
+            atomic_aggregate: attr.atomic_aggregate,
+            aggregator: attr.aggregator,
+            communities: attr.communities.map(Arc::new),
+            ext_communities: attr.ext_communities.map(Arc::new),
+            large_communities: attr.large_communities.map(Arc::new),
+            otc: attr.otc,
+        }
+    }
+}
+
+impl From<Attr> for Arc<AttrSet> {
+    fn from(attr: Attr) -> Self {
+        Arc::new(AttrSet::from(attr))
+    }
+}
+
+impl HeapSize for AttrSet {
+    /// The shared attributes are accounted for by their own interners.
+    fn heap_size(&self) -> usize {
+        0
+    }
+}
+
+/// Interned path attributes of a BGP instance. A route holds an
+/// `Arc<AttrSet>`, routes with the same attributes share one set and sets
+/// differing in a few attributes share the rest.
+#[derive(Debug, Default)]
+pub struct AttrStore {
+    as_paths: Interner<AsPath>,
+    communities: Interner<Communities>,
+    ext_communities: Interner<ExtCommunities>,
+    large_communities: Interner<LargeCommunities>,
+    sets: Interner<AttrSet>,
+}
+
+impl AttrStore {
+    pub fn new() -> Self {
+        AttrStore::default()
+    }
+
+    pub fn intern(&mut self, attr: Attr) -> Arc<AttrSet> {
+        let set = AttrSet {
+            origin: attr.origin,
+            as_path: self.as_paths.intern(attr.as_path),
+            next_hop: attr.next_hop,
+            med: attr.med,
+            local_pref: attr.local_pref,
+            atomic_aggregate: attr.atomic_aggregate,
+            aggregator: attr.aggregator,
+            communities: attr.communities.map(|c| self.communities.intern(c)),
+            ext_communities: attr.ext_communities.map(|c| self.ext_communities.intern(c)),
+            large_communities: attr
+                .large_communities
+                .map(|c| self.large_communities.intern(c)),
+            otc: attr.otc,
+        };
+        self.sets.intern(set)
+    }
+
+    /// The shared copy of `set`, with the attributes in it shared as well.
+    pub fn intern_set(&mut self, set: Arc<AttrSet>) -> Arc<AttrSet> {
+        if let Some(shared) = self.sets.values.get(&set) {
+            return shared.clone();
+        }
+        let set = AttrSet {
+            as_path: self.as_paths.intern_arc(set.as_path.clone()),
+            communities: set
+                .communities
+                .clone()
+                .map(|c| self.communities.intern_arc(c)),
+            ext_communities: set
+                .ext_communities
+                .clone()
+                .map(|c| self.ext_communities.intern_arc(c)),
+            large_communities: set
+                .large_communities
+                .clone()
+                .map(|c| self.large_communities.intern_arc(c)),
+            ..(*set).clone()
+        };
+        self.sets.intern(set)
+    }
+
+    /// Distinct attribute sets.
+    pub fn len(&self) -> usize {
+        self.sets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sets.is_empty()
+    }
+
+    /// Drop the attributes no route refers to anymore. Sets go first, they
+    /// hold references to the others.
+    pub fn collect(&mut self) {
+        self.sets.collect();
+        self.as_paths.collect();
+        self.communities.collect();
+        self.ext_communities.collect();
+        self.large_communities.collect();
+    }
+
+    /// Memory usage per attribute type.
+    pub fn stats(&self) -> Vec<(&'static str, InternStats)> {
+        vec![
+            ("attribute sets", self.sets.stats()),
+            ("as-paths", self.as_paths.stats()),
+            ("communities", self.communities.stats()),
+            ("extended communities", self.ext_communities.stats()),
+            ("large communities", self.large_communities.stats()),
+        ]
+    }
+
+    /// `show bgp memory` like summary.
+    pub fn show(&self) -> String {
+        self.stats()
+            .iter()
+            .map(|(name, stats)| format!("{:<22} {}\n", name, stats))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn attr(path: &str, med: u32) -> Attr {
+        let mut attr = Attr::new();
+        attr.as_path = path.parse().unwrap();
+        attr.med = Some(med);
+        attr.communities = Communities::from_str("65000:1 no-export");
+        attr
+    }
+
+    #[test]
+    fn share() {
+        let mut store = AttrStore::new();
+        let a = store.intern(attr("65001 65002", 0));
+        let b = store.intern(attr("65001 65002", 0));
+        assert!(Arc::ptr_eq(&a, &b));
+        assert_eq!(a.attr(), attr("65001 65002", 0));
+
+        // Sets differing in MED share the AS path and communities.
+        let c = store.intern(attr("65001 65002", 10));
+        assert!(!Arc::ptr_eq(&a, &c));
+        assert!(Arc::ptr_eq(&a.as_path, &c.as_path));
+        assert!(Arc::ptr_eq(
+            a.communities.as_ref().unwrap(),
+            c.communities.as_ref().unwrap()
+        ));
+
+        let stats = store.stats();
+        assert_eq!(
+            stats[0].1,
+            InternStats {
+                entries: 2,
+                refs: 3,
+                bytes: 2 * size_of::<AttrSet>()
+            }
+        );
+        assert_eq!(stats[1].1.entries, 1);
+        assert_eq!(stats[1].1.refs, 2);
+        assert_eq!(stats[2].1.bytes, size_of::<Communities>() + 8);
+        assert!(store.show().starts_with("attribute sets         2 entries"));
+
+        // Unreferenced attributes are collected.
+        drop((a, b));
+        store.collect();
+        assert_eq!(store.stats()[0].1.entries, 1);
+        assert_eq!(store.stats()[1].1.refs, 1);
+        drop(c);
+        store.collect();
+        assert!(store.stats().iter().all(|(_, stats)| stats.entries == 0));
+    }
+}
@@ -0,0 +1,192 @@
+#![allow(dead_code)]
+
+use super::attr::ATTR_TYPE_LARGE_COMMUNITIES;
+use super::attr::{AttrError, AttrHeader, ATTR_FLAG_OPTIONAL, ATTR_FLAG_TRANSITIVE};
+use byteorder::{NetworkEndian, ReadBytesExt, WriteBytesExt};
+use std::fmt;
+use std::io::Cursor;
+
+/// Global administrator, local data part 1 and local data part 2.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct LargeCommunity(pub u32, pub u32, pub u32);
+
+impl LargeCommunity {
+    /// Parse the canonical `a:b:c` format.
+    pub fn parse(s: &str) -> Option<LargeCommunity> {
+        let strs: Vec<&str> = s.split(':').collect();
+        if strs.len() != 3 {
+            return None;
+        }
+        Some(LargeCommunity(
+            strs[0].parse().ok()?,
+            strs[1].parse().ok()?,
+            strs[2].parse().ok()?,
+        ))
+    }
+}
+
+impl fmt::Display for LargeCommunity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.0, self.1, self.2)
+    }
+}
+
+/// Large communities (RFC 8092).
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct LargeCommunities(Vec<LargeCommunity>);
+
+impl LargeCommunities {
+    const ATTR_FLAGS: u8 = ATTR_FLAG_OPTIONAL | ATTR_FLAG_TRANSITIVE;
+
+    pub fn new() -> Self {
+        LargeCommunities(Vec::<LargeCommunity>::new())
+    }
+
+    pub fn push(&mut self, value: LargeCommunity) {
+        self.0.push(value)
+    }
+
+    pub fn contains(&self, x: &LargeCommunity) -> bool {
+        self.0.contains(x)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, LargeCommunity> {
+        self.0.iter()
+    }
+
+    pub fn retain<F>(&mut self, f: F)
+    where
+        F: FnMut(&LargeCommunity) -> bool,
+    {
+        self.0.retain(f)
+    }
+
+    /// Append communities from `other` which are not already present.
+    pub fn merge(&mut self, other: &LargeCommunities) {
+        for v in other.iter() {
+            if !self.contains(v) {
+                self.push(*v);
+            }
+        }
+    }
+
+    pub fn from_str(s: &str) -> Option<LargeCommunities> {
+        let mut coms = LargeCommunities::new();
+        for s in s.split(' ') {
+            coms.push(LargeCommunity::parse(s)?);
+        }
+        Some(coms)
+    }
+
+    pub fn to_json(&self) -> String {
+        format!(r#"{{"largeCommunity": "{}"}}"#, self)
+    }
+
+    /// Encode as a LARGE_COMMUNITY path attribute. Nothing is written when
+    /// there are no communities.
+    pub fn to_bytes(&self, buf: &mut Vec<u8>) -> Result<usize, anyhow::Error> {
+        if self.is_empty() {
+            return Ok(0);
+        }
+        let header = AttrHeader::new(
+            LargeCommunities::ATTR_FLAGS,
+            ATTR_TYPE_LARGE_COMMUNITIES,
+            self.len() * 12,
+        );
+        let mut len = header.to_bytes(buf)?;
+        for val in self.iter() {
+            buf.write_u32::<NetworkEndian>(val.0)?;
+            buf.write_u32::<NetworkEndian>(val.1)?;
+            buf.write_u32::<NetworkEndian>(val.2)?;
+            len += 12;
+        }
+        Ok(len)
+    }
+
+    /// Decode the `len` octets value of a LARGE_COMMUNITY path attribute.
+    pub fn from_bytes(
+        c: &mut Cursor<&[u8]>,
+        len: usize,
+    ) -> Result<LargeCommunities, anyhow::Error> {
+        if len == 0 || !len.is_multiple_of(12) {
+            return Err(AttrError::Length {
+                typ: ATTR_TYPE_LARGE_COMMUNITIES,
+                len,
+            }
+            .into());
+        }
+        let mut coms = LargeCommunities::new();
+        for _ in 0..len / 12 {
+            coms.push(LargeCommunity(
+                c.read_u32::<NetworkEndian>()?,
+                c.read_u32::<NetworkEndian>()?,
+                c.read_u32::<NetworkEndian>()?,
+            ));
+        }
+        Ok(coms)
+    }
+}
+
+impl fmt::Display for LargeCommunities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let strs: Vec<String> = self.iter().map(|c| c.to_string()).collect();
+        write!(f, "{}", strs.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn from_str() {
+        let lcom = LargeCommunities::from_str("65000:1:2 4200000000:4294967295:0").unwrap();
+        assert_eq!(lcom.len(), 2);
+        assert_eq!(format!("{}", lcom), "65000:1:2 4200000000:4294967295:0");
+        assert!(lcom.contains(&LargeCommunity(65000, 1, 2)));
+
+        assert!(LargeCommunities::from_str("").is_none());
+        assert!(LargeCommunities::from_str("65000:1").is_none());
+        assert!(LargeCommunities::from_str("65000:1:2:3").is_none());
+        assert!(LargeCommunities::from_str("65000:1:4294967296").is_none());
+        assert!(LargeCommunities::from_str("no-export").is_none());
+    }
+
+    #[test]
+    fn to_json() {
+        let lcom = LargeCommunities::from_str("65000:1:2 65000:3:4").unwrap();
+        assert_eq!(
+            lcom.to_json(),
+            String::from(r#"{"largeCommunity": "65000:1:2 65000:3:4"}"#)
+        );
+    }
+
+    #[test]
+    fn to_bytes() {
+        let lcom = LargeCommunities::from_str("65000:1:2").unwrap();
+        let mut buf = Vec::new();
+        assert_eq!(lcom.to_bytes(&mut buf).unwrap(), 15);
+        assert_eq!(
+            buf,
+            vec![0xc0, 32, 12, 0, 0, 0xfd, 0xe8, 0, 0, 0, 1, 0, 0, 0, 2]
+        );
+        let mut c = Cursor::new(&buf[3..]);
+        assert_eq!(LargeCommunities::from_bytes(&mut c, 12).unwrap(), lcom);
+
+        let mut c = Cursor::new(&buf[3..]);
+        assert!(LargeCommunities::from_bytes(&mut c, 8).is_err());
+        let mut c = Cursor::new(&buf[3..]);
+        assert!(LargeCommunities::from_bytes(&mut c, 24).is_err());
+
+        let mut buf = Vec::new();
+        assert_eq!(LargeCommunities::new().to_bytes(&mut buf).unwrap(), 0);
+    }
+}
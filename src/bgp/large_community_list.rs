@@ -0,0 +1,132 @@
+#![allow(dead_code)]
+
+use super::as_path_list::bgp_regex;
+use super::{Action, LargeCommunities, LargeCommunity};
+use regex::Regex;
+
+#[derive(Clone, Debug)]
+pub enum LargeCommunityMatch {
+    /// Matches when every listed community is present.
+    Standard(LargeCommunities),
+    /// Regular expression against the string form of the communities.
+    Expanded(Regex),
+}
+
+#[derive(Clone, Debug)]
+pub struct LargeCommunityListEntry {
+    pub action: Action,
+    pub val: LargeCommunityMatch,
+}
+
+impl LargeCommunityListEntry {
+    pub fn standard(action: Action, coms: LargeCommunities) -> Self {
+        LargeCommunityListEntry {
+            action,
+            val: LargeCommunityMatch::Standard(coms),
+        }
+    }
+
+    pub fn expanded(action: Action, s: &str) -> Result<Self, regex::Error> {
+        Ok(LargeCommunityListEntry {
+            action,
+            val: LargeCommunityMatch::Expanded(bgp_regex(s)?),
+        })
+    }
+
+    fn matches(&self, coms: &LargeCommunities) -> bool {
+        match &self.val {
+            LargeCommunityMatch::Standard(list) => list.iter().all(|c| coms.contains(c)),
+            LargeCommunityMatch::Expanded(regex) => regex.is_match(&coms.to_string()),
+        }
+    }
+
+    fn matches_exact(&self, coms: &LargeCommunities) -> bool {
+        match &self.val {
+            LargeCommunityMatch::Standard(list) => {
+                list.iter().all(|c| coms.contains(c)) && coms.iter().all(|c| list.contains(c))
+            }
+            LargeCommunityMatch::Expanded(_) => self.matches(coms),
+        }
+    }
+}
+
+/// Large community list, evaluated like `CommunityList`.
+#[derive(Clone, Debug, Default)]
+pub struct LargeCommunityList {
+    entries: Vec<LargeCommunityListEntry>,
+}
+
+impl LargeCommunityList {
+    pub fn new() -> Self {
+        LargeCommunityList {
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, entry: LargeCommunityListEntry) {
+        self.entries.push(entry)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn apply(&self, coms: &LargeCommunities) -> Action {
+        for entry in self.entries.iter() {
+            if entry.matches(coms) {
+                return entry.action;
+            }
+        }
+        Action::Deny
+    }
+
+    pub fn apply_exact(&self, coms: &LargeCommunities) -> Action {
+        for entry in self.entries.iter() {
+            if entry.matches_exact(coms) {
+                return entry.action;
+            }
+        }
+        Action::Deny
+    }
+
+    /// Remove each community which, on its own, is permitted by this list.
+    pub fn delete(&self, coms: &mut LargeCommunities) {
+        coms.retain(|c: &LargeCommunity| {
+            let mut single = LargeCommunities::new();
+            single.push(*c);
+            self.apply(&single) != Action::Permit
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn apply() {
+        let mut list = LargeCommunityList::new();
+        list.push(LargeCommunityListEntry::standard(
+            Action::Permit,
+            LargeCommunities::from_str("65000:1:1 65000:1:2").unwrap(),
+        ));
+        list.push(LargeCommunityListEntry::expanded(Action::Permit, "^65000:2:").unwrap());
+
+        let apply = |s: &str| list.apply(&LargeCommunities::from_str(s).unwrap());
+        assert_eq!(apply("65000:1:2 65000:1:1 65000:1:3"), Action::Permit);
+        assert_eq!(apply("65000:1:1"), Action::Deny);
+        assert_eq!(apply("65000:2:5"), Action::Permit);
+
+        let exact = |s: &str| list.apply_exact(&LargeCommunities::from_str(s).unwrap());
+        assert_eq!(exact("65000:1:2 65000:1:1"), Action::Permit);
+        assert_eq!(exact("65000:1:2 65000:1:1 65000:1:3"), Action::Deny);
+
+        let mut coms = LargeCommunities::from_str("65000:2:1 65001:0:0 65000:2:9").unwrap();
+        list.delete(&mut coms);
+        assert_eq!(format!("{}", coms), "65001:0:0");
+    }
+}
@@ -0,0 +1,144 @@
+#![allow(dead_code)]
+
+use super::{NeighborError, Prefix};
+use std::net::IpAddr;
+
+/// Dynamic neighbors accepted by default, `bgp listen limit`.
+pub const LISTEN_LIMIT_DEFAULT: usize = 100;
+pub const LISTEN_LIMIT_MAX: usize = 5000;
+
+/// `bgp listen range <prefix> peer-group <name>`, connections from
+/// addresses inside `prefix` which are not configured neighbors create a
+/// dynamic neighbor in the peer-group.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ListenRange {
+    pub prefix: Prefix,
+    pub peer_group: String,
+}
+
+/// One `bgp listen ...` configuration line.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ListenCommand {
+    Range(ListenRange),
+    NoRange(Prefix),
+    /// Most dynamic neighbors at once, `None` returns to the default.
+    Limit(Option<usize>),
+}
+
+impl ListenCommand {
+    /// Parse a `[no] bgp listen ...` configuration line.
+    pub fn parse(line: &str) -> Result<ListenCommand, NeighborError> {
+        let syntax = || NeighborError::Syntax(line.to_string());
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let cmd = match words.as_slice() {
+            ["bgp", "listen", "range", prefix, "peer-group", name] => {
+                ListenCommand::Range(ListenRange {
+                    prefix: prefix.parse().map_err(|_| syntax())?,
+                    peer_group: name.to_string(),
+                })
+            }
+            ["no", "bgp", "listen", "range", prefix, ..] => {
+                ListenCommand::NoRange(prefix.parse().map_err(|_| syntax())?)
+            }
+            ["bgp", "listen", "limit", limit] => {
+                ListenCommand::Limit(Some(limit.parse().map_err(|_| syntax())?))
+            }
+            ["no", "bgp", "listen", "limit", ..] => ListenCommand::Limit(None),
+            _ => return Err(syntax()),
+        };
+        Ok(cmd)
+    }
+}
+
+/// The listen ranges of a BGP instance.
+#[derive(Clone, Debug)]
+pub struct ListenRanges {
+    ranges: Vec<ListenRange>,
+    pub limit: usize,
+}
+
+impl ListenRanges {
+    pub fn new() -> Self {
+        ListenRanges {
+            ranges: Vec::new(),
+            limit: LISTEN_LIMIT_DEFAULT,
+        }
+    }
+
+    /// Apply `cmd`. A range replaces the one of the same prefix.
+    pub fn apply(&mut self, cmd: ListenCommand) -> Result<(), NeighborError> {
+        match cmd {
+            ListenCommand::Range(range) => {
+                self.ranges.retain(|r| r.prefix != range.prefix);
+                self.ranges.push(range);
+            }
+            ListenCommand::NoRange(prefix) => self.ranges.retain(|r| r.prefix != prefix),
+            ListenCommand::Limit(Some(limit)) if limit == 0 || limit > LISTEN_LIMIT_MAX => {
+                return Err(NeighborError::ListenLimit(limit));
+            }
+            ListenCommand::Limit(limit) => self.limit = limit.unwrap_or(LISTEN_LIMIT_DEFAULT),
+        }
+        Ok(())
+    }
+
+    /// The most specific range containing `addr`.
+    pub fn lookup(&self, addr: &IpAddr) -> Option<&ListenRange> {
+        self.ranges
+            .iter()
+            .filter(|r| r.prefix.contains_addr(addr))
+            .max_by_key(|r| r.prefix.prefixlen())
+    }
+
+    pub fn ranges(&self) -> &[ListenRange] {
+        &self.ranges
+    }
+}
+
+impl Default for ListenRanges {
+    fn default() -> Self {
+        ListenRanges::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn group(ranges: &ListenRanges, addr: &str) -> Option<String> {
+        ranges
+            .lookup(&addr.parse().unwrap())
+            .map(|r| r.peer_group.clone())
+    }
+
+    #[test]
+    fn lookup() {
+        let mut ranges = ListenRanges::new();
+        for line in &[
+            "bgp listen range 10.0.0.0/16 peer-group TOR",
+            "bgp listen range 10.0.1.0/24 peer-group LAB",
+            "bgp listen range 2001:db8::/32 peer-group TOR6",
+            "bgp listen limit 10",
+        ] {
+            ranges.apply(ListenCommand::parse(line).unwrap()).unwrap();
+        }
+        assert_eq!(group(&ranges, "10.0.2.1").as_deref(), Some("TOR"));
+        assert_eq!(group(&ranges, "10.0.1.1").as_deref(), Some("LAB"));
+        assert_eq!(group(&ranges, "2001:db8::1").as_deref(), Some("TOR6"));
+        assert_eq!(group(&ranges, "10.1.0.1"), None);
+        assert_eq!(ranges.limit, 10);
+
+        ranges
+            .apply(ListenCommand::parse("no bgp listen range 10.0.1.0/24").unwrap())
+            .unwrap();
+        assert_eq!(group(&ranges, "10.0.1.1").as_deref(), Some("TOR"));
+        assert_eq!(
+            ranges.apply(ListenCommand::Limit(Some(0))),
+            Err(NeighborError::ListenLimit(0))
+        );
+        ranges
+            .apply(ListenCommand::parse("no bgp listen limit").unwrap())
+            .unwrap();
+        assert_eq!(ranges.limit, LISTEN_LIMIT_DEFAULT);
+        assert!(ListenCommand::parse("bgp listen range 10.0.0.0/33 peer-group X").is_err());
+    }
+}
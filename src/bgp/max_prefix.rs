@@ -0,0 +1,181 @@
+#![allow(dead_code)]
+
+use super::{Family, MessageNotification, NOTIFY_CEASE, NOTIFY_CEASE_MAX_PREFIX};
+use byteorder::{NetworkEndian, WriteBytesExt};
+use std::time::Duration;
+
+pub const MAX_PREFIX_THRESHOLD_DEFAULT: u8 = 75;
+
+/// `neighbor <addr> maximum-prefix <limit> [<threshold>] [warning-only |
+/// restart <minutes>]` for one AFI/SAFI.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MaxPrefix {
+    pub limit: u32,
+    /// Percentage of `limit` at which a warning is logged.
+    pub threshold: u8,
+    /// Only log when the limit is exceeded instead of closing the session.
+    pub warning_only: bool,
+    /// How long a session closed for exceeding the limit stays down. `None`
+    /// keeps it down until it is cleared by the operator.
+    pub restart: Option<Duration>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MaxPrefixEvent {
+    ThresholdReached,
+    LimitExceeded,
+}
+
+impl MaxPrefix {
+    pub fn new(limit: u32) -> Self {
+        MaxPrefix {
+            limit,
+            threshold: MAX_PREFIX_THRESHOLD_DEFAULT,
+            warning_only: false,
+            restart: None,
+        }
+    }
+
+    fn threshold_count(&self) -> u64 {
+        self.limit as u64 * self.threshold as u64 / 100
+    }
+
+    /// Cease NOTIFICATION to send for `event`, `None` when the session should
+    /// stay up. The data carries AFI, SAFI and the upper bound (RFC 4486 4).
+    pub fn notification(
+        &self,
+        family: Family,
+        event: MaxPrefixEvent,
+    ) -> Option<MessageNotification> {
+        if event != MaxPrefixEvent::LimitExceeded || self.warning_only {
+            return None;
+        }
+        let mut data = Vec::with_capacity(7);
+        data.write_u16::<NetworkEndian>(family.afi).ok()?;
+        data.write_u8(family.safi).ok()?;
+        data.write_u32::<NetworkEndian>(self.limit).ok()?;
+        Some(MessageNotification::new(
+            NOTIFY_CEASE,
+            NOTIFY_CEASE_MAX_PREFIX,
+            data,
+        ))
+    }
+}
+
+/// Number of prefixes accepted from a neighbor for one AFI/SAFI.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PrefixCounter {
+    count: u32,
+    threshold_reached: bool,
+    limit_exceeded: bool,
+}
+
+impl PrefixCounter {
+    pub fn new() -> Self {
+        PrefixCounter::default()
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// Count one more accepted prefix. Each event is reported once and again
+    /// only after the count dropped back below the level which raised it.
+    pub fn increment(&mut self, max: Option<&MaxPrefix>) -> Option<MaxPrefixEvent> {
+        self.count = self.count.saturating_add(1);
+        let max = max?;
+
+        if self.count > max.limit {
+            self.threshold_reached = true;
+            if !self.limit_exceeded {
+                self.limit_exceeded = true;
+                return Some(MaxPrefixEvent::LimitExceeded);
+            }
+        } else if self.count as u64 >= max.threshold_count() && !self.threshold_reached {
+            self.threshold_reached = true;
+            return Some(MaxPrefixEvent::ThresholdReached);
+        }
+        None
+    }
+
+    pub fn decrement(&mut self, max: Option<&MaxPrefix>) {
+        self.count = self.count.saturating_sub(1);
+        if let Some(max) = max {
+            if self.count <= max.limit {
+                self.limit_exceeded = false;
+            }
+            if (self.count as u64) < max.threshold_count() {
+                self.threshold_reached = false;
+            }
+        }
+    }
+
+    /// Forget everything, called when the session goes down.
+    pub fn reset(&mut self) {
+        *self = PrefixCounter::default();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bgp::{AFI_IP, SAFI_UNICAST};
+
+    #[test]
+    fn increment() {
+        let max = MaxPrefix::new(4);
+        let mut counter = PrefixCounter::new();
+
+        assert_eq!(counter.increment(Some(&max)), None);
+        assert_eq!(counter.increment(Some(&max)), None);
+        assert_eq!(
+            counter.increment(Some(&max)),
+            Some(MaxPrefixEvent::ThresholdReached)
+        );
+        assert_eq!(counter.increment(Some(&max)), None);
+        assert_eq!(
+            counter.increment(Some(&max)),
+            Some(MaxPrefixEvent::LimitExceeded)
+        );
+        assert_eq!(counter.increment(Some(&max)), None);
+        assert_eq!(counter.count(), 6);
+
+        counter.decrement(Some(&max));
+        counter.decrement(Some(&max));
+        assert_eq!(
+            counter.increment(Some(&max)),
+            Some(MaxPrefixEvent::LimitExceeded)
+        );
+
+        counter.reset();
+        assert_eq!(counter.count(), 0);
+        assert_eq!(counter.increment(None), None);
+    }
+
+    #[test]
+    fn notification() {
+        let family = Family {
+            afi: AFI_IP,
+            safi: SAFI_UNICAST,
+        };
+        let mut max = MaxPrefix::new(1000);
+        assert_eq!(
+            max.notification(family, MaxPrefixEvent::ThresholdReached),
+            None
+        );
+
+        let n = max
+            .notification(family, MaxPrefixEvent::LimitExceeded)
+            .unwrap();
+        assert_eq!(n.code, NOTIFY_CEASE);
+        assert_eq!(n.subcode, NOTIFY_CEASE_MAX_PREFIX);
+        assert_eq!(n.data, vec![0, 1, 1, 0, 0, 0x03, 0xe8]);
+        assert_eq!(format!("{}", n), "Cease/Maximum Number of Prefixes Reached");
+
+        max.warning_only = true;
+        assert_eq!(
+            max.notification(family, MaxPrefixEvent::LimitExceeded),
+            None
+        );
+    }
+}
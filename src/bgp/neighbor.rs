@@ -1,6 +1,380 @@
 #![allow(dead_code)]
+use super::LOCAL_PREF_DEFAULT;
+use super::{Action, Direction, Family, MaxPrefix, Policy, PolicyContext, Role, Route};
+use super::{
+    AdvertiseMap, AllowasIn, LocalAs, RemoteAs, SendCommunity, Timers, AFI_IP, AFI_IP6,
+    SAFI_UNICAST,
+};
+use super::{Capabilities, Capability, ConnectMode, Initiator, MessageNotification, BGP_PORT};
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// Relationship of a neighbor to the local AS, which decides how well-known
+/// communities restrict advertisement.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PeerType {
+    Internal,
+    /// External neighbor inside our confederation.
+    ConfedExternal,
+    External,
+}
+
+#[derive(Clone, Debug)]
 pub struct Neighbor {
-    pub ipaddr: std::net::IpAddr,
+    pub ipaddr: IpAddr,
+    /// TCP port the neighbor is connected to.
+    pub port: u16,
+    /// AS expected in the neighbor's OPEN, any AS is accepted when unset.
+    pub remote_as: Option<RemoteAs>,
+    pub local_as: Option<LocalAs>,
+    pub description: Option<String>,
+    /// Local address connections to the neighbor are made from.
+    pub update_source: Option<IpAddr>,
+    /// TTL of packets to an external neighbor, which must be directly
+    /// connected when unset.
+    pub ebgp_multihop: Option<u8>,
+    /// Overrides the timers of the BGP instance.
+    pub timers: Option<Timers>,
+    /// TCP MD5 signature key (RFC 2385).
+    pub password: Option<String>,
+    /// The session is administratively down.
+    pub shutdown: bool,
+    /// Address families negotiated with the neighbor.
+    pub activate: BTreeSet<Family>,
+    pub route_map_in: Option<String>,
+    pub route_map_out: Option<String>,
+    pub prefix_list_in: Option<String>,
+    pub prefix_list_out: Option<String>,
+    pub max_prefix: BTreeMap<Family, MaxPrefix>,
+    pub next_hop_self: bool,
+    pub send_community: SendCommunity,
+    pub allowas_in: Option<AllowasIn>,
+    /// Conditional advertisement.
+    pub advertise_map: Option<AdvertiseMap>,
+    /// `neighbor <addr> local-role <role> [strict-mode]`.
+    pub local_role: Option<Role>,
+    /// Require the neighbor to announce its role.
+    pub strict_role: bool,
+    /// Advertise the Extended Message capability (RFC 8654).
+    pub extended_message: bool,
+    pub connect_mode: ConnectMode,
+    /// Interface of a neighbor at a link-local address.
+    pub interface: Option<String>,
+    /// Advertise the Extended Next Hop Encoding capability (RFC 8950).
+    pub extended_nexthop: bool,
+}
+
+impl Neighbor {
+    pub fn new(ipaddr: IpAddr) -> Self {
+        Neighbor {
+            ipaddr,
+            port: BGP_PORT,
+            remote_as: None,
+            local_as: None,
+            description: None,
+            update_source: None,
+            ebgp_multihop: None,
+            timers: None,
+            password: None,
+            shutdown: false,
+            activate: std::iter::once(Family {
+                afi: AFI_IP,
+                safi: SAFI_UNICAST,
+            })
+            .collect(),
+            route_map_in: None,
+            route_map_out: None,
+            prefix_list_in: None,
+            prefix_list_out: None,
+            max_prefix: BTreeMap::new(),
+            next_hop_self: false,
+            send_community: SendCommunity::default(),
+            allowas_in: None,
+            advertise_map: None,
+            local_role: None,
+            strict_role: false,
+            extended_message: true,
+            connect_mode: ConnectMode::default(),
+            interface: None,
+            extended_nexthop: false,
+        }
+    }
+
+    /// Whether a connection opened by `initiator` is used for the session.
+    pub fn connection_allowed(&self, initiator: Initiator) -> bool {
+        self.connect_mode.allows(initiator)
+    }
+
+    /// AS the session is brought up with, `asn` is the instance's AS.
+    pub fn local_asn(&self, asn: u32) -> u32 {
+        self.local_as.map_or(asn, |local| local.asn)
+    }
+
+    /// Type of the session when it is known from the configured remote AS.
+    pub fn peer_type(&self, asn: u32) -> Option<PeerType> {
+        self.remote_as
+            .map(|remote| remote.peer_type(self.local_asn(asn)))
+    }
+
+    /// TTL to set on the connection. External neighbors are one hop away
+    /// unless multihop is configured.
+    pub fn ttl(&self, asn: u32) -> Option<u32> {
+        match self.peer_type(asn) {
+            Some(PeerType::External) => Some(self.ebgp_multihop.unwrap_or(1) as u32),
+            _ => None,
+        }
+    }
+
+    /// Hold time to announce, `hold_time` is the instance's.
+    pub fn hold_time(&self, hold_time: u16) -> u16 {
+        self.timers.map_or(hold_time, |timers| timers.hold_time)
+    }
+
+    /// Interval of KEEPALIVE messages for the negotiated `hold` time.
+    pub fn keepalive(&self, hold: Duration) -> Duration {
+        match self.timers {
+            Some(timers) => Duration::from_secs(timers.keepalive as u64).min(hold / 3),
+            None => hold / 3,
+        }
+    }
+
+    /// Whether the session has to be reset to apply the configuration
+    /// `other`. Policy changes are applied to the running session.
+    pub fn session_changed(&self, other: &Neighbor) -> bool {
+        self.port != other.port
+            || self.remote_as != other.remote_as
+            || self.local_as != other.local_as
+            || self.update_source != other.update_source
+            || self.ebgp_multihop != other.ebgp_multihop
+            || self.timers != other.timers
+            || self.password != other.password
+            || self.activate != other.activate
+            || self.local_role != other.local_role
+            || self.strict_role != other.strict_role
+            || self.extended_message != other.extended_message
+            || self.connect_mode != other.connect_mode
+            || self.interface != other.interface
+            || self.extended_nexthop != other.extended_nexthop
+    }
+
+    /// Whether IPv4 routes may be exchanged with IPv6 next hops. Always for
+    /// a neighbor on an interface, which has no IPv4 address to use.
+    pub fn extended_nexthop_enabled(&self) -> bool {
+        self.extended_nexthop || self.interface.is_some()
+    }
+
+    /// Index of the interface of an unnumbered neighbor.
+    pub fn ifindex(&self) -> Option<u32> {
+        self.interface
+            .as_deref()
+            .and_then(|name| super::tcp::ifindex(name).ok())
+    }
+
+    /// Optional capabilities to send in our OPEN.
+    pub fn capabilities(&self) -> Vec<Capability> {
+        let mut caps: Vec<Capability> = self
+            .activate
+            .iter()
+            .map(|family| Capability::MultiProtocol(*family))
+            .collect();
+        if self.extended_message {
+            caps.push(Capability::ExtendedMessage);
+        }
+        if self.extended_nexthop_enabled() {
+            let families: Vec<(Family, u16)> = self
+                .activate
+                .iter()
+                .filter(|family| family.afi == AFI_IP)
+                .map(|family| (*family, AFI_IP6))
+                .collect();
+            if !families.is_empty() {
+                caps.push(Capability::ExtendedNextHop(families));
+            }
+        }
+        if let Some(role) = self.local_role {
+            caps.push(Capability::Role(role as u8));
+        }
+        caps
+    }
+
+    /// Check the roles announced in the neighbor's OPEN.
+    pub fn role_check(&self, caps: &Capabilities) -> Result<Option<Role>, MessageNotification> {
+        super::role_check(self.local_role, self.strict_role, caps)
+    }
+
+    /// Only-to-Customer ingress leak prevention, a no-op without a local
+    /// role.
+    pub fn otc_in(&self, route: &mut Route, remote_as: u32) -> Action {
+        match self.local_role {
+            Some(role) => {
+                let mut otc = route.attr.otc;
+                let action = role.otc_in(&mut otc, remote_as);
+                if otc != route.attr.otc {
+                    route.attr_mut().otc = otc;
+                }
+                action
+            }
+            None => Action::Permit,
+        }
+    }
+
+    /// Direction of ASPA verification for routes from the neighbor:
+    /// downstream when it is our provider, upstream when it is a customer,
+    /// lateral peer or route server. None without a local role.
+    pub fn aspa_downstream(&self) -> Option<bool> {
+        self.local_role.map(|role| role == Role::Customer)
+    }
+
+    /// Only-to-Customer egress leak prevention, a no-op without a local
+    /// role.
+    pub fn otc_out(&self, route: &mut Route, local_as: u32) -> Action {
+        match self.local_role {
+            Some(role) => {
+                let mut otc = route.attr.otc;
+                let action = role.otc_out(&mut otc, local_as);
+                if otc != route.attr.otc {
+                    route.attr_mut().otc = otc;
+                }
+                action
+            }
+            None => Action::Permit,
+        }
+    }
+
+    /// Whether the local AS appears in the AS path of a received route more
+    /// often than allowas-in permits.
+    pub fn as_loop(&self, route: &Route, asn: u32) -> bool {
+        let asn = self.local_asn(asn);
+        let path = &route.attr.as_path;
+        let count = path.count(asn);
+        match self.allowas_in {
+            _ if count == 0 => false,
+            Some(allowas_in) => !allowas_in.allows(count, path.origin_as() == Some(asn)),
+            None => true,
+        }
+    }
+
+    /// Prepend the local-as of the session to the AS path of a received
+    /// route unless no-prepend is set.
+    pub fn attr_in(&self, route: &mut Route) {
+        if let Some(local) = self.local_as {
+            if !local.no_prepend {
+                route.attr_mut().as_path_mut().prepend(local.asn, 1);
+            }
+        }
+    }
+
+    /// Attributes which are not passed on as received, reset before the
+    /// outbound policy may set them. An external neighbor gets no MED
+    /// unless the route is originated locally (RFC 4271 5.1.4), an
+    /// internal one always gets a LOCAL_PREF (5.1.5).
+    pub fn attr_reset_out(&self, route: &mut Route, peer_type: PeerType) {
+        if peer_type == PeerType::Internal {
+            if route.attr.local_pref.is_none() {
+                route.attr_mut().local_pref = Some(LOCAL_PREF_DEFAULT);
+            }
+        } else if route.peer.is_some() && route.attr.med.is_some() {
+            route.attr_mut().med = None;
+        }
+    }
+
+    /// Whether a lateral peer, to which NO_PEER routes are not advertised.
+    fn is_lateral(&self) -> bool {
+        matches!(
+            self.local_role,
+            Some(Role::Peer | Role::RouteServer | Role::RsClient)
+        )
+    }
+
+    /// Whether the well-known communities of `route` allow advertising it
+    /// to a neighbor of `peer_type` (RFC 1997, RFC 3765).
+    pub fn advertise_allowed(&self, route: &Route, peer_type: PeerType) -> bool {
+        match &route.attr.communities {
+            Some(coms) => coms.advertise_allowed(peer_type, self.is_lateral()),
+            None => true,
+        }
+    }
+
+    /// Outbound attribute handling. Routes to an external neighbor get `asn`
+    /// and the local-as prepended, next-hop-self and locally originated
+    /// routes get our address `local` as next hop and communities which are
+    /// not sent are removed.
+    pub fn attr_out(
+        &self,
+        route: &mut Route,
+        asn: u32,
+        peer_type: PeerType,
+        local: Option<IpAddr>,
+    ) {
+        let originated = route.peer.is_none();
+        let attr = route.attr_mut();
+        if peer_type != PeerType::Internal {
+            match self.local_as {
+                Some(local) => {
+                    if !local.replace_as {
+                        attr.as_path_mut().prepend(asn, 1);
+                    }
+                    attr.as_path_mut().prepend(local.asn, 1);
+                }
+                None => attr.as_path_mut().prepend(asn, 1),
+            }
+        }
+        if self.next_hop_self || peer_type != PeerType::Internal || originated {
+            if let Some(local) = local {
+                attr.next_hop = Some(local);
+            }
+        }
+        if !self.send_community.standard {
+            attr.communities = None;
+        }
+        if !self.send_community.extended {
+            attr.ext_communities = None;
+        }
+        if !self.send_community.large {
+            attr.large_communities = None;
+        }
+    }
+
+    fn prefix_list(&self, policy: &Policy, name: &Option<String>, route: &Route) -> Action {
+        match name {
+            Some(name) => match policy.prefix_lists.get(name) {
+                Some(plist) => plist.apply(&route.prefix),
+                None => Action::Deny,
+            },
+            None => Action::Permit,
+        }
+    }
+
+    /// Apply the inbound prefix-list and route-map, routes are permitted
+    /// when none is set.
+    pub fn policy_in(&self, policy: &Policy, route: &mut Route) -> Action {
+        if self.prefix_list(policy, &self.prefix_list_in, route) == Action::Deny {
+            return Action::Deny;
+        }
+        match &self.route_map_in {
+            Some(name) => {
+                let ctx = PolicyContext::new(Direction::In, Some(self.ipaddr), None);
+                policy.apply(name, route, &ctx)
+            }
+            None => Action::Permit,
+        }
+    }
+
+    /// Apply the outbound prefix-list and route-map. `local` is our address
+    /// on the session.
+    pub fn policy_out(&self, policy: &Policy, route: &mut Route, local: Option<IpAddr>) -> Action {
+        if self.prefix_list(policy, &self.prefix_list_out, route) == Action::Deny {
+            return Action::Deny;
+        }
+        match &self.route_map_out {
+            Some(name) => {
+                let ctx = PolicyContext::new(Direction::Out, Some(self.ipaddr), local);
+                policy.apply(name, route, &ctx)
+            }
+            None => Action::Permit,
+        }
+    }
 }
 
 impl std::cmp::Ord for Neighbor {
@@ -74,29 +448,140 @@ impl NeighborVec {
 
 #[cfg(test)]
 mod test {
-    use super::Neighbor;
-    use super::NeighborVec;
+    use super::*;
+    use crate::bgp::{AsPath, Attr, Communities, NeighborCommand, Prefix};
     use std::net::IpAddr;
+    use std::sync::Arc;
+
+    fn configure(neighbor: &mut Neighbor, line: &str) {
+        NeighborCommand::parse(line)
+            .unwrap()
+            .apply(neighbor)
+            .unwrap();
+    }
+
+    fn route(path: &str) -> Route {
+        let mut attr = Attr::new();
+        attr.as_path = path.parse().unwrap();
+        Route::new("10.0.0.0/8".parse::<Prefix>().unwrap(), attr)
+    }
+
+    #[test]
+    fn extended_nexthop() {
+        let enhe = Capability::ExtendedNextHop(vec![(
+            Family {
+                afi: AFI_IP,
+                safi: SAFI_UNICAST,
+            },
+            AFI_IP6,
+        )]);
+        let mut n = Neighbor::new("fe80::1".parse().unwrap());
+        assert!(!n.capabilities().contains(&enhe));
+        configure(&mut n, "interface eth0");
+        assert!(n.capabilities().contains(&enhe));
+
+        let mut m = Neighbor::new("2001:db8::1".parse().unwrap());
+        configure(&mut m, "capability extended-nexthop");
+        assert!(m.capabilities().contains(&enhe));
+        configure(&mut m, "no ipv4 unicast activate");
+        assert!(!m.capabilities().contains(&enhe));
+    }
+
+    #[test]
+    fn as_loop() {
+        let mut n = Neighbor::new("192.168.0.1".parse().unwrap());
+        assert!(!n.as_loop(&route("65001 65002"), 65000));
+        assert!(n.as_loop(&route("65001 65000 65002"), 65000));
+        configure(&mut n, "allowas-in 1");
+        assert!(!n.as_loop(&route("65001 65000 65002"), 65000));
+        assert!(n.as_loop(&route("65000 65001 65000"), 65000));
+        configure(&mut n, "allowas-in origin");
+        assert!(n.as_loop(&route("65001 65000 65002"), 65000));
+        assert!(!n.as_loop(&route("65001 65000"), 65000));
+
+        // The local-as of the session is looked for.
+        configure(&mut n, "no allowas-in");
+        configure(&mut n, "local-as 65100");
+        assert!(!n.as_loop(&route("65001 65000"), 65000));
+        assert!(n.as_loop(&route("65001 65100"), 65000));
+    }
+
+    #[test]
+    fn attr() {
+        let local: IpAddr = "192.168.0.2".parse().unwrap();
+        let mut n = Neighbor::new("192.168.0.1".parse().unwrap());
+        configure(&mut n, "local-as 65100");
+        let mut r = route("65001");
+        n.attr_in(&mut r);
+        assert_eq!(*r.attr.as_path, "65100 65001".parse::<AsPath>().unwrap());
+
+        let mut r = route("65001");
+        r.attr_mut().communities = Communities::from_str("65000:1").map(Arc::new);
+        n.attr_out(&mut r, 65000, PeerType::External, Some(local));
+        assert_eq!(
+            *r.attr.as_path,
+            "65100 65000 65001".parse::<AsPath>().unwrap()
+        );
+        assert_eq!(r.attr.next_hop, Some(local));
+        assert!(r.attr.communities.is_some());
+
+        configure(&mut n, "local-as 65100 no-prepend replace-as");
+        configure(&mut n, "no send-community standard");
+        let mut r = route("65001");
+        n.attr_in(&mut r);
+        r.attr_mut().communities = Communities::from_str("65000:1").map(Arc::new);
+        n.attr_out(&mut r, 65000, PeerType::External, Some(local));
+        assert_eq!(*r.attr.as_path, "65100 65001".parse::<AsPath>().unwrap());
+        assert_eq!(r.attr.communities, None);
+
+        // Internal neighbors keep the next hop of received routes unless
+        // next-hop-self is set.
+        let mut n = Neighbor::new("192.168.0.1".parse().unwrap());
+        let mut r = route("65001");
+        r.peer = Some("192.168.0.3".parse().unwrap());
+        n.attr_out(&mut r, 65000, PeerType::Internal, Some(local));
+        assert_eq!(*r.attr.as_path, "65001".parse::<AsPath>().unwrap());
+        assert_eq!(r.attr.next_hop, None);
+        configure(&mut n, "next-hop-self");
+        n.attr_out(&mut r, 65000, PeerType::Internal, Some(local));
+        assert_eq!(r.attr.next_hop, Some(local));
+
+        // Locally originated routes always have our address.
+        let n = Neighbor::new("192.168.0.1".parse().unwrap());
+        let mut r = route("");
+        n.attr_out(&mut r, 65000, PeerType::Internal, Some(local));
+        assert_eq!(r.attr.next_hop, Some(local));
+    }
+
+    #[test]
+    fn session_changed() {
+        let n = Neighbor::new("192.168.0.1".parse().unwrap());
+        let mut m = n.clone();
+        configure(&mut m, "description upstream");
+        configure(&mut m, "prefix-list IN in");
+        assert!(!n.session_changed(&m));
+        configure(&mut m, "timers 10 30");
+        assert!(n.session_changed(&m));
+        assert_eq!(m.hold_time(90), 30);
+        assert_eq!(
+            m.keepalive(Duration::from_secs(30)),
+            Duration::from_secs(10)
+        );
+        assert_eq!(
+            n.keepalive(Duration::from_secs(90)),
+            Duration::from_secs(30)
+        );
+    }
 
     #[test]
     fn sort_add() {
         let mut v = NeighborVec::new();
 
-        let n1 = Neighbor {
-            ipaddr: "192.168.55.1".parse::<IpAddr>().unwrap(),
-        };
-        let n2 = Neighbor {
-            ipaddr: "192.168.55.2".parse().unwrap(),
-        };
-        let n3 = Neighbor {
-            ipaddr: "10.0.0.1".parse().unwrap(),
-        };
-        let n4 = Neighbor {
-            ipaddr: "192.168.55.2".parse().unwrap(),
-        };
-        let n5 = Neighbor {
-            ipaddr: "::1".parse::<IpAddr>().unwrap(),
-        };
+        let n1 = Neighbor::new("192.168.55.1".parse::<IpAddr>().unwrap());
+        let n2 = Neighbor::new("192.168.55.2".parse().unwrap());
+        let n3 = Neighbor::new("10.0.0.1".parse().unwrap());
+        let n4 = Neighbor::new("192.168.55.2".parse().unwrap());
+        let n5 = Neighbor::new("::1".parse::<IpAddr>().unwrap());
 
         assert_eq!(v.insert_sort(n1).unwrap(), ());
         assert_eq!(v.insert_sort(n2).unwrap(), ());
@@ -0,0 +1,943 @@
+#![allow(dead_code)]
+
+use super::aspath::AS_TRANS;
+use super::max_prefix::MAX_PREFIX_THRESHOLD_DEFAULT;
+use super::{AdvertiseCondition, AdvertiseMap, DampeningError, Direction, Family, MaxPrefix};
+use super::{Neighbor, PeerType};
+use super::{AFI_IP, AFI_IP6, SAFI_MPLS_VPN, SAFI_MULTICAST, SAFI_UNICAST};
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Longest TCP MD5 signature key (RFC 2385).
+pub const PASSWORD_MAX_LEN: usize = 80;
+pub const DESCRIPTION_MAX_LEN: usize = 80;
+pub const ALLOWAS_IN_MAX: u8 = 10;
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum NeighborError {
+    #[error("unknown neighbor command: {0}")]
+    Syntax(String),
+    #[error("invalid AS number {0}")]
+    InvalidAs(u32),
+    #[error("local-as {0} is the AS of the BGP instance")]
+    LocalAsSame(u32),
+    #[error("hold time {0} must be 0 or at least 3 seconds")]
+    HoldTime(u16),
+    #[error("keepalive {0} exceeds the hold time")]
+    Keepalive(u16),
+    #[error("TTL {0} out of range 1-255")]
+    Ttl(u8),
+    #[error("password longer than {} characters", PASSWORD_MAX_LEN)]
+    PasswordLength,
+    #[error("description longer than {} characters", DESCRIPTION_MAX_LEN)]
+    DescriptionLength,
+    #[error("update-source {0} is not of the neighbor's address family")]
+    UpdateSource(IpAddr),
+    #[error("maximum-prefix limit must not be 0")]
+    MaxPrefix,
+    #[error("allowas-in count {0} out of range 1-{}", ALLOWAS_IN_MAX)]
+    AllowasIn(u8),
+    #[error("neighbor {0} is not configured")]
+    NotConfigured(IpAddr),
+    #[error("peer-group {0} does not exist")]
+    UnknownPeerGroup(String),
+    #[error("template {0} does not exist")]
+    UnknownTemplate(String),
+    #[error("template {0} inherits from itself")]
+    InheritLoop(String),
+    #[error("listen limit {} out of range 1-{}", .0, super::listen_range::LISTEN_LIMIT_MAX)]
+    ListenLimit(usize),
+    #[error("dynamic neighbor limit {0} reached")]
+    DynamicLimit(usize),
+    #[error("neighbor {0} is shut down")]
+    Shutdown(IpAddr),
+    #[error("interface is only for IPv6 link-local neighbors, not {0}")]
+    Interface(IpAddr),
+    #[error(transparent)]
+    Dampening(#[from] DampeningError),
+}
+
+/// `neighbor <addr> remote-as <asn|internal|external>`. The keywords
+/// accept any AS inside or outside of the local AS.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RemoteAs {
+    Asn(u32),
+    Internal,
+    External,
+}
+
+impl RemoteAs {
+    /// Whether the AS in the neighbor's OPEN is the configured one.
+    pub fn matches(&self, asn: u32, local_as: u32) -> bool {
+        match self {
+            RemoteAs::Asn(remote) => *remote == asn,
+            RemoteAs::Internal => asn == local_as,
+            RemoteAs::External => asn != local_as,
+        }
+    }
+
+    /// Type of the session, known before the OPEN has been received.
+    pub fn peer_type(&self, local_as: u32) -> PeerType {
+        match self {
+            RemoteAs::Asn(asn) if *asn == local_as => PeerType::Internal,
+            RemoteAs::Internal => PeerType::Internal,
+            _ => PeerType::External,
+        }
+    }
+}
+
+impl fmt::Display for RemoteAs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RemoteAs::Asn(asn) => write!(f, "{}", asn),
+            RemoteAs::Internal => write!(f, "internal"),
+            RemoteAs::External => write!(f, "external"),
+        }
+    }
+}
+
+impl FromStr for RemoteAs {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "internal" => Ok(RemoteAs::Internal),
+            "external" => Ok(RemoteAs::External),
+            _ => s.parse().map(RemoteAs::Asn).map_err(|_| ()),
+        }
+    }
+}
+
+/// `neighbor <addr> local-as <asn> [no-prepend [replace-as]]`, the AS the
+/// session is brought up with instead of the instance's AS.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LocalAs {
+    pub asn: u32,
+    /// Do not prepend `asn` to the AS path of received routes.
+    pub no_prepend: bool,
+    /// Only `asn` is prepended to advertised routes, not the real AS.
+    pub replace_as: bool,
+}
+
+impl LocalAs {
+    pub fn new(asn: u32) -> Self {
+        LocalAs {
+            asn,
+            no_prepend: false,
+            replace_as: false,
+        }
+    }
+}
+
+/// `neighbor <addr> timers <keepalive> <holdtime>`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Timers {
+    pub keepalive: u16,
+    pub hold_time: u16,
+}
+
+/// Community attributes sent to a neighbor, `neighbor <addr>
+/// send-community [standard|extended|large|both|all]`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SendCommunity {
+    pub standard: bool,
+    pub extended: bool,
+    pub large: bool,
+}
+
+impl SendCommunity {
+    pub fn all() -> Self {
+        SendCommunity {
+            standard: true,
+            extended: true,
+            large: true,
+        }
+    }
+
+    fn parse(s: Option<&str>) -> Option<Self> {
+        let none = SendCommunity {
+            standard: false,
+            extended: false,
+            large: false,
+        };
+        match s {
+            Some("standard") | None => Some(SendCommunity {
+                standard: true,
+                ..none
+            }),
+            Some("extended") => Some(SendCommunity {
+                extended: true,
+                ..none
+            }),
+            Some("large") => Some(SendCommunity {
+                large: true,
+                ..none
+            }),
+            Some("both") => Some(SendCommunity {
+                standard: true,
+                extended: true,
+                ..none
+            }),
+            Some("all") => Some(SendCommunity::all()),
+            Some(_) => None,
+        }
+    }
+
+    /// One value per community type set in `self`.
+    fn split(self) -> Vec<SendCommunity> {
+        let none = SendCommunity {
+            standard: false,
+            extended: false,
+            large: false,
+        };
+        let mut split = Vec::new();
+        if self.standard {
+            split.push(SendCommunity {
+                standard: true,
+                ..none
+            });
+        }
+        if self.extended {
+            split.push(SendCommunity {
+                extended: true,
+                ..none
+            });
+        }
+        if self.large {
+            split.push(SendCommunity {
+                large: true,
+                ..none
+            });
+        }
+        split
+    }
+
+    fn contains(&self, other: SendCommunity) -> bool {
+        (!other.standard || self.standard)
+            && (!other.extended || self.extended)
+            && (!other.large || self.large)
+    }
+
+    fn set(&mut self, other: SendCommunity, on: bool) {
+        if other.standard {
+            self.standard = on;
+        }
+        if other.extended {
+            self.extended = on;
+        }
+        if other.large {
+            self.large = on;
+        }
+    }
+}
+
+impl fmt::Display for SendCommunity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.standard, self.extended, self.large) {
+            (true, false, false) => write!(f, "standard"),
+            (false, true, false) => write!(f, "extended"),
+            (false, false, true) => write!(f, "large"),
+            (true, true, false) => write!(f, "both"),
+            _ => write!(f, "all"),
+        }
+    }
+}
+
+impl Default for SendCommunity {
+    fn default() -> Self {
+        SendCommunity::all()
+    }
+}
+
+/// `neighbor <addr> allowas-in [<count>|origin]`, accept routes with the
+/// local AS in the AS path.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AllowasIn {
+    /// The local AS may appear up to this many times.
+    Count(u8),
+    /// The local AS may appear only as the origin AS.
+    Origin,
+}
+
+impl AllowasIn {
+    /// Whether `count` occurrences of the local AS are accepted, `origin`
+    /// is set when the local AS originated the route.
+    pub fn allows(&self, count: usize, origin: bool) -> bool {
+        match self {
+            AllowasIn::Count(max) => count <= *max as usize,
+            AllowasIn::Origin => origin,
+        }
+    }
+}
+
+/// Parse `<afi> <safi>` as in `ipv4 unicast` or `ipv6 vpn`.
+pub fn parse_family(afi: &str, safi: &str) -> Option<Family> {
+    let afi = match afi {
+        "ipv4" => AFI_IP,
+        "ipv6" => AFI_IP6,
+        _ => return None,
+    };
+    let safi = match safi {
+        "unicast" => SAFI_UNICAST,
+        "multicast" => SAFI_MULTICAST,
+        "vpn" => SAFI_MPLS_VPN,
+        _ => return None,
+    };
+    Some(Family { afi, safi })
+}
+
+/// Whether `addr` is an IPv6 link-local address, fe80::/10.
+pub fn is_link_local(addr: &IpAddr) -> bool {
+    match addr {
+        IpAddr::V6(addr) => addr.segments()[0] & 0xffc0 == 0xfe80,
+        IpAddr::V4(_) => false,
+    }
+}
+
+/// `<afi> <safi>` of `family` as accepted by `parse_family`.
+pub fn family_name(family: &Family) -> String {
+    let afi = match family.afi {
+        AFI_IP => "ipv4".to_string(),
+        AFI_IP6 => "ipv6".to_string(),
+        afi => format!("afi-{}", afi),
+    };
+    let safi = match family.safi {
+        SAFI_UNICAST => "unicast".to_string(),
+        SAFI_MULTICAST => "multicast".to_string(),
+        SAFI_MPLS_VPN => "vpn".to_string(),
+        safi => format!("safi-{}", safi),
+    };
+    format!("{} {}", afi, safi)
+}
+
+/// One `neighbor <addr> ...` configuration line. `None` values are the
+/// `no` form which returns to the default.
+#[derive(Clone, Debug, PartialEq)]
+pub enum NeighborCommand {
+    RemoteAs(RemoteAs),
+    LocalAs(Option<LocalAs>),
+    Description(Option<String>),
+    UpdateSource(Option<IpAddr>),
+    EbgpMultihop(Option<u8>),
+    Timers(Option<Timers>),
+    Password(Option<String>),
+    Shutdown(bool),
+    Activate(Family, bool),
+    RouteMap(Direction, Option<String>),
+    PrefixList(Direction, Option<String>),
+    MaxPrefix(Family, Option<MaxPrefix>),
+    NextHopSelf(bool),
+    SendCommunity(SendCommunity, bool),
+    AllowasIn(Option<AllowasIn>),
+    AdvertiseMap(Option<AdvertiseMap>),
+    /// Interface of a link-local neighbor.
+    Interface(Option<String>),
+    CapabilityExtendedNexthop(bool),
+    /// Make the neighbor a member of the peer-group.
+    PeerGroup(Option<String>),
+    /// Inherit the settings of a template, for peer-groups and templates.
+    Inherit(Option<String>),
+}
+
+fn direction(s: Option<&str>) -> Option<Direction> {
+    match s? {
+        "in" => Some(Direction::In),
+        "out" => Some(Direction::Out),
+        _ => None,
+    }
+}
+
+impl NeighborCommand {
+    /// Parse the part of a configuration line after `neighbor <addr>`.
+    /// Address family specific commands take an optional `<afi> <safi>`
+    /// prefix, IPv4 unicast when it is omitted.
+    pub fn parse(line: &str) -> Result<NeighborCommand, NeighborError> {
+        let syntax = || NeighborError::Syntax(line.to_string());
+        let mut words: Vec<&str> = line.split_whitespace().collect();
+        let no = words.first() == Some(&"no");
+        if no {
+            words.remove(0);
+        }
+        let family = match words.as_slice() {
+            [afi, safi, ..] => parse_family(afi, safi),
+            _ => None,
+        };
+        if family.is_some() {
+            words.drain(..2);
+        }
+        let family = family.unwrap_or(Family {
+            afi: AFI_IP,
+            safi: SAFI_UNICAST,
+        });
+        let cmd = match (no, words.as_slice()) {
+            (false, ["remote-as", asn]) => {
+                NeighborCommand::RemoteAs(asn.parse().map_err(|_| syntax())?)
+            }
+            (false, ["local-as", asn, opts @ ..]) => {
+                let mut local = LocalAs::new(asn.parse().map_err(|_| syntax())?);
+                match opts {
+                    [] => {}
+                    ["no-prepend"] => local.no_prepend = true,
+                    ["no-prepend", "replace-as"] => {
+                        local.no_prepend = true;
+                        local.replace_as = true;
+                    }
+                    _ => return Err(syntax()),
+                }
+                NeighborCommand::LocalAs(Some(local))
+            }
+            (true, ["local-as", ..]) => NeighborCommand::LocalAs(None),
+            (false, ["description", text @ ..]) if !text.is_empty() => {
+                NeighborCommand::Description(Some(text.join(" ")))
+            }
+            (true, ["description", ..]) => NeighborCommand::Description(None),
+            (false, ["update-source", addr]) => {
+                NeighborCommand::UpdateSource(Some(addr.parse().map_err(|_| syntax())?))
+            }
+            (true, ["update-source", ..]) => NeighborCommand::UpdateSource(None),
+            (false, ["ebgp-multihop"]) => NeighborCommand::EbgpMultihop(Some(255)),
+            (false, ["ebgp-multihop", ttl]) => {
+                NeighborCommand::EbgpMultihop(Some(ttl.parse().map_err(|_| syntax())?))
+            }
+            (true, ["ebgp-multihop", ..]) => NeighborCommand::EbgpMultihop(None),
+            (false, ["timers", keepalive, hold_time]) => NeighborCommand::Timers(Some(Timers {
+                keepalive: keepalive.parse().map_err(|_| syntax())?,
+                hold_time: hold_time.parse().map_err(|_| syntax())?,
+            })),
+            (true, ["timers", ..]) => NeighborCommand::Timers(None),
+            (false, ["password", password]) => {
+                NeighborCommand::Password(Some(password.to_string()))
+            }
+            (true, ["password", ..]) => NeighborCommand::Password(None),
+            (no, ["shutdown"]) => NeighborCommand::Shutdown(!no),
+            (no, ["activate"]) => NeighborCommand::Activate(family, !no),
+            (no, ["route-map", name, dir]) => NeighborCommand::RouteMap(
+                direction(Some(dir)).ok_or_else(syntax)?,
+                if no { None } else { Some(name.to_string()) },
+            ),
+            (true, ["route-map", dir]) => {
+                NeighborCommand::RouteMap(direction(Some(dir)).ok_or_else(syntax)?, None)
+            }
+            (no, ["prefix-list", name, dir]) => NeighborCommand::PrefixList(
+                direction(Some(dir)).ok_or_else(syntax)?,
+                if no { None } else { Some(name.to_string()) },
+            ),
+            (true, ["prefix-list", dir]) => {
+                NeighborCommand::PrefixList(direction(Some(dir)).ok_or_else(syntax)?, None)
+            }
+            (false, ["maximum-prefix", limit, opts @ ..]) => {
+                let mut max = MaxPrefix::new(limit.parse().map_err(|_| syntax())?);
+                let mut opts = opts;
+                if let [threshold, rest @ ..] = opts {
+                    if let Ok(threshold) = threshold.parse() {
+                        max.threshold = threshold;
+                        opts = rest;
+                    }
+                }
+                match opts {
+                    [] => {}
+                    ["warning-only"] => max.warning_only = true,
+                    ["restart", minutes] => {
+                        let minutes: u64 = minutes.parse().map_err(|_| syntax())?;
+                        max.restart = Some(Duration::from_secs(minutes * 60));
+                    }
+                    _ => return Err(syntax()),
+                }
+                NeighborCommand::MaxPrefix(family, Some(max))
+            }
+            (true, ["maximum-prefix", ..]) => NeighborCommand::MaxPrefix(family, None),
+            (no, ["next-hop-self"]) => NeighborCommand::NextHopSelf(!no),
+            (no, ["send-community", opts @ ..]) if opts.len() <= 1 => {
+                let send = SendCommunity::parse(opts.first().copied()).ok_or_else(syntax)?;
+                NeighborCommand::SendCommunity(send, !no)
+            }
+            (false, ["allowas-in"]) => NeighborCommand::AllowasIn(Some(AllowasIn::Count(3))),
+            (false, ["allowas-in", "origin"]) => {
+                NeighborCommand::AllowasIn(Some(AllowasIn::Origin))
+            }
+            (false, ["allowas-in", count]) => NeighborCommand::AllowasIn(Some(AllowasIn::Count(
+                count.parse().map_err(|_| syntax())?,
+            ))),
+            (true, ["allowas-in", ..]) => NeighborCommand::AllowasIn(None),
+            (false, ["advertise-map", name, kind, condition]) => {
+                let condition = match *kind {
+                    "exist-map" => AdvertiseCondition::Exist(condition.to_string()),
+                    "non-exist-map" => AdvertiseCondition::NonExist(condition.to_string()),
+                    _ => return Err(syntax()),
+                };
+                NeighborCommand::AdvertiseMap(Some(AdvertiseMap::new(name, condition)))
+            }
+            (true, ["advertise-map", ..]) => NeighborCommand::AdvertiseMap(None),
+            (false, ["interface", name]) => NeighborCommand::Interface(Some(name.to_string())),
+            (true, ["interface", ..]) => NeighborCommand::Interface(None),
+            (no, ["capability", "extended-nexthop"]) => {
+                NeighborCommand::CapabilityExtendedNexthop(!no)
+            }
+            (false, ["peer-group", name]) => NeighborCommand::PeerGroup(Some(name.to_string())),
+            (true, ["peer-group", ..]) => NeighborCommand::PeerGroup(None),
+            (false, ["inherit", name]) => NeighborCommand::Inherit(Some(name.to_string())),
+            (true, ["inherit", ..]) => NeighborCommand::Inherit(None),
+            _ => return Err(syntax()),
+        };
+        Ok(cmd)
+    }
+
+    /// Check values which are invalid whatever else is configured.
+    pub fn validate(&self, neighbor: &Neighbor) -> Result<(), NeighborError> {
+        let valid_as = |asn: u32| {
+            if asn == 0 || asn == AS_TRANS {
+                Err(NeighborError::InvalidAs(asn))
+            } else {
+                Ok(())
+            }
+        };
+        match self {
+            NeighborCommand::RemoteAs(RemoteAs::Asn(asn)) => valid_as(*asn),
+            NeighborCommand::LocalAs(Some(local)) => valid_as(local.asn),
+            NeighborCommand::Description(Some(text)) if text.len() > DESCRIPTION_MAX_LEN => {
+                Err(NeighborError::DescriptionLength)
+            }
+            NeighborCommand::UpdateSource(Some(addr))
+                if addr.is_ipv4() != neighbor.ipaddr.is_ipv4() =>
+            {
+                Err(NeighborError::UpdateSource(*addr))
+            }
+            NeighborCommand::EbgpMultihop(Some(0)) => Err(NeighborError::Ttl(0)),
+            NeighborCommand::Timers(Some(timers)) => {
+                if timers.hold_time == 1 || timers.hold_time == 2 {
+                    Err(NeighborError::HoldTime(timers.hold_time))
+                } else if timers.keepalive > timers.hold_time {
+                    Err(NeighborError::Keepalive(timers.keepalive))
+                } else {
+                    Ok(())
+                }
+            }
+            NeighborCommand::Password(Some(password)) if password.len() > PASSWORD_MAX_LEN => {
+                Err(NeighborError::PasswordLength)
+            }
+            NeighborCommand::MaxPrefix(_, Some(max)) if max.limit == 0 => {
+                Err(NeighborError::MaxPrefix)
+            }
+            NeighborCommand::AllowasIn(Some(AllowasIn::Count(count)))
+                if *count == 0 || *count > ALLOWAS_IN_MAX =>
+            {
+                Err(NeighborError::AllowasIn(*count))
+            }
+            NeighborCommand::Interface(Some(_)) if !is_link_local(&neighbor.ipaddr) => {
+                Err(NeighborError::Interface(neighbor.ipaddr))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Validate and apply the command to `neighbor`.
+    pub fn apply(&self, neighbor: &mut Neighbor) -> Result<(), NeighborError> {
+        self.validate(neighbor)?;
+        self.set(neighbor);
+        Ok(())
+    }
+
+    /// Apply the command to `neighbor` without validating it. Peer-group
+    /// membership and inheritance are not settings of the neighbor.
+    pub fn set(&self, neighbor: &mut Neighbor) {
+        match self.clone() {
+            NeighborCommand::RemoteAs(remote_as) => neighbor.remote_as = Some(remote_as),
+            NeighborCommand::LocalAs(local_as) => neighbor.local_as = local_as,
+            NeighborCommand::Description(text) => neighbor.description = text,
+            NeighborCommand::UpdateSource(addr) => neighbor.update_source = addr,
+            NeighborCommand::EbgpMultihop(ttl) => neighbor.ebgp_multihop = ttl,
+            NeighborCommand::Timers(timers) => neighbor.timers = timers,
+            NeighborCommand::Password(password) => neighbor.password = password,
+            NeighborCommand::Shutdown(shutdown) => neighbor.shutdown = shutdown,
+            NeighborCommand::Activate(family, true) => {
+                neighbor.activate.insert(family);
+            }
+            NeighborCommand::Activate(family, false) => {
+                neighbor.activate.remove(&family);
+            }
+            NeighborCommand::RouteMap(Direction::In, name) => neighbor.route_map_in = name,
+            NeighborCommand::RouteMap(Direction::Out, name) => neighbor.route_map_out = name,
+            NeighborCommand::PrefixList(Direction::In, name) => neighbor.prefix_list_in = name,
+            NeighborCommand::PrefixList(Direction::Out, name) => neighbor.prefix_list_out = name,
+            NeighborCommand::MaxPrefix(family, Some(max)) => {
+                neighbor.max_prefix.insert(family, max);
+            }
+            NeighborCommand::MaxPrefix(family, None) => {
+                neighbor.max_prefix.remove(&family);
+            }
+            NeighborCommand::NextHopSelf(on) => neighbor.next_hop_self = on,
+            NeighborCommand::SendCommunity(send, on) => neighbor.send_community.set(send, on),
+            NeighborCommand::AllowasIn(allowas_in) => neighbor.allowas_in = allowas_in,
+            NeighborCommand::AdvertiseMap(map) => neighbor.advertise_map = map,
+            NeighborCommand::Interface(name) => neighbor.interface = name,
+            NeighborCommand::CapabilityExtendedNexthop(on) => neighbor.extended_nexthop = on,
+            NeighborCommand::PeerGroup(_) | NeighborCommand::Inherit(_) => {}
+        }
+    }
+
+    /// The setting changed by the command, commands with the same key
+    /// replace each other.
+    pub fn key(&self) -> String {
+        match self {
+            NeighborCommand::RemoteAs(_) => "remote-as".to_string(),
+            NeighborCommand::LocalAs(_) => "local-as".to_string(),
+            NeighborCommand::Description(_) => "description".to_string(),
+            NeighborCommand::UpdateSource(_) => "update-source".to_string(),
+            NeighborCommand::EbgpMultihop(_) => "ebgp-multihop".to_string(),
+            NeighborCommand::Timers(_) => "timers".to_string(),
+            NeighborCommand::Password(_) => "password".to_string(),
+            NeighborCommand::Shutdown(_) => "shutdown".to_string(),
+            NeighborCommand::Activate(family, _) => format!("{} activate", family_name(family)),
+            NeighborCommand::RouteMap(dir, _) => format!("route-map {}", direction_name(*dir)),
+            NeighborCommand::PrefixList(dir, _) => {
+                format!("prefix-list {}", direction_name(*dir))
+            }
+            NeighborCommand::MaxPrefix(family, _) => {
+                format!("{} maximum-prefix", family_name(family))
+            }
+            NeighborCommand::NextHopSelf(_) => "next-hop-self".to_string(),
+            NeighborCommand::SendCommunity(send, _) => format!("send-community {}", send),
+            NeighborCommand::AllowasIn(_) => "allowas-in".to_string(),
+            NeighborCommand::AdvertiseMap(_) => "advertise-map".to_string(),
+            NeighborCommand::Interface(_) => "interface".to_string(),
+            NeighborCommand::CapabilityExtendedNexthop(_) => {
+                "capability extended-nexthop".to_string()
+            }
+            NeighborCommand::PeerGroup(_) => "peer-group".to_string(),
+            NeighborCommand::Inherit(_) => "inherit".to_string(),
+        }
+    }
+
+    /// The command setting `neighbor`'s current value of the same key.
+    pub fn current(&self, neighbor: &Neighbor) -> Option<NeighborCommand> {
+        let cmd = match self {
+            NeighborCommand::RemoteAs(_) => NeighborCommand::RemoteAs(neighbor.remote_as?),
+            NeighborCommand::LocalAs(_) => NeighborCommand::LocalAs(neighbor.local_as),
+            NeighborCommand::Description(_) => {
+                NeighborCommand::Description(neighbor.description.clone())
+            }
+            NeighborCommand::UpdateSource(_) => {
+                NeighborCommand::UpdateSource(neighbor.update_source)
+            }
+            NeighborCommand::EbgpMultihop(_) => {
+                NeighborCommand::EbgpMultihop(neighbor.ebgp_multihop)
+            }
+            NeighborCommand::Timers(_) => NeighborCommand::Timers(neighbor.timers),
+            NeighborCommand::Password(_) => NeighborCommand::Password(neighbor.password.clone()),
+            NeighborCommand::Shutdown(_) => NeighborCommand::Shutdown(neighbor.shutdown),
+            NeighborCommand::Activate(family, _) => {
+                NeighborCommand::Activate(*family, neighbor.activate.contains(family))
+            }
+            NeighborCommand::RouteMap(Direction::In, _) => {
+                NeighborCommand::RouteMap(Direction::In, neighbor.route_map_in.clone())
+            }
+            NeighborCommand::RouteMap(Direction::Out, _) => {
+                NeighborCommand::RouteMap(Direction::Out, neighbor.route_map_out.clone())
+            }
+            NeighborCommand::PrefixList(Direction::In, _) => {
+                NeighborCommand::PrefixList(Direction::In, neighbor.prefix_list_in.clone())
+            }
+            NeighborCommand::PrefixList(Direction::Out, _) => {
+                NeighborCommand::PrefixList(Direction::Out, neighbor.prefix_list_out.clone())
+            }
+            NeighborCommand::MaxPrefix(family, _) => {
+                NeighborCommand::MaxPrefix(*family, neighbor.max_prefix.get(family).cloned())
+            }
+            NeighborCommand::NextHopSelf(_) => NeighborCommand::NextHopSelf(neighbor.next_hop_self),
+            NeighborCommand::SendCommunity(send, _) => {
+                NeighborCommand::SendCommunity(*send, neighbor.send_community.contains(*send))
+            }
+            NeighborCommand::AllowasIn(_) => NeighborCommand::AllowasIn(neighbor.allowas_in),
+            NeighborCommand::AdvertiseMap(_) => {
+                NeighborCommand::AdvertiseMap(neighbor.advertise_map.clone())
+            }
+            NeighborCommand::Interface(_) => NeighborCommand::Interface(neighbor.interface.clone()),
+            NeighborCommand::CapabilityExtendedNexthop(_) => {
+                NeighborCommand::CapabilityExtendedNexthop(neighbor.extended_nexthop)
+            }
+            NeighborCommand::PeerGroup(_) | NeighborCommand::Inherit(_) => return None,
+        };
+        Some(cmd)
+    }
+
+    /// Whether this is the `no` form of the command.
+    pub fn is_negation(&self) -> bool {
+        matches!(
+            self,
+            NeighborCommand::LocalAs(None)
+                | NeighborCommand::Description(None)
+                | NeighborCommand::UpdateSource(None)
+                | NeighborCommand::EbgpMultihop(None)
+                | NeighborCommand::Timers(None)
+                | NeighborCommand::Password(None)
+                | NeighborCommand::Shutdown(false)
+                | NeighborCommand::Activate(_, false)
+                | NeighborCommand::RouteMap(_, None)
+                | NeighborCommand::PrefixList(_, None)
+                | NeighborCommand::MaxPrefix(_, None)
+                | NeighborCommand::NextHopSelf(false)
+                | NeighborCommand::SendCommunity(_, false)
+                | NeighborCommand::AllowasIn(None)
+                | NeighborCommand::AdvertiseMap(None)
+                | NeighborCommand::Interface(None)
+                | NeighborCommand::CapabilityExtendedNexthop(false)
+                | NeighborCommand::PeerGroup(None)
+                | NeighborCommand::Inherit(None)
+        )
+    }
+
+    /// Split into commands changing a single key each, only `send-community
+    /// both|all` changes several.
+    pub fn split(self) -> Vec<NeighborCommand> {
+        match self {
+            NeighborCommand::SendCommunity(send, on) => send
+                .split()
+                .into_iter()
+                .map(|send| NeighborCommand::SendCommunity(send, on))
+                .collect(),
+            cmd => vec![cmd],
+        }
+    }
+}
+
+fn direction_name(dir: Direction) -> &'static str {
+    match dir {
+        Direction::In => "in",
+        Direction::Out => "out",
+    }
+}
+
+/// The configuration line, as accepted by `NeighborCommand::parse`.
+impl fmt::Display for NeighborCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let no = if self.is_negation() { "no " } else { "" };
+        match self {
+            NeighborCommand::RemoteAs(remote_as) => write!(f, "remote-as {}", remote_as),
+            NeighborCommand::LocalAs(Some(local)) => {
+                write!(f, "local-as {}", local.asn)?;
+                if local.no_prepend {
+                    write!(f, " no-prepend")?;
+                }
+                if local.replace_as {
+                    write!(f, " replace-as")?;
+                }
+                Ok(())
+            }
+            NeighborCommand::Description(Some(text)) => write!(f, "description {}", text),
+            NeighborCommand::UpdateSource(Some(addr)) => write!(f, "update-source {}", addr),
+            NeighborCommand::EbgpMultihop(Some(ttl)) => write!(f, "ebgp-multihop {}", ttl),
+            NeighborCommand::Timers(Some(timers)) => {
+                write!(f, "timers {} {}", timers.keepalive, timers.hold_time)
+            }
+            NeighborCommand::Password(Some(password)) => write!(f, "password {}", password),
+            NeighborCommand::Activate(family, _) => {
+                write!(f, "{}{} activate", no, family_name(family))
+            }
+            NeighborCommand::RouteMap(dir, Some(name)) => {
+                write!(f, "route-map {} {}", name, direction_name(*dir))
+            }
+            NeighborCommand::PrefixList(dir, Some(name)) => {
+                write!(f, "prefix-list {} {}", name, direction_name(*dir))
+            }
+            NeighborCommand::MaxPrefix(family, Some(max)) => {
+                write!(f, "{} maximum-prefix {}", family_name(family), max.limit)?;
+                if max.threshold != MAX_PREFIX_THRESHOLD_DEFAULT {
+                    write!(f, " {}", max.threshold)?;
+                }
+                if max.warning_only {
+                    write!(f, " warning-only")?;
+                }
+                if let Some(restart) = max.restart {
+                    write!(f, " restart {}", restart.as_secs() / 60)?;
+                }
+                Ok(())
+            }
+            NeighborCommand::MaxPrefix(family, None) => {
+                write!(f, "no {} maximum-prefix", family_name(family))
+            }
+            NeighborCommand::SendCommunity(send, _) => write!(f, "{}send-community {}", no, send),
+            NeighborCommand::AllowasIn(Some(AllowasIn::Count(count))) => {
+                write!(f, "allowas-in {}", count)
+            }
+            NeighborCommand::AllowasIn(Some(AllowasIn::Origin)) => write!(f, "allowas-in origin"),
+            NeighborCommand::AdvertiseMap(Some(map)) => write!(f, "{}", map),
+            NeighborCommand::Interface(Some(name)) => write!(f, "interface {}", name),
+            NeighborCommand::PeerGroup(Some(name)) => write!(f, "peer-group {}", name),
+            NeighborCommand::Inherit(Some(name)) => write!(f, "inherit {}", name),
+            NeighborCommand::RouteMap(dir, None) => {
+                write!(f, "no route-map {}", direction_name(*dir))
+            }
+            NeighborCommand::PrefixList(dir, None) => {
+                write!(f, "no prefix-list {}", direction_name(*dir))
+            }
+            cmd => write!(f, "{}{}", no, cmd.key()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn neighbor() -> Neighbor {
+        Neighbor::new("192.168.0.1".parse().unwrap())
+    }
+
+    fn apply(neighbor: &mut Neighbor, line: &str) -> Result<(), NeighborError> {
+        NeighborCommand::parse(line)?.apply(neighbor)
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            NeighborCommand::parse("remote-as internal"),
+            Ok(NeighborCommand::RemoteAs(RemoteAs::Internal))
+        );
+        assert_eq!(
+            NeighborCommand::parse("local-as 65010 no-prepend replace-as"),
+            Ok(NeighborCommand::LocalAs(Some(LocalAs {
+                asn: 65010,
+                no_prepend: true,
+                replace_as: true
+            })))
+        );
+        assert!(NeighborCommand::parse("local-as 65010 replace-as").is_err());
+        assert_eq!(
+            NeighborCommand::parse("description core  router 1"),
+            Ok(NeighborCommand::Description(Some(
+                "core router 1".to_string()
+            )))
+        );
+        assert_eq!(
+            NeighborCommand::parse("no shutdown"),
+            Ok(NeighborCommand::Shutdown(false))
+        );
+        assert_eq!(
+            NeighborCommand::parse("ipv6 unicast activate"),
+            Ok(NeighborCommand::Activate(
+                Family {
+                    afi: AFI_IP6,
+                    safi: SAFI_UNICAST
+                },
+                true
+            ))
+        );
+        assert_eq!(
+            NeighborCommand::parse("no route-map out"),
+            Ok(NeighborCommand::RouteMap(Direction::Out, None))
+        );
+        let mut max = MaxPrefix::new(1000);
+        max.threshold = 90;
+        max.restart = Some(Duration::from_secs(300));
+        assert_eq!(
+            NeighborCommand::parse("maximum-prefix 1000 90 restart 5"),
+            Ok(NeighborCommand::MaxPrefix(
+                Family {
+                    afi: AFI_IP,
+                    safi: SAFI_UNICAST
+                },
+                Some(max)
+            ))
+        );
+        assert_eq!(
+            NeighborCommand::parse("allowas-in origin"),
+            Ok(NeighborCommand::AllowasIn(Some(AllowasIn::Origin)))
+        );
+        let line = "advertise-map OURS non-exist-map PRIMARY";
+        let cmd = NeighborCommand::parse(line).unwrap();
+        assert_eq!(cmd.to_string(), line);
+        assert!(NeighborCommand::parse("advertise-map OURS PRIMARY").is_err());
+        assert_eq!(
+            NeighborCommand::parse("no advertise-map"),
+            Ok(NeighborCommand::AdvertiseMap(None))
+        );
+        assert_eq!(
+            NeighborCommand::parse("remote-as foo"),
+            Err(NeighborError::Syntax("remote-as foo".to_string()))
+        );
+    }
+
+    #[test]
+    fn validate() {
+        let mut n = neighbor();
+        assert_eq!(
+            apply(&mut n, "remote-as 23456"),
+            Err(NeighborError::InvalidAs(23456))
+        );
+        assert_eq!(
+            apply(&mut n, "timers 10 2"),
+            Err(NeighborError::HoldTime(2))
+        );
+        assert_eq!(
+            apply(&mut n, "timers 60 30"),
+            Err(NeighborError::Keepalive(60))
+        );
+        assert_eq!(
+            apply(&mut n, "update-source 2001:db8::1"),
+            Err(NeighborError::UpdateSource("2001:db8::1".parse().unwrap()))
+        );
+        assert_eq!(
+            apply(&mut n, &format!("password {}", "x".repeat(81))),
+            Err(NeighborError::PasswordLength)
+        );
+        assert_eq!(apply(&mut n, "ebgp-multihop 0"), Err(NeighborError::Ttl(0)));
+        assert_eq!(
+            apply(&mut n, "allowas-in 11"),
+            Err(NeighborError::AllowasIn(11))
+        );
+        assert_eq!(
+            apply(&mut n, "maximum-prefix 0"),
+            Err(NeighborError::MaxPrefix)
+        );
+        assert_eq!(
+            apply(&mut n, "interface eth0"),
+            Err(NeighborError::Interface(n.ipaddr))
+        );
+        // Nothing was changed by the rejected commands.
+        assert_eq!(n.remote_as, None);
+        assert_eq!(n.timers, None);
+        assert!(n.max_prefix.is_empty());
+    }
+
+    #[test]
+    fn apply_commands() {
+        let mut n = neighbor();
+        apply(&mut n, "remote-as 65001").unwrap();
+        apply(&mut n, "ebgp-multihop").unwrap();
+        apply(&mut n, "ipv6 unicast activate").unwrap();
+        apply(&mut n, "no ipv4 unicast activate").unwrap();
+        apply(&mut n, "no send-community large").unwrap();
+        apply(&mut n, "prefix-list CUSTOMER in").unwrap();
+        assert_eq!(n.remote_as, Some(RemoteAs::Asn(65001)));
+        assert_eq!(n.ebgp_multihop, Some(255));
+        assert_eq!(
+            n.activate.iter().collect::<Vec<_>>(),
+            vec![&Family {
+                afi: AFI_IP6,
+                safi: SAFI_UNICAST
+            }]
+        );
+        assert!(n.send_community.standard && n.send_community.extended);
+        assert!(!n.send_community.large);
+        assert_eq!(n.prefix_list_in.as_deref(), Some("CUSTOMER"));
+
+        apply(&mut n, "no prefix-list CUSTOMER in").unwrap();
+        apply(&mut n, "no ebgp-multihop").unwrap();
+        assert_eq!(n.prefix_list_in, None);
+        assert_eq!(n.ebgp_multihop, None);
+    }
+
+    #[test]
+    fn remote_as() {
+        assert!(RemoteAs::Internal.matches(65000, 65000));
+        assert!(!RemoteAs::Internal.matches(65001, 65000));
+        assert!(RemoteAs::External.matches(65001, 65000));
+        assert!(RemoteAs::Asn(65001).matches(65001, 65000));
+        assert_eq!(RemoteAs::Asn(65000).peer_type(65000), PeerType::Internal);
+        assert_eq!(RemoteAs::External.peer_type(65000), PeerType::External);
+        assert_eq!("internal".parse(), Ok(RemoteAs::Internal));
+        assert_eq!(RemoteAs::Asn(65001).to_string(), "65001");
+    }
+}
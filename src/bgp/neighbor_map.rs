@@ -15,6 +15,10 @@ impl NeighborMap {
         self.0.insert(key, value)
     }
 
+    pub fn remove(&mut self, key: &IpAddr) -> Option<Neighbor> {
+        self.0.remove(key)
+    }
+
     pub fn get(&self, key: &std::net::IpAddr) -> Option<&Neighbor> {
         self.0.get(key)
     }
@@ -34,21 +38,11 @@ mod test {
     fn insert() {
         let mut v = NeighborMap::new();
 
-        let n1 = Neighbor {
-            ipaddr: "192.168.55.1".parse::<IpAddr>().unwrap(),
-        };
-        let n2 = Neighbor {
-            ipaddr: "192.168.55.2".parse().unwrap(),
-        };
-        let n3 = Neighbor {
-            ipaddr: "10.0.0.1".parse().unwrap(),
-        };
-        let n4 = Neighbor {
-            ipaddr: "192.168.55.2".parse().unwrap(),
-        };
-        let n5 = Neighbor {
-            ipaddr: "::1".parse::<IpAddr>().unwrap(),
-        };
+        let n1 = Neighbor::new("192.168.55.1".parse::<IpAddr>().unwrap());
+        let n2 = Neighbor::new("192.168.55.2".parse().unwrap());
+        let n3 = Neighbor::new("10.0.0.1".parse().unwrap());
+        let n4 = Neighbor::new("192.168.55.2".parse().unwrap());
+        let n5 = Neighbor::new("::1".parse::<IpAddr>().unwrap());
 
         if let Some(_) = v.insert(n1.ipaddr, n1) {
             panic!("n1 is already inserted");
@@ -85,8 +79,8 @@ mod test {
         let mut v = NeighborMap::new();
         let addr1: IpAddr = "192.168.55.1".parse::<IpAddr>().unwrap();
         let addr2: IpAddr = "192.168.55.1".parse::<IpAddr>().unwrap();
-        let n1 = Neighbor { ipaddr: addr1 };
-        let n2 = Neighbor { ipaddr: addr2 };
+        let n1 = Neighbor::new(addr1);
+        let n2 = Neighbor::new(addr2);
 
         let ret = v.insert(addr1, n1);
         match ret {
@@ -0,0 +1,200 @@
+#![allow(dead_code)]
+
+use super::redistribute::LOCAL_ROUTE_WEIGHT;
+use super::{Action, Attr, NeighborError, Origin, Policy, PolicyContext, Prefix, Route};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// `network <prefix> [route-map <name>] [backdoor]`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Network {
+    pub prefix: Prefix,
+    pub route_map: Option<String>,
+    /// The prefix is reachable through the IGP as well. It is not
+    /// advertised, and eBGP routes for it are not installed so that the IGP
+    /// route is used.
+    pub backdoor: bool,
+}
+
+impl Network {
+    pub fn new(prefix: Prefix) -> Self {
+        Network {
+            prefix,
+            route_map: None,
+            backdoor: false,
+        }
+    }
+
+    /// Parse a `network ...` configuration line.
+    pub fn parse(line: &str) -> Result<Network, NeighborError> {
+        let syntax = || NeighborError::Syntax(line.to_string());
+        let mut words = line.split_whitespace();
+        if words.next() != Some("network") {
+            return Err(syntax());
+        }
+        let prefix = words.next().ok_or_else(syntax)?;
+        let mut network = Network::new(prefix.parse().map_err(|_| syntax())?);
+        while let Some(word) = words.next() {
+            match word {
+                "route-map" => {
+                    network.route_map = Some(words.next().ok_or_else(syntax)?.to_string())
+                }
+                "backdoor" => network.backdoor = true,
+                _ => return Err(syntax()),
+            }
+        }
+        Ok(network)
+    }
+
+    /// Build the locally originated route, or `None` when the route-map
+    /// denies it.
+    pub fn route(&self, policy: &Policy) -> Option<Route> {
+        let mut attr = Attr::new();
+        attr.origin = Origin::Igp;
+
+        let mut route = Route::new(self.prefix, attr);
+        route.weight = LOCAL_ROUTE_WEIGHT;
+
+        if let Some(name) = &self.route_map {
+            if policy.apply(name, &mut route, &PolicyContext::local()) == Action::Deny {
+                return None;
+            }
+        }
+        Some(route)
+    }
+}
+
+/// The network statements of a BGP instance.
+#[derive(Clone, Debug)]
+pub struct Networks {
+    networks: BTreeMap<Prefix, Network>,
+    /// `bgp network import-check`, only originate a network when the system
+    /// RIB has a route for exactly that prefix. On by default.
+    pub import_check: bool,
+}
+
+impl Default for Networks {
+    fn default() -> Self {
+        Networks {
+            networks: BTreeMap::new(),
+            import_check: true,
+        }
+    }
+}
+
+impl Networks {
+    pub fn new() -> Self {
+        Networks::default()
+    }
+
+    /// Apply a `[no] network ...` or `[no] bgp network import-check`
+    /// configuration line. A network replaces the one of the same prefix.
+    pub fn configure(&mut self, line: &str) -> Result<(), NeighborError> {
+        let (no, rest) = match line.strip_prefix("no ") {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        if rest
+            .split_whitespace()
+            .eq(["bgp", "network", "import-check"])
+        {
+            self.import_check = !no;
+            return Ok(());
+        }
+        let network = Network::parse(rest)?;
+        if no {
+            self.networks.remove(&network.prefix);
+        } else {
+            self.networks.insert(network.prefix, network);
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, prefix: &Prefix) -> Option<&Network> {
+        self.networks.get(prefix)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Network> {
+        self.networks.values()
+    }
+
+    /// Whether eBGP routes for `prefix` are overridden by the IGP route.
+    pub fn is_backdoor(&self, prefix: &Prefix) -> bool {
+        self.networks.get(prefix).is_some_and(|n| n.backdoor)
+    }
+
+    pub fn backdoors(&self) -> BTreeSet<Prefix> {
+        self.networks
+            .values()
+            .filter(|n| n.backdoor)
+            .map(|n| n.prefix)
+            .collect()
+    }
+
+    /// The routes to originate into the Loc-RIB given the prefixes of the
+    /// system RIB. Backdoor networks are never originated.
+    pub fn originate(&self, policy: &Policy, rib: &BTreeSet<Prefix>) -> Vec<Route> {
+        self.networks
+            .values()
+            .filter(|n| !n.backdoor)
+            .filter(|n| !self.import_check || rib.contains(&n.prefix))
+            .filter_map(|n| n.route(policy))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rib(prefixes: &[&str]) -> BTreeSet<Prefix> {
+        prefixes.iter().map(|p| p.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn parse() {
+        let n = Network::parse("network 10.0.0.0/8 route-map ORIGIN backdoor").unwrap();
+        assert_eq!(n.route_map.as_deref(), Some("ORIGIN"));
+        assert!(n.backdoor);
+        assert!(Network::parse("network 10.0.0.0/8 route-map").is_err());
+        assert!(Network::parse("network 10.0.0.0/33").is_err());
+
+        let mut networks = Networks::new();
+        networks.configure("network 2001:db8::/32").unwrap();
+        networks.configure("no bgp network import-check").unwrap();
+        assert!(!networks.import_check);
+        networks.configure("no network 2001:db8::/32").unwrap();
+        assert_eq!(networks.iter().count(), 0);
+    }
+
+    #[test]
+    fn originate() {
+        let policy = Policy::new();
+        let mut networks = Networks::new();
+        networks.configure("network 10.0.0.0/8").unwrap();
+        networks.configure("network 192.0.2.0/24").unwrap();
+        networks
+            .configure("network 198.51.100.0/24 backdoor")
+            .unwrap();
+        networks
+            .configure("network 203.0.113.0/24 route-map NONE")
+            .unwrap();
+        let system = rib(&[
+            "10.0.0.0/8",
+            "198.51.100.0/24",
+            "203.0.113.0/24",
+            "192.0.2.0/25",
+        ]);
+
+        let routes = networks.originate(&policy, &system);
+        assert_eq!(routes.len(), 1);
+        assert_eq!(routes[0].prefix, "10.0.0.0/8".parse().unwrap());
+        assert_eq!(routes[0].weight, LOCAL_ROUTE_WEIGHT);
+        assert_eq!(routes[0].attr.origin, Origin::Igp);
+
+        // Without import-check the RIB does not matter.
+        networks.import_check = false;
+        assert_eq!(networks.originate(&policy, &rib(&[])).len(), 2);
+        assert!(networks.is_backdoor(&"198.51.100.0/24".parse().unwrap()));
+        assert!(!networks.is_backdoor(&"10.0.0.0/8".parse().unwrap()));
+    }
+}
@@ -0,0 +1,162 @@
+#![allow(dead_code)]
+
+use crate::bgp::packet::{BgpNotificationPacket, MutableBgpNotificationPacket};
+use pnet::packet::Packet;
+use std::fmt;
+use std::io::{Error, ErrorKind};
+
+// Error codes (RFC 4271 4.5).
+pub const NOTIFY_HEADER_ERR: u8 = 1;
+pub const NOTIFY_OPEN_ERR: u8 = 2;
+pub const NOTIFY_UPDATE_ERR: u8 = 3;
+pub const NOTIFY_HOLD_TIMER_EXPIRED: u8 = 4;
+pub const NOTIFY_FSM_ERR: u8 = 5;
+pub const NOTIFY_CEASE: u8 = 6;
+pub const NOTIFY_ROUTE_REFRESH_ERR: u8 = 7;
+
+// Message Header Error subcodes.
+pub const NOTIFY_HEADER_NOT_SYNC: u8 = 1;
+pub const NOTIFY_HEADER_BAD_MESLEN: u8 = 2;
+pub const NOTIFY_HEADER_BAD_MESTYPE: u8 = 3;
+
+// OPEN Message Error subcodes.
+pub const NOTIFY_OPEN_UNSUP_VERSION: u8 = 1;
+pub const NOTIFY_OPEN_BAD_PEER_AS: u8 = 2;
+pub const NOTIFY_OPEN_BAD_BGP_IDENT: u8 = 3;
+pub const NOTIFY_OPEN_UNSUP_PARAM: u8 = 4;
+pub const NOTIFY_OPEN_UNACEP_HOLDTIME: u8 = 6;
+pub const NOTIFY_OPEN_UNSUP_CAPABILITY: u8 = 7;
+pub const NOTIFY_OPEN_ROLE_MISMATCH: u8 = 11;
+
+// UPDATE Message Error subcodes.
+pub const NOTIFY_UPDATE_MAL_ATTR: u8 = 1;
+pub const NOTIFY_UPDATE_UNREC_WELLKNOWN: u8 = 2;
+pub const NOTIFY_UPDATE_MISS_WELLKNOWN: u8 = 3;
+pub const NOTIFY_UPDATE_ATTR_FLAG_ERR: u8 = 4;
+pub const NOTIFY_UPDATE_ATTR_LENG_ERR: u8 = 5;
+pub const NOTIFY_UPDATE_INVAL_ORIGIN: u8 = 6;
+pub const NOTIFY_UPDATE_INVAL_NEXT_HOP: u8 = 8;
+pub const NOTIFY_UPDATE_OPT_ATTR_ERR: u8 = 9;
+pub const NOTIFY_UPDATE_INVAL_NETWORK: u8 = 10;
+pub const NOTIFY_UPDATE_MAL_AS_PATH: u8 = 11;
+
+// Cease subcodes (RFC 4486).
+pub const NOTIFY_CEASE_MAX_PREFIX: u8 = 1;
+pub const NOTIFY_CEASE_ADMIN_SHUTDOWN: u8 = 2;
+pub const NOTIFY_CEASE_PEER_UNCONFIG: u8 = 3;
+pub const NOTIFY_CEASE_ADMIN_RESET: u8 = 4;
+pub const NOTIFY_CEASE_CONNECT_REJECT: u8 = 5;
+pub const NOTIFY_CEASE_CONFIG_CHANGE: u8 = 6;
+pub const NOTIFY_CEASE_COLLISION_RESOLUTION: u8 = 7;
+pub const NOTIFY_CEASE_OUT_OF_RESOURCE: u8 = 8;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MessageNotification {
+    pub code: u8,
+    pub subcode: u8,
+    pub data: Vec<u8>,
+}
+
+impl MessageNotification {
+    pub fn new(code: u8, subcode: u8, data: Vec<u8>) -> Self {
+        MessageNotification {
+            code,
+            subcode,
+            data,
+        }
+    }
+
+    pub fn from_bytes(buf: &[u8]) -> Result<Self, anyhow::Error> {
+        let notification =
+            BgpNotificationPacket::new(buf).ok_or(Error::from(ErrorKind::UnexpectedEof))?;
+        Ok(MessageNotification {
+            code: notification.get_error_code(),
+            subcode: notification.get_error_subcode(),
+            data: notification.payload().to_vec(),
+        })
+    }
+
+    /// Bad Message Length carries the erroneous length field (RFC 4271
+    /// 6.1).
+    pub fn bad_message_length(len: u16) -> Self {
+        MessageNotification::new(
+            NOTIFY_HEADER_ERR,
+            NOTIFY_HEADER_BAD_MESLEN,
+            len.to_be_bytes().to_vec(),
+        )
+    }
+
+    pub fn to_bytes(&self, buf: &mut Vec<u8>) -> Result<usize, anyhow::Error> {
+        let start = buf.len();
+        let offset = MutableBgpNotificationPacket::minimum_packet_size();
+        let len = offset + self.data.len();
+        buf.resize(start + len, 0);
+        let mut notification = MutableBgpNotificationPacket::new(&mut buf[start..]).unwrap();
+        notification.set_error_code(self.code);
+        notification.set_error_subcode(self.subcode);
+        buf[start + offset..].copy_from_slice(&self.data);
+
+        Ok(len)
+    }
+}
+
+fn code_str(code: u8) -> &'static str {
+    match code {
+        NOTIFY_HEADER_ERR => "Message Header Error",
+        NOTIFY_OPEN_ERR => "OPEN Message Error",
+        NOTIFY_UPDATE_ERR => "UPDATE Message Error",
+        NOTIFY_HOLD_TIMER_EXPIRED => "Hold Timer Expired",
+        NOTIFY_FSM_ERR => "Finite State Machine Error",
+        NOTIFY_CEASE => "Cease",
+        NOTIFY_ROUTE_REFRESH_ERR => "ROUTE-REFRESH Message Error",
+        _ => "Unknown",
+    }
+}
+
+fn subcode_str(code: u8, subcode: u8) -> &'static str {
+    match (code, subcode) {
+        (NOTIFY_HEADER_ERR, NOTIFY_HEADER_NOT_SYNC) => "Connection Not Synchronized",
+        (NOTIFY_HEADER_ERR, NOTIFY_HEADER_BAD_MESLEN) => "Bad Message Length",
+        (NOTIFY_HEADER_ERR, NOTIFY_HEADER_BAD_MESTYPE) => "Bad Message Type",
+        (NOTIFY_OPEN_ERR, NOTIFY_OPEN_UNSUP_VERSION) => "Unsupported Version Number",
+        (NOTIFY_OPEN_ERR, NOTIFY_OPEN_BAD_PEER_AS) => "Bad Peer AS",
+        (NOTIFY_OPEN_ERR, NOTIFY_OPEN_BAD_BGP_IDENT) => "Bad BGP Identifier",
+        (NOTIFY_OPEN_ERR, NOTIFY_OPEN_UNSUP_PARAM) => "Unsupported Optional Parameter",
+        (NOTIFY_OPEN_ERR, NOTIFY_OPEN_UNACEP_HOLDTIME) => "Unacceptable Hold Time",
+        (NOTIFY_OPEN_ERR, NOTIFY_OPEN_UNSUP_CAPABILITY) => "Unsupported Capability",
+        (NOTIFY_OPEN_ERR, NOTIFY_OPEN_ROLE_MISMATCH) => "Role Mismatch",
+        (NOTIFY_UPDATE_ERR, NOTIFY_UPDATE_MAL_ATTR) => "Malformed Attribute List",
+        (NOTIFY_UPDATE_ERR, NOTIFY_UPDATE_UNREC_WELLKNOWN) => "Unrecognized Well-known Attribute",
+        (NOTIFY_UPDATE_ERR, NOTIFY_UPDATE_MISS_WELLKNOWN) => "Missing Well-known Attribute",
+        (NOTIFY_UPDATE_ERR, NOTIFY_UPDATE_ATTR_FLAG_ERR) => "Attribute Flags Error",
+        (NOTIFY_UPDATE_ERR, NOTIFY_UPDATE_ATTR_LENG_ERR) => "Attribute Length Error",
+        (NOTIFY_UPDATE_ERR, NOTIFY_UPDATE_INVAL_ORIGIN) => "Invalid ORIGIN Attribute",
+        (NOTIFY_UPDATE_ERR, NOTIFY_UPDATE_INVAL_NEXT_HOP) => "Invalid NEXT_HOP Attribute",
+        (NOTIFY_UPDATE_ERR, NOTIFY_UPDATE_OPT_ATTR_ERR) => "Optional Attribute Error",
+        (NOTIFY_UPDATE_ERR, NOTIFY_UPDATE_INVAL_NETWORK) => "Invalid Network Field",
+        (NOTIFY_UPDATE_ERR, NOTIFY_UPDATE_MAL_AS_PATH) => "Malformed AS_PATH",
+        (NOTIFY_CEASE, NOTIFY_CEASE_MAX_PREFIX) => "Maximum Number of Prefixes Reached",
+        (NOTIFY_CEASE, NOTIFY_CEASE_ADMIN_SHUTDOWN) => "Administrative Shutdown",
+        (NOTIFY_CEASE, NOTIFY_CEASE_PEER_UNCONFIG) => "Peer De-configured",
+        (NOTIFY_CEASE, NOTIFY_CEASE_ADMIN_RESET) => "Administrative Reset",
+        (NOTIFY_CEASE, NOTIFY_CEASE_CONNECT_REJECT) => "Connection Rejected",
+        (NOTIFY_CEASE, NOTIFY_CEASE_CONFIG_CHANGE) => "Other Configuration Change",
+        (NOTIFY_CEASE, NOTIFY_CEASE_COLLISION_RESOLUTION) => "Connection Collision Resolution",
+        (NOTIFY_CEASE, NOTIFY_CEASE_OUT_OF_RESOURCE) => "Out of Resources",
+        (_, 0) => "Unspecific",
+        _ => "Unknown",
+    }
+}
+
+impl std::error::Error for MessageNotification {}
+
+impl fmt::Display for MessageNotification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}/{}",
+            code_str(self.code),
+            subcode_str(self.code, self.subcode)
+        )
+    }
+}
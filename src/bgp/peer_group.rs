@@ -0,0 +1,540 @@
+#![allow(dead_code)]
+
+use super::{Neighbor, NeighborCommand, NeighborError};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fmt::Write;
+use std::net::{IpAddr, Ipv4Addr};
+
+/// Settings configured at one level of the inheritance chain, keyed by
+/// `NeighborCommand::key()` so that a later command replaces an earlier one.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NeighborConfig {
+    /// Peer-group of a neighbor, or template of a peer-group or template.
+    pub parent: Option<String>,
+    commands: BTreeMap<String, NeighborCommand>,
+}
+
+impl NeighborConfig {
+    pub fn new() -> Self {
+        NeighborConfig::default()
+    }
+
+    /// Set `cmd`, `inherited` being the settings without this level. A
+    /// `no` command resulting in the inherited value removes the setting
+    /// instead of overriding it.
+    pub fn set(&mut self, cmd: NeighborCommand, inherited: &Neighbor) {
+        for cmd in cmd.split() {
+            if cmd.is_negation() && cmd.current(inherited).as_ref() == Some(&cmd) {
+                self.commands.remove(&cmd.key());
+            } else {
+                self.commands.insert(cmd.key(), cmd);
+            }
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&NeighborCommand> {
+        self.commands.get(key)
+    }
+
+    pub fn commands(&self) -> impl Iterator<Item = &NeighborCommand> {
+        self.commands.values()
+    }
+
+    fn apply(&self, neighbor: &mut Neighbor) -> Result<(), NeighborError> {
+        for cmd in self.commands.values() {
+            cmd.apply(neighbor)?;
+        }
+        Ok(())
+    }
+
+    fn set_all(&self, neighbor: &mut Neighbor) {
+        for cmd in self.commands.values() {
+            cmd.set(neighbor);
+        }
+    }
+}
+
+/// Where the effective value of a setting is configured.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Source {
+    Template(String),
+    PeerGroup(String),
+    Neighbor,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Template(name) => write!(f, "template {}", name),
+            Source::PeerGroup(name) => write!(f, "peer-group {}", name),
+            Source::Neighbor => write!(f, "neighbor"),
+        }
+    }
+}
+
+/// Neighbor configuration with inheritance. A neighbor inherits the
+/// settings of its peer-group, a peer-group or template those of the
+/// template it inherits from; the more specific setting wins.
+#[derive(Clone, Debug)]
+pub struct PeerGroups {
+    /// AS of the BGP instance.
+    asn: u32,
+    /// Configuration given when the neighbor was added, and the settings
+    /// configured on the neighbor itself.
+    neighbors: BTreeMap<IpAddr, (Neighbor, NeighborConfig)>,
+    peer_groups: BTreeMap<String, NeighborConfig>,
+    templates: BTreeMap<String, NeighborConfig>,
+}
+
+impl PeerGroups {
+    pub fn new(asn: u32) -> Self {
+        PeerGroups {
+            asn,
+            neighbors: BTreeMap::new(),
+            peer_groups: BTreeMap::new(),
+            templates: BTreeMap::new(),
+        }
+    }
+
+    /// Add a neighbor configured as `base`, replacing any previous one.
+    pub fn add(&mut self, base: Neighbor) {
+        self.neighbors
+            .insert(base.ipaddr, (base, NeighborConfig::new()));
+    }
+
+    pub fn remove(&mut self, addr: &IpAddr) {
+        self.neighbors.remove(addr);
+    }
+
+    pub fn contains_peer_group(&self, name: &str) -> bool {
+        self.peer_groups.contains_key(name)
+    }
+
+    pub fn contains(&self, addr: &IpAddr) -> bool {
+        self.neighbors.contains_key(addr)
+    }
+
+    /// Peer-group of the neighbor at `addr`.
+    pub fn peer_group_of(&self, addr: &IpAddr) -> Option<&str> {
+        self.neighbors.get(addr)?.1.parent.as_deref()
+    }
+
+    /// Neighbors which are members of the peer-group `name`.
+    pub fn members(&self, name: &str) -> Vec<IpAddr> {
+        self.neighbors
+            .iter()
+            .filter(|(_, (_, config))| config.parent.as_deref() == Some(name))
+            .map(|(addr, _)| *addr)
+            .collect()
+    }
+
+    /// Settings of the peer-group `name` with those it inherits, for an
+    /// address not known yet.
+    pub fn peer_group_defaults(&self, name: &str) -> Result<Neighbor, NeighborError> {
+        Ok(PeerGroups::defaults(&self.layers(Some(name), None)?))
+    }
+
+    /// Levels inherited by the peer-group `peer_group` or, without one, by
+    /// the template `template`, least specific first.
+    fn layers(
+        &self,
+        peer_group: Option<&str>,
+        template: Option<&str>,
+    ) -> Result<Vec<(Source, &NeighborConfig)>, NeighborError> {
+        let mut layers = Vec::new();
+        let mut template = template;
+        if let Some(name) = peer_group {
+            let config = self
+                .peer_groups
+                .get(name)
+                .ok_or_else(|| NeighborError::UnknownPeerGroup(name.to_string()))?;
+            layers.push((Source::PeerGroup(name.to_string()), config));
+            template = config.parent.as_deref();
+        }
+        while let Some(name) = template {
+            let source = Source::Template(name.to_string());
+            if layers.iter().any(|(s, _)| *s == source) {
+                return Err(NeighborError::InheritLoop(name.to_string()));
+            }
+            let config = self
+                .templates
+                .get(name)
+                .ok_or_else(|| NeighborError::UnknownTemplate(name.to_string()))?;
+            layers.push((source, config));
+            template = config.parent.as_deref();
+        }
+        layers.reverse();
+        Ok(layers)
+    }
+
+    /// Settings inherited by the neighbor at `addr`, from the
+    /// configuration it was added with and its peer-group.
+    fn inherited(&self, addr: &IpAddr) -> Result<Neighbor, NeighborError> {
+        let (base, config) = self
+            .neighbors
+            .get(addr)
+            .ok_or(NeighborError::NotConfigured(*addr))?;
+        let mut neighbor = base.clone();
+        for (_, layer) in self.layers(config.parent.as_deref(), None)? {
+            layer.set_all(&mut neighbor);
+        }
+        Ok(neighbor)
+    }
+
+    /// Defaults with the settings of `layers`, the neighbor address being
+    /// unknown at peer-group and template level.
+    fn defaults(layers: &[(Source, &NeighborConfig)]) -> Neighbor {
+        let mut neighbor = Neighbor::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        for (_, layer) in layers {
+            layer.set_all(&mut neighbor);
+        }
+        neighbor
+    }
+
+    /// Effective configuration of the neighbor at `addr`.
+    pub fn resolve(&self, addr: &IpAddr) -> Result<Neighbor, NeighborError> {
+        let (base, config) = self
+            .neighbors
+            .get(addr)
+            .ok_or(NeighborError::NotConfigured(*addr))?;
+        let mut neighbor = base.clone();
+        for (_, layer) in self.layers(config.parent.as_deref(), None)? {
+            layer.apply(&mut neighbor)?;
+        }
+        config.apply(&mut neighbor)?;
+        if let Some(local) = neighbor.local_as {
+            if local.asn == self.asn {
+                return Err(NeighborError::LocalAsSame(local.asn));
+            }
+        }
+        Ok(neighbor)
+    }
+
+    /// Resolve `addrs` in `self` which replaces `old` when all of them are
+    /// valid. `old` is left unchanged otherwise.
+    fn commit(
+        self,
+        old: &mut PeerGroups,
+        addrs: &[IpAddr],
+    ) -> Result<Vec<Neighbor>, NeighborError> {
+        let neighbors = addrs
+            .iter()
+            .map(|addr| self.resolve(addr))
+            .collect::<Result<Vec<_>, _>>()?;
+        *old = self;
+        Ok(neighbors)
+    }
+
+    /// Apply `cmd` to the neighbor at `addr` and return its effective
+    /// configuration. `remote-as` and `peer-group` configure a new neighbor.
+    pub fn configure(
+        &mut self,
+        addr: IpAddr,
+        cmd: NeighborCommand,
+    ) -> Result<Neighbor, NeighborError> {
+        let mut next = self.clone();
+        if !next.neighbors.contains_key(&addr) {
+            match cmd {
+                NeighborCommand::RemoteAs(_) | NeighborCommand::PeerGroup(Some(_)) => {
+                    next.add(Neighbor::new(addr))
+                }
+                _ => return Err(NeighborError::NotConfigured(addr)),
+            }
+        }
+        match cmd {
+            NeighborCommand::Inherit(_) => return Err(NeighborError::Syntax(cmd.to_string())),
+            NeighborCommand::PeerGroup(group) => {
+                if let Some(name) = &group {
+                    if !next.peer_groups.contains_key(name) {
+                        return Err(NeighborError::UnknownPeerGroup(name.to_string()));
+                    }
+                }
+                if let Some((_, config)) = next.neighbors.get_mut(&addr) {
+                    config.parent = group;
+                }
+            }
+            cmd => {
+                let inherited = next.inherited(&addr)?;
+                cmd.validate(&inherited)?;
+                if let Some((_, config)) = next.neighbors.get_mut(&addr) {
+                    config.set(cmd, &inherited);
+                }
+            }
+        }
+        let mut neighbors = next.commit(self, &[addr])?;
+        Ok(neighbors.remove(0))
+    }
+
+    /// Apply `cmd` to the settings `config` of a peer-group or template.
+    fn set_level(
+        &self,
+        config: &mut NeighborConfig,
+        cmd: NeighborCommand,
+    ) -> Result<(), NeighborError> {
+        match cmd {
+            NeighborCommand::PeerGroup(_) => Err(NeighborError::Syntax(cmd.to_string())),
+            NeighborCommand::Inherit(template) => {
+                config.parent = template;
+                Ok(())
+            }
+            cmd => {
+                let inherited = PeerGroups::defaults(&self.layers(None, config.parent.as_deref())?);
+                // The address family of update-source is checked per member.
+                if !matches!(cmd, NeighborCommand::UpdateSource(_)) {
+                    cmd.validate(&inherited)?;
+                }
+                config.set(cmd, &inherited);
+                Ok(())
+            }
+        }
+    }
+
+    /// Apply `cmd` to the peer-group `name`, creating it, and return the
+    /// effective configuration of its members.
+    pub fn configure_peer_group(
+        &mut self,
+        name: &str,
+        cmd: NeighborCommand,
+    ) -> Result<Vec<Neighbor>, NeighborError> {
+        let mut next = self.clone();
+        let mut config = next.peer_groups.remove(name).unwrap_or_default();
+        next.set_level(&mut config, cmd)?;
+        next.peer_groups.insert(name.to_string(), config);
+        next.layers(Some(name), None)?;
+        let members = next.members(name);
+        next.commit(self, &members)
+    }
+
+    /// Apply `cmd` to the template `name`, creating it, and return the
+    /// effective configuration of the neighbors inheriting from it.
+    pub fn configure_template(
+        &mut self,
+        name: &str,
+        cmd: NeighborCommand,
+    ) -> Result<Vec<Neighbor>, NeighborError> {
+        let mut next = self.clone();
+        let mut config = next.templates.remove(name).unwrap_or_default();
+        next.set_level(&mut config, cmd)?;
+        next.templates.insert(name.to_string(), config);
+        next.layers(None, Some(name))?;
+        let source = Source::Template(name.to_string());
+        let mut members = Vec::new();
+        for (addr, (_, config)) in &next.neighbors {
+            let layers = next.layers(config.parent.as_deref(), None)?;
+            if layers.iter().any(|(s, _)| *s == source) {
+                members.push(*addr);
+            }
+        }
+        next.commit(self, &members)
+    }
+
+    /// Effective configuration of the neighbor at `addr`, one setting per
+    /// line annotated with where inherited values come from and which
+    /// inherited values the neighbor's own settings override.
+    pub fn show(&self, addr: &IpAddr) -> Option<String> {
+        let (_, own) = self.neighbors.get(addr)?;
+        let mut layers = self.layers(own.parent.as_deref(), None).ok()?;
+        layers.push((Source::Neighbor, own));
+        let keys: BTreeSet<&String> = layers
+            .iter()
+            .flat_map(|(_, layer)| layer.commands.keys())
+            .collect();
+        let mut out = format!("neighbor {}\n", addr);
+        if let Some(name) = &own.parent {
+            writeln!(out, "  peer-group {}", name).ok()?;
+        }
+        for key in keys {
+            // Most specific first.
+            let mut values = layers
+                .iter()
+                .rev()
+                .filter_map(|(source, layer)| Some((source, layer.get(key)?)));
+            let (source, cmd) = values.next()?;
+            let cmd = cmd.to_string();
+            let line = match (source, values.next()) {
+                (Source::Neighbor, None) => format!("  {}", cmd),
+                (Source::Neighbor, Some((inherited, old))) => {
+                    format!("  {:<40} overrides {}: {}", cmd, inherited, old)
+                }
+                (source, _) => format!("  {:<40} inherited from {}", cmd, source),
+            };
+            writeln!(out, "{}", line.trim_end()).ok()?;
+        }
+        Some(out)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bgp::Family;
+    use crate::bgp::{Timers, AFI_IP6, SAFI_UNICAST};
+
+    fn cmd(line: &str) -> NeighborCommand {
+        NeighborCommand::parse(line).unwrap()
+    }
+
+    fn route_server() -> PeerGroups {
+        let mut groups = PeerGroups::new(65000);
+        groups
+            .configure_template("BASE", cmd("timers 30 90"))
+            .unwrap();
+        groups
+            .configure_template("BASE", cmd("ipv6 unicast activate"))
+            .unwrap();
+        groups
+            .configure_peer_group("RS", cmd("inherit BASE"))
+            .unwrap();
+        groups
+            .configure_peer_group("RS", cmd("description route server client"))
+            .unwrap();
+        groups
+            .configure_peer_group("RS", cmd("route-map RS-IN in"))
+            .unwrap();
+        groups
+            .configure_peer_group("RS", cmd("next-hop-self"))
+            .unwrap();
+        for (addr, asn) in &[("192.0.2.1", 65001), ("192.0.2.2", 65002)] {
+            let addr = addr.parse().unwrap();
+            groups
+                .configure(addr, cmd(&format!("remote-as {}", asn)))
+                .unwrap();
+            groups.configure(addr, cmd("peer-group RS")).unwrap();
+        }
+        groups
+    }
+
+    #[test]
+    fn inherit() {
+        let groups = route_server();
+        let n = groups.resolve(&"192.0.2.1".parse().unwrap()).unwrap();
+        assert_eq!(n.description.as_deref(), Some("route server client"));
+        assert_eq!(n.route_map_in.as_deref(), Some("RS-IN"));
+        assert!(n.next_hop_self);
+        assert_eq!(
+            n.timers,
+            Some(Timers {
+                keepalive: 30,
+                hold_time: 90
+            })
+        );
+        assert!(n.activate.contains(&Family {
+            afi: AFI_IP6,
+            safi: SAFI_UNICAST
+        }));
+        assert_eq!(
+            groups.members("RS"),
+            vec![
+                "192.0.2.1".parse::<IpAddr>().unwrap(),
+                "192.0.2.2".parse().unwrap()
+            ]
+        );
+    }
+
+    #[test]
+    fn overrides() {
+        let mut groups = route_server();
+        let addr: IpAddr = "192.0.2.2".parse().unwrap();
+        let n = groups.configure(addr, cmd("timers 10 30")).unwrap();
+        assert_eq!(n.timers.unwrap().hold_time, 30);
+        // A negation of an inherited setting overrides it.
+        let n = groups.configure(addr, cmd("no next-hop-self")).unwrap();
+        assert!(!n.next_hop_self);
+        assert!(groups.neighbors[&addr].1.get("next-hop-self").is_some());
+        // A negation resulting in the inherited value removes the setting.
+        groups
+            .configure_peer_group("RS", cmd("no next-hop-self"))
+            .unwrap();
+        assert!(groups.peer_groups["RS"].get("next-hop-self").is_none());
+        let n = groups.configure(addr, cmd("no next-hop-self")).unwrap();
+        assert!(!n.next_hop_self);
+        assert!(groups.neighbors[&addr].1.get("next-hop-self").is_none());
+
+        // Changes of the peer-group only affect its members and leave
+        // overridden settings alone.
+        let other: IpAddr = "198.51.100.1".parse().unwrap();
+        groups.configure(other, cmd("remote-as 65003")).unwrap();
+        let changed = groups
+            .configure_template("BASE", cmd("timers 20 60"))
+            .unwrap();
+        let changed: Vec<_> = changed.iter().map(|n| (n.ipaddr, n.timers)).collect();
+        assert_eq!(changed.len(), 2);
+        assert_eq!(changed[0].1.unwrap().hold_time, 60);
+        assert_eq!(changed[1].1.unwrap().hold_time, 30);
+        assert_eq!(groups.resolve(&other).unwrap().timers, None);
+    }
+
+    #[test]
+    fn errors() {
+        let mut groups = route_server();
+        let addr: IpAddr = "192.0.2.1".parse().unwrap();
+        assert_eq!(
+            groups.configure(addr, cmd("peer-group NONE")),
+            Err(NeighborError::UnknownPeerGroup("NONE".to_string()))
+        );
+        assert_eq!(
+            groups.configure_peer_group("RS", cmd("inherit NONE")),
+            Err(NeighborError::UnknownTemplate("NONE".to_string()))
+        );
+        groups.configure_template("A", cmd("inherit BASE")).unwrap();
+        assert_eq!(
+            groups.configure_template("BASE", cmd("inherit A")),
+            Err(NeighborError::InheritLoop("BASE".to_string()))
+        );
+        // Rejected for one member, so for the whole peer-group.
+        assert_eq!(
+            groups.configure_peer_group("RS", cmd("local-as 65000")),
+            Err(NeighborError::LocalAsSame(65000))
+        );
+        assert!(groups.peer_groups["RS"].get("local-as").is_none());
+        assert_eq!(
+            groups.configure_peer_group("RS", cmd("timers 30 10")),
+            Err(NeighborError::Keepalive(30))
+        );
+        assert_eq!(
+            groups.configure("192.0.2.9".parse().unwrap(), cmd("shutdown")),
+            Err(NeighborError::NotConfigured("192.0.2.9".parse().unwrap()))
+        );
+    }
+
+    #[test]
+    fn show() {
+        let mut groups = route_server();
+        let addr: IpAddr = "192.0.2.2".parse().unwrap();
+        groups.configure(addr, cmd("timers 10 30")).unwrap();
+        let show = groups.show(&addr).unwrap();
+        let lines: Vec<&str> = show.lines().collect();
+        assert_eq!(lines[0], "neighbor 192.0.2.2");
+        assert_eq!(lines[1], "  peer-group RS");
+        assert!(lines
+            .contains(&"  description route server client          inherited from peer-group RS"));
+        assert!(lines.contains(&"  remote-as 65002"));
+        assert!(lines.contains(
+            &"  timers 10 30                             overrides template BASE: timers 30 90"
+        ));
+        assert!(lines
+            .contains(&"  ipv6 unicast activate                    inherited from template BASE"));
+    }
+
+    #[test]
+    fn display() {
+        for line in &[
+            "remote-as 65001",
+            "local-as 65010 no-prepend replace-as",
+            "description route server client",
+            "no ebgp-multihop",
+            "ipv6 unicast activate",
+            "no ipv4 unicast activate",
+            "route-map RS-IN in",
+            "no prefix-list out",
+            "ipv4 unicast maximum-prefix 1000 90 restart 5",
+            "no send-community large",
+            "allowas-in origin",
+            "shutdown",
+            "no peer-group",
+        ] {
+            assert_eq!(cmd(line).to_string(), *line);
+        }
+    }
+}
@@ -0,0 +1,131 @@
+#![allow(dead_code)]
+
+use super::{AsPathList, CommunityList, LargeCommunityList, PrefixList, Route, RouteMap};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Permit,
+    Deny,
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Permit => write!(f, "permit"),
+            Action::Deny => write!(f, "deny"),
+        }
+    }
+}
+
+impl FromStr for Action {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "permit" => Ok(Action::Permit),
+            "deny" => Ok(Action::Deny),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    In,
+    Out,
+}
+
+/// Where a route-map is being applied. `peer` is the neighbor the route is
+/// received from or advertised to and `local` is our address on that
+/// session. Both are `None` for redistribution and network statements.
+#[derive(Clone, Copy, Debug)]
+pub struct PolicyContext {
+    pub direction: Direction,
+    pub peer: Option<IpAddr>,
+    pub local: Option<IpAddr>,
+}
+
+impl PolicyContext {
+    pub fn new(direction: Direction, peer: Option<IpAddr>, local: Option<IpAddr>) -> Self {
+        PolicyContext {
+            direction,
+            peer,
+            local,
+        }
+    }
+
+    pub fn local() -> Self {
+        PolicyContext::new(Direction::In, None, None)
+    }
+}
+
+/// Named prefix-lists, AS path lists, community lists, large community lists
+/// and route-maps.
+#[derive(Debug, Default)]
+pub struct Policy {
+    pub prefix_lists: BTreeMap<String, PrefixList>,
+    pub as_path_lists: BTreeMap<String, AsPathList>,
+    pub community_lists: BTreeMap<String, CommunityList>,
+    pub large_community_lists: BTreeMap<String, LargeCommunityList>,
+    pub route_maps: BTreeMap<String, RouteMap>,
+}
+
+impl Policy {
+    pub fn new() -> Self {
+        Policy::default()
+    }
+
+    /// Apply route-map `name` to `route`. A reference to a route-map which
+    /// is not defined denies every route.
+    pub fn apply(&self, name: &str, route: &mut Route, ctx: &PolicyContext) -> Action {
+        match self.route_maps.get(name) {
+            Some(map) => map.apply(self, route, ctx),
+            None => Action::Deny,
+        }
+    }
+
+    /// Check `route` against route-map `name` without applying its set
+    /// clauses.
+    pub fn check(&self, name: &str, route: &Route, ctx: &PolicyContext) -> Action {
+        match self.route_maps.get(name) {
+            Some(map) => map.check(self, route, ctx),
+            None => Action::Deny,
+        }
+    }
+}
+
+/// Policy the tests of other modules filter with.
+#[cfg(test)]
+pub mod fixture {
+    use super::*;
+    use crate::bgp::{PrefixListEntry, RouteMapEntry, RouteMapMatch};
+
+    /// Define the prefix-list `name` permitting `prefix` and the route-map
+    /// `name` permitting the routes it matches. Returns the route-map
+    /// entry, for set clauses.
+    pub fn permit<'a>(policy: &'a mut Policy, name: &str, prefix: &str) -> &'a mut RouteMapEntry {
+        let mut plist = PrefixList::new();
+        plist.push(PrefixListEntry::new(
+            Action::Permit,
+            prefix.parse().unwrap(),
+        ));
+        policy.prefix_lists.insert(name.to_string(), plist);
+        let mut entry = RouteMapEntry::new(Action::Permit);
+        entry
+            .matches
+            .push(RouteMapMatch::PrefixList(name.to_string()));
+        let mut map = RouteMap::new();
+        map.insert(10, entry);
+        policy.route_maps.insert(name.to_string(), map);
+        policy
+            .route_maps
+            .get_mut(name)
+            .unwrap()
+            .get_mut(10)
+            .unwrap()
+    }
+}
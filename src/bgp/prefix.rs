@@ -0,0 +1,240 @@
+#![allow(dead_code)]
+
+use byteorder::{ReadBytesExt, WriteBytesExt};
+use std::fmt;
+use std::io::{Cursor, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum PrefixError {
+    #[error("malformed prefix")]
+    Malformed,
+    #[error("invalid prefix length {0}")]
+    InvalidLength(u8),
+}
+
+/// IPv4 or IPv6 prefix. Host bits are always cleared so two prefixes which
+/// cover the same range compare equal.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Prefix {
+    addr: IpAddr,
+    len: u8,
+}
+
+impl Prefix {
+    pub fn new(addr: IpAddr, len: u8) -> Result<Self, PrefixError> {
+        let max = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        if len > max {
+            return Err(PrefixError::InvalidLength(len));
+        }
+        Ok(Prefix {
+            addr: apply_mask(addr, len),
+            len,
+        })
+    }
+
+    pub fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    pub fn prefixlen(&self) -> u8 {
+        self.len
+    }
+
+    pub fn max_prefixlen(&self) -> u8 {
+        match self.addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        }
+    }
+
+    pub fn is_ipv4(&self) -> bool {
+        self.addr.is_ipv4()
+    }
+
+    pub fn is_ipv6(&self) -> bool {
+        self.addr.is_ipv6()
+    }
+
+    /// Returns true when `other` is equal to or more specific than this prefix.
+    pub fn contains(&self, other: &Prefix) -> bool {
+        if self.is_ipv4() != other.is_ipv4() || self.len > other.len {
+            return false;
+        }
+        apply_mask(other.addr, self.len) == self.addr
+    }
+
+    /// The host route to the last address of the prefix, the greatest
+    /// prefix it contains in `Prefix` order.
+    pub fn last(&self) -> Prefix {
+        let addr = match self.addr {
+            IpAddr::V4(v4) => {
+                let host = u32::MAX.checked_shr(self.len as u32).unwrap_or(0);
+                IpAddr::V4(Ipv4Addr::from(u32::from(v4) | host))
+            }
+            IpAddr::V6(v6) => {
+                let host = u128::MAX.checked_shr(self.len as u32).unwrap_or(0);
+                IpAddr::V6(Ipv6Addr::from(u128::from(v6) | host))
+            }
+        };
+        Prefix {
+            addr,
+            len: self.max_prefixlen(),
+        }
+    }
+
+    pub fn contains_addr(&self, addr: &IpAddr) -> bool {
+        if self.addr.is_ipv4() != addr.is_ipv4() {
+            return false;
+        }
+        apply_mask(*addr, self.len) == self.addr
+    }
+    /// Decode one NLRI prefix, a length octet followed by the minimum
+    /// number of address octets (RFC 4271 4.3).
+    pub fn from_bytes(c: &mut Cursor<&[u8]>, ipv6: bool) -> Result<Prefix, PrefixError> {
+        let len = c.read_u8().map_err(|_| PrefixError::Malformed)?;
+        let max = if ipv6 { 128 } else { 32 };
+        if len > max {
+            return Err(PrefixError::InvalidLength(len));
+        }
+        let mut octets = [0u8; 16];
+        let n = (len as usize).div_ceil(8);
+        c.read_exact(&mut octets[..n])
+            .map_err(|_| PrefixError::Malformed)?;
+        let addr = if ipv6 {
+            IpAddr::V6(Ipv6Addr::from(octets))
+        } else {
+            IpAddr::V4(Ipv4Addr::new(octets[0], octets[1], octets[2], octets[3]))
+        };
+        Prefix::new(addr, len)
+    }
+
+    pub fn to_bytes(&self, buf: &mut Vec<u8>) -> Result<usize, anyhow::Error> {
+        let n = (self.len as usize).div_ceil(8);
+        buf.write_u8(self.len)?;
+        match self.addr {
+            IpAddr::V4(v4) => buf.extend_from_slice(&v4.octets()[..n]),
+            IpAddr::V6(v6) => buf.extend_from_slice(&v6.octets()[..n]),
+        }
+        Ok(1 + n)
+    }
+}
+
+fn apply_mask(addr: IpAddr, len: u8) -> IpAddr {
+    match addr {
+        IpAddr::V4(v4) => {
+            let mask = if len == 0 {
+                0
+            } else {
+                u32::MAX << (32 - len as u32)
+            };
+            IpAddr::V4(Ipv4Addr::from(u32::from(v4) & mask))
+        }
+        IpAddr::V6(v6) => {
+            let mask = if len == 0 {
+                0
+            } else {
+                u128::MAX << (128 - len as u32)
+            };
+            IpAddr::V6(Ipv6Addr::from(u128::from(v6) & mask))
+        }
+    }
+}
+
+impl FromStr for Prefix {
+    type Err = PrefixError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let strs: Vec<&str> = s.split('/').collect();
+        if strs.len() != 2 {
+            return Err(PrefixError::Malformed);
+        }
+        let addr = strs[0]
+            .parse::<IpAddr>()
+            .map_err(|_| PrefixError::Malformed)?;
+        let len = strs[1].parse::<u8>().map_err(|_| PrefixError::Malformed)?;
+        Prefix::new(addr, len)
+    }
+}
+
+impl fmt::Display for Prefix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.len)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn from_str() {
+        let p: Prefix = "10.0.0.1/8".parse().unwrap();
+        assert_eq!(format!("{}", p), "10.0.0.0/8");
+        assert_eq!(p.prefixlen(), 8);
+
+        let p: Prefix = "2001:db8::1/32".parse().unwrap();
+        assert_eq!(format!("{}", p), "2001:db8::/32");
+
+        let p: Prefix = "0.0.0.0/0".parse().unwrap();
+        assert_eq!(format!("{}", p), "0.0.0.0/0");
+
+        assert_eq!(
+            "10.0.0.0/33".parse::<Prefix>(),
+            Err(PrefixError::InvalidLength(33))
+        );
+        assert_eq!("10.0.0.0".parse::<Prefix>(), Err(PrefixError::Malformed));
+        assert_eq!("10.0.0/8".parse::<Prefix>(), Err(PrefixError::Malformed));
+    }
+
+    #[test]
+    fn contains() {
+        let p: Prefix = "10.0.0.0/8".parse().unwrap();
+        assert!(p.contains(&"10.1.0.0/16".parse().unwrap()));
+        assert!(p.contains(&"10.0.0.0/8".parse().unwrap()));
+        assert!(!p.contains(&"10.0.0.0/7".parse().unwrap()));
+        assert!(!p.contains(&"11.0.0.0/16".parse().unwrap()));
+        assert!(!p.contains(&"::/0".parse().unwrap()));
+
+        assert_eq!(p.last(), "10.255.255.255/32".parse().unwrap());
+        let h: Prefix = "2001:db8::1/128".parse().unwrap();
+        assert_eq!(h.last(), h);
+
+        let d: Prefix = "0.0.0.0/0".parse().unwrap();
+        assert!(d.contains(&p));
+        assert_eq!(d.last(), "255.255.255.255/32".parse().unwrap());
+        assert!(d.contains_addr(&"192.168.0.1".parse().unwrap()));
+        assert!(!d.contains_addr(&"::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn to_bytes() {
+        let p: Prefix = "10.1.0.0/17".parse().unwrap();
+        let mut buf = Vec::new();
+        assert_eq!(p.to_bytes(&mut buf).unwrap(), 4);
+        assert_eq!(buf, vec![17, 10, 1, 0]);
+        let mut c = Cursor::new(&buf[..]);
+        assert_eq!(Prefix::from_bytes(&mut c, false).unwrap(), p);
+
+        let p: Prefix = "2001:db8::/32".parse().unwrap();
+        let mut buf = Vec::new();
+        assert_eq!(p.to_bytes(&mut buf).unwrap(), 5);
+        let mut c = Cursor::new(&buf[..]);
+        assert_eq!(Prefix::from_bytes(&mut c, true).unwrap(), p);
+
+        let mut c = Cursor::new(&[33u8, 10, 0, 0, 0, 0][..]);
+        assert_eq!(
+            Prefix::from_bytes(&mut c, false),
+            Err(PrefixError::InvalidLength(33))
+        );
+        let mut c = Cursor::new(&[24u8, 10, 0][..]);
+        assert_eq!(
+            Prefix::from_bytes(&mut c, false),
+            Err(PrefixError::Malformed)
+        );
+    }
+}
@@ -0,0 +1,135 @@
+#![allow(dead_code)]
+
+use super::{Action, Prefix};
+use std::collections::BTreeMap;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PrefixListEntry {
+    pub action: Action,
+    pub prefix: Prefix,
+    pub ge: Option<u8>,
+    pub le: Option<u8>,
+}
+
+impl PrefixListEntry {
+    pub fn new(action: Action, prefix: Prefix) -> Self {
+        PrefixListEntry {
+            action,
+            prefix,
+            ge: None,
+            le: None,
+        }
+    }
+
+    /// Without `ge` or `le` the prefix length must match exactly. `ge` alone
+    /// extends the range up to the maximum length of the family and `le`
+    /// alone starts the range at the entry's own length.
+    pub fn matches(&self, p: &Prefix) -> bool {
+        if !self.prefix.contains(p) {
+            return false;
+        }
+        let len = p.prefixlen();
+        if self.ge.is_none() && self.le.is_none() {
+            return len == self.prefix.prefixlen();
+        }
+        let ge = self.ge.unwrap_or(self.prefix.prefixlen());
+        let le = self.le.unwrap_or(self.prefix.max_prefixlen());
+        len >= ge && len <= le
+    }
+}
+
+/// Ordered list of prefix entries. The first matching entry decides the
+/// result and a prefix which matches no entry is denied.
+#[derive(Clone, Debug, Default)]
+pub struct PrefixList {
+    entries: BTreeMap<u32, PrefixListEntry>,
+}
+
+impl PrefixList {
+    const SEQ_STEP: u32 = 5;
+
+    pub fn new() -> Self {
+        PrefixList {
+            entries: BTreeMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, seq: u32, entry: PrefixListEntry) -> Option<PrefixListEntry> {
+        self.entries.insert(seq, entry)
+    }
+
+    /// Append `entry` after the last one, using the next multiple of 5 as
+    /// its sequence number.
+    pub fn push(&mut self, entry: PrefixListEntry) -> u32 {
+        let seq = match self.entries.keys().next_back() {
+            Some(last) => (last / Self::SEQ_STEP + 1) * Self::SEQ_STEP,
+            None => Self::SEQ_STEP,
+        };
+        self.entries.insert(seq, entry);
+        seq
+    }
+
+    pub fn remove(&mut self, seq: u32) -> Option<PrefixListEntry> {
+        self.entries.remove(&seq)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn apply(&self, p: &Prefix) -> Action {
+        for entry in self.entries.values() {
+            if entry.matches(p) {
+                return entry.action;
+            }
+        }
+        Action::Deny
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(action: Action, s: &str, ge: Option<u8>, le: Option<u8>) -> PrefixListEntry {
+        PrefixListEntry {
+            action,
+            prefix: s.parse().unwrap(),
+            ge,
+            le,
+        }
+    }
+
+    #[test]
+    fn apply() {
+        let mut plist = PrefixList::new();
+        plist.push(entry(Action::Deny, "10.0.0.0/8", Some(25), None));
+        plist.push(entry(Action::Permit, "10.0.0.0/8", None, Some(24)));
+        plist.push(entry(Action::Permit, "192.168.0.0/16", None, None));
+        assert_eq!(plist.len(), 3);
+
+        let apply = |s: &str| plist.apply(&s.parse().unwrap());
+        assert_eq!(apply("10.0.0.0/8"), Action::Permit);
+        assert_eq!(apply("10.1.0.0/16"), Action::Permit);
+        assert_eq!(apply("10.1.1.0/24"), Action::Permit);
+        assert_eq!(apply("10.1.1.0/25"), Action::Deny);
+        assert_eq!(apply("192.168.0.0/16"), Action::Permit);
+        assert_eq!(apply("192.168.1.0/24"), Action::Deny);
+        assert_eq!(apply("172.16.0.0/12"), Action::Deny);
+        assert_eq!(apply("2001:db8::/32"), Action::Deny);
+    }
+
+    #[test]
+    fn push() {
+        let mut plist = PrefixList::new();
+        let e = entry(Action::Permit, "0.0.0.0/0", None, None);
+        assert_eq!(plist.push(e.clone()), 5);
+        assert_eq!(plist.push(e.clone()), 10);
+        plist.insert(12, e.clone());
+        assert_eq!(plist.push(e), 15);
+    }
+}
@@ -0,0 +1,88 @@
+#![allow(dead_code)]
+
+use super::{Action, Attr, Origin, Policy, PolicyContext, Prefix, Route};
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// Weight given to routes originated by this router.
+pub const LOCAL_ROUTE_WEIGHT: u32 = 32768;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RedistributeType {
+    Kernel,
+    Connected,
+    Static,
+    Ospf,
+    Isis,
+}
+
+impl fmt::Display for RedistributeType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            RedistributeType::Kernel => "kernel",
+            RedistributeType::Connected => "connected",
+            RedistributeType::Static => "static",
+            RedistributeType::Ospf => "ospf",
+            RedistributeType::Isis => "isis",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl FromStr for RedistributeType {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "kernel" => Ok(RedistributeType::Kernel),
+            "connected" => Ok(RedistributeType::Connected),
+            "static" => Ok(RedistributeType::Static),
+            "ospf" => Ok(RedistributeType::Ospf),
+            "isis" => Ok(RedistributeType::Isis),
+            _ => Err(()),
+        }
+    }
+}
+
+/// `redistribute <type> [metric <med>] [route-map <name>]`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Redistribute {
+    pub typ: RedistributeType,
+    pub metric: Option<u32>,
+    pub route_map: Option<String>,
+}
+
+impl Redistribute {
+    pub fn new(typ: RedistributeType) -> Self {
+        Redistribute {
+            typ,
+            metric: None,
+            route_map: None,
+        }
+    }
+
+    /// Build the BGP route for a prefix imported from the system RIB, or
+    /// `None` when the route-map denies it.
+    pub fn route(
+        &self,
+        policy: &Policy,
+        prefix: Prefix,
+        next_hop: Option<IpAddr>,
+    ) -> Option<Route> {
+        let mut attr = Attr::new();
+        attr.origin = Origin::Incomplete;
+        attr.med = self.metric;
+        attr.next_hop = next_hop;
+
+        let mut route = Route::new(prefix, attr);
+        route.weight = LOCAL_ROUTE_WEIGHT;
+
+        if let Some(name) = &self.route_map {
+            if policy.apply(name, &mut route, &PolicyContext::local()) == Action::Deny {
+                return None;
+            }
+        }
+        Some(route)
+    }
+}
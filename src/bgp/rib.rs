@@ -0,0 +1,385 @@
+#![allow(dead_code)]
+
+use super::{Origin, PeerType, Prefix, Route};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr};
+
+/// LOCAL_PREF of routes without one.
+pub const LOCAL_PREF_DEFAULT: u32 = 100;
+
+/// Where a path in the RIB comes from. Locally originated routes are kept
+/// apart from each other as well as from the neighbors'.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RouteSource {
+    /// `network` statement.
+    Network,
+    /// `aggregate-address`.
+    Aggregate,
+    Neighbor(IpAddr),
+}
+
+impl RouteSource {
+    pub fn is_local(&self) -> bool {
+        !matches!(self, RouteSource::Neighbor(_))
+    }
+}
+
+fn origin_rank(origin: Origin) -> u8 {
+    match origin {
+        Origin::Igp => 0,
+        Origin::Egp => 1,
+        Origin::Incomplete => 2,
+    }
+}
+
+/// The Adj-RIBs-In of the neighbors, after inbound policy, together with the
+/// locally originated routes, and the best path of each prefix selected
+/// from them, the Loc-RIB (RFC 4271 3.2).
+#[derive(Clone, Debug, Default)]
+pub struct Rib {
+    paths: BTreeMap<Prefix, BTreeMap<RouteSource, Route>>,
+    best: BTreeMap<Prefix, RouteSource>,
+    /// Type and BGP Identifier of each established neighbor, eBGP paths
+    /// are preferred, then the lowest BGP Identifier.
+    peers: BTreeMap<IpAddr, (PeerType, Ipv4Addr)>,
+    /// `bgp bestpath prefix-validate disallow-invalid`
+    pub disallow_invalid: bool,
+}
+
+impl Rib {
+    pub fn new() -> Self {
+        Rib::default()
+    }
+
+    pub fn peer_up(&mut self, addr: IpAddr, peer_type: PeerType, router_id: Ipv4Addr) {
+        self.peers.insert(addr, (peer_type, router_id));
+    }
+
+    /// Type of the established neighbor at `addr`.
+    pub fn peer_type(&self, addr: &IpAddr) -> Option<PeerType> {
+        self.peers.get(addr).map(|(peer_type, _)| *peer_type)
+    }
+
+    fn is_external(&self, source: &RouteSource) -> bool {
+        match source {
+            RouteSource::Neighbor(addr) => self.peer_type(addr) != Some(PeerType::Internal),
+            _ => false,
+        }
+    }
+
+    fn router_id(&self, source: &RouteSource) -> Option<Ipv4Addr> {
+        match source {
+            RouteSource::Neighbor(addr) => self.peers.get(addr).map(|(_, id)| *id),
+            _ => None,
+        }
+    }
+
+    /// Compare two paths to the same prefix, `Ordering::Less` when `a` is
+    /// preferred. The decision process of RFC 4271 9.1.2.2 preceded by
+    /// weight, with locally originated routes preferred after LOCAL_PREF
+    /// and the neighbor address deciding after the BGP Identifier.
+    fn compare(&self, a: (&RouteSource, &Route), b: (&RouteSource, &Route)) -> Ordering {
+        let ((sa, ra), (sb, rb)) = (a, b);
+        let local_pref = |r: &Route| r.attr.local_pref.unwrap_or(LOCAL_PREF_DEFAULT);
+        rb.weight
+            .cmp(&ra.weight)
+            .then_with(|| local_pref(rb).cmp(&local_pref(ra)))
+            .then_with(|| sb.is_local().cmp(&sa.is_local()))
+            .then_with(|| ra.attr.as_path.length().cmp(&rb.attr.as_path.length()))
+            .then_with(|| origin_rank(ra.attr.origin).cmp(&origin_rank(rb.attr.origin)))
+            .then_with(|| {
+                // MEDs only compare between routes from the same AS.
+                if ra.attr.as_path.first_as() == rb.attr.as_path.first_as() {
+                    ra.attr.med.unwrap_or(0).cmp(&rb.attr.med.unwrap_or(0))
+                } else {
+                    Ordering::Equal
+                }
+            })
+            .then_with(|| self.is_external(sb).cmp(&self.is_external(sa)))
+            .then_with(|| self.router_id(sa).cmp(&self.router_id(sb)))
+            .then_with(|| sa.cmp(sb))
+    }
+
+    /// Select the best path of `prefix` again.
+    fn select(&mut self, prefix: Prefix) -> Option<RouteSource> {
+        let paths = self.paths.get(&prefix);
+        let best = paths.and_then(|paths| {
+            paths
+                .iter()
+                .filter(|(_, route)| route.best_path_eligible(self.disallow_invalid))
+                .min_by(|a, b| self.compare(*a, *b))
+                .map(|(source, _)| *source)
+        });
+        if paths.is_some_and(|paths| paths.is_empty()) {
+            self.paths.remove(&prefix);
+        }
+        match best {
+            Some(source) => self.best.insert(prefix, source),
+            None => self.best.remove(&prefix),
+        };
+        best
+    }
+
+    /// Put the path from `source` in place of the one it had for the same
+    /// prefix. Returns whether the best path of the prefix changed.
+    pub fn update(&mut self, source: RouteSource, route: Route) -> bool {
+        let prefix = route.prefix;
+        let paths = self.paths.entry(prefix).or_default();
+        if paths.get(&source) == Some(&route) {
+            return false;
+        }
+        paths.insert(source, route);
+        let old = self.best.get(&prefix).copied();
+        let new = self.select(prefix);
+        old != new || new == Some(source)
+    }
+
+    /// Remove the path from `source` to `prefix`. Returns whether the best
+    /// path of the prefix changed.
+    pub fn withdraw(&mut self, source: RouteSource, prefix: Prefix) -> bool {
+        let removed = self
+            .paths
+            .get_mut(&prefix)
+            .and_then(|paths| paths.remove(&source));
+        if removed.is_none() {
+            return false;
+        }
+        let old = self.best.get(&prefix).copied();
+        old != self.select(prefix)
+    }
+
+    /// Suppress the path from `source` to `prefix` for route flap
+    /// dampening, or reuse it. Returns whether the best path changed.
+    pub fn set_damped(&mut self, source: RouteSource, prefix: Prefix, damped: bool) -> bool {
+        match self
+            .paths
+            .get_mut(&prefix)
+            .and_then(|paths| paths.get_mut(&source))
+        {
+            Some(route) if route.damped != damped => route.damped = damped,
+            _ => return false,
+        }
+        let old = self.best.get(&prefix).copied();
+        old != self.select(prefix)
+    }
+
+    /// Paths suppressed by route flap dampening.
+    pub fn damped(&self) -> Vec<(RouteSource, Prefix)> {
+        self.paths
+            .iter()
+            .flat_map(|(prefix, paths)| {
+                paths
+                    .iter()
+                    .filter(|(_, route)| route.damped)
+                    .map(move |(source, _)| (*source, *prefix))
+            })
+            .collect()
+    }
+
+    /// Let `f` change the local properties of the path from each source,
+    /// returning whether it did. Returns the prefixes whose best path
+    /// changed.
+    pub fn modify(&mut self, mut f: impl FnMut(&RouteSource, &mut Route) -> bool) -> Vec<Prefix> {
+        let mut modified = Vec::new();
+        for (prefix, paths) in self.paths.iter_mut() {
+            let mut changed = false;
+            for (source, route) in paths.iter_mut() {
+                changed |= f(source, route);
+            }
+            if changed {
+                modified.push(*prefix);
+            }
+        }
+        modified
+            .into_iter()
+            .filter(|prefix| {
+                let old = self.best.get(prefix).copied();
+                old != self.select(*prefix)
+            })
+            .collect()
+    }
+
+    /// Remove every path from the neighbor at `addr` once its session is
+    /// down. Returns the prefixes whose best path changed.
+    pub fn peer_down(&mut self, addr: IpAddr) -> Vec<Prefix> {
+        self.peers.remove(&addr);
+        let source = RouteSource::Neighbor(addr);
+        let prefixes: Vec<Prefix> = self
+            .paths
+            .iter()
+            .filter(|(_, paths)| paths.contains_key(&source))
+            .map(|(prefix, _)| *prefix)
+            .collect();
+        prefixes
+            .into_iter()
+            .filter(|prefix| self.withdraw(source, *prefix))
+            .collect()
+    }
+
+    /// Prefixes with a path from `source`.
+    pub fn prefixes_from(&self, source: RouteSource) -> Vec<Prefix> {
+        self.paths
+            .iter()
+            .filter(|(_, paths)| paths.contains_key(&source))
+            .map(|(prefix, _)| *prefix)
+            .collect()
+    }
+
+    /// Path from `source` to `prefix`.
+    pub fn path(&self, source: RouteSource, prefix: &Prefix) -> Option<&Route> {
+        self.paths.get(prefix)?.get(&source)
+    }
+
+    /// Best path to `prefix`.
+    pub fn best(&self, prefix: &Prefix) -> Option<&Route> {
+        let source = self.best.get(prefix)?;
+        self.paths.get(prefix)?.get(source)
+    }
+
+    /// Source of the best path to `prefix`.
+    pub fn best_source(&self, prefix: &Prefix) -> Option<RouteSource> {
+        self.best.get(prefix).copied()
+    }
+
+    /// The Loc-RIB, the best path of every prefix.
+    pub fn loc_rib(&self) -> impl Iterator<Item = &Route> {
+        self.best
+            .iter()
+            .filter_map(move |(prefix, source)| self.paths.get(prefix)?.get(source))
+    }
+
+    /// The best paths to the prefixes `prefix` contains, itself included.
+    pub fn loc_rib_within(&self, prefix: &Prefix) -> impl Iterator<Item = &Route> {
+        let prefix = *prefix;
+        self.best
+            .range(prefix..=prefix.last())
+            .filter(move |(p, _)| prefix.contains(p))
+            .filter_map(move |(p, source)| self.paths.get(p)?.get(source))
+    }
+
+    /// Every path to `prefix`.
+    pub fn paths(&self, prefix: &Prefix) -> impl Iterator<Item = (&RouteSource, &Route)> {
+        self.paths.get(prefix).into_iter().flatten()
+    }
+
+    pub fn len(&self) -> usize {
+        self.best.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.best.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bgp::Attr;
+
+    fn route(prefix: &str, path: &str) -> Route {
+        let mut attr = Attr::new();
+        attr.as_path = path.parse().unwrap();
+        Route::new(prefix.parse().unwrap(), attr)
+    }
+
+    fn neighbor(addr: &str) -> RouteSource {
+        RouteSource::Neighbor(addr.parse().unwrap())
+    }
+
+    #[test]
+    fn best_path() {
+        let mut rib = Rib::new();
+        let (a, b) = (neighbor("192.0.2.1"), neighbor("192.0.2.2"));
+        let id = "10.0.0.1".parse().unwrap();
+        rib.peer_up("192.0.2.1".parse().unwrap(), PeerType::External, id);
+        rib.peer_up("192.0.2.2".parse().unwrap(), PeerType::Internal, id);
+        let prefix: Prefix = "10.0.0.0/8".parse().unwrap();
+
+        assert!(rib.update(a, route("10.0.0.0/8", "65001 65010")));
+        assert_eq!(rib.best_source(&prefix), Some(a));
+        // Shorter AS path.
+        assert!(rib.update(b, route("10.0.0.0/8", "65010")));
+        assert_eq!(rib.best_source(&prefix), Some(b));
+        // Equal length, eBGP is preferred.
+        assert!(rib.update(a, route("10.0.0.0/8", "65001")));
+        assert_eq!(rib.best_source(&prefix), Some(a));
+        // LOCAL_PREF goes before the AS path.
+        let mut r = route("10.0.0.0/8", "65010 65020");
+        r.attr_mut().local_pref = Some(200);
+        assert!(rib.update(b, r.clone()));
+        assert_eq!(rib.best(&prefix), Some(&r));
+        // A change of a path which is not the best.
+        assert!(!rib.update(a, route("10.0.0.0/8", "65002")));
+        assert!(!rib.update(a, route("10.0.0.0/8", "65002")));
+
+        // Local routes win over equal neighbor routes.
+        let mut local = route("10.0.0.0/8", "");
+        local.attr_mut().local_pref = Some(200);
+        assert!(rib.update(RouteSource::Network, local));
+        assert_eq!(rib.best_source(&prefix), Some(RouteSource::Network));
+        assert!(rib.withdraw(RouteSource::Network, prefix));
+        assert_eq!(rib.best_source(&prefix), Some(b));
+        assert_eq!(rib.loc_rib().count(), 1);
+
+        // Losing the best path falls back to the next one.
+        assert_eq!(rib.peer_down("192.0.2.2".parse().unwrap()), vec![prefix]);
+        assert_eq!(rib.best_source(&prefix), Some(a));
+        assert!(!rib.withdraw(b, prefix));
+        assert!(rib.withdraw(a, prefix));
+        assert!(rib.is_empty());
+        assert_eq!(rib.paths(&prefix).count(), 0);
+    }
+
+    #[test]
+    fn loc_rib_within() {
+        let mut rib = Rib::new();
+        let a = neighbor("192.0.2.1");
+        for prefix in [
+            "9.0.0.0/8",
+            "10.0.0.0/7",
+            "10.0.0.0/8",
+            "10.1.0.0/16",
+            "11.0.0.0/8",
+        ] {
+            rib.update(a, route(prefix, "65001"));
+        }
+        let within: Vec<String> = rib
+            .loc_rib_within(&"10.0.0.0/8".parse().unwrap())
+            .map(|route| route.prefix.to_string())
+            .collect();
+        assert_eq!(within, vec!["10.0.0.0/8", "10.1.0.0/16"]);
+    }
+
+    #[test]
+    fn med() {
+        let mut rib = Rib::new();
+        let prefix: Prefix = "10.0.0.0/8".parse().unwrap();
+        let med = |path: &str, med: u32| {
+            let mut r = route("10.0.0.0/8", path);
+            r.attr_mut().med = Some(med);
+            r
+        };
+        rib.update(neighbor("192.0.2.1"), med("65001", 20));
+        rib.update(neighbor("192.0.2.2"), med("65001", 10));
+        assert_eq!(rib.best_source(&prefix), Some(neighbor("192.0.2.2")));
+
+        // Not compared between different neighbor ASes, the lower address
+        // wins.
+        rib.update(neighbor("192.0.2.2"), med("65002", 10));
+        assert_eq!(rib.best_source(&prefix), Some(neighbor("192.0.2.1")));
+
+        // The lower BGP Identifier goes before the address.
+        let mut external = |addr: &str, id: &str| {
+            rib.peer_up(
+                addr.parse().unwrap(),
+                PeerType::External,
+                id.parse().unwrap(),
+            )
+        };
+        external("192.0.2.1", "10.0.0.9");
+        external("192.0.2.2", "10.0.0.1");
+        rib.update(neighbor("192.0.2.1"), med("65002", 10));
+        assert_eq!(rib.best_source(&prefix), Some(neighbor("192.0.2.2")));
+    }
+}
@@ -0,0 +1,217 @@
+#![allow(dead_code)]
+
+use super::{Action, Capabilities, Capability, MessageNotification};
+use super::{NOTIFY_OPEN_ERR, NOTIFY_OPEN_ROLE_MISMATCH};
+use std::fmt;
+use std::str::FromStr;
+
+/// BGP Role of the local speaker on a session (RFC 9234 4.1). The neighbor
+/// takes the opposite role.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Role {
+    Provider = 0,
+    RouteServer = 1,
+    RsClient = 2,
+    Customer = 3,
+    Peer = 4,
+}
+
+impl Role {
+    pub fn from_u8(val: u8) -> Option<Self> {
+        match val {
+            0 => Some(Role::Provider),
+            1 => Some(Role::RouteServer),
+            2 => Some(Role::RsClient),
+            3 => Some(Role::Customer),
+            4 => Some(Role::Peer),
+            _ => None,
+        }
+    }
+
+    /// Role the neighbor must announce for the session to come up.
+    pub fn neighbor(&self) -> Role {
+        match self {
+            Role::Provider => Role::Customer,
+            Role::RouteServer => Role::RsClient,
+            Role::RsClient => Role::RouteServer,
+            Role::Customer => Role::Provider,
+            Role::Peer => Role::Peer,
+        }
+    }
+
+    /// Only-to-Customer ingress procedure (RFC 9234 5). Routes with OTC
+    /// from a customer or RS-client, or with an OTC other than the peer's AS
+    /// from a lateral peer, are leaks and denied. Routes from a provider,
+    /// peer or route server are marked with the neighbor's AS.
+    pub fn otc_in(&self, otc: &mut Option<u32>, remote_as: u32) -> Action {
+        match (self.neighbor(), *otc) {
+            (Role::Customer | Role::RsClient, Some(_)) => Action::Deny,
+            (Role::Peer, Some(otc)) if otc != remote_as => Action::Deny,
+            (Role::Provider | Role::Peer | Role::RouteServer, None) => {
+                *otc = Some(remote_as);
+                Action::Permit
+            }
+            _ => Action::Permit,
+        }
+    }
+
+    /// Only-to-Customer egress procedure (RFC 9234 5). Routes with OTC are
+    /// not sent to providers, peers or route servers. Routes sent to
+    /// customers, peers or RS-clients are marked with the local AS.
+    pub fn otc_out(&self, otc: &mut Option<u32>, local_as: u32) -> Action {
+        let neighbor = self.neighbor();
+        if otc.is_some() && matches!(neighbor, Role::Provider | Role::Peer | Role::RouteServer) {
+            return Action::Deny;
+        }
+        if otc.is_none() && matches!(neighbor, Role::Customer | Role::Peer | Role::RsClient) {
+            *otc = Some(local_as);
+        }
+        Action::Permit
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Role::Provider => "provider",
+            Role::RouteServer => "rs-server",
+            Role::RsClient => "rs-client",
+            Role::Customer => "customer",
+            Role::Peer => "peer",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl FromStr for Role {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "provider" => Ok(Role::Provider),
+            "rs-server" => Ok(Role::RouteServer),
+            "rs-client" => Ok(Role::RsClient),
+            "customer" => Ok(Role::Customer),
+            "peer" => Ok(Role::Peer),
+            _ => Err(()),
+        }
+    }
+}
+
+fn role_mismatch() -> MessageNotification {
+    MessageNotification::new(NOTIFY_OPEN_ERR, NOTIFY_OPEN_ROLE_MISMATCH, Vec::new())
+}
+
+/// Check the BGP Role capabilities of a received OPEN against the local
+/// role (RFC 9234 4.2). Returns the neighbor's role, or the Role Mismatch
+/// NOTIFICATION when the roles do not pair up, several different roles
+/// were announced, or `strict` is set and the neighbor sent none.
+pub fn role_check(
+    local: Option<Role>,
+    strict: bool,
+    caps: &Capabilities,
+) -> Result<Option<Role>, MessageNotification> {
+    let mut remote: Option<u8> = None;
+    for cap in caps.get_ref() {
+        if let Capability::Role(val) = cap {
+            match remote {
+                Some(prev) if prev != *val => return Err(role_mismatch()),
+                _ => remote = Some(*val),
+            }
+        }
+    }
+    let local = match local {
+        Some(local) => local,
+        None => return Ok(remote.and_then(Role::from_u8)),
+    };
+    match remote {
+        Some(val) if Role::from_u8(val) == Some(local.neighbor()) => Ok(Some(local.neighbor())),
+        Some(_) => Err(role_mismatch()),
+        None if strict => Err(role_mismatch()),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn caps(roles: &[u8]) -> Capabilities {
+        let mut caps = Capabilities::new();
+        for role in roles {
+            caps.push(Capability::Role(*role));
+        }
+        caps
+    }
+
+    #[test]
+    fn check() {
+        let provider = Some(Role::Provider);
+        assert_eq!(
+            role_check(provider, false, &caps(&[3])),
+            Ok(Some(Role::Customer))
+        );
+        assert_eq!(
+            role_check(provider, false, &caps(&[4])),
+            Err(role_mismatch())
+        );
+        assert_eq!(
+            role_check(provider, false, &caps(&[9])),
+            Err(role_mismatch())
+        );
+        assert_eq!(role_check(provider, false, &caps(&[])), Ok(None));
+        assert_eq!(role_check(provider, true, &caps(&[])), Err(role_mismatch()));
+        assert_eq!(
+            role_check(None, true, &caps(&[3, 3])),
+            Ok(Some(Role::Customer))
+        );
+        assert_eq!(
+            role_check(None, false, &caps(&[3, 4])),
+            Err(role_mismatch())
+        );
+        assert_eq!(
+            role_check(Some(Role::RouteServer), false, &caps(&[2])),
+            Ok(Some(Role::RsClient))
+        );
+        assert_eq!(
+            format!("{}", role_mismatch()),
+            "OPEN Message Error/Role Mismatch"
+        );
+    }
+
+    #[test]
+    fn otc() {
+        // From a provider the route is marked with the provider's AS and
+        // not sent on to another provider or a peer.
+        let mut otc = None;
+        assert_eq!(Role::Customer.otc_in(&mut otc, 65001), Action::Permit);
+        assert_eq!(otc, Some(65001));
+        assert_eq!(
+            Role::Customer.otc_out(&mut otc.clone(), 65000),
+            Action::Deny
+        );
+        assert_eq!(Role::Peer.otc_out(&mut otc.clone(), 65000), Action::Deny);
+        assert_eq!(Role::Provider.otc_out(&mut otc, 65000), Action::Permit);
+        assert_eq!(otc, Some(65001));
+
+        // A customer must not send routes with OTC.
+        let mut otc = Some(65001);
+        assert_eq!(Role::Provider.otc_in(&mut otc, 65002), Action::Deny);
+        assert_eq!(Role::RouteServer.otc_in(&mut otc, 65002), Action::Deny);
+
+        // A lateral peer may only send routes with its own AS as OTC.
+        assert_eq!(Role::Peer.otc_in(&mut otc.clone(), 65002), Action::Deny);
+        assert_eq!(Role::Peer.otc_in(&mut otc, 65001), Action::Permit);
+
+        // Routes from customers are marked when sent to customers and peers.
+        let mut otc = None;
+        assert_eq!(Role::Provider.otc_in(&mut otc, 65002), Action::Permit);
+        assert_eq!(otc, None);
+        assert_eq!(
+            Role::Customer.otc_out(&mut otc.clone(), 65000),
+            Action::Permit
+        );
+        assert_eq!(Role::Peer.otc_out(&mut otc, 65000), Action::Permit);
+        assert_eq!(otc, Some(65000));
+    }
+}
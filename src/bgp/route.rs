@@ -0,0 +1,120 @@
+#![allow(dead_code)]
+
+use super::{AttrSet, DampeningParams, Prefix};
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// RPKI origin validation state of a route (RFC 6811).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum RpkiState {
+    Valid,
+    Invalid,
+    #[default]
+    NotFound,
+}
+
+impl fmt::Display for RpkiState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            RpkiState::Valid => "valid",
+            RpkiState::Invalid => "invalid",
+            RpkiState::NotFound => "notfound",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl FromStr for RpkiState {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "valid" => Ok(RpkiState::Valid),
+            "invalid" => Ok(RpkiState::Invalid),
+            "notfound" => Ok(RpkiState::NotFound),
+            _ => Err(()),
+        }
+    }
+}
+
+/// ASPA AS path verification state of a route.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum AspaState {
+    Valid,
+    Invalid,
+    #[default]
+    Unknown,
+}
+
+impl fmt::Display for AspaState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            AspaState::Valid => "valid",
+            AspaState::Invalid => "invalid",
+            AspaState::Unknown => "unknown",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl FromStr for AspaState {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "valid" => Ok(AspaState::Valid),
+            "invalid" => Ok(AspaState::Invalid),
+            "unknown" => Ok(AspaState::Unknown),
+            _ => Err(()),
+        }
+    }
+}
+
+/// A path to `prefix` together with the local properties which are not
+/// carried on the wire. The path attributes are shared, see `AttrStore`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Route {
+    pub prefix: Prefix,
+    pub attr: Arc<AttrSet>,
+    /// Neighbor the route was learned from, `None` for local routes.
+    pub peer: Option<IpAddr>,
+    /// Learned from an internal neighbor.
+    pub ibgp: bool,
+    pub weight: u32,
+    pub rpki: RpkiState,
+    pub aspa: AspaState,
+    /// Dampening parameters set by the dampening route-map.
+    pub dampening: Option<DampeningParams>,
+    /// Suppressed by route flap dampening.
+    pub damped: bool,
+}
+
+impl Route {
+    pub fn new(prefix: Prefix, attr: impl Into<Arc<AttrSet>>) -> Self {
+        Route {
+            prefix,
+            attr: attr.into(),
+            peer: None,
+            ibgp: false,
+            weight: 0,
+            rpki: RpkiState::default(),
+            aspa: AspaState::default(),
+            dampening: None,
+            damped: false,
+        }
+    }
+
+    /// The path attributes to modify, copied when shared.
+    pub fn attr_mut(&mut self) -> &mut AttrSet {
+        Arc::make_mut(&mut self.attr)
+    }
+
+    /// Whether the route is a candidate for best path selection. Damped
+    /// routes are not, nor are RPKI invalid routes when `disallow_invalid`
+    /// is configured (`bgp bestpath prefix-validate disallow-invalid`).
+    pub fn best_path_eligible(&self, disallow_invalid: bool) -> bool {
+        !(self.damped || disallow_invalid && self.rpki == RpkiState::Invalid)
+    }
+}
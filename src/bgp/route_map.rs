@@ -0,0 +1,515 @@
+#![allow(dead_code)]
+
+use super::{Action, Communities, Direction, LargeCommunities, Origin, Policy, PolicyContext};
+use super::{AspaState, AttrSet, DampeningParams, Route, RpkiState};
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RouteMapMatch {
+    PrefixList(String),
+    AsPathList(String),
+    CommunityList { name: String, exact: bool },
+    LargeCommunityList { name: String, exact: bool },
+    NextHop(IpAddr),
+    Med(u32),
+    LocalPref(u32),
+    Origin(Origin),
+    Rpki(RpkiState),
+    Aspa(AspaState),
+    Peer(IpAddr),
+}
+
+impl RouteMapMatch {
+    /// Lists referenced by name which do not exist never match.
+    fn matches(&self, policy: &Policy, route: &Route, ctx: &PolicyContext) -> bool {
+        match self {
+            RouteMapMatch::PrefixList(name) => match policy.prefix_lists.get(name) {
+                Some(plist) => plist.apply(&route.prefix) == Action::Permit,
+                None => false,
+            },
+            RouteMapMatch::AsPathList(name) => match policy.as_path_lists.get(name) {
+                Some(list) => list.apply(&route.attr.as_path) == Action::Permit,
+                None => false,
+            },
+            RouteMapMatch::CommunityList { name, exact } => {
+                let list = match policy.community_lists.get(name) {
+                    Some(list) => list,
+                    None => return false,
+                };
+                let empty = Communities::new();
+                let coms = route.attr.communities.as_deref().unwrap_or(&empty);
+                let action = if *exact {
+                    list.apply_exact(coms)
+                } else {
+                    list.apply(coms)
+                };
+                action == Action::Permit
+            }
+            RouteMapMatch::LargeCommunityList { name, exact } => {
+                let list = match policy.large_community_lists.get(name) {
+                    Some(list) => list,
+                    None => return false,
+                };
+                let empty = LargeCommunities::new();
+                let coms = route.attr.large_communities.as_deref().unwrap_or(&empty);
+                let action = if *exact {
+                    list.apply_exact(coms)
+                } else {
+                    list.apply(coms)
+                };
+                action == Action::Permit
+            }
+            RouteMapMatch::NextHop(addr) => route.attr.next_hop == Some(*addr),
+            RouteMapMatch::Med(med) => route.attr.med == Some(*med),
+            RouteMapMatch::LocalPref(local_pref) => route.attr.local_pref == Some(*local_pref),
+            RouteMapMatch::Origin(origin) => route.attr.origin == *origin,
+            RouteMapMatch::Rpki(state) => route.rpki == *state,
+            RouteMapMatch::Aspa(state) => route.aspa == *state,
+            RouteMapMatch::Peer(addr) => ctx.peer == Some(*addr),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RouteMapSet {
+    LocalPref(u32),
+    Med(u32),
+    Weight(u32),
+    AsPathPrepend(Vec<u32>),
+    AsPathExclude(Vec<u32>),
+    CommunityAdd(Communities),
+    CommunityReplace(Communities),
+    /// Delete communities permitted by the named community list.
+    CommunityDelete(String),
+    LargeCommunityAdd(LargeCommunities),
+    LargeCommunityReplace(LargeCommunities),
+    /// Delete large communities permitted by the named large community list.
+    LargeCommunityDelete(String),
+    NextHop(IpAddr),
+    NextHopSelf,
+    /// The neighbor address for inbound policy, our own session address for
+    /// outbound policy.
+    NextHopPeerAddress,
+    Origin(Origin),
+    /// Per route dampening parameters, used by `bgp dampening route-map`.
+    Dampening(DampeningParams),
+}
+
+impl RouteMapSet {
+    fn apply(&self, policy: &Policy, route: &mut Route, ctx: &PolicyContext) {
+        match self {
+            RouteMapSet::Weight(v) => route.weight = *v,
+            RouteMapSet::Dampening(params) => route.dampening = Some(*params),
+            _ => self.apply_attr(policy, route.attr_mut(), ctx),
+        }
+    }
+
+    fn apply_attr(&self, policy: &Policy, attr: &mut AttrSet, ctx: &PolicyContext) {
+        match self {
+            RouteMapSet::LocalPref(v) => attr.local_pref = Some(*v),
+            RouteMapSet::Med(v) => attr.med = Some(*v),
+            RouteMapSet::AsPathPrepend(asns) => {
+                for asn in asns.iter().rev() {
+                    attr.as_path_mut().prepend(*asn, 1);
+                }
+            }
+            RouteMapSet::AsPathExclude(asns) => {
+                for asn in asns.iter() {
+                    attr.as_path_mut().exclude(*asn);
+                }
+            }
+            RouteMapSet::CommunityAdd(coms) => match attr.communities_mut() {
+                Some(c) => c.merge(coms),
+                None => attr.communities = Some(Arc::new(coms.clone())),
+            },
+            RouteMapSet::CommunityReplace(coms) => {
+                attr.communities = if coms.is_empty() {
+                    None
+                } else {
+                    Some(Arc::new(coms.clone()))
+                };
+            }
+            RouteMapSet::CommunityDelete(name) => {
+                if let (Some(list), Some(coms)) =
+                    (policy.community_lists.get(name), attr.communities_mut())
+                {
+                    list.delete(coms);
+                    if coms.is_empty() {
+                        attr.communities = None;
+                    }
+                }
+            }
+            RouteMapSet::LargeCommunityAdd(coms) => match attr.large_communities_mut() {
+                Some(c) => c.merge(coms),
+                None => attr.large_communities = Some(Arc::new(coms.clone())),
+            },
+            RouteMapSet::LargeCommunityReplace(coms) => {
+                attr.large_communities = if coms.is_empty() {
+                    None
+                } else {
+                    Some(Arc::new(coms.clone()))
+                };
+            }
+            RouteMapSet::LargeCommunityDelete(name) => {
+                if let (Some(list), Some(coms)) = (
+                    policy.large_community_lists.get(name),
+                    attr.large_communities_mut(),
+                ) {
+                    list.delete(coms);
+                    if coms.is_empty() {
+                        attr.large_communities = None;
+                    }
+                }
+            }
+            RouteMapSet::NextHop(addr) => attr.next_hop = Some(*addr),
+            RouteMapSet::NextHopSelf => {
+                if ctx.local.is_some() {
+                    attr.next_hop = ctx.local;
+                }
+            }
+            RouteMapSet::NextHopPeerAddress => {
+                let addr = match ctx.direction {
+                    Direction::In => ctx.peer,
+                    Direction::Out => ctx.local,
+                };
+                if addr.is_some() {
+                    attr.next_hop = addr;
+                }
+            }
+            RouteMapSet::Origin(origin) => attr.origin = *origin,
+            RouteMapSet::Weight(_) | RouteMapSet::Dampening(_) => {}
+        }
+    }
+}
+
+/// What to do after a permit entry matched and its set clauses ran.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OnMatch {
+    /// Stop and permit the route.
+    Exit,
+    /// Continue with the following entry (`on-match next`, `continue`).
+    Next,
+    /// Continue with the entry of the given sequence number (`on-match goto`,
+    /// `continue <seq>`). Only forward jumps are allowed.
+    Goto(u32),
+}
+
+#[derive(Clone, Debug)]
+pub struct RouteMapEntry {
+    pub action: Action,
+    pub matches: Vec<RouteMapMatch>,
+    pub sets: Vec<RouteMapSet>,
+    pub on_match: OnMatch,
+}
+
+impl RouteMapEntry {
+    pub fn new(action: Action) -> Self {
+        RouteMapEntry {
+            action,
+            matches: Vec::new(),
+            sets: Vec::new(),
+            on_match: OnMatch::Exit,
+        }
+    }
+
+    /// An entry without match clauses matches every route.
+    fn matches(&self, policy: &Policy, route: &Route, ctx: &PolicyContext) -> bool {
+        self.matches.iter().all(|m| m.matches(policy, route, ctx))
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RouteMap {
+    entries: BTreeMap<u32, RouteMapEntry>,
+}
+
+impl RouteMap {
+    pub fn new() -> Self {
+        RouteMap {
+            entries: BTreeMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, seq: u32, entry: RouteMapEntry) -> Option<RouteMapEntry> {
+        self.entries.insert(seq, entry)
+    }
+
+    pub fn remove(&mut self, seq: u32) -> Option<RouteMapEntry> {
+        self.entries.remove(&seq)
+    }
+
+    pub fn get_mut(&mut self, seq: u32) -> Option<&mut RouteMapEntry> {
+        self.entries.get_mut(&seq)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Evaluate entries in sequence order. A matching deny entry denies the
+    /// route immediately. A matching permit entry applies its set clauses and
+    /// either permits the route or moves on according to `on_match`, in which
+    /// case the route stays permitted unless a later deny entry matches. A
+    /// route which matches no entry is denied.
+    pub fn apply(&self, policy: &Policy, route: &mut Route, ctx: &PolicyContext) -> Action {
+        self.walk(|entry| {
+            if !entry.matches(policy, route, ctx) {
+                return false;
+            }
+            if entry.action == Action::Permit {
+                for set in entry.sets.iter() {
+                    set.apply(policy, route, ctx);
+                }
+            }
+            true
+        })
+    }
+
+    /// Evaluate the entries like `apply()` without applying set clauses,
+    /// entries reached through `on_match` see the route unchanged.
+    pub fn check(&self, policy: &Policy, route: &Route, ctx: &PolicyContext) -> Action {
+        self.walk(|entry| entry.matches(policy, route, ctx))
+    }
+
+    /// Walk the entries in sequence order, `matched` tells whether the
+    /// route matches an entry and applies its set clauses.
+    fn walk(&self, mut matched: impl FnMut(&RouteMapEntry) -> bool) -> Action {
+        let mut result = Action::Deny;
+        let mut start = 0u32;
+
+        'outer: loop {
+            for (&seq, entry) in self.entries.range(start..) {
+                if !matched(entry) {
+                    continue;
+                }
+                if entry.action == Action::Deny {
+                    return Action::Deny;
+                }
+                result = Action::Permit;
+
+                match entry.on_match {
+                    OnMatch::Exit => return Action::Permit,
+                    OnMatch::Next => continue,
+                    OnMatch::Goto(next) if next > seq => {
+                        start = next;
+                        continue 'outer;
+                    }
+                    OnMatch::Goto(_) => return Action::Permit,
+                }
+            }
+            return result;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bgp::{Attr, CommunityList, CommunityListEntry, PrefixList, PrefixListEntry};
+    use crate::bgp::{LargeCommunityList, LargeCommunityListEntry};
+
+    fn route(prefix: &str) -> Route {
+        let mut attr = Attr::new();
+        attr.as_path = "100 200".parse().unwrap();
+        attr.next_hop = Some("192.168.0.1".parse().unwrap());
+        Route::new(prefix.parse().unwrap(), attr)
+    }
+
+    fn policy() -> Policy {
+        let mut policy = Policy::new();
+        let mut plist = PrefixList::new();
+        plist.push(PrefixListEntry {
+            action: Action::Permit,
+            prefix: "10.0.0.0/8".parse().unwrap(),
+            ge: None,
+            le: Some(24),
+        });
+        policy.prefix_lists.insert("TEN".to_string(), plist);
+
+        let mut clist = CommunityList::new();
+        clist.push(CommunityListEntry::standard(
+            Action::Permit,
+            Communities::from_str("100:1").unwrap(),
+        ));
+        policy.community_lists.insert("C1".to_string(), clist);
+        policy
+    }
+
+    #[test]
+    fn apply() {
+        let mut policy = policy();
+        let mut map = RouteMap::new();
+
+        let mut entry = RouteMapEntry::new(Action::Deny);
+        entry.matches.push(RouteMapMatch::Rpki(RpkiState::Invalid));
+        map.insert(5, entry);
+
+        let mut entry = RouteMapEntry::new(Action::Permit);
+        entry
+            .matches
+            .push(RouteMapMatch::PrefixList("TEN".to_string()));
+        entry.sets.push(RouteMapSet::LocalPref(200));
+        entry
+            .sets
+            .push(RouteMapSet::AsPathPrepend(vec![65000, 65000]));
+        entry.sets.push(RouteMapSet::CommunityAdd(
+            Communities::from_str("100:1 100:2").unwrap(),
+        ));
+        map.insert(10, entry);
+        policy.route_maps.insert("IN".to_string(), map);
+
+        let ctx = PolicyContext::new(Direction::In, Some("192.168.0.2".parse().unwrap()), None);
+
+        let mut r = route("10.1.0.0/16");
+        assert_eq!(policy.apply("IN", &mut r, &ctx), Action::Permit);
+        assert_eq!(r.attr.local_pref, Some(200));
+        assert_eq!(format!("{}", r.attr.as_path), "65000 65000 100 200");
+        assert_eq!(
+            format!("{}", r.attr.communities.as_ref().unwrap()),
+            "100:1 100:2"
+        );
+
+        let mut r = route("10.1.0.0/16");
+        r.rpki = RpkiState::Invalid;
+        assert_eq!(policy.apply("IN", &mut r, &ctx), Action::Deny);
+
+        let mut r = route("172.16.0.0/16");
+        assert_eq!(policy.apply("IN", &mut r, &ctx), Action::Deny);
+        assert_eq!(r.attr.local_pref, None);
+
+        let mut r = route("10.1.0.0/16");
+        assert_eq!(policy.apply("UNDEFINED", &mut r, &ctx), Action::Deny);
+    }
+
+    #[test]
+    fn on_match() {
+        let mut policy = policy();
+        let mut map = RouteMap::new();
+
+        let mut entry = RouteMapEntry::new(Action::Permit);
+        entry.sets.push(RouteMapSet::Med(10));
+        entry.on_match = OnMatch::Goto(30);
+        map.insert(10, entry);
+
+        let mut entry = RouteMapEntry::new(Action::Permit);
+        entry.sets.push(RouteMapSet::Med(20));
+        map.insert(20, entry);
+
+        let mut entry = RouteMapEntry::new(Action::Permit);
+        entry.matches.push(RouteMapMatch::CommunityList {
+            name: "C1".to_string(),
+            exact: false,
+        });
+        entry
+            .sets
+            .push(RouteMapSet::CommunityDelete("C1".to_string()));
+        entry.sets.push(RouteMapSet::Weight(100));
+        entry.on_match = OnMatch::Next;
+        map.insert(30, entry);
+
+        let mut entry = RouteMapEntry::new(Action::Deny);
+        entry.matches.push(RouteMapMatch::Med(99));
+        map.insert(40, entry);
+        policy.route_maps.insert("OUT".to_string(), map);
+
+        let ctx = PolicyContext::new(
+            Direction::Out,
+            Some("192.168.0.2".parse().unwrap()),
+            Some("192.168.0.254".parse().unwrap()),
+        );
+
+        // Entry 20 is skipped and 30 does not match, the result of 10 stands.
+        let mut r = route("10.0.0.0/8");
+        assert_eq!(policy.apply("OUT", &mut r, &ctx), Action::Permit);
+        assert_eq!(r.attr.med, Some(10));
+        assert_eq!(r.weight, 0);
+
+        let mut r = route("10.0.0.0/8");
+        r.attr_mut().communities = Communities::from_str("100:1").map(Arc::new);
+        assert_eq!(policy.apply("OUT", &mut r, &ctx), Action::Permit);
+        assert_eq!(r.attr.communities, None);
+        assert_eq!(r.weight, 100);
+
+        // A later deny entry overrides an earlier permit.
+        policy
+            .route_maps
+            .get_mut("OUT")
+            .unwrap()
+            .get_mut(10)
+            .unwrap()
+            .sets
+            .push(RouteMapSet::Med(99));
+        let mut r = route("10.0.0.0/8");
+        assert_eq!(policy.apply("OUT", &mut r, &ctx), Action::Deny);
+
+        // Checked without the set clauses, entry 40 sees no MED.
+        let r = route("10.0.0.0/8");
+        assert_eq!(policy.check("OUT", &r, &ctx), Action::Permit);
+        assert_eq!(policy.check("NONE", &r, &ctx), Action::Deny);
+    }
+
+    #[test]
+    fn large_community() {
+        let mut policy = Policy::new();
+        let mut list = LargeCommunityList::new();
+        list.push(LargeCommunityListEntry::expanded(Action::Permit, "^65000:0:").unwrap());
+        policy
+            .large_community_lists
+            .insert("IXP-ACTION".to_string(), list);
+
+        let mut map = RouteMap::new();
+        let mut entry = RouteMapEntry::new(Action::Permit);
+        entry.matches.push(RouteMapMatch::LargeCommunityList {
+            name: "IXP-ACTION".to_string(),
+            exact: false,
+        });
+        entry
+            .sets
+            .push(RouteMapSet::LargeCommunityDelete("IXP-ACTION".to_string()));
+        entry.sets.push(RouteMapSet::LargeCommunityAdd(
+            LargeCommunities::from_str("65001:1:1").unwrap(),
+        ));
+        map.insert(10, entry);
+        policy.route_maps.insert("LC".to_string(), map);
+
+        let ctx = PolicyContext::local();
+        let mut r = route("10.0.0.0/8");
+        assert_eq!(policy.apply("LC", &mut r, &ctx), Action::Deny);
+
+        r.attr_mut().large_communities =
+            LargeCommunities::from_str("65000:0:6939 65002:1:1").map(Arc::new);
+        assert_eq!(policy.apply("LC", &mut r, &ctx), Action::Permit);
+        assert_eq!(
+            format!("{}", r.attr.large_communities.as_ref().unwrap()),
+            "65002:1:1 65001:1:1"
+        );
+    }
+
+    #[test]
+    fn next_hop() {
+        let mut policy = Policy::new();
+        let mut map = RouteMap::new();
+        let mut entry = RouteMapEntry::new(Action::Permit);
+        entry.sets.push(RouteMapSet::NextHopPeerAddress);
+        map.insert(10, entry);
+        policy.route_maps.insert("NH".to_string(), map);
+
+        let peer: IpAddr = "192.168.0.2".parse().unwrap();
+        let local: IpAddr = "192.168.0.254".parse().unwrap();
+
+        let mut r = route("10.0.0.0/8");
+        let ctx = PolicyContext::new(Direction::In, Some(peer), Some(local));
+        policy.apply("NH", &mut r, &ctx);
+        assert_eq!(r.attr.next_hop, Some(peer));
+
+        let ctx = PolicyContext::new(Direction::Out, Some(peer), Some(local));
+        policy.apply("NH", &mut r, &ctx);
+        assert_eq!(r.attr.next_hop, Some(local));
+    }
+}
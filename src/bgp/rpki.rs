@@ -0,0 +1,1103 @@
+#![allow(dead_code)]
+
+use super::{AspaTable, Prefix, Route, RpkiState};
+use byteorder::{NetworkEndian, ReadBytesExt, WriteBytesExt};
+use bytes::{Buf, BytesMut};
+use futures::sink::SinkExt;
+use std::collections::BTreeMap;
+use std::io::{Cursor, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio_stream::StreamExt;
+use tokio_util::codec::{Decoder, Encoder, Framed};
+
+pub const RTR_PORT: u16 = 323;
+/// Version 2 carries ASPA records, older caches are negotiated down.
+pub const RTR_VERSION: u8 = 2;
+
+const RTR_HEADER_LEN: usize = 8;
+/// Upper bound on a PDU, anything larger is treated as corrupt.
+const RTR_PDU_MAX_LEN: usize = 65535;
+
+const PDU_SERIAL_NOTIFY: u8 = 0;
+const PDU_SERIAL_QUERY: u8 = 1;
+const PDU_RESET_QUERY: u8 = 2;
+const PDU_CACHE_RESPONSE: u8 = 3;
+const PDU_IPV4_PREFIX: u8 = 4;
+const PDU_IPV6_PREFIX: u8 = 6;
+const PDU_END_OF_DATA: u8 = 7;
+const PDU_CACHE_RESET: u8 = 8;
+const PDU_ROUTER_KEY: u8 = 9;
+const PDU_ERROR_REPORT: u8 = 10;
+const PDU_ASPA: u8 = 11;
+
+// Error Report codes (RFC 8210 12).
+pub const RTR_ERR_CORRUPT_DATA: u16 = 0;
+pub const RTR_ERR_INTERNAL: u16 = 1;
+pub const RTR_ERR_NO_DATA: u16 = 2;
+pub const RTR_ERR_INVALID_REQUEST: u16 = 3;
+pub const RTR_ERR_UNSUP_VERSION: u16 = 4;
+pub const RTR_ERR_UNSUP_PDU_TYPE: u16 = 5;
+pub const RTR_ERR_WITHDRAW_UNKNOWN: u16 = 6;
+pub const RTR_ERR_DUPLICATE_ANNOUNCE: u16 = 7;
+pub const RTR_ERR_UNEXPECTED_VERSION: u16 = 8;
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum RtrError {
+    #[error("corrupt RTR PDU")]
+    Corrupt,
+    #[error("unexpected RTR protocol version {0}")]
+    Version(u8),
+    #[error("unsupported RTR PDU type {0}")]
+    PduType(u8),
+    #[error("unexpected RTR PDU {0}")]
+    Unexpected(&'static str),
+    #[error("withdrawal of unknown VRP {0}")]
+    WithdrawUnknown(Vrp),
+    #[error("withdrawal of unknown ASPA of AS{0}")]
+    WithdrawUnknownAspa(u32),
+    #[error("duplicate announcement of VRP {0}")]
+    DuplicateAnnounce(Vrp),
+    #[error("cache reported error {code}: {text}")]
+    Report { code: u16, text: String },
+}
+
+/// Validated ROA Payload, `asn` may originate `prefix` and more specifics up
+/// to `max_len`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Vrp {
+    pub prefix: Prefix,
+    pub max_len: u8,
+    pub asn: u32,
+}
+
+impl Vrp {
+    pub fn new(prefix: Prefix, max_len: u8, asn: u32) -> Self {
+        Vrp {
+            prefix,
+            max_len,
+            asn,
+        }
+    }
+}
+
+impl std::fmt::Display for Vrp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{} AS{}", self.prefix, self.max_len, self.asn)
+    }
+}
+
+/// VRPs indexed by prefix.
+#[derive(Debug, Default)]
+pub struct VrpTable {
+    vrps: BTreeMap<Prefix, Vec<Vrp>>,
+    len: usize,
+}
+
+impl VrpTable {
+    pub fn new() -> Self {
+        VrpTable::default()
+    }
+
+    pub fn insert(&mut self, vrp: Vrp) -> bool {
+        let vrps = self.vrps.entry(vrp.prefix).or_default();
+        if vrps.contains(&vrp) {
+            return false;
+        }
+        vrps.push(vrp);
+        self.len += 1;
+        true
+    }
+
+    pub fn remove(&mut self, vrp: &Vrp) -> bool {
+        let vrps = match self.vrps.get_mut(&vrp.prefix) {
+            Some(vrps) => vrps,
+            None => return false,
+        };
+        let len = vrps.len();
+        vrps.retain(|v| v != vrp);
+        let removed = vrps.len() != len;
+        if vrps.is_empty() {
+            self.vrps.remove(&vrp.prefix);
+        }
+        if removed {
+            self.len -= 1;
+        }
+        removed
+    }
+
+    pub fn contains(&self, vrp: &Vrp) -> bool {
+        match self.vrps.get(&vrp.prefix) {
+            Some(vrps) => vrps.contains(vrp),
+            None => false,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.vrps.clear();
+        self.len = 0;
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Vrp> {
+        self.vrps.values().flatten()
+    }
+
+    /// Route origin validation of `prefix` originated by `origin` (RFC 6811
+    /// 2). `None` stands for an origin which can not be determined, such as
+    /// a path ending in an AS_SET, and never matches.
+    pub fn validate(&self, prefix: &Prefix, origin: Option<u32>) -> RpkiState {
+        let mut covered = false;
+        for len in 0..=prefix.prefixlen() {
+            let covering = match Prefix::new(prefix.addr(), len) {
+                Ok(covering) => covering,
+                Err(_) => continue,
+            };
+            for vrp in self.vrps.get(&covering).into_iter().flatten() {
+                covered = true;
+                if prefix.prefixlen() <= vrp.max_len && vrp.asn != 0 && origin == Some(vrp.asn) {
+                    return RpkiState::Valid;
+                }
+            }
+        }
+        if covered {
+            RpkiState::Invalid
+        } else {
+            RpkiState::NotFound
+        }
+    }
+
+    /// Set the validation state of `route`. Routes with an empty AS path
+    /// originate in `local_as`.
+    pub fn validate_route(&self, route: &mut Route, local_as: u32) {
+        let origin = if route.attr.as_path.is_empty() {
+            Some(local_as)
+        } else {
+            route.attr.as_path.origin_as()
+        };
+        route.rpki = self.validate(&route.prefix, origin);
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RtrPdu {
+    SerialNotify {
+        session_id: u16,
+        serial: u32,
+    },
+    SerialQuery {
+        session_id: u16,
+        serial: u32,
+    },
+    ResetQuery,
+    CacheResponse {
+        session_id: u16,
+    },
+    Prefix {
+        announce: bool,
+        vrp: Vrp,
+    },
+    EndOfData {
+        session_id: u16,
+        serial: u32,
+        refresh: u32,
+        retry: u32,
+        expire: u32,
+    },
+    CacheReset,
+    /// BGPsec router keys are not used and only skipped.
+    RouterKey,
+    /// Providers of `customer`, none for a withdrawal (version 2).
+    Aspa {
+        announce: bool,
+        customer: u32,
+        providers: Vec<u32>,
+    },
+    ErrorReport {
+        code: u16,
+        pdu: Vec<u8>,
+        text: String,
+    },
+}
+
+impl RtrPdu {
+    pub fn to_bytes(&self, version: u8, buf: &mut Vec<u8>) -> Result<usize, anyhow::Error> {
+        let start = buf.len();
+        let (typ, field) = match self {
+            RtrPdu::SerialNotify { session_id, .. } => (PDU_SERIAL_NOTIFY, *session_id),
+            RtrPdu::SerialQuery { session_id, .. } => (PDU_SERIAL_QUERY, *session_id),
+            RtrPdu::ResetQuery => (PDU_RESET_QUERY, 0),
+            RtrPdu::CacheResponse { session_id } => (PDU_CACHE_RESPONSE, *session_id),
+            RtrPdu::Prefix { vrp, .. } if vrp.prefix.is_ipv4() => (PDU_IPV4_PREFIX, 0),
+            RtrPdu::Prefix { .. } => (PDU_IPV6_PREFIX, 0),
+            RtrPdu::EndOfData { session_id, .. } => (PDU_END_OF_DATA, *session_id),
+            RtrPdu::CacheReset => (PDU_CACHE_RESET, 0),
+            RtrPdu::RouterKey => return Err(RtrError::PduType(PDU_ROUTER_KEY).into()),
+            RtrPdu::ErrorReport { code, .. } => (PDU_ERROR_REPORT, *code),
+            // Flags in the first octet, the second is zero.
+            RtrPdu::Aspa { announce, .. } => (PDU_ASPA, (*announce as u16) << 8),
+        };
+        buf.write_u8(version)?;
+        buf.write_u8(typ)?;
+        buf.write_u16::<NetworkEndian>(field)?;
+        // Length is filled in below.
+        buf.write_u32::<NetworkEndian>(0)?;
+
+        match self {
+            RtrPdu::SerialNotify { serial, .. } | RtrPdu::SerialQuery { serial, .. } => {
+                buf.write_u32::<NetworkEndian>(*serial)?;
+            }
+            RtrPdu::Prefix { announce, vrp } => {
+                buf.write_u8(*announce as u8)?;
+                buf.write_u8(vrp.prefix.prefixlen())?;
+                buf.write_u8(vrp.max_len)?;
+                buf.write_u8(0)?;
+                match vrp.prefix.addr() {
+                    IpAddr::V4(addr) => buf.extend_from_slice(&addr.octets()),
+                    IpAddr::V6(addr) => buf.extend_from_slice(&addr.octets()),
+                }
+                buf.write_u32::<NetworkEndian>(vrp.asn)?;
+            }
+            RtrPdu::EndOfData {
+                serial,
+                refresh,
+                retry,
+                expire,
+                ..
+            } => {
+                buf.write_u32::<NetworkEndian>(*serial)?;
+                if version > 0 {
+                    buf.write_u32::<NetworkEndian>(*refresh)?;
+                    buf.write_u32::<NetworkEndian>(*retry)?;
+                    buf.write_u32::<NetworkEndian>(*expire)?;
+                }
+            }
+            RtrPdu::ErrorReport { pdu, text, .. } => {
+                buf.write_u32::<NetworkEndian>(pdu.len() as u32)?;
+                buf.extend_from_slice(pdu);
+                buf.write_u32::<NetworkEndian>(text.len() as u32)?;
+                buf.extend_from_slice(text.as_bytes());
+            }
+            RtrPdu::Aspa {
+                customer,
+                providers,
+                ..
+            } => {
+                buf.write_u32::<NetworkEndian>(*customer)?;
+                for provider in providers {
+                    buf.write_u32::<NetworkEndian>(*provider)?;
+                }
+            }
+            _ => {}
+        }
+
+        let len = buf.len() - start;
+        buf[start + 4..start + 8].copy_from_slice(&(len as u32).to_be_bytes());
+        Ok(len)
+    }
+
+    /// Decode one complete PDU of `buf.len()` octets.
+    pub fn from_bytes(buf: &[u8], version: u8) -> Result<RtrPdu, RtrError> {
+        if buf.len() < RTR_HEADER_LEN {
+            return Err(RtrError::Corrupt);
+        }
+        let mut c = Cursor::new(buf);
+        let pdu_version = c.read_u8().map_err(|_| RtrError::Corrupt)?;
+        let typ = c.read_u8().map_err(|_| RtrError::Corrupt)?;
+        let field = c
+            .read_u16::<NetworkEndian>()
+            .map_err(|_| RtrError::Corrupt)?;
+        c.set_position(RTR_HEADER_LEN as u64);
+
+        // Error reports are understood whatever their version.
+        if pdu_version != version && typ != PDU_ERROR_REPORT {
+            return Err(RtrError::Version(pdu_version));
+        }
+        let len = buf.len();
+        let read_u32 =
+            |c: &mut Cursor<&[u8]>| c.read_u32::<NetworkEndian>().map_err(|_| RtrError::Corrupt);
+
+        let pdu = match typ {
+            PDU_SERIAL_NOTIFY | PDU_SERIAL_QUERY if len == 12 => {
+                let serial = read_u32(&mut c)?;
+                if typ == PDU_SERIAL_NOTIFY {
+                    RtrPdu::SerialNotify {
+                        session_id: field,
+                        serial,
+                    }
+                } else {
+                    RtrPdu::SerialQuery {
+                        session_id: field,
+                        serial,
+                    }
+                }
+            }
+            PDU_RESET_QUERY if len == 8 => RtrPdu::ResetQuery,
+            PDU_CACHE_RESPONSE if len == 8 => RtrPdu::CacheResponse { session_id: field },
+            PDU_IPV4_PREFIX | PDU_IPV6_PREFIX => {
+                let ipv6 = typ == PDU_IPV6_PREFIX;
+                if len != if ipv6 { 32 } else { 20 } {
+                    return Err(RtrError::Corrupt);
+                }
+                let flags = buf[8];
+                let prefix_len = buf[9];
+                let max_len = buf[10];
+                c.set_position(12);
+                let addr = if ipv6 {
+                    let mut octets = [0u8; 16];
+                    c.read_exact(&mut octets).map_err(|_| RtrError::Corrupt)?;
+                    IpAddr::V6(Ipv6Addr::from(octets))
+                } else {
+                    IpAddr::V4(Ipv4Addr::from(read_u32(&mut c)?))
+                };
+                let asn = read_u32(&mut c)?;
+                let prefix = Prefix::new(addr, prefix_len).map_err(|_| RtrError::Corrupt)?;
+                if max_len < prefix_len || max_len > prefix.max_prefixlen() {
+                    return Err(RtrError::Corrupt);
+                }
+                RtrPdu::Prefix {
+                    announce: flags & 0x01 != 0,
+                    vrp: Vrp::new(prefix, max_len, asn),
+                }
+            }
+            PDU_END_OF_DATA if (version == 0 && len == 12) || (version > 0 && len == 24) => {
+                let serial = read_u32(&mut c)?;
+                let (refresh, retry, expire) = if version > 0 {
+                    (read_u32(&mut c)?, read_u32(&mut c)?, read_u32(&mut c)?)
+                } else {
+                    (3600, 600, 7200)
+                };
+                RtrPdu::EndOfData {
+                    session_id: field,
+                    serial,
+                    refresh,
+                    retry,
+                    expire,
+                }
+            }
+            PDU_CACHE_RESET if len == 8 => RtrPdu::CacheReset,
+            PDU_ROUTER_KEY => RtrPdu::RouterKey,
+            PDU_ERROR_REPORT => {
+                let pdu_len = read_u32(&mut c)? as usize;
+                let start = c.position() as usize;
+                if start + pdu_len + 4 > len {
+                    return Err(RtrError::Corrupt);
+                }
+                let pdu = buf[start..start + pdu_len].to_vec();
+                c.set_position((start + pdu_len) as u64);
+                let text_len = read_u32(&mut c)? as usize;
+                let start = c.position() as usize;
+                if start + text_len > len {
+                    return Err(RtrError::Corrupt);
+                }
+                let text = String::from_utf8_lossy(&buf[start..start + text_len]).to_string();
+                RtrPdu::ErrorReport {
+                    code: field,
+                    pdu,
+                    text,
+                }
+            }
+            PDU_ASPA if version >= 2 && len >= 12 && len.is_multiple_of(4) => {
+                let customer = read_u32(&mut c)?;
+                let mut providers = Vec::new();
+                for _ in 0..(len - 12) / 4 {
+                    providers.push(read_u32(&mut c)?);
+                }
+                RtrPdu::Aspa {
+                    announce: field >> 8 & 0x01 != 0,
+                    customer,
+                    providers,
+                }
+            }
+            PDU_ASPA if version >= 2 => return Err(RtrError::Corrupt),
+            PDU_SERIAL_NOTIFY | PDU_SERIAL_QUERY | PDU_RESET_QUERY | PDU_CACHE_RESPONSE
+            | PDU_END_OF_DATA | PDU_CACHE_RESET => return Err(RtrError::Corrupt),
+            _ => return Err(RtrError::PduType(typ)),
+        };
+        Ok(pdu)
+    }
+}
+
+/// Frames RTR PDUs on the cache connection.
+pub struct RtrCodec {
+    pub version: u8,
+}
+
+impl RtrCodec {
+    pub fn new(version: u8) -> Self {
+        RtrCodec { version }
+    }
+}
+
+impl Decoder for RtrCodec {
+    type Item = RtrPdu;
+    type Error = anyhow::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<RtrPdu>, anyhow::Error> {
+        if src.len() < RTR_HEADER_LEN {
+            return Ok(None);
+        }
+        let len = u32::from_be_bytes([src[4], src[5], src[6], src[7]]) as usize;
+        if !(RTR_HEADER_LEN..=RTR_PDU_MAX_LEN).contains(&len) {
+            return Err(RtrError::Corrupt.into());
+        }
+        if src.len() < len {
+            return Ok(None);
+        }
+        let pdu = RtrPdu::from_bytes(&src[..len], self.version);
+        src.advance(len);
+        Ok(Some(pdu?))
+    }
+}
+
+impl Encoder<RtrPdu> for RtrCodec {
+    type Error = anyhow::Error;
+
+    fn encode(&mut self, pdu: RtrPdu, dst: &mut BytesMut) -> Result<(), anyhow::Error> {
+        let mut buf = Vec::new();
+        pdu.to_bytes(self.version, &mut buf)?;
+        dst.extend_from_slice(&buf);
+        Ok(())
+    }
+}
+
+/// Everything learned from validator caches.
+#[derive(Debug, Default)]
+pub struct RpkiTable {
+    pub vrps: VrpTable,
+    pub aspas: AspaTable,
+}
+
+impl RpkiTable {
+    pub fn new() -> Self {
+        RpkiTable::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.vrps.clear();
+        self.aspas.clear();
+    }
+
+    /// The ASPAs delivered by the caches, or `local` while there are none.
+    pub fn aspas<'a>(&'a self, local: &'a AspaTable) -> &'a AspaTable {
+        if self.aspas.is_empty() {
+            local
+        } else {
+            &self.aspas
+        }
+    }
+}
+
+/// RTR client state, independent of the transport. Changes of one cache
+/// response are staged and applied to the table at End of Data.
+#[derive(Debug)]
+pub struct RtrSession {
+    pub session_id: Option<u16>,
+    pub serial: Option<u32>,
+    pub refresh: Duration,
+    pub retry: Duration,
+    pub expire: Duration,
+    reset: bool,
+    response: Option<Vec<RtrPdu>>,
+}
+
+impl Default for RtrSession {
+    fn default() -> Self {
+        RtrSession {
+            session_id: None,
+            serial: None,
+            refresh: Duration::from_secs(3600),
+            retry: Duration::from_secs(600),
+            expire: Duration::from_secs(7200),
+            reset: false,
+            response: None,
+        }
+    }
+}
+
+impl RtrSession {
+    pub fn new() -> Self {
+        RtrSession::default()
+    }
+
+    /// Query to send: an incremental Serial Query once synchronized, a
+    /// Reset Query otherwise.
+    pub fn query(&mut self) -> RtrPdu {
+        match (self.session_id, self.serial) {
+            (Some(session_id), Some(serial)) => {
+                self.reset = false;
+                RtrPdu::SerialQuery { session_id, serial }
+            }
+            _ => {
+                self.reset = true;
+                RtrPdu::ResetQuery
+            }
+        }
+    }
+
+    /// Forget the session so the next query fetches the full table.
+    pub fn clear(&mut self) {
+        self.session_id = None;
+        self.serial = None;
+        self.response = None;
+    }
+
+    /// Process `pdu` from the cache. Returns the PDU to send in reply.
+    pub fn handle(
+        &mut self,
+        pdu: RtrPdu,
+        table: &mut RpkiTable,
+    ) -> Result<Option<RtrPdu>, RtrError> {
+        match pdu {
+            RtrPdu::SerialNotify { session_id, .. } => {
+                if self.response.is_some() || self.session_id != Some(session_id) {
+                    return Ok(None);
+                }
+                Ok(Some(self.query()))
+            }
+            RtrPdu::CacheResponse { session_id } => {
+                if !self.reset && self.session_id != Some(session_id) {
+                    return Err(RtrError::Corrupt);
+                }
+                self.session_id = Some(session_id);
+                self.response = Some(Vec::new());
+                Ok(None)
+            }
+            RtrPdu::Prefix { .. } | RtrPdu::Aspa { .. } => match self.response.as_mut() {
+                Some(response) => {
+                    response.push(pdu);
+                    Ok(None)
+                }
+                None => Err(RtrError::Unexpected("Prefix")),
+            },
+            RtrPdu::EndOfData {
+                session_id,
+                serial,
+                refresh,
+                retry,
+                expire,
+            } => {
+                let response = match self.response.take() {
+                    Some(response) if self.session_id == Some(session_id) => response,
+                    _ => return Err(RtrError::Unexpected("End of Data")),
+                };
+                self.apply(response, table)?;
+                self.serial = Some(serial);
+                self.refresh = Duration::from_secs(refresh as u64);
+                self.retry = Duration::from_secs(retry as u64);
+                self.expire = Duration::from_secs(expire as u64);
+                Ok(None)
+            }
+            RtrPdu::CacheReset => {
+                self.clear();
+                Ok(Some(self.query()))
+            }
+            RtrPdu::RouterKey => Ok(None),
+            RtrPdu::ErrorReport { code, text, .. } => Err(RtrError::Report { code, text }),
+            RtrPdu::SerialQuery { .. } | RtrPdu::ResetQuery => Err(RtrError::Unexpected("query")),
+        }
+    }
+
+    fn apply(&mut self, response: Vec<RtrPdu>, table: &mut RpkiTable) -> Result<(), RtrError> {
+        if self.reset {
+            table.clear();
+        }
+        for pdu in response {
+            match pdu {
+                RtrPdu::Prefix { announce, vrp } => {
+                    if announce {
+                        if !table.vrps.insert(vrp) {
+                            return Err(RtrError::DuplicateAnnounce(vrp));
+                        }
+                    } else if !table.vrps.remove(&vrp) {
+                        return Err(RtrError::WithdrawUnknown(vrp));
+                    }
+                }
+                // An announcement replaces the providers of the customer.
+                RtrPdu::Aspa {
+                    announce,
+                    customer,
+                    providers,
+                } => {
+                    if announce {
+                        table.aspas.insert(customer, &providers);
+                    } else if !table.aspas.remove(customer) {
+                        return Err(RtrError::WithdrawUnknownAspa(customer));
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Error Report to send the cache before closing the session on `e`.
+fn error_report(e: &RtrError) -> Option<RtrPdu> {
+    let code = match e {
+        RtrError::Corrupt | RtrError::Unexpected(_) => RTR_ERR_CORRUPT_DATA,
+        RtrError::Version(_) => RTR_ERR_UNEXPECTED_VERSION,
+        RtrError::PduType(_) => RTR_ERR_UNSUP_PDU_TYPE,
+        RtrError::WithdrawUnknown(_) | RtrError::WithdrawUnknownAspa(_) => RTR_ERR_WITHDRAW_UNKNOWN,
+        RtrError::DuplicateAnnounce(_) => RTR_ERR_DUPLICATE_ANNOUNCE,
+        // Never answer an error with an error.
+        RtrError::Report { .. } => return None,
+    };
+    Some(RtrPdu::ErrorReport {
+        code,
+        pdu: Vec::new(),
+        text: e.to_string(),
+    })
+}
+
+/// RPKI-to-Router client of one validator cache. The table is shared with
+/// the BGP instance and `updated` is notified with the serial after
+/// each synchronization so paths can be validated again.
+pub struct RtrClient {
+    pub addr: SocketAddr,
+    pub session: RtrSession,
+    pub table: Arc<RwLock<RpkiTable>>,
+    pub updated: Option<mpsc::UnboundedSender<u32>>,
+    /// Protocol version, lowered when the cache does not support it.
+    pub version: u8,
+    synced: Option<Instant>,
+}
+
+impl RtrClient {
+    pub fn new(addr: SocketAddr, table: Arc<RwLock<RpkiTable>>) -> Self {
+        RtrClient {
+            addr,
+            session: RtrSession::new(),
+            table,
+            updated: None,
+            version: RTR_VERSION,
+            synced: None,
+        }
+    }
+
+    /// Connect to the cache and keep the table synchronized until the
+    /// connection is closed or fails.
+    pub async fn connect(&mut self) -> Result<(), anyhow::Error> {
+        let stream = TcpStream::connect(self.addr).await?;
+        let mut framed = Framed::new(stream, RtrCodec::new(self.version));
+        framed.send(self.session.query()).await?;
+
+        loop {
+            let pdu = tokio::select! {
+                pdu = framed.next() => match pdu {
+                    Some(pdu) => pdu?,
+                    None => return Ok(()),
+                },
+                _ = tokio::time::sleep(self.session.refresh) => {
+                    framed.send(self.session.query()).await?;
+                    continue;
+                }
+            };
+            let end_of_data = matches!(pdu, RtrPdu::EndOfData { .. });
+            let result = {
+                let mut table = self.table.write().unwrap();
+                self.session.handle(pdu, &mut table)
+            };
+            match result {
+                Ok(Some(reply)) => framed.send(reply).await?,
+                Ok(None) => {}
+                // Retry with the previous version when the cache does not
+                // speak ours (RFC 8210 7).
+                Err(RtrError::Report { code, .. })
+                    if code == RTR_ERR_UNSUP_VERSION
+                        && self.session.session_id.is_none()
+                        && self.version > 0 =>
+                {
+                    self.version -= 1;
+                    println!("RTR {}: version {}", self.addr, self.version);
+                    return Ok(());
+                }
+                Err(e) => {
+                    if let Some(report) = error_report(&e) {
+                        let _ = framed.send(report).await;
+                    }
+                    self.session.clear();
+                    return Err(e.into());
+                }
+            }
+            if end_of_data {
+                self.synced = Some(Instant::now());
+                if let (Some(tx), Some(serial)) = (&self.updated, self.session.serial) {
+                    let _ = tx.send(serial);
+                }
+            }
+        }
+    }
+
+    /// Connect forever, waiting the retry interval between attempts. The
+    /// table is flushed when it was not refreshed within the expire
+    /// interval.
+    pub async fn run(mut self) {
+        loop {
+            if let Err(e) = self.connect().await {
+                println!("RTR {}: {}", self.addr, e);
+            }
+            if let Some(synced) = self.synced {
+                if synced.elapsed() >= self.session.expire {
+                    println!("RTR {}: data expired", self.addr);
+                    self.table.write().unwrap().clear();
+                    self.session.clear();
+                    self.synced = None;
+                }
+            }
+            tokio::time::sleep(self.session.retry).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bgp::{Action, Attr, Policy, PolicyContext, RouteMap, RouteMapEntry};
+    use crate::bgp::{RouteMapMatch, RpkiState};
+    use tokio::net::TcpListener;
+
+    fn vrp(s: &str, max_len: u8, asn: u32) -> Vrp {
+        Vrp::new(s.parse().unwrap(), max_len, asn)
+    }
+
+    #[test]
+    fn validate() {
+        let mut table = VrpTable::new();
+        assert!(table.insert(vrp("10.0.0.0/8", 16, 100)));
+        assert!(!table.insert(vrp("10.0.0.0/8", 16, 100)));
+        table.insert(vrp("10.1.0.0/16", 24, 200));
+        table.insert(vrp("192.0.2.0/24", 24, 0));
+        assert_eq!(table.len(), 3);
+
+        let p = "10.1.0.0/16".parse().unwrap();
+        assert_eq!(table.validate(&p, Some(100)), RpkiState::Valid);
+        assert_eq!(table.validate(&p, Some(200)), RpkiState::Valid);
+        assert_eq!(table.validate(&p, Some(300)), RpkiState::Invalid);
+        assert_eq!(table.validate(&p, None), RpkiState::Invalid);
+
+        let p = "10.2.0.0/24".parse().unwrap();
+        assert_eq!(table.validate(&p, Some(100)), RpkiState::Invalid);
+
+        // AS 0 never validates.
+        let p = "192.0.2.0/24".parse().unwrap();
+        assert_eq!(table.validate(&p, Some(0)), RpkiState::Invalid);
+
+        let p = "172.16.0.0/12".parse().unwrap();
+        assert_eq!(table.validate(&p, Some(100)), RpkiState::NotFound);
+
+        assert!(table.remove(&vrp("10.0.0.0/8", 16, 100)));
+        assert!(!table.remove(&vrp("10.0.0.0/8", 16, 100)));
+        assert_eq!(table.len(), 2);
+    }
+
+    #[test]
+    fn route_map() {
+        let mut table = VrpTable::new();
+        table.insert(vrp("10.0.0.0/8", 8, 100));
+
+        let mut policy = Policy::new();
+        let mut map = RouteMap::new();
+        let mut entry = RouteMapEntry::new(Action::Deny);
+        entry.matches.push(RouteMapMatch::Rpki(RpkiState::Invalid));
+        map.insert(10, entry);
+        map.insert(20, RouteMapEntry::new(Action::Permit));
+        policy.route_maps.insert("ROV".to_string(), map);
+
+        let mut attr = Attr::new();
+        attr.as_path = "200 100".parse().unwrap();
+        let mut route = Route::new("10.0.0.0/8".parse().unwrap(), attr);
+        table.validate_route(&mut route, 65000);
+        assert_eq!(route.rpki, RpkiState::Valid);
+        let ctx = PolicyContext::local();
+        assert_eq!(policy.apply("ROV", &mut route, &ctx), Action::Permit);
+
+        route.attr_mut().as_path = Arc::new("100 200".parse().unwrap());
+        table.validate_route(&mut route, 65000);
+        assert_eq!(route.rpki, RpkiState::Invalid);
+        assert_eq!(policy.apply("ROV", &mut route, &ctx), Action::Deny);
+    }
+
+    #[test]
+    fn pdu() {
+        let pdus = vec![
+            RtrPdu::SerialNotify {
+                session_id: 1,
+                serial: 2,
+            },
+            RtrPdu::SerialQuery {
+                session_id: 1,
+                serial: 2,
+            },
+            RtrPdu::ResetQuery,
+            RtrPdu::CacheResponse { session_id: 1 },
+            RtrPdu::Prefix {
+                announce: true,
+                vrp: vrp("10.0.0.0/8", 24, 65000),
+            },
+            RtrPdu::Prefix {
+                announce: false,
+                vrp: vrp("2001:db8::/32", 48, 65000),
+            },
+            RtrPdu::EndOfData {
+                session_id: 1,
+                serial: 2,
+                refresh: 3,
+                retry: 4,
+                expire: 5,
+            },
+            RtrPdu::CacheReset,
+            RtrPdu::ErrorReport {
+                code: RTR_ERR_NO_DATA,
+                pdu: vec![1, 2, 0, 0, 0, 0, 0, 8],
+                text: "no data".to_string(),
+            },
+            RtrPdu::Aspa {
+                announce: true,
+                customer: 64496,
+                providers: vec![64497, 64498],
+            },
+            RtrPdu::Aspa {
+                announce: false,
+                customer: 64496,
+                providers: vec![],
+            },
+        ];
+        for pdu in pdus {
+            let mut buf = Vec::new();
+            let len = pdu.to_bytes(RTR_VERSION, &mut buf).unwrap();
+            assert_eq!(len, buf.len());
+            assert_eq!(RtrPdu::from_bytes(&buf, RTR_VERSION).unwrap(), pdu);
+        }
+
+        // Max length shorter than the prefix.
+        let mut buf = Vec::new();
+        RtrPdu::Prefix {
+            announce: true,
+            vrp: vrp("10.0.0.0/8", 8, 65000),
+        }
+        .to_bytes(RTR_VERSION, &mut buf)
+        .unwrap();
+        buf[10] = 7;
+        assert_eq!(
+            RtrPdu::from_bytes(&buf, RTR_VERSION),
+            Err(RtrError::Corrupt)
+        );
+        assert_eq!(RtrPdu::from_bytes(&buf, 0), Err(RtrError::Version(2)));
+
+        // ASPA is unknown before version 2.
+        let mut buf = Vec::new();
+        let aspa = RtrPdu::Aspa {
+            announce: true,
+            customer: 64496,
+            providers: vec![64497],
+        };
+        aspa.to_bytes(1, &mut buf).unwrap();
+        assert_eq!(
+            RtrPdu::from_bytes(&buf, 1),
+            Err(RtrError::PduType(PDU_ASPA))
+        );
+    }
+
+    #[test]
+    fn decoder() {
+        let mut buf = Vec::new();
+        RtrPdu::ResetQuery.to_bytes(RTR_VERSION, &mut buf).unwrap();
+        RtrPdu::CacheReset.to_bytes(RTR_VERSION, &mut buf).unwrap();
+
+        let mut codec = RtrCodec::new(RTR_VERSION);
+        let mut src = BytesMut::from(&buf[..5]);
+        assert!(codec.decode(&mut src).unwrap().is_none());
+        src.extend_from_slice(&buf[5..]);
+        assert_eq!(codec.decode(&mut src).unwrap(), Some(RtrPdu::ResetQuery));
+        assert_eq!(codec.decode(&mut src).unwrap(), Some(RtrPdu::CacheReset));
+        assert!(codec.decode(&mut src).unwrap().is_none());
+
+        let mut src = BytesMut::from(&[1u8, 2, 0, 0, 0, 0, 0, 4][..]);
+        assert!(codec.decode(&mut src).is_err());
+    }
+
+    #[test]
+    fn session() {
+        let mut session = RtrSession::new();
+        let mut table = RpkiTable::new();
+        assert_eq!(session.query(), RtrPdu::ResetQuery);
+
+        let prefix = |announce, v| RtrPdu::Prefix { announce, vrp: v };
+        let end = |serial| RtrPdu::EndOfData {
+            session_id: 7,
+            serial,
+            refresh: 60,
+            retry: 30,
+            expire: 600,
+        };
+
+        // Prefixes outside a cache response.
+        assert!(session
+            .handle(prefix(true, vrp("10.0.0.0/8", 8, 1)), &mut table)
+            .is_err());
+
+        session
+            .handle(RtrPdu::CacheResponse { session_id: 7 }, &mut table)
+            .unwrap();
+        session
+            .handle(prefix(true, vrp("10.0.0.0/8", 8, 1)), &mut table)
+            .unwrap();
+        session
+            .handle(
+                RtrPdu::Aspa {
+                    announce: true,
+                    customer: 64496,
+                    providers: vec![64497],
+                },
+                &mut table,
+            )
+            .unwrap();
+        assert!(table.vrps.is_empty());
+        session.handle(end(1), &mut table).unwrap();
+        assert_eq!(table.vrps.len(), 1);
+        assert!(table.aspas.get(64496).unwrap().contains(&64497));
+        assert_eq!(session.serial, Some(1));
+        assert_eq!(session.refresh, Duration::from_secs(60));
+
+        let reply = session
+            .handle(
+                RtrPdu::SerialNotify {
+                    session_id: 7,
+                    serial: 2,
+                },
+                &mut table,
+            )
+            .unwrap();
+        assert_eq!(
+            reply,
+            Some(RtrPdu::SerialQuery {
+                session_id: 7,
+                serial: 1
+            })
+        );
+
+        // Session id changed without a reset.
+        assert_eq!(
+            session.handle(RtrPdu::CacheResponse { session_id: 8 }, &mut table),
+            Err(RtrError::Corrupt)
+        );
+
+        session
+            .handle(RtrPdu::CacheResponse { session_id: 7 }, &mut table)
+            .unwrap();
+        session
+            .handle(prefix(false, vrp("10.1.0.0/16", 16, 1)), &mut table)
+            .unwrap();
+        assert_eq!(
+            session.handle(end(2), &mut table),
+            Err(RtrError::WithdrawUnknown(vrp("10.1.0.0/16", 16, 1)))
+        );
+
+        let reply = session.handle(RtrPdu::CacheReset, &mut table).unwrap();
+        assert_eq!(reply, Some(RtrPdu::ResetQuery));
+        assert_eq!(session.serial, None);
+    }
+
+    async fn cache_send(framed: &mut Framed<tokio::net::TcpStream, RtrCodec>, pdus: Vec<RtrPdu>) {
+        for pdu in pdus {
+            framed.send(pdu).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn client() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // Fake cache: full table on reset, one change after a notify.
+        let cache = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut framed = Framed::new(stream, RtrCodec::new(RTR_VERSION));
+            assert_eq!(framed.next().await.unwrap().unwrap(), RtrPdu::ResetQuery);
+            let end = |serial| RtrPdu::EndOfData {
+                session_id: 42,
+                serial,
+                refresh: 3600,
+                retry: 600,
+                expire: 7200,
+            };
+            cache_send(
+                &mut framed,
+                vec![
+                    RtrPdu::CacheResponse { session_id: 42 },
+                    RtrPdu::Prefix {
+                        announce: true,
+                        vrp: vrp("10.0.0.0/8", 16, 100),
+                    },
+                    RtrPdu::Prefix {
+                        announce: true,
+                        vrp: vrp("2001:db8::/32", 48, 100),
+                    },
+                    end(1),
+                    RtrPdu::SerialNotify {
+                        session_id: 42,
+                        serial: 2,
+                    },
+                ],
+            )
+            .await;
+            assert_eq!(
+                framed.next().await.unwrap().unwrap(),
+                RtrPdu::SerialQuery {
+                    session_id: 42,
+                    serial: 1
+                }
+            );
+            cache_send(
+                &mut framed,
+                vec![
+                    RtrPdu::CacheResponse { session_id: 42 },
+                    RtrPdu::Prefix {
+                        announce: false,
+                        vrp: vrp("10.0.0.0/8", 16, 100),
+                    },
+                    RtrPdu::Prefix {
+                        announce: true,
+                        vrp: vrp("10.0.0.0/8", 24, 200),
+                    },
+                    end(2),
+                ],
+            )
+            .await;
+            framed
+        });
+
+        let table = Arc::new(RwLock::new(RpkiTable::new()));
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut client = RtrClient::new(addr, table.clone());
+        client.updated = Some(tx);
+        let client = tokio::spawn(async move { client.connect().await });
+
+        assert_eq!(rx.recv().await, Some(1));
+        assert_eq!(table.read().unwrap().vrps.len(), 2);
+
+        assert_eq!(rx.recv().await, Some(2));
+        {
+            let table = table.read().unwrap();
+            assert_eq!(table.vrps.len(), 2);
+            let p = "10.1.1.0/24".parse().unwrap();
+            assert_eq!(table.vrps.validate(&p, Some(200)), RpkiState::Valid);
+            assert_eq!(table.vrps.validate(&p, Some(100)), RpkiState::Invalid);
+        }
+
+        // Closing the cache connection ends the client.
+        drop(cache.await.unwrap());
+        assert!(client.await.unwrap().is_ok());
+    }
+}
@@ -0,0 +1,2830 @@
+#![allow(dead_code)]
+
+use super::client::max_message_len;
+use super::tcp;
+use super::CONDITIONAL_ADVERTISEMENT_TIMER_DEFAULT;
+use super::NOTIFY_HOLD_TIMER_EXPIRED;
+use super::{collision_check, collision_notification, Event, Initiator, Message};
+use super::{discover, Family, Fib, FibChange, MessageUpdate, NextHop, Prefix, RA_INTERVAL};
+use super::{family_name, AFI_IP, NOTIFY_CEASE_ADMIN_RESET, SAFI_UNICAST};
+use super::{Action, AdjOut, Aggregates, AttrSet, AttrStore, BgpTypes, Policy, Rib, Route};
+use super::{AspaTable, DampeningCommand, Dampenings, RpkiTable, RtrClient, DAMP_REUSE_TICK};
+use super::{ConnectMode, ListenCommand, ListenRanges, MaxPrefixEvent, Mrai, PeerGroups};
+use super::{GroupUpdate, RouteSource, UpdateGroupKey, UpdateGroups, UpdatePacker};
+use super::{MessageNotification, MessageOpen, Neighbor, NeighborMap, Peer, PeerType, State};
+use super::{NeighborCommand, NeighborError, Networks, NOTIFY_CEASE_CONFIG_CHANGE};
+use super::{BGP_PORT, NOTIFY_CEASE, NOTIFY_CEASE_ADMIN_SHUTDOWN, NOTIFY_FSM_ERR};
+use futures::{SinkExt, StreamExt};
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, BTreeSet};
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep_until, Instant};
+use tokio_util::codec::Framed;
+
+/// Hold time until the neighbor's OPEN has been received (RFC 4271 8).
+const OPEN_HOLD_TIME: Duration = Duration::from_secs(240);
+
+/// Settings of the local BGP instance shared by all sessions.
+#[derive(Clone, Debug)]
+pub struct BgpConfig {
+    pub asn: u32,
+    pub router_id: Ipv4Addr,
+    pub hold_time: u16,
+    /// Port connections are accepted on.
+    pub port: u16,
+    pub connect_retry: Duration,
+    /// MinRouteAdvertisementInterval of external and internal neighbors.
+    pub mrai: Mrai,
+    /// `bgp conditional-advertisement timer`, the interval advertise-map
+    /// conditions are re-evaluated at.
+    pub conditional_advertisement_timer: Duration,
+    /// RPKI validator caches the VRPs are fetched from over RTR.
+    pub rpki_caches: Vec<SocketAddr>,
+    /// `bgp bestpath prefix-validate disallow-invalid`
+    pub disallow_invalid: bool,
+    /// ASPAs in validator JSON, used while no cache delivered any.
+    pub aspa_file: Option<PathBuf>,
+}
+
+impl BgpConfig {
+    pub fn new(asn: u32, router_id: Ipv4Addr) -> Self {
+        BgpConfig {
+            asn,
+            router_id,
+            hold_time: 90,
+            port: BGP_PORT,
+            connect_retry: Duration::from_secs(120),
+            mrai: Mrai::default(),
+            conditional_advertisement_timer: CONDITIONAL_ADVERTISEMENT_TIMER_DEFAULT,
+            rpki_caches: Vec::new(),
+            disallow_invalid: false,
+            aspa_file: None,
+        }
+    }
+}
+
+type Connection = Framed<TcpStream, Peer>;
+
+/// Routes announced and prefixes withdrawn by an UPDATE, with their
+/// AFI/SAFI.
+type Changes = (Vec<(Family, Route)>, Vec<(Family, Prefix)>);
+
+/// How a connection ended.
+enum Closed {
+    /// A connection accepted meanwhile won the collision and is used next,
+    /// with the neighbor's OPEN when it has been received on it.
+    Next(Box<Connection>, Option<MessageOpen>),
+    Down,
+    /// Closed for a configuration change, connect again right away.
+    Reset,
+    /// Closed for exceeding maximum-prefix, stays Idle for the restart time
+    /// or until cleared when there is none.
+    MaxPrefix(Option<Duration>),
+    Stop,
+}
+
+/// Address of the neighbor a connection comes from, IPv4 neighbors connect
+/// to a dual stack listener with mapped addresses.
+fn peer_addr(addr: SocketAddr) -> IpAddr {
+    match addr.ip() {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(IpAddr::V6(v6), IpAddr::V4),
+        ip => ip,
+    }
+}
+
+/// ConnectRetry time reduced by a random 0 to 25% (RFC 4271 10), so two
+/// speakers connecting to each other at once do not collide again on every
+/// retry.
+fn jitter(time: Duration) -> Duration {
+    let random = RandomState::new().build_hasher().finish();
+    time - time * (random % 250) as u32 / 1000
+}
+
+fn cease(subcode: u8) -> MessageNotification {
+    MessageNotification::new(NOTIFY_CEASE, subcode, Vec::new())
+}
+
+/// What sessions tell bgpd about the routes of their neighbors.
+enum RibEvent {
+    /// The session with the neighbor of the BGP Identifier is established
+    /// on connection `id`, or the update group key of the neighbor changed.
+    Up(Box<Neighbor>, UpdateGroupKey, Ipv4Addr, u64),
+    /// Routes received from the neighbor at the address and the prefixes it
+    /// withdrew.
+    Update(IpAddr, Vec<Route>, Vec<Prefix>),
+    /// The established session went down.
+    Down(IpAddr),
+}
+
+/// BGP session with one neighbor, driven by its own task.
+struct Session {
+    config: Arc<BgpConfig>,
+    neighbor: Neighbor,
+    rx: mpsc::UnboundedReceiver<Event>,
+    state: watch::Sender<State>,
+    /// Set for a dynamic neighbor, which only lives as long as its session.
+    /// Its address is sent when the session ends.
+    down: Option<mpsc::UnboundedSender<IpAddr>>,
+    /// Prefixes the neighbor announced on the current connection, counted
+    /// against maximum-prefix.
+    received: BTreeMap<Family, BTreeSet<Prefix>>,
+    /// Set once maximum-prefix closed the session, which stays Idle until
+    /// the restart time or until cleared when it is `None`.
+    limited: Option<Option<Instant>>,
+    rib: mpsc::UnboundedSender<RibEvent>,
+    /// Id of the last established connection, Adj-RIB-Out changes sent for
+    /// an earlier one are dropped.
+    conn_id: u64,
+    established: bool,
+}
+
+impl Session {
+    fn set_state(&self, state: State) {
+        let _ = self.state.send(state);
+    }
+
+    fn local_as(&self) -> u32 {
+        self.neighbor.local_asn(self.config.asn)
+    }
+
+    fn open(&self) -> MessageOpen {
+        MessageOpen::local(
+            self.local_as(),
+            self.neighbor.hold_time(self.config.hold_time),
+            self.config.router_id,
+            self.neighbor.capabilities(),
+        )
+    }
+
+    /// Take the new configuration of the neighbor. Returns the Cease
+    /// NOTIFICATION to close the current connection with when the change
+    /// does not apply to a running session.
+    fn reconfigure(&mut self, neighbor: Neighbor) -> Option<MessageNotification> {
+        let n = if neighbor.shutdown && !self.neighbor.shutdown {
+            Some(cease(NOTIFY_CEASE_ADMIN_SHUTDOWN))
+        } else if self.neighbor.session_changed(&neighbor) {
+            Some(cease(NOTIFY_CEASE_CONFIG_CHANGE))
+        } else {
+            None
+        };
+        self.neighbor = neighbor;
+        n
+    }
+
+    fn routes(&self, update: &MessageUpdate) -> Changes {
+        let ipv4 = Family {
+            afi: AFI_IP,
+            safi: SAFI_UNICAST,
+        };
+        let mut withdrawn: Vec<_> = update.withdrawn.iter().map(|p| (ipv4, *p)).collect();
+        if let Some(unreach) = &update.mp_unreach {
+            withdrawn.extend(unreach.withdrawn.iter().map(|p| (unreach.family, *p)));
+        }
+        // The routes of an UPDATE share its attributes.
+        let route = |prefix: &Prefix, attr: &Arc<AttrSet>| {
+            let mut route = Route::new(*prefix, attr.clone());
+            route.peer = Some(self.neighbor.ipaddr);
+            route
+        };
+        let attr = Arc::new(AttrSet::from(update.attr.clone()));
+        let mut routes: Vec<_> = update
+            .nlri
+            .iter()
+            .map(|p| (ipv4, route(p, &attr)))
+            .collect();
+        if let Some(reach) = &update.mp_reach {
+            let mut attr = (*attr).clone();
+            attr.next_hop = Some(reach.next_hop);
+            let attr = Arc::new(attr);
+            routes.extend(reach.nlri.iter().map(|p| (reach.family, route(p, &attr))));
+        }
+        (routes, withdrawn)
+    }
+
+    /// Count the prefixes announced and withdrawn per AFI/SAFI. Returns the
+    /// Cease NOTIFICATION and the restart time when the maximum-prefix limit
+    /// of a family closes the session.
+    fn count_prefixes(
+        &mut self,
+        peer: &mut Peer,
+        routes: &[(Family, Route)],
+        withdrawn: &[(Family, Prefix)],
+    ) -> Option<(MessageNotification, Option<Duration>)> {
+        for (family, prefix) in withdrawn {
+            if let Some(received) = self.received.get_mut(family) {
+                if received.remove(prefix) {
+                    peer.prefix_withdrawn(&self.neighbor, *family);
+                }
+            }
+        }
+        for (family, route) in routes {
+            if !self
+                .received
+                .entry(*family)
+                .or_default()
+                .insert(route.prefix)
+            {
+                continue;
+            }
+            let event = match peer.prefix_accepted(&self.neighbor, *family) {
+                Some(event) => event,
+                None => continue,
+            };
+            let max = self.neighbor.max_prefix.get(family)?;
+            let count = peer.prefix_count[family].count();
+            match event {
+                MaxPrefixEvent::ThresholdReached => println!(
+                    "{}: {} prefixes of {} reached {}% of limit {}",
+                    self.neighbor.ipaddr,
+                    count,
+                    family_name(family),
+                    max.threshold,
+                    max.limit
+                ),
+                MaxPrefixEvent::LimitExceeded => println!(
+                    "{}: {} prefixes of {} exceed limit {}",
+                    self.neighbor.ipaddr,
+                    count,
+                    family_name(family),
+                    max.limit
+                ),
+            }
+            if let Some(n) = max.notification(*family, event) {
+                return Some((n, max.restart));
+            }
+        }
+        None
+    }
+
+    /// Withdraw everything received in the families disabled by a
+    /// malformed MP_REACH_NLRI or MP_UNREACH_NLRI (RFC 7606 2).
+    fn withdraw_disabled(&mut self, peer: &mut Peer) -> Vec<Prefix> {
+        let mut withdrawn = Vec::new();
+        for family in &peer.disabled {
+            let prefixes = match self.received.remove(family) {
+                Some(prefixes) => prefixes,
+                None => continue,
+            };
+            println!(
+                "{}: {} disabled, {} prefixes withdrawn",
+                self.neighbor.ipaddr,
+                family_name(family),
+                prefixes.len()
+            );
+            if let Some(counter) = peer.prefix_count.get_mut(family) {
+                counter.reset();
+            }
+            withdrawn.extend(prefixes);
+        }
+        withdrawn
+    }
+
+    /// Take an UPDATE received on the established connection, dropping the
+    /// routes Only-to-Customer denies. Returns the Cease NOTIFICATION and
+    /// the restart time when maximum-prefix closes the session.
+    fn update_received(
+        &mut self,
+        peer: &mut Peer,
+        update: &MessageUpdate,
+    ) -> Option<(MessageNotification, Option<Duration>)> {
+        for e in &update.errors {
+            println!("{}: UPDATE error {}", self.neighbor.ipaddr, e);
+        }
+        let disabled = self.withdraw_disabled(peer);
+        let (routes, mut withdrawn) = self.routes(update);
+        // Route leaks are treated as withdrawn (RFC 9234 5).
+        let mut accepted = Vec::with_capacity(routes.len());
+        for (family, mut route) in routes {
+            match self.neighbor.otc_in(&mut route, peer.remote_as) {
+                Action::Permit => accepted.push((family, route)),
+                Action::Deny => withdrawn.push((family, route.prefix)),
+            }
+        }
+        let limit = self.count_prefixes(peer, &accepted, &withdrawn);
+        if limit.is_none() {
+            let mut prefixes = disabled;
+            prefixes.extend(withdrawn.into_iter().map(|(_, prefix)| prefix));
+            let routes = accepted.into_iter().map(|(_, route)| route).collect();
+            let _ = self
+                .rib
+                .send(RibEvent::Update(self.neighbor.ipaddr, routes, prefixes));
+        }
+        limit
+    }
+
+    /// Update group key of the neighbor on the established `conn`.
+    fn group_key(&self, conn: &Connection) -> UpdateGroupKey {
+        let local = conn.get_ref().local_addr().ok().map(peer_addr);
+        UpdateGroupKey::new(&self.neighbor, conn.codec(), local)
+    }
+
+    /// Let bgpd send the Adj-RIB-Out for `key` on the established `conn`.
+    fn up(&mut self, conn: &Connection, key: UpdateGroupKey) {
+        self.established = true;
+        let neighbor = Box::new(self.neighbor.clone());
+        let router_id = conn.codec().router_id;
+        let _ = self
+            .rib
+            .send(RibEvent::Up(neighbor, key, router_id, self.conn_id));
+    }
+
+    /// Close `conn` with `n` for a configuration change.
+    async fn reset(&self, conn: &mut Connection, n: MessageNotification) -> Closed {
+        println!("{}: {}", self.neighbor.ipaddr, n);
+        let _ = conn.send(Message::Notification(n)).await;
+        Closed::Reset
+    }
+
+    async fn run(mut self) {
+        let mut next = None;
+        let mut delay = Duration::from_secs(0);
+        loop {
+            let (conn, initiator, open) = match next.take() {
+                Some((conn, open)) => (conn, Initiator::Remote, open),
+                None => {
+                    let (stream, initiator) = match self.wait_connection(delay).await {
+                        Some(conn) => conn,
+                        None => break,
+                    };
+                    match self.open_sent(stream).await {
+                        Some(conn) => (conn, initiator, None),
+                        None if self.down.is_some() => break,
+                        None => continue,
+                    }
+                }
+            };
+            delay = jitter(self.config.connect_retry);
+            let closed = self.connection(conn, initiator, open).await;
+            self.received.clear();
+            if self.established {
+                self.established = false;
+                let _ = self.rib.send(RibEvent::Down(self.neighbor.ipaddr));
+            }
+            match closed {
+                Closed::Next(conn, open) => next = Some((*conn, open)),
+                Closed::Down | Closed::Reset | Closed::MaxPrefix(_) if self.down.is_some() => break,
+                Closed::Down => {}
+                Closed::Reset => delay = Duration::from_secs(0),
+                Closed::MaxPrefix(restart) => {
+                    self.limited = Some(restart.map(|restart| Instant::now() + restart));
+                    delay = Duration::from_secs(0);
+                }
+                Closed::Stop => break,
+            }
+        }
+        self.set_state(State::Idle);
+        if let Some(down) = &self.down {
+            let _ = down.send(self.neighbor.ipaddr);
+        }
+    }
+
+    /// Wait for an accepted connection, connecting to the neighbor after
+    /// `delay` unless it is passive. A neighbor which is shut down, or was
+    /// closed by maximum-prefix, stays Idle. Returns `None` when the session
+    /// is stopped.
+    async fn wait_connection(&mut self, delay: Duration) -> Option<(TcpStream, Initiator)> {
+        let mut deadline = Instant::now() + delay;
+        loop {
+            let neighbor = &self.neighbor;
+            let addr = SocketAddr::new(neighbor.ipaddr, neighbor.port);
+            let source = neighbor.update_source;
+            let password = neighbor.password.clone();
+            let interface = neighbor.interface.clone();
+            let idle = neighbor.shutdown || self.limited.is_some();
+            let connects = !idle && neighbor.connection_allowed(Initiator::Local);
+            let restart = self.limited.flatten();
+            self.set_state(if idle { State::Idle } else { State::Active });
+            tokio::select! {
+                ev = self.rx.recv() => match ev {
+                    Some(Event::Accept((stream, _))) => {
+                        if !idle && self.neighbor.connection_allowed(Initiator::Remote) {
+                            return Some((stream, Initiator::Remote));
+                        }
+                    }
+                    Some(Event::Config(neighbor)) => {
+                        if self.reconfigure(*neighbor).is_some() {
+                            deadline = Instant::now();
+                        }
+                    }
+                    Some(Event::Clear) => {
+                        self.limited = None;
+                        deadline = Instant::now();
+                    }
+                    Some(Event::Stop) | None => return None,
+                    Some(_) => {}
+                },
+                _ = sleep_until(restart.unwrap_or_else(Instant::now)), if restart.is_some() => {
+                    println!("{}: maximum-prefix restart", self.neighbor.ipaddr);
+                    self.limited = None;
+                    deadline = Instant::now();
+                },
+                res = async {
+                    sleep_until(deadline).await;
+                    tcp::connect(addr, source, password.as_deref(), interface.as_deref()).await
+                }, if connects => match res {
+                    Ok(stream) => return Some((stream, Initiator::Local)),
+                    Err(e) => {
+                        println!("{}: connect error {}", addr, e);
+                        deadline = Instant::now() + jitter(self.config.connect_retry);
+                    }
+                },
+            }
+        }
+    }
+
+    /// Send our OPEN on a new connection.
+    async fn open_sent(&self, stream: TcpStream) -> Option<Connection> {
+        if let Some(ttl) = self.neighbor.ttl(self.config.asn) {
+            stream.set_ttl(ttl).ok()?;
+        }
+        let mut conn = Framed::new(stream, Peer::new(State::OpenSent));
+        conn.send(Message::Open(self.open())).await.ok()?;
+        Some(conn)
+    }
+
+    /// Run the session over the connection opened by `initiator` until it
+    /// closes. `open` is the neighbor's OPEN when it has been received
+    /// already.
+    async fn connection(
+        &mut self,
+        mut conn: Connection,
+        initiator: Initiator,
+        open: Option<MessageOpen>,
+    ) -> Closed {
+        self.set_state(State::OpenSent);
+
+        // A connection accepted meanwhile is driven as well, each side only
+        // reads the OPEN on the connection it accepted when both connected
+        // at once. Which one is kept is decided once the neighbor's BGP
+        // Identifier arrives on the accepted one.
+        let mut pending: Option<Connection> = None;
+        let mut open = open;
+        let deadline = Instant::now() + OPEN_HOLD_TIME;
+        let open = loop {
+            if pending.is_none() {
+                if let Some(open) = open.take() {
+                    break open;
+                }
+            }
+            tokio::select! {
+                msg = conn.next(), if open.is_none() => match msg {
+                    Some(Ok(Message::Open(msg))) => open = Some(msg),
+                    msg => return self.unexpected(&mut conn, msg).await,
+                },
+                msg = async { pending.as_mut().unwrap().next().await }, if pending.is_some() => {
+                    let new = pending.take().unwrap();
+                    if let Some(Ok(Message::Open(msg))) = msg {
+                        if let Some(next) = self.collision(&mut conn, initiator, State::OpenSent, &msg, new).await {
+                            return Closed::Next(Box::new(next), Some(msg));
+                        }
+                    }
+                },
+                ev = self.rx.recv() => match ev {
+                    Some(Event::Accept((stream, _))) => {
+                        if self.neighbor.connection_allowed(Initiator::Remote) {
+                            pending = self.open_sent(stream).await;
+                        }
+                    }
+                    Some(Event::Config(neighbor)) => {
+                        if let Some(n) = self.reconfigure(*neighbor) {
+                            return self.reset(&mut conn, n).await;
+                        }
+                    }
+                    Some(Event::Stop) | None => return self.stop(&mut conn).await,
+                    Some(_) => {}
+                },
+                _ = sleep_until(deadline) => return self.hold_expired(&mut conn).await,
+            }
+        };
+        if let Err(n) = conn
+            .codec_mut()
+            .open_received(&self.neighbor, self.local_as(), &open)
+        {
+            println!("{}: OPEN error {}", self.neighbor.ipaddr, n);
+            let _ = conn.send(Message::Notification(n)).await;
+            return Closed::Down;
+        }
+        if conn.send(Message::KeepAlive).await.is_err() {
+            return Closed::Down;
+        }
+        conn.codec_mut().state = State::OpenConfirm;
+        self.set_state(State::OpenConfirm);
+
+        let hold = self.neighbor.hold_time(self.config.hold_time);
+        let hold = Duration::from_secs(hold.min(open.hold_time()) as u64);
+        let keepalive = self.neighbor.keepalive(hold);
+        let mut hold_deadline = Instant::now() + hold;
+        let mut keepalive_deadline = Instant::now() + keepalive;
+        let mut packer = UpdatePacker::new(Duration::ZERO);
+        let mut key = None;
+        loop {
+            let state = conn.codec().state;
+            let out = packer.deadline();
+            tokio::select! {
+                msg = conn.next() => {
+                    match (state, msg) {
+                        (State::OpenConfirm, Some(Ok(Message::KeepAlive))) => {
+                            println!("{}: established", self.neighbor.ipaddr);
+                            conn.codec_mut().state = State::Established;
+                            self.set_state(State::Established);
+                            self.conn_id += 1;
+                            packer.set_mrai(self.config.mrai.interval(conn.codec().peer_type));
+                            let new = self.group_key(&conn);
+                            key = Some(new.clone());
+                            self.up(&conn, new);
+                        }
+                        (State::Established, Some(Ok(Message::KeepAlive))) => {}
+                        (State::Established, Some(Ok(Message::Update(update)))) => {
+                            if let Some((n, restart)) = self.update_received(conn.codec_mut(), &update) {
+                                println!("{}: {}", self.neighbor.ipaddr, n);
+                                let _ = conn.send(Message::Notification(n)).await;
+                                return Closed::MaxPrefix(restart);
+                            }
+                            // Families may have been disabled.
+                            let new = self.group_key(&conn);
+                            if key.as_ref() != Some(&new) {
+                                key = Some(new.clone());
+                                self.up(&conn, new);
+                            }
+                        }
+                        (_, msg) => return self.unexpected(&mut conn, msg).await,
+                    }
+                    // Only a message from the neighbor keeps the session up.
+                    hold_deadline = Instant::now() + hold;
+                },
+                ev = self.rx.recv() => match ev {
+                    Some(Event::Accept((stream, _))) => {
+                        if !self.neighbor.connection_allowed(Initiator::Remote) {
+                            continue;
+                        }
+                        // The BGP Identifier is known from the OPEN on this
+                        // connection, no need to wait for the new one's.
+                        let new = Framed::new(stream, Peer::new(State::Idle));
+                        if let Some(new) = self.collision(&mut conn, initiator, state, &open, new).await {
+                            match self.open_sent(new.into_inner()).await {
+                                Some(new) => return Closed::Next(Box::new(new), None),
+                                None => return Closed::Down,
+                            }
+                        }
+                    }
+                    Some(Event::Config(neighbor)) => {
+                        if let Some(n) = self.reconfigure(*neighbor) {
+                            return self.reset(&mut conn, n).await;
+                        }
+                        if state == State::Established {
+                            let new = self.group_key(&conn);
+                            key = Some(new.clone());
+                            self.up(&conn, new);
+                        }
+                    }
+                    Some(Event::Adj(id, changes)) if id == self.conn_id && state == State::Established => {
+                        for change in changes {
+                            match change {
+                                AdjOut::Announce(route) => packer.announce(&route),
+                                AdjOut::Withdraw(prefix) => packer.withdraw(prefix),
+                            }
+                        }
+                    }
+                    Some(Event::Clear) => {
+                        return self.reset(&mut conn, cease(NOTIFY_CEASE_ADMIN_RESET)).await;
+                    }
+                    Some(Event::Stop) | None => return self.stop(&mut conn).await,
+                    Some(_) => {}
+                },
+                _ = sleep_until(keepalive_deadline), if !hold.is_zero() => {
+                    if conn.send(Message::KeepAlive).await.is_err() {
+                        return Closed::Down;
+                    }
+                    keepalive_deadline = Instant::now() + keepalive;
+                },
+                _ = sleep_until(out.unwrap_or_else(Instant::now)), if out.is_some() => {
+                    let ctx = conn.codec().update_context();
+                    let max_len = max_message_len(BgpTypes::UPDATE, conn.codec().extended_message);
+                    let (updates, errors) = packer.flush(Instant::now(), &ctx, max_len);
+                    for e in errors {
+                        println!("{}: {}", self.neighbor.ipaddr, e);
+                    }
+                    for update in updates {
+                        if conn.send(Message::Update(update)).await.is_err() {
+                            return Closed::Down;
+                        }
+                    }
+                },
+                _ = sleep_until(hold_deadline), if !hold.is_zero() => {
+                    return self.hold_expired(&mut conn).await;
+                },
+            }
+        }
+    }
+
+    /// Resolve the collision of `new`, accepted while the connection opened
+    /// by `initiator` is in `state`, with `open` from the neighbor. The
+    /// losing connection is closed with a Cease NOTIFICATION, `new` is
+    /// returned when it wins.
+    async fn collision(
+        &self,
+        conn: &mut Connection,
+        initiator: Initiator,
+        state: State,
+        open: &MessageOpen,
+        mut new: Connection,
+    ) -> Option<Connection> {
+        let close = match state {
+            State::Established => Some(Initiator::Remote),
+            // The neighbor gave up on its previous connection.
+            _ if initiator == Initiator::Remote => Some(Initiator::Local),
+            _ => collision_check(
+                Initiator::Remote,
+                state,
+                self.config.router_id,
+                self.config.asn,
+                open.router_id(),
+                open.asn(),
+            ),
+        };
+        println!(
+            "{}: connection collision in {:?}",
+            self.neighbor.ipaddr, state
+        );
+        if close == Some(Initiator::Remote) {
+            let _ = new
+                .send(Message::Notification(collision_notification()))
+                .await;
+            None
+        } else {
+            let _ = conn
+                .send(Message::Notification(collision_notification()))
+                .await;
+            Some(new)
+        }
+    }
+
+    /// The connection ends with `msg`, which is not expected in the current
+    /// state.
+    async fn unexpected(
+        &self,
+        conn: &mut Connection,
+        msg: Option<Result<Message, anyhow::Error>>,
+    ) -> Closed {
+        let addr = self.neighbor.ipaddr;
+        match msg {
+            Some(Ok(Message::Notification(n))) => println!("{}: received {}", addr, n),
+            Some(Ok(msg)) => {
+                println!("{}: unexpected {:?}", addr, msg);
+                let n = MessageNotification::new(NOTIFY_FSM_ERR, 0, Vec::new());
+                let _ = conn.send(Message::Notification(n)).await;
+            }
+            Some(Err(e)) => {
+                println!("{}: {}", addr, e);
+                if let Some(n) = Peer::notification(&e) {
+                    let _ = conn.send(Message::Notification(n)).await;
+                }
+            }
+            None => println!("{}: connection closed", addr),
+        }
+        Closed::Down
+    }
+
+    async fn hold_expired(&self, conn: &mut Connection) -> Closed {
+        println!("{}: hold timer expired", self.neighbor.ipaddr);
+        let n = MessageNotification::new(NOTIFY_HOLD_TIMER_EXPIRED, 0, Vec::new());
+        let _ = conn.send(Message::Notification(n)).await;
+        Closed::Down
+    }
+
+    async fn stop(&self, conn: &mut Connection) -> Closed {
+        let n = cease(NOTIFY_CEASE_ADMIN_SHUTDOWN);
+        let _ = conn.send(Message::Notification(n)).await;
+        Closed::Stop
+    }
+}
+
+struct SessionHandle {
+    tx: mpsc::UnboundedSender<Event>,
+    state: watch::Receiver<State>,
+    task: JoinHandle<()>,
+    /// Id of the established connection.
+    conn_id: Option<u64>,
+}
+
+/// Add the changes of update groups to those of each member.
+fn member_changes(updates: Vec<GroupUpdate>, changes: &mut BTreeMap<IpAddr, Vec<AdjOut>>) {
+    for update in updates {
+        for member in update.members {
+            changes
+                .entry(member)
+                .or_default()
+                .push(update.change.clone());
+        }
+    }
+}
+
+/// BGP daemon, one session task per configured neighbor. Accepted
+/// connections are handed to the session of the neighbor they come from.
+pub struct Bgpd {
+    pub config: Arc<BgpConfig>,
+    /// Effective configuration of the neighbors.
+    pub neighbors: NeighborMap,
+    /// Neighbor configuration as entered, with peer-groups and templates.
+    pub peer_groups: PeerGroups,
+    sessions: BTreeMap<IpAddr, SessionHandle>,
+    /// Socket connections are accepted on while serving.
+    listener: Option<RawFd>,
+    config_tx: mpsc::UnboundedSender<ConfigRequest>,
+    config_rx: mpsc::UnboundedReceiver<ConfigRequest>,
+    pub listen: ListenRanges,
+    /// Neighbors created for connections from a listen range.
+    dynamic: BTreeSet<IpAddr>,
+    /// Passwords of the listen ranges installed on the listening socket.
+    range_passwords: BTreeMap<Prefix, String>,
+    down_tx: mpsc::UnboundedSender<IpAddr>,
+    down_rx: mpsc::UnboundedReceiver<IpAddr>,
+    /// Interfaces neighbors are discovered on by their Router
+    /// Advertisements.
+    interfaces: BTreeMap<String, Unnumbered>,
+    ra_tx: mpsc::UnboundedSender<(String, io::Result<Ipv6Addr>)>,
+    ra_rx: mpsc::UnboundedReceiver<(String, io::Result<Ipv6Addr>)>,
+    /// Changes of the routes installed in the kernel, for the thread
+    /// installing them.
+    fib: Option<mpsc::UnboundedSender<FibChange>>,
+    /// Next hops of the best paths installed, those from unnumbered
+    /// neighbors.
+    installed: BTreeMap<Prefix, NextHop>,
+    /// Prefix-lists, route-maps and the other filters neighbors refer to.
+    pub policy: Policy,
+    rib: Rib,
+    /// Path attributes of the routes in the RIB, shared between them.
+    attrs: AttrStore,
+    /// Routes replaced or withdrawn since unreferenced attributes were
+    /// last collected.
+    released: usize,
+    groups: UpdateGroups,
+    networks: Networks,
+    /// Prefixes of the system RIB, networks are only originated when it
+    /// has a route for them with import-check.
+    system_rib: BTreeSet<Prefix>,
+    aggregates: Aggregates,
+    /// More specific routes of aggregates which are not advertised.
+    suppressed: BTreeSet<Prefix>,
+    /// Routes suppressed by each aggregate.
+    suppressed_by: BTreeMap<Prefix, BTreeSet<Prefix>>,
+    /// Route flap dampening of the received routes.
+    dampenings: Dampenings,
+    /// Data of the RPKI caches, received routes are validated against it.
+    rpki: Arc<RwLock<RpkiTable>>,
+    /// Serial of each synchronization with a cache.
+    rpki_tx: mpsc::UnboundedSender<u32>,
+    rpki_rx: mpsc::UnboundedReceiver<u32>,
+    /// RTR clients of `BgpConfig::rpki_caches`.
+    rtr: Vec<JoinHandle<()>>,
+    /// ASPAs loaded from `BgpConfig::aspa_file`.
+    aspas: AspaTable,
+    rib_tx: mpsc::UnboundedSender<RibEvent>,
+    rib_rx: mpsc::UnboundedReceiver<RibEvent>,
+}
+
+/// `neighbor <interface> interface peer-group <name>`, BGP unnumbered.
+struct Unnumbered {
+    peer_group: String,
+    /// Link-local address of the neighbor, once discovered.
+    neighbor: Option<IpAddr>,
+    task: JoinHandle<()>,
+}
+
+/// What a configuration command is applied to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConfigTarget {
+    Neighbor(IpAddr),
+    PeerGroup(String),
+    Template(String),
+}
+
+enum Request {
+    Neighbor(ConfigTarget, NeighborCommand),
+    Listen(ListenCommand),
+    Interface(String, Option<String>),
+    Network(String),
+    SystemRoute(Prefix, bool),
+    Aggregate(String),
+    Dampening(String),
+    Clear(IpAddr),
+}
+
+type ConfigRequest = (Request, oneshot::Sender<Result<(), NeighborError>>);
+
+/// Changes the configuration of a serving daemon.
+#[derive(Clone)]
+pub struct BgpdHandle {
+    tx: mpsc::UnboundedSender<ConfigRequest>,
+}
+
+impl BgpdHandle {
+    /// Apply `cmd` to the neighbor at `addr`, see `Bgpd::configure()`.
+    pub async fn configure(&self, addr: IpAddr, cmd: NeighborCommand) -> Result<(), NeighborError> {
+        let stopped = NeighborError::NotConfigured(addr);
+        let target = ConfigTarget::Neighbor(addr);
+        self.request(Request::Neighbor(target, cmd), stopped).await
+    }
+
+    /// Apply `cmd` to the peer-group `name`, see
+    /// `Bgpd::configure_peer_group()`.
+    pub async fn configure_peer_group(
+        &self,
+        name: &str,
+        cmd: NeighborCommand,
+    ) -> Result<(), NeighborError> {
+        let stopped = NeighborError::UnknownPeerGroup(name.to_string());
+        let target = ConfigTarget::PeerGroup(name.to_string());
+        self.request(Request::Neighbor(target, cmd), stopped).await
+    }
+
+    /// Apply `cmd` to the template `name`, see `Bgpd::configure_template()`.
+    pub async fn configure_template(
+        &self,
+        name: &str,
+        cmd: NeighborCommand,
+    ) -> Result<(), NeighborError> {
+        let stopped = NeighborError::UnknownTemplate(name.to_string());
+        let target = ConfigTarget::Template(name.to_string());
+        self.request(Request::Neighbor(target, cmd), stopped).await
+    }
+
+    /// Apply a `bgp listen` command, see `Bgpd::configure_listen()`.
+    pub async fn configure_listen(&self, cmd: ListenCommand) -> Result<(), NeighborError> {
+        let stopped = NeighborError::Syntax("bgpd stopped".to_string());
+        self.request(Request::Listen(cmd), stopped).await
+    }
+
+    /// Peer on an interface, see `Bgpd::configure_interface()`.
+    pub async fn configure_interface(
+        &self,
+        name: &str,
+        peer_group: Option<&str>,
+    ) -> Result<(), NeighborError> {
+        let stopped = NeighborError::Syntax("bgpd stopped".to_string());
+        let request = Request::Interface(name.to_string(), peer_group.map(str::to_string));
+        self.request(request, stopped).await
+    }
+
+    /// Apply a `network` line, see `Bgpd::configure_network()`.
+    pub async fn configure_network(&self, line: &str) -> Result<(), NeighborError> {
+        let stopped = NeighborError::Syntax("bgpd stopped".to_string());
+        self.request(Request::Network(line.to_string()), stopped)
+            .await
+    }
+
+    /// The system RIB has a route for `prefix`, see
+    /// `Bgpd::add_system_route()`.
+    pub async fn add_system_route(&self, prefix: Prefix) -> Result<(), NeighborError> {
+        let stopped = NeighborError::Syntax("bgpd stopped".to_string());
+        self.request(Request::SystemRoute(prefix, true), stopped)
+            .await
+    }
+
+    /// The system RIB has no route for `prefix` anymore.
+    pub async fn remove_system_route(&self, prefix: Prefix) -> Result<(), NeighborError> {
+        let stopped = NeighborError::Syntax("bgpd stopped".to_string());
+        self.request(Request::SystemRoute(prefix, false), stopped)
+            .await
+    }
+
+    /// Apply an `aggregate-address` line, see `Bgpd::configure_aggregate()`.
+    pub async fn configure_aggregate(&self, line: &str) -> Result<(), NeighborError> {
+        let stopped = NeighborError::Syntax("bgpd stopped".to_string());
+        self.request(Request::Aggregate(line.to_string()), stopped)
+            .await
+    }
+
+    /// Apply a `bgp dampening` line, see `Bgpd::configure_dampening()`.
+    pub async fn configure_dampening(&self, line: &str) -> Result<(), NeighborError> {
+        let stopped = NeighborError::Syntax("bgpd stopped".to_string());
+        self.request(Request::Dampening(line.to_string()), stopped)
+            .await
+    }
+
+    /// Reset the session of the neighbor at `addr`, see `Bgpd::clear()`.
+    pub async fn clear(&self, addr: IpAddr) -> Result<(), NeighborError> {
+        let stopped = NeighborError::NotConfigured(addr);
+        self.request(Request::Clear(addr), stopped).await
+    }
+
+    /// Send `request`, failing with `stopped` when bgpd does not serve.
+    async fn request(&self, request: Request, stopped: NeighborError) -> Result<(), NeighborError> {
+        let (tx, rx) = oneshot::channel();
+        if self.tx.send((request, tx)).is_err() {
+            return Err(stopped);
+        }
+        rx.await.unwrap_or(Err(stopped))
+    }
+}
+
+impl Bgpd {
+    pub fn new(config: BgpConfig) -> Self {
+        let (config_tx, config_rx) = mpsc::unbounded_channel();
+        let (down_tx, down_rx) = mpsc::unbounded_channel();
+        let (ra_tx, ra_rx) = mpsc::unbounded_channel();
+        let (rib_tx, rib_rx) = mpsc::unbounded_channel();
+        let (rpki_tx, rpki_rx) = mpsc::unbounded_channel();
+        let mut rib = Rib::new();
+        rib.disallow_invalid = config.disallow_invalid;
+        Bgpd {
+            peer_groups: PeerGroups::new(config.asn),
+            groups: UpdateGroups::new(config.asn, config.conditional_advertisement_timer),
+            config: Arc::new(config),
+            neighbors: NeighborMap::new(),
+            sessions: BTreeMap::new(),
+            listener: None,
+            config_tx,
+            config_rx,
+            listen: ListenRanges::new(),
+            dynamic: BTreeSet::new(),
+            range_passwords: BTreeMap::new(),
+            down_tx,
+            down_rx,
+            interfaces: BTreeMap::new(),
+            ra_tx,
+            ra_rx,
+            fib: None,
+            installed: BTreeMap::new(),
+            policy: Policy::new(),
+            rib,
+            attrs: AttrStore::new(),
+            released: 0,
+            networks: Networks::new(),
+            system_rib: BTreeSet::new(),
+            aggregates: Aggregates::new(),
+            suppressed: BTreeSet::new(),
+            suppressed_by: BTreeMap::new(),
+            dampenings: Dampenings::new(),
+            rpki: Arc::new(RwLock::new(RpkiTable::new())),
+            rpki_tx,
+            rpki_rx,
+            rtr: Vec::new(),
+            aspas: AspaTable::new(),
+            rib_tx,
+            rib_rx,
+        }
+    }
+
+    /// Install the best paths received from unnumbered neighbors in `fib`,
+    /// from a blocking thread.
+    pub fn set_fib(&mut self, fib: Fib) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::task::spawn_blocking(move || fib.run(rx));
+        self.fib = Some(tx);
+    }
+
+    pub fn handle(&self) -> BgpdHandle {
+        BgpdHandle {
+            tx: self.config_tx.clone(),
+        }
+    }
+
+    /// Apply `cmd` to the neighbor at `addr`. `remote-as` or `peer-group`
+    /// configures a new neighbor. Only the session of that neighbor is
+    /// affected, it is reset when the change does not apply to a running
+    /// session.
+    pub fn configure(&mut self, addr: IpAddr, cmd: NeighborCommand) -> Result<(), NeighborError> {
+        let neighbor = self.peer_groups.configure(addr, cmd)?;
+        self.update(neighbor);
+        Ok(())
+    }
+
+    /// Apply `cmd` to the peer-group `name`, creating it, and to the
+    /// members which do not override the setting. Nothing changes when the
+    /// result is invalid for any member.
+    pub fn configure_peer_group(
+        &mut self,
+        name: &str,
+        cmd: NeighborCommand,
+    ) -> Result<(), NeighborError> {
+        for neighbor in self.peer_groups.configure_peer_group(name, cmd)? {
+            self.update(neighbor);
+        }
+        self.set_range_passwords();
+        Ok(())
+    }
+
+    /// Apply `cmd` to the template `name`, creating it, and to the
+    /// neighbors inheriting from it like `configure_peer_group()`.
+    pub fn configure_template(
+        &mut self,
+        name: &str,
+        cmd: NeighborCommand,
+    ) -> Result<(), NeighborError> {
+        for neighbor in self.peer_groups.configure_template(name, cmd)? {
+            self.update(neighbor);
+        }
+        self.set_range_passwords();
+        Ok(())
+    }
+
+    /// Apply a `bgp listen` command. Dynamic neighbors no longer inside a
+    /// listen range are stopped.
+    pub fn configure_listen(&mut self, cmd: ListenCommand) -> Result<(), NeighborError> {
+        if let ListenCommand::Range(range) = &cmd {
+            if !self.peer_groups.contains_peer_group(&range.peer_group) {
+                return Err(NeighborError::UnknownPeerGroup(range.peer_group.clone()));
+            }
+        }
+        self.listen.apply(cmd)?;
+        for addr in &self.dynamic {
+            if self.listen.lookup(addr).is_none() {
+                if let Some(session) = self.sessions.get(addr) {
+                    let _ = session.tx.send(Event::Stop);
+                }
+            }
+        }
+        self.set_range_passwords();
+        Ok(())
+    }
+
+    fn apply(&mut self, request: Request) -> Result<(), NeighborError> {
+        match request {
+            Request::Neighbor(ConfigTarget::Neighbor(addr), cmd) => self.configure(addr, cmd),
+            Request::Neighbor(ConfigTarget::PeerGroup(name), cmd) => {
+                self.configure_peer_group(&name, cmd)
+            }
+            Request::Neighbor(ConfigTarget::Template(name), cmd) => {
+                self.configure_template(&name, cmd)
+            }
+            Request::Listen(cmd) => self.configure_listen(cmd),
+            Request::Interface(name, peer_group) => {
+                self.configure_interface(&name, peer_group.as_deref())
+            }
+            Request::Network(line) => self.configure_network(&line),
+            Request::SystemRoute(prefix, true) => {
+                self.add_system_route(prefix);
+                Ok(())
+            }
+            Request::SystemRoute(prefix, false) => {
+                self.remove_system_route(prefix);
+                Ok(())
+            }
+            Request::Aggregate(line) => self.configure_aggregate(&line),
+            Request::Dampening(line) => self.configure_dampening(&line),
+            Request::Clear(addr) => self.clear(&addr),
+        }
+    }
+
+    /// Peer with the router on interface `name`, configured by
+    /// `peer_group`, without addresses of our own. Router Advertisements
+    /// are sent there and the neighbor is created at the link-local
+    /// address of the ones received. `None` removes the neighbor.
+    pub fn configure_interface(
+        &mut self,
+        name: &str,
+        peer_group: Option<&str>,
+    ) -> Result<(), NeighborError> {
+        let peer_group = match peer_group {
+            Some(peer_group) => peer_group,
+            None => {
+                if let Some(old) = self.interfaces.remove(name) {
+                    old.task.abort();
+                    if let Some(addr) = old.neighbor {
+                        self.remove_neighbor(addr);
+                    }
+                }
+                return Ok(());
+            }
+        };
+        if !self.peer_groups.contains_peer_group(peer_group) {
+            return Err(NeighborError::UnknownPeerGroup(peer_group.to_string()));
+        }
+        if let Some(unnumbered) = self.interfaces.get_mut(name) {
+            unnumbered.peer_group = peer_group.to_string();
+            if let Some(addr) = unnumbered.neighbor {
+                let group = NeighborCommand::PeerGroup(Some(peer_group.to_string()));
+                self.configure(addr, group)?;
+            }
+            return Ok(());
+        }
+        let task = tokio::spawn(discover(name.to_string(), RA_INTERVAL, self.ra_tx.clone()));
+        let unnumbered = Unnumbered {
+            peer_group: peer_group.to_string(),
+            neighbor: None,
+            task,
+        };
+        self.interfaces.insert(name.to_string(), unnumbered);
+        Ok(())
+    }
+
+    /// A Router Advertisement from `addr` was received on interface
+    /// `name`. The neighbor is created there, or moved when its address
+    /// changed.
+    fn neighbor_discovered(&mut self, name: String, addr: Ipv6Addr) {
+        let addr = IpAddr::V6(addr);
+        let (peer_group, old) = match self.interfaces.get(&name) {
+            Some(unnumbered) if unnumbered.neighbor == Some(addr) => return,
+            Some(unnumbered) => (unnumbered.peer_group.clone(), unnumbered.neighbor),
+            None => return,
+        };
+        if self.neighbors.get(&addr).is_some() {
+            println!("{}: neighbor {} is configured already", name, addr);
+            return;
+        }
+        if let Some(old) = old {
+            self.remove_neighbor(old);
+        }
+        let mut base = Neighbor::new(addr);
+        base.interface = Some(name.clone());
+        self.peer_groups.add(base);
+        match self
+            .peer_groups
+            .configure(addr, NeighborCommand::PeerGroup(Some(peer_group)))
+        {
+            Ok(neighbor) => {
+                println!("{}: neighbor {} discovered", name, addr);
+                if let Some(unnumbered) = self.interfaces.get_mut(&name) {
+                    unnumbered.neighbor = Some(addr);
+                }
+                self.start(neighbor, false);
+            }
+            Err(e) => {
+                println!("{}: neighbor {} error {}", name, addr, e);
+                self.peer_groups.remove(&addr);
+            }
+        }
+    }
+
+    /// Stop the session of the neighbor at `addr` and forget it.
+    fn remove_neighbor(&mut self, addr: IpAddr) {
+        if let Some(session) = self.sessions.remove(&addr) {
+            let _ = session.tx.send(Event::Stop);
+        }
+        self.neighbors.remove(&addr);
+        self.peer_groups.remove(&addr);
+    }
+
+    /// Effective configuration of the neighbor at `addr` and where it is
+    /// inherited from, see `PeerGroups::show()`.
+    pub fn show_neighbor(&self, addr: &IpAddr) -> Option<String> {
+        let show = self.peer_groups.show(addr)?;
+        match self.listen.lookup(addr) {
+            Some(range) if self.dynamic.contains(addr) => Some(show.replacen(
+                "\n",
+                &format!("\n  dynamic, listen range {}\n", range.prefix),
+                1,
+            )),
+            _ => Some(show),
+        }
+    }
+
+    pub fn is_dynamic(&self, addr: &IpAddr) -> bool {
+        self.dynamic.contains(addr)
+    }
+
+    /// Start the session of a new neighbor, or pass the changed
+    /// configuration to its session.
+    fn update(&mut self, neighbor: Neighbor) {
+        let addr = neighbor.ipaddr;
+        let session = match self.sessions.get(&addr) {
+            Some(session) => session,
+            None => return self.start(neighbor, false),
+        };
+        let _ = session.tx.send(Event::Config(Box::new(neighbor.clone())));
+        let password = self.neighbors.get(&addr).map(|old| &old.password);
+        if password != Some(&neighbor.password) {
+            self.set_password(&neighbor);
+        }
+        self.neighbors.insert(addr, neighbor);
+    }
+
+    /// Install the neighbor's password on the listening socket, so that
+    /// its connections are accepted.
+    fn set_password(&self, neighbor: &Neighbor) {
+        if let Some(fd) = self.listener {
+            if let Err(e) = tcp::set_md5sig(fd, neighbor.ipaddr, neighbor.password.as_deref()) {
+                println!("{}: password error {}", neighbor.ipaddr, e);
+            }
+        }
+    }
+
+    /// Install the password of each listen range's peer-group on the
+    /// listening socket for the range's prefix, so that connections from
+    /// unknown addresses inside it are accepted, and remove those of ranges
+    /// gone or without a password.
+    fn set_range_passwords(&mut self) {
+        let fd = match self.listener {
+            Some(fd) => fd,
+            None => return,
+        };
+        let mut passwords = BTreeMap::new();
+        for range in self.listen.ranges() {
+            if let Ok(Neighbor {
+                password: Some(password),
+                ..
+            }) = self.peer_groups.peer_group_defaults(&range.peer_group)
+            {
+                passwords.insert(range.prefix, password);
+            }
+        }
+        for prefix in self.range_passwords.keys() {
+            if !passwords.contains_key(prefix) {
+                if let Err(e) = tcp::set_md5sig_prefix(fd, prefix, None) {
+                    println!("{}: password error {}", prefix, e);
+                }
+            }
+        }
+        for (prefix, password) in &passwords {
+            if self.range_passwords.get(prefix) != Some(password) {
+                if let Err(e) = tcp::set_md5sig_prefix(fd, prefix, Some(password)) {
+                    println!("{}: password error {}", prefix, e);
+                }
+            }
+        }
+        self.range_passwords = passwords;
+    }
+
+    /// Configure `neighbor` and start its session. Configuration
+    /// commands are applied on top of it.
+    pub fn add_neighbor(&mut self, neighbor: Neighbor) {
+        self.peer_groups.add(neighbor.clone());
+        self.start(neighbor, false);
+    }
+
+    /// Create a passive neighbor for `addr` in the peer-group of the listen
+    /// range containing it.
+    fn add_dynamic(&mut self, addr: IpAddr) -> Result<(), NeighborError> {
+        let range = self
+            .listen
+            .lookup(&addr)
+            .ok_or(NeighborError::NotConfigured(addr))?;
+        if self.dynamic.len() >= self.listen.limit {
+            return Err(NeighborError::DynamicLimit(self.listen.limit));
+        }
+        let group = NeighborCommand::PeerGroup(Some(range.peer_group.clone()));
+        let mut base = Neighbor::new(addr);
+        base.connect_mode = ConnectMode::Passive;
+        self.peer_groups.add(base);
+        let neighbor = match self.peer_groups.configure(addr, group) {
+            Ok(neighbor) if neighbor.shutdown => Err(NeighborError::Shutdown(addr)),
+            res => res,
+        };
+        match neighbor {
+            Ok(neighbor) => {
+                println!("{}: dynamic neighbor created", addr);
+                self.dynamic.insert(addr);
+                self.start(neighbor, true);
+                Ok(())
+            }
+            Err(e) => {
+                self.peer_groups.remove(&addr);
+                Err(e)
+            }
+        }
+    }
+
+    /// Remove the dynamic neighbor at `addr` once its session has ended.
+    fn remove_dynamic(&mut self, addr: IpAddr) {
+        if self.dynamic.remove(&addr) {
+            println!("{}: dynamic neighbor removed", addr);
+            self.sessions.remove(&addr);
+            if let Some(neighbor) = self.neighbors.remove(&addr) {
+                if neighbor.password.is_some() {
+                    self.set_password(&Neighbor::new(addr));
+                }
+            }
+            self.peer_groups.remove(&addr);
+        }
+    }
+
+    fn start(&mut self, neighbor: Neighbor, dynamic: bool) {
+        let addr = neighbor.ipaddr;
+        if neighbor.password.is_some() {
+            self.set_password(&neighbor);
+        }
+        let (tx, rx) = mpsc::unbounded_channel();
+        let (state_tx, state) = watch::channel(State::Idle);
+        let session = Session {
+            config: self.config.clone(),
+            neighbor: neighbor.clone(),
+            rx,
+            state: state_tx,
+            down: if dynamic {
+                Some(self.down_tx.clone())
+            } else {
+                None
+            },
+            received: BTreeMap::new(),
+            limited: None,
+            rib: self.rib_tx.clone(),
+            conn_id: 0,
+            established: false,
+        };
+        let task = tokio::spawn(session.run());
+        self.neighbors.insert(addr, neighbor);
+        let handle = SessionHandle {
+            tx,
+            state,
+            task,
+            conn_id: None,
+        };
+        if let Some(old) = self.sessions.insert(addr, handle) {
+            let _ = old.tx.send(Event::Stop);
+        }
+    }
+
+    /// `clear bgp <addr>`: reset the session of the neighbor at `addr` with
+    /// a Cease NOTIFICATION, or let it connect again when maximum-prefix
+    /// keeps it Idle.
+    pub fn clear(&self, addr: &IpAddr) -> Result<(), NeighborError> {
+        let session = self
+            .sessions
+            .get(addr)
+            .ok_or(NeighborError::NotConfigured(*addr))?;
+        let _ = session.tx.send(Event::Clear);
+        Ok(())
+    }
+
+    /// The Loc-RIB, best paths of all prefixes.
+    pub fn loc_rib(&self) -> impl Iterator<Item = &Route> {
+        self.rib.loc_rib()
+    }
+
+    /// Apply a `[no] aggregate-address ...` configuration line, the
+    /// aggregates are originated again.
+    pub fn configure_aggregate(&mut self, line: &str) -> Result<(), NeighborError> {
+        let prefix = self.aggregates.configure(line)?;
+        let changed = self.aggregate(prefix);
+        self.best_changed(changed);
+        Ok(())
+    }
+
+    /// Apply a `[no] [<afi> <safi>] bgp dampening ...` line. Paths which
+    /// are no longer suppressed afterwards are reused.
+    pub fn configure_dampening(&mut self, line: &str) -> Result<(), NeighborError> {
+        let cmd = DampeningCommand::parse(line)?;
+        self.dampenings.configure(cmd, std::time::Instant::now());
+        let mut changed = BTreeSet::new();
+        for (source, prefix) in self.rib.damped() {
+            if let RouteSource::Neighbor(addr) = source {
+                if !self.dampenings.is_suppressed(&(prefix, addr))
+                    && self.rib.set_damped(source, prefix, false)
+                {
+                    changed.insert(prefix);
+                }
+            }
+        }
+        self.best_changed(changed);
+        Ok(())
+    }
+
+    /// Advance the dampening reuse lists, reusing the paths whose
+    /// suppression ended.
+    fn dampening_tick(&mut self) {
+        let mut changed = BTreeSet::new();
+        for (prefix, addr) in self.dampenings.tick(std::time::Instant::now()) {
+            if self
+                .rib
+                .set_damped(RouteSource::Neighbor(addr), prefix, false)
+            {
+                changed.insert(prefix);
+            }
+        }
+        self.best_changed(changed);
+    }
+
+    /// Originate the aggregate of `prefix` from the best paths it contains
+    /// again, or withdraw it when it is not configured anymore. Returns the
+    /// prefixes whose best path or suppression changed.
+    fn aggregate(&mut self, prefix: Prefix) -> BTreeSet<Prefix> {
+        let rib = &self.rib;
+        let aggregated = self.aggregates.get(&prefix).and_then(|aggregate| {
+            let contributors = rib
+                .loc_rib_within(&prefix)
+                .filter(|route| rib.best_source(&route.prefix) != Some(RouteSource::Aggregate));
+            aggregate.aggregate(
+                &self.policy,
+                self.config.asn,
+                self.config.router_id,
+                contributors,
+            )
+        });
+        let (route, suppressed) = match aggregated {
+            Some(aggregated) => (Some(aggregated.route), aggregated.suppressed),
+            None => (None, BTreeSet::new()),
+        };
+
+        let old = self.suppressed_by.remove(&prefix).unwrap_or_default();
+        let mut changed: BTreeSet<Prefix> =
+            old.symmetric_difference(&suppressed).copied().collect();
+        if !suppressed.is_empty() {
+            self.suppressed_by.insert(prefix, suppressed);
+        }
+        for p in &changed {
+            if self.suppressed_by.values().any(|s| s.contains(p)) {
+                self.suppressed.insert(*p);
+            } else {
+                self.suppressed.remove(p);
+            }
+        }
+
+        let source = RouteSource::Aggregate;
+        let released = self.rib.path(source, &prefix).is_some() as usize;
+        let best = match route {
+            Some(mut route) => {
+                route.attr = self.attrs.intern_set(route.attr);
+                self.rib.update(source, route)
+            }
+            None => self.rib.withdraw(source, prefix),
+        };
+        if best {
+            changed.insert(prefix);
+        }
+        self.release(released);
+        changed
+    }
+
+    /// Replace the routes originated from the local `source` by `routes`.
+    /// Returns the prefixes whose best path changed.
+    fn originate(&mut self, source: RouteSource, routes: Vec<Route>) -> BTreeSet<Prefix> {
+        let mut changed = BTreeSet::new();
+        let mut old: BTreeSet<Prefix> = self.rib.prefixes_from(source).into_iter().collect();
+        let released = old.len();
+        for mut route in routes {
+            route.attr = self.attrs.intern_set(route.attr);
+            let prefix = route.prefix;
+            old.remove(&prefix);
+            if self.rib.update(source, route) {
+                changed.insert(prefix);
+            }
+        }
+        for prefix in old {
+            if self.rib.withdraw(source, prefix) {
+                changed.insert(prefix);
+            }
+        }
+        self.release(released);
+        changed
+    }
+
+    /// Apply a `[no] network ...` or `[no] bgp network import-check`
+    /// configuration line, the networks are originated again.
+    pub fn configure_network(&mut self, line: &str) -> Result<(), NeighborError> {
+        let backdoors = self.networks.backdoors();
+        self.networks.configure(line)?;
+        for prefix in backdoors.symmetric_difference(&self.networks.backdoors()) {
+            self.install(*prefix);
+        }
+        self.networks_changed();
+        Ok(())
+    }
+
+    /// The system RIB has a route for `prefix`, networks waiting for it
+    /// with import-check are originated.
+    pub fn add_system_route(&mut self, prefix: Prefix) {
+        if self.system_rib.insert(prefix) {
+            self.networks_changed();
+        }
+    }
+
+    /// The system RIB has no route for `prefix` anymore.
+    pub fn remove_system_route(&mut self, prefix: Prefix) {
+        if self.system_rib.remove(&prefix) {
+            self.networks_changed();
+        }
+    }
+
+    fn networks_changed(&mut self) {
+        let routes = self.networks.originate(&self.policy, &self.system_rib);
+        let changed = self.originate(RouteSource::Network, routes);
+        self.best_changed(changed);
+    }
+
+    /// Send each neighbor its Adj-RIB-Out changes.
+    fn send_changes(&self, changes: BTreeMap<IpAddr, Vec<AdjOut>>) {
+        for (addr, changes) in changes {
+            if let Some(session) = self.sessions.get(&addr) {
+                if let Some(id) = session.conn_id {
+                    let _ = session.tx.send(Event::Adj(id, changes));
+                }
+            }
+        }
+    }
+
+    /// Install the best path to `prefix` when it is from an unnumbered
+    /// neighbor, through the neighbor's link-local address whatever the
+    /// family (RFC 8950), or remove the one installed. eBGP paths of
+    /// backdoor networks are left to the IGP.
+    fn install(&mut self, prefix: Prefix) {
+        let fib = match &self.fib {
+            Some(fib) => fib,
+            None => return,
+        };
+        let backdoor = self.networks.is_backdoor(&prefix);
+        let nexthop = match self.rib.best_source(&prefix) {
+            Some(RouteSource::Neighbor(addr))
+                if backdoor && self.rib.peer_type(&addr) != Some(PeerType::Internal) =>
+            {
+                None
+            }
+            Some(RouteSource::Neighbor(addr)) => self
+                .neighbors
+                .get(&addr)
+                .filter(|neighbor| neighbor.interface.is_some())
+                .map(|neighbor| NextHop {
+                    addr,
+                    ifindex: neighbor.ifindex(),
+                }),
+            _ => None,
+        };
+        let change = match (nexthop, self.installed.get(&prefix)) {
+            (Some(nexthop), Some(old)) if nexthop == *old => return,
+            (Some(nexthop), _) => {
+                self.installed.insert(prefix, nexthop);
+                FibChange::Install(prefix, nexthop)
+            }
+            (None, Some(_)) => FibChange::Remove(prefix, self.installed.remove(&prefix).unwrap()),
+            (None, None) => return,
+        };
+        let _ = fib.send(change);
+    }
+
+    /// Pass the new best paths of `prefixes` to the update groups, after
+    /// the aggregates they contribute to. Suppressed routes are withdrawn.
+    fn best_changed(&mut self, prefixes: impl IntoIterator<Item = Prefix>) {
+        let mut prefixes: BTreeSet<Prefix> = prefixes.into_iter().collect();
+        let mut aggregates: Vec<Prefix> = prefixes
+            .iter()
+            .flat_map(|prefix| self.aggregates.covering(prefix))
+            .map(|aggregate| aggregate.prefix)
+            .collect::<BTreeSet<Prefix>>()
+            .into_iter()
+            .collect();
+        // More specific aggregates first, their routes compete with the
+        // contributors of the others.
+        aggregates.sort_by_key(|prefix| std::cmp::Reverse(prefix.prefixlen()));
+        for aggregate in aggregates {
+            prefixes.extend(self.aggregate(aggregate));
+        }
+        let mut changes: BTreeMap<IpAddr, Vec<AdjOut>> = BTreeMap::new();
+        let mut conditions = Vec::new();
+        for prefix in prefixes {
+            self.install(prefix);
+            let best = if self.suppressed.contains(&prefix) {
+                None
+            } else {
+                self.rib.best(&prefix)
+            };
+            let updates = self
+                .groups
+                .best_changed(&self.policy, &mut self.attrs, prefix, best);
+            member_changes(updates, &mut changes);
+            conditions.extend(self.groups.track(&self.policy, prefix, best));
+        }
+        if !conditions.is_empty() {
+            conditions.sort_unstable();
+            conditions.dedup();
+            self.advertise_conditional(Some(&conditions), &mut changes);
+        }
+        self.send_changes(changes);
+    }
+
+    /// Announce or withdraw the routes of the advertise-maps whose
+    /// condition changed, of the groups `changed` or of the conditions due
+    /// when `None`, adding the changes of the members to `changes`.
+    fn advertise_conditional(
+        &mut self,
+        changed: Option<&[u32]>,
+        changes: &mut BTreeMap<IpAddr, Vec<AdjOut>>,
+    ) {
+        if self.groups.deadline().is_none() {
+            return;
+        }
+        let suppressed = &self.suppressed;
+        let best: Vec<&Route> = self
+            .rib
+            .loc_rib()
+            .filter(|route| !suppressed.contains(&route.prefix))
+            .collect();
+        let updates = match changed {
+            Some(ids) => self
+                .groups
+                .readvertise(&self.policy, &mut self.attrs, ids, &best),
+            None => self
+                .groups
+                .evaluate(&self.policy, &mut self.attrs, &best, Instant::now()),
+        };
+        member_changes(updates, changes);
+    }
+
+    /// Routes received from the neighbor at `addr` after inbound policy.
+    /// Routes which loop or are denied replace earlier ones like a
+    /// withdrawal.
+    fn routes_received(&mut self, addr: IpAddr, routes: Vec<Route>, withdrawn: Vec<Prefix>) {
+        let neighbor = match self.neighbors.get(&addr) {
+            Some(neighbor) => neighbor,
+            None => return,
+        };
+        let source = RouteSource::Neighbor(addr);
+        let mut changed = BTreeSet::new();
+        let released = routes.len() + withdrawn.len();
+        let now = std::time::Instant::now();
+        for prefix in withdrawn {
+            if let Some(old) = self.rib.path(source, &prefix) {
+                self.dampenings.withdraw(old, &self.policy, now);
+            }
+            if self.rib.withdraw(source, prefix) {
+                changed.insert(prefix);
+            }
+        }
+        let ibgp = self.rib.peer_type(&addr) == Some(PeerType::Internal);
+        let downstream = if ibgp {
+            None
+        } else {
+            neighbor.aspa_downstream()
+        };
+        let rpki = self.rpki.read().unwrap();
+        let aspas = rpki.aspas(&self.aspas);
+        for mut route in routes {
+            route.ibgp = ibgp;
+            neighbor.attr_in(&mut route);
+            rpki.vrps.validate_route(&mut route, self.config.asn);
+            if let Some(downstream) = downstream {
+                aspas.verify_route(&mut route, downstream);
+            }
+            if route
+                .attr
+                .communities
+                .as_ref()
+                .is_some_and(|coms| coms.has_inbound_action())
+            {
+                let mut attr = route.attr.attr();
+                attr.apply_well_known_communities();
+                route.attr = attr.into();
+            }
+            let prefix = route.prefix;
+            let old = self.rib.path(source, &prefix);
+            let best = if neighbor.as_loop(&route, self.config.asn)
+                || neighbor.policy_in(&self.policy, &mut route) == Action::Deny
+            {
+                if let Some(old) = old {
+                    self.dampenings.withdraw(old, &self.policy, now);
+                }
+                self.rib.withdraw(source, prefix)
+            } else {
+                // A re-advertisement after a withdrawal is only checked for
+                // suppression, the withdrawal was charged already.
+                let attr_changed = old.is_some_and(|old| old.attr != route.attr);
+                route.damped = self
+                    .dampenings
+                    .update(&route, attr_changed, &self.policy, now);
+                route.attr = self.attrs.intern_set(route.attr);
+                self.rib.update(source, route)
+            };
+            if best {
+                changed.insert(prefix);
+            }
+        }
+        drop(rpki);
+        self.best_changed(changed);
+        self.release(released);
+    }
+
+    /// Validate and verify the received paths again once a cache
+    /// synchronized. Only their validation states change, inbound policy is
+    /// not applied again.
+    fn revalidate(&mut self) {
+        let rpki = self.rpki.clone();
+        let rpki = rpki.read().unwrap();
+        let aspas = rpki.aspas(&self.aspas);
+        let asn = self.config.asn;
+        let neighbors = &self.neighbors;
+        let changed = self.rib.modify(|source, route| {
+            let addr = match source {
+                RouteSource::Neighbor(addr) => addr,
+                _ => return false,
+            };
+            let old = (route.rpki, route.aspa);
+            rpki.vrps.validate_route(route, asn);
+            let downstream = neighbors.get(addr).and_then(|n| n.aspa_downstream());
+            match downstream {
+                Some(downstream) if !route.ibgp => aspas.verify_route(route, downstream),
+                _ => {}
+            }
+            (route.rpki, route.aspa) != old
+        });
+        drop(rpki);
+        self.best_changed(changed);
+    }
+
+    /// Account for `routes` replaced or withdrawn, and drop the attributes
+    /// no route refers to anymore once as many routes were released as
+    /// there are attribute sets.
+    fn release(&mut self, routes: usize) {
+        self.released += routes;
+        if self.released > self.attrs.len() {
+            self.attrs.collect();
+            self.released = 0;
+        }
+    }
+
+    /// Memory used by the path attributes of the routes, per attribute
+    /// type, and by the Adj-RIB-Out of the update groups.
+    pub fn show_memory(&self) -> String {
+        let routes = self.groups.adj_out_len();
+        let bytes = routes * (std::mem::size_of::<Prefix>() + std::mem::size_of::<Route>());
+        format!(
+            "{}{:<22} {} entries, {} bytes\n",
+            self.attrs.show(),
+            "adj-rib-out",
+            routes,
+            bytes
+        )
+    }
+
+    fn rib_event(&mut self, ev: RibEvent) {
+        match ev {
+            RibEvent::Up(neighbor, key, router_id, id) => {
+                let addr = neighbor.ipaddr;
+                match self.sessions.get_mut(&addr) {
+                    Some(session) if session.conn_id != Some(id) => {
+                        session.conn_id = Some(id);
+                        // A new connection starts from an empty
+                        // Adj-RIB-Out.
+                        self.groups.leave(&addr);
+                    }
+                    Some(_) => {}
+                    None => return,
+                }
+                self.rib.peer_up(addr, key.peer_type, router_id);
+                let suppressed = &self.suppressed;
+                let best = self
+                    .rib
+                    .loc_rib()
+                    .filter(|route| !suppressed.contains(&route.prefix));
+                let changes = self
+                    .groups
+                    .join(&self.policy, &mut self.attrs, &neighbor, key, best);
+                self.send_changes(BTreeMap::from([(addr, changes)]));
+            }
+            RibEvent::Update(addr, routes, withdrawn) => {
+                self.routes_received(addr, routes, withdrawn)
+            }
+            RibEvent::Down(addr) => {
+                if let Some(session) = self.sessions.get_mut(&addr) {
+                    session.conn_id = None;
+                }
+                self.groups.leave(&addr);
+                let changed = self.rib.peer_down(addr);
+                self.best_changed(changed);
+                self.attrs.collect();
+                self.released = 0;
+            }
+        }
+    }
+
+    /// Session state of the neighbor at `addr`, changes can be awaited.
+    pub fn state(&self, addr: &IpAddr) -> Option<watch::Receiver<State>> {
+        self.sessions.get(addr).map(|s| s.state.clone())
+    }
+
+    /// Route an accepted connection by its source address. A connection
+    /// from an unknown address inside a listen range creates a dynamic
+    /// neighbor, others are closed.
+    pub fn accept(&mut self, stream: TcpStream, addr: SocketAddr) {
+        let ip = peer_addr(addr);
+        if self.neighbors.get(&ip).is_none() && self.listen.lookup(&ip).is_some() {
+            if let Err(e) = self.add_dynamic(ip) {
+                println!("{}: connection closed, {}", addr, e);
+                return;
+            }
+        }
+        match (self.neighbors.get(&ip), self.sessions.get(&ip)) {
+            (Some(_), Some(session)) => {
+                let _ = session.tx.send(Event::Accept((stream, addr)));
+            }
+            _ => println!("{}: connection from unknown neighbor closed", addr),
+        }
+    }
+
+    /// Stop every session, sending Cease NOTIFICATIONs on established
+    /// connections, and wait for the tasks to end.
+    pub async fn shutdown(&mut self) {
+        for unnumbered in self.interfaces.values() {
+            unnumbered.task.abort();
+        }
+        for rtr in self.rtr.drain(..) {
+            rtr.abort();
+        }
+        for session in self.sessions.values() {
+            let _ = session.tx.send(Event::Stop);
+        }
+        for (_, session) in std::mem::take(&mut self.sessions) {
+            let _ = session.task.await;
+        }
+        if let Some(fib) = &self.fib {
+            for (prefix, nexthop) in std::mem::take(&mut self.installed) {
+                let _ = fib.send(FibChange::Remove(prefix, nexthop));
+            }
+        }
+    }
+
+    /// Accept connections on `listener` until `shutdown` completes, then
+    /// stop all sessions.
+    /// Configuration changes sent through a `BgpdHandle` are applied
+    /// meanwhile.
+    pub async fn serve<F: Future<Output = ()>>(&mut self, listener: TcpListener, shutdown: F) {
+        self.listener = Some(listener.as_raw_fd());
+        for addr in self.sessions.keys() {
+            if let Some(neighbor) = self.neighbors.get(addr) {
+                if neighbor.password.is_some() {
+                    self.set_password(neighbor);
+                }
+            }
+        }
+        self.set_range_passwords();
+        if let Some(path) = &self.config.aspa_file {
+            match AspaTable::load(path) {
+                Ok(aspas) => self.aspas = aspas,
+                Err(e) => println!("{}: {}", path.display(), e),
+            }
+        }
+        for addr in &self.config.rpki_caches {
+            let mut client = RtrClient::new(*addr, self.rpki.clone());
+            client.updated = Some(self.rpki_tx.clone());
+            self.rtr.push(tokio::spawn(client.run()));
+        }
+        tokio::pin!(shutdown);
+        let mut reuse = tokio::time::interval(DAMP_REUSE_TICK);
+        loop {
+            let due = self.groups.deadline();
+            tokio::select! {
+                res = listener.accept() => match res {
+                    Ok((stream, addr)) => self.accept(stream, addr),
+                    Err(e) => println!("accept error {}", e),
+                },
+                Some((request, reply)) = self.config_rx.recv() => {
+                    let _ = reply.send(self.apply(request));
+                },
+                Some(addr) = self.down_rx.recv() => self.remove_dynamic(addr),
+                Some(ev) = self.rib_rx.recv() => self.rib_event(ev),
+                Some((name, res)) = self.ra_rx.recv() => match res {
+                    Ok(addr) => self.neighbor_discovered(name, addr),
+                    Err(e) => println!("{}: router advertisement error {}", name, e),
+                },
+                _ = sleep_until(due.unwrap_or_else(Instant::now)), if due.is_some() => {
+                    let mut changes = BTreeMap::new();
+                    self.advertise_conditional(None, &mut changes);
+                    self.send_changes(changes);
+                }
+                _ = reuse.tick(), if !self.dampenings.is_empty() => self.dampening_tick(),
+                Some(_) = self.rpki_rx.recv() => self.revalidate(),
+                _ = &mut shutdown => break,
+            }
+        }
+        self.listener = None;
+        self.range_passwords.clear();
+        self.shutdown().await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bgp::attr::ATTR_TYPE_MP_REACH_NLRI;
+    use crate::bgp::{AspaState, RpkiState, Vrp};
+    use crate::bgp::{Attr, UpdateError, AFI_IP6, NOTIFY_UPDATE_MAL_ATTR};
+    use crate::bgp::{Communities, ErrorHandling, MaxPrefix, MpReach, MpUnreach, Role};
+    use tokio::sync::oneshot;
+    use tokio::time::timeout;
+
+    async fn wait(state: &mut watch::Receiver<State>, f: impl Fn(State) -> bool) {
+        let wait = async {
+            while !f(*state.borrow()) {
+                state.changed().await.unwrap();
+            }
+        };
+        timeout(Duration::from_secs(10), wait).await.unwrap();
+    }
+
+    fn bgpd(asn: u32, id: &str, port: u16, peer_port: u16) -> Bgpd {
+        let mut config = BgpConfig::new(asn, id.parse().unwrap());
+        config.port = port;
+        config.connect_retry = Duration::from_millis(200);
+        let mut bgpd = Bgpd::new(config);
+        let mut neighbor = Neighbor::new("127.0.0.1".parse().unwrap());
+        neighbor.port = peer_port;
+        bgpd.add_neighbor(neighbor);
+        bgpd
+    }
+
+    #[tokio::test]
+    async fn session() {
+        let la = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let lb = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let pa = la.local_addr().unwrap().port();
+        let pb = lb.local_addr().unwrap().port();
+
+        // Both sides connect at once, the collision leaves one session.
+        let addr: IpAddr = "127.0.0.1".parse().unwrap();
+        let mut a = bgpd(65001, "10.0.0.1", pa, pb);
+        let mut b = bgpd(65002, "10.0.0.2", pb, pa);
+        let mut sa = a.state(&addr).unwrap();
+        let mut sb = b.state(&addr).unwrap();
+
+        let (stop_a, stopped_a) = oneshot::channel::<()>();
+        let (stop_b, stopped_b) = oneshot::channel::<()>();
+        let ta = tokio::spawn(async move {
+            a.serve(la, async {
+                let _ = stopped_a.await;
+            })
+            .await
+        });
+        let tb = tokio::spawn(async move {
+            b.serve(lb, async {
+                let _ = stopped_b.await;
+            })
+            .await
+        });
+
+        wait(&mut sa, |s| s == State::Established).await;
+        wait(&mut sb, |s| s == State::Established).await;
+
+        // Shutting down one side takes the session down on the other.
+        stop_a.send(()).unwrap();
+        ta.await.unwrap();
+        wait(&mut sb, |s| s != State::Established).await;
+
+        stop_b.send(()).unwrap();
+        tb.await.unwrap();
+        assert_eq!(*sb.borrow(), State::Idle);
+    }
+
+    /// Connect to `to` as AS 65002 and send OPEN and KEEPALIVE.
+    async fn connect(to: SocketAddr, hold_time: u16) -> Connection {
+        connect_from("127.0.0.1", 65002, to, hold_time).await
+    }
+
+    async fn connect_from(from: &str, asn: u32, to: SocketAddr, hold_time: u16) -> Connection {
+        let socket = tokio::net::TcpSocket::new_v4().unwrap();
+        socket
+            .bind(SocketAddr::new(from.parse().unwrap(), 0))
+            .unwrap();
+        let stream = socket.connect(to).await.unwrap();
+        let mut conn = Framed::new(stream, Peer::new(State::OpenSent));
+        conn.codec_mut().as4 = true;
+        let id = "10.0.0.2".parse().unwrap();
+        let open = MessageOpen::local(asn, hold_time, id, Vec::new());
+        conn.send(Message::Open(open)).await.unwrap();
+        conn.send(Message::KeepAlive).await.unwrap();
+        conn
+    }
+
+    #[tokio::test]
+    async fn hold_timer() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let to = listener.local_addr().unwrap();
+        let mut config = BgpConfig::new(65001, "10.0.0.1".parse().unwrap());
+        config.port = to.port();
+        config.hold_time = 3;
+        let mut bgpd = Bgpd::new(config);
+        let mut neighbor = Neighbor::new("127.0.0.1".parse().unwrap());
+        neighbor.connect_mode = ConnectMode::Passive;
+        bgpd.add_neighbor(neighbor);
+        let mut state = bgpd.state(&"127.0.0.1".parse().unwrap()).unwrap();
+        let (stop, stopped) = oneshot::channel::<()>();
+        let task = tokio::spawn(async move {
+            bgpd.serve(listener, async {
+                let _ = stopped.await;
+            })
+            .await
+        });
+
+        // Establish, then fall silent.
+        let conn = connect(to, 3).await;
+        wait(&mut state, |s| s == State::Established).await;
+
+        // Connections losing the collision do not keep the session up.
+        let reconnect = tokio::spawn(async move {
+            loop {
+                let _ = TcpStream::connect(to).await;
+                tokio::time::sleep(Duration::from_millis(200)).await;
+            }
+        });
+        wait(&mut state, |s| s != State::Established).await;
+        reconnect.abort();
+        drop(conn);
+        stop.send(()).unwrap();
+        task.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn max_prefix() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let to = listener.local_addr().unwrap();
+        let addr: IpAddr = "127.0.0.1".parse().unwrap();
+        let mut config = BgpConfig::new(65001, "10.0.0.1".parse().unwrap());
+        config.port = to.port();
+        let mut bgpd = Bgpd::new(config);
+        let handle = bgpd.handle();
+        let mut neighbor = Neighbor::new(addr);
+        neighbor.connect_mode = ConnectMode::Passive;
+        let ipv4 = Family {
+            afi: AFI_IP,
+            safi: SAFI_UNICAST,
+        };
+        neighbor.max_prefix.insert(ipv4, MaxPrefix::new(2));
+        bgpd.add_neighbor(neighbor);
+        let mut state = bgpd.state(&addr).unwrap();
+        let (stop, stopped) = oneshot::channel::<()>();
+        let task = tokio::spawn(async move {
+            bgpd.serve(listener, async {
+                let _ = stopped.await;
+            })
+            .await
+        });
+
+        let mut conn = connect(to, 90).await;
+        wait(&mut state, |s| s == State::Established).await;
+        let mut update = MessageUpdate::new();
+        update.attr.as_path = "65002".parse().unwrap();
+        update.attr.next_hop = Some("10.0.0.2".parse().unwrap());
+        update.nlri = vec!["10.1.0.0/16".parse().unwrap()];
+        update.withdrawn = vec!["10.9.0.0/16".parse().unwrap()];
+        conn.send(Message::Update(update.clone())).await.unwrap();
+
+        // Withdrawals make room, the same prefix is counted once.
+        update.nlri = vec![
+            "10.1.0.0/16".parse().unwrap(),
+            "10.2.0.0/16".parse().unwrap(),
+        ];
+        update.withdrawn = vec!["10.1.0.0/16".parse().unwrap()];
+        conn.send(Message::Update(update.clone())).await.unwrap();
+        update.withdrawn = Vec::new();
+        update.nlri = vec!["10.3.0.0/16".parse().unwrap()];
+        conn.send(Message::Update(update)).await.unwrap();
+        loop {
+            match conn.next().await {
+                Some(Ok(Message::Open(_) | Message::KeepAlive)) => {}
+                Some(Ok(Message::Notification(n))) => {
+                    let cease = MaxPrefix::new(2).notification(ipv4, MaxPrefixEvent::LimitExceeded);
+                    assert_eq!(Some(n), cease);
+                    break;
+                }
+                msg => panic!("unexpected {:?}", msg),
+            }
+        }
+        assert!(conn.next().await.is_none());
+
+        // Without a restart time the neighbor stays Idle until cleared.
+        wait(&mut state, |s| s == State::Idle).await;
+        let mut conn = connect(to, 90).await;
+        assert!(!matches!(conn.next().await, Some(Ok(_))));
+        assert_eq!(*state.borrow(), State::Idle);
+        handle.clear(addr).await.unwrap();
+        wait(&mut state, |s| s == State::Active).await;
+        let _conn = connect(to, 90).await;
+        wait(&mut state, |s| s == State::Established).await;
+
+        stop.send(()).unwrap();
+        task.await.unwrap();
+    }
+
+    fn new_session(neighbor: Neighbor) -> Session {
+        let (_, rx) = mpsc::unbounded_channel();
+        let (state, _) = watch::channel(State::Idle);
+        Session {
+            config: Arc::new(BgpConfig::new(65001, "10.0.0.1".parse().unwrap())),
+            neighbor,
+            rx,
+            state,
+            down: None,
+            received: BTreeMap::new(),
+            limited: None,
+            rib: mpsc::unbounded_channel().0,
+            conn_id: 0,
+            established: false,
+        }
+    }
+
+    #[test]
+    fn afi_safi_disable() {
+        let mut session = new_session(Neighbor::new("10.0.0.2".parse().unwrap()));
+        let mut peer = Peer::new(State::Established);
+        let ipv4 = Family {
+            afi: AFI_IP,
+            safi: SAFI_UNICAST,
+        };
+        let ipv6 = Family {
+            afi: AFI_IP6,
+            safi: SAFI_UNICAST,
+        };
+        let mut update = MessageUpdate::new();
+        update.attr.as_path = "65002".parse().unwrap();
+        update.attr.next_hop = Some("10.0.0.2".parse().unwrap());
+        update.nlri = vec!["10.1.0.0/16".parse().unwrap()];
+        update.mp_reach = Some(MpReach {
+            family: ipv6,
+            next_hop: "2001:db8::2".parse().unwrap(),
+            link_local: None,
+            nlri: vec![
+                "2001:db8:1::/48".parse().unwrap(),
+                "2001:db8:2::/48".parse().unwrap(),
+            ],
+        });
+        let update = peer.update_received(update);
+        assert_eq!(session.update_received(&mut peer, &update), None);
+        assert_eq!(session.received[&ipv6].len(), 2);
+        assert_eq!(peer.prefix_count[&ipv6].count(), 2);
+
+        // A malformed MP_REACH_NLRI withdraws all IPv6 prefixes, IPv4 ones
+        // stay.
+        let mut update = MessageUpdate::new();
+        update.errors.push(UpdateError {
+            handling: ErrorHandling::AfiSafiDisable,
+            subcode: NOTIFY_UPDATE_MAL_ATTR,
+            typ: ATTR_TYPE_MP_REACH_NLRI,
+            family: Some(ipv6),
+        });
+        let update = peer.update_received(update);
+        assert_eq!(session.update_received(&mut peer, &update), None);
+        assert!(!session.received.contains_key(&ipv6));
+        assert_eq!(session.received[&ipv4].len(), 1);
+        assert_eq!(peer.prefix_count[&ipv6].count(), 0);
+        assert_eq!(peer.prefix_count[&ipv4].count(), 1);
+    }
+
+    #[test]
+    fn otc_in() {
+        let mut neighbor = Neighbor::new("10.0.0.2".parse().unwrap());
+        neighbor.local_role = Some(Role::Provider);
+        let mut session = new_session(neighbor);
+        let mut peer = Peer::new(State::Established);
+        peer.remote_as = 65002;
+        let ipv4 = Family {
+            afi: AFI_IP,
+            safi: SAFI_UNICAST,
+        };
+        let mut update = MessageUpdate::new();
+        update.attr.as_path = "65002".parse().unwrap();
+        update.attr.next_hop = Some("10.0.0.2".parse().unwrap());
+        update.nlri = vec!["10.1.0.0/16".parse().unwrap()];
+        assert_eq!(session.update_received(&mut peer, &update), None);
+        assert_eq!(session.received[&ipv4].len(), 1);
+
+        // A customer's route with OTC is a leak and replaces the route
+        // accepted before like a withdrawal.
+        update.attr.otc = Some(65010);
+        assert_eq!(session.update_received(&mut peer, &update), None);
+        assert!(session.received[&ipv4].is_empty());
+        assert_eq!(peer.prefix_count[&ipv4].count(), 0);
+    }
+
+    /// The next UPDATE received on `conn`.
+    async fn next_update(conn: &mut Connection) -> MessageUpdate {
+        let next = async {
+            loop {
+                match conn.next().await {
+                    Some(Ok(Message::Update(update))) => return update,
+                    Some(Ok(Message::Open(_) | Message::KeepAlive)) => {}
+                    msg => panic!("unexpected {:?}", msg),
+                }
+            }
+        };
+        timeout(Duration::from_secs(10), next).await.unwrap()
+    }
+
+    /// Bgpd of AS 65001 listening on `listener` for passive neighbors at
+    /// 127.0.0.1 and 127.0.0.2, advertising to them every `mrai`.
+    fn passive_bgpd(listener: &TcpListener, mrai: Duration) -> Bgpd {
+        let mut config = BgpConfig::new(65001, "10.0.0.1".parse().unwrap());
+        config.port = listener.local_addr().unwrap().port();
+        config.mrai = Mrai {
+            ebgp: mrai,
+            ibgp: mrai,
+        };
+        let mut bgpd = Bgpd::new(config);
+        for addr in &["127.0.0.1", "127.0.0.2"] {
+            let mut neighbor = Neighbor::new(addr.parse().unwrap());
+            neighbor.connect_mode = ConnectMode::Passive;
+            bgpd.add_neighbor(neighbor);
+        }
+        bgpd
+    }
+
+    /// Serve `bgpd` and connect to it from 127.0.0.1 as AS 65002 and from
+    /// 127.0.0.2 as AS 65003. Sending on the channel stops it.
+    async fn serve_passive(
+        mut bgpd: Bgpd,
+        listener: TcpListener,
+    ) -> (Connection, Connection, oneshot::Sender<()>, JoinHandle<()>) {
+        let to = listener.local_addr().unwrap();
+        let mut states: Vec<_> = ["127.0.0.1", "127.0.0.2"]
+            .iter()
+            .map(|addr| bgpd.state(&addr.parse().unwrap()).unwrap())
+            .collect();
+        let (stop, stopped) = oneshot::channel::<()>();
+        let task = tokio::spawn(async move {
+            bgpd.serve(listener, async {
+                let _ = stopped.await;
+            })
+            .await
+        });
+
+        let a = connect_from("127.0.0.1", 65002, to, 90).await;
+        let b = connect_from("127.0.0.2", 65003, to, 90).await;
+        for state in states.iter_mut() {
+            wait(state, |s| s == State::Established).await;
+        }
+        (a, b, stop, task)
+    }
+
+    #[tokio::test]
+    async fn routes() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let bgpd = passive_bgpd(&listener, Duration::ZERO);
+        let (mut a, mut b, stop, task) = serve_passive(bgpd, listener).await;
+
+        // Received from one neighbor, advertised to the other with our AS
+        // and address.
+        let mut update = MessageUpdate::new();
+        update.attr.as_path = "65002".parse().unwrap();
+        update.attr.next_hop = Some("10.0.0.2".parse().unwrap());
+        update.nlri = vec!["10.1.0.0/16".parse().unwrap()];
+        a.send(Message::Update(update)).await.unwrap();
+        let update = next_update(&mut b).await;
+        assert_eq!(update.nlri, vec!["10.1.0.0/16".parse().unwrap()]);
+        assert_eq!(update.attr.as_path.to_string(), "65001 65002");
+        assert_eq!(update.attr.next_hop, Some("127.0.0.1".parse().unwrap()));
+
+        // Routes are not sent back, the next UPDATE is the withdrawal.
+        let mut update = MessageUpdate::new();
+        update.withdrawn = vec!["10.1.0.0/16".parse().unwrap()];
+        a.send(Message::Update(update)).await.unwrap();
+        let update = next_update(&mut b).await;
+        assert_eq!(update.withdrawn, vec!["10.1.0.0/16".parse().unwrap()]);
+
+        // Withdrawn in MP_UNREACH_NLRI as well.
+        let mut update = MessageUpdate::new();
+        update.attr.as_path = "65002".parse().unwrap();
+        update.attr.next_hop = Some("10.0.0.2".parse().unwrap());
+        update.nlri = vec!["10.2.0.0/16".parse().unwrap()];
+        a.send(Message::Update(update)).await.unwrap();
+        assert_eq!(next_update(&mut b).await.nlri.len(), 1);
+        let mut update = MessageUpdate::new();
+        update.mp_unreach = Some(MpUnreach {
+            family: Family {
+                afi: AFI_IP,
+                safi: SAFI_UNICAST,
+            },
+            withdrawn: vec!["10.2.0.0/16".parse().unwrap()],
+        });
+        a.send(Message::Update(update)).await.unwrap();
+        let update = next_update(&mut b).await;
+        assert_eq!(update.withdrawn, vec!["10.2.0.0/16".parse().unwrap()]);
+
+        // A route from b goes to a, and is withdrawn once b is gone.
+        let mut update = MessageUpdate::new();
+        update.attr.as_path = "65003".parse().unwrap();
+        update.attr.next_hop = Some("10.0.0.3".parse().unwrap());
+        update.nlri = vec!["10.3.0.0/16".parse().unwrap()];
+        b.send(Message::Update(update)).await.unwrap();
+        let update = next_update(&mut a).await;
+        assert_eq!(update.nlri, vec!["10.3.0.0/16".parse().unwrap()]);
+        drop(b);
+        let update = next_update(&mut a).await;
+        assert_eq!(update.withdrawn, vec!["10.3.0.0/16".parse().unwrap()]);
+
+        stop.send(()).unwrap();
+        task.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn well_known_communities() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let bgpd = passive_bgpd(&listener, Duration::ZERO);
+        let (mut a, mut b, stop, task) = serve_passive(bgpd, listener).await;
+
+        // NO_EXPORT, and BLACKHOLE which implies it, stay in the AS, the
+        // next UPDATE b gets is the route without communities.
+        for (prefix, community) in [("10.1.0.0/16", "no-export"), ("10.2.0.0/16", "blackhole")] {
+            let mut update = MessageUpdate::new();
+            update.attr.as_path = "65002".parse().unwrap();
+            update.attr.next_hop = Some("10.0.0.2".parse().unwrap());
+            update.attr.communities = Communities::from_str(community);
+            update.nlri = vec![prefix.parse().unwrap()];
+            a.send(Message::Update(update)).await.unwrap();
+        }
+        let mut update = MessageUpdate::new();
+        update.attr.as_path = "65002".parse().unwrap();
+        update.attr.next_hop = Some("10.0.0.2".parse().unwrap());
+        update.nlri = vec!["10.3.0.0/16".parse().unwrap()];
+        a.send(Message::Update(update)).await.unwrap();
+        let update = next_update(&mut b).await;
+        assert_eq!(update.nlri, vec!["10.3.0.0/16".parse().unwrap()]);
+
+        stop.send(()).unwrap();
+        task.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn mrai() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mrai = Duration::from_millis(500);
+        let bgpd = passive_bgpd(&listener, mrai);
+        let (mut a, mut b, stop, task) = serve_passive(bgpd, listener).await;
+
+        // The second announcement waits for the MinRouteAdvertisementInterval.
+        let start = Instant::now();
+        for prefix in ["10.1.0.0/16", "10.2.0.0/16"] {
+            let mut update = MessageUpdate::new();
+            update.attr.as_path = "65002".parse().unwrap();
+            update.attr.next_hop = Some("10.0.0.2".parse().unwrap());
+            update.nlri = vec![prefix.parse().unwrap()];
+            a.send(Message::Update(update)).await.unwrap();
+            assert_eq!(next_update(&mut b).await.nlri.len(), 1);
+        }
+        assert!(Instant::now() >= start + mrai);
+
+        stop.send(()).unwrap();
+        task.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn aggregate() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut bgpd = passive_bgpd(&listener, Duration::ZERO);
+        bgpd.configure_aggregate("aggregate-address 10.0.0.0/8 summary-only")
+            .unwrap();
+        let (mut a, mut b, stop, task) = serve_passive(bgpd, listener).await;
+
+        // The more specific route is suppressed, the aggregate goes to both.
+        let mut update = MessageUpdate::new();
+        update.attr.as_path = "65002".parse().unwrap();
+        update.attr.next_hop = Some("10.0.0.2".parse().unwrap());
+        update.nlri = vec!["10.1.0.0/16".parse().unwrap()];
+        a.send(Message::Update(update)).await.unwrap();
+        for conn in [&mut a, &mut b] {
+            let update = next_update(conn).await;
+            assert_eq!(update.nlri, vec!["10.0.0.0/8".parse().unwrap()]);
+            assert_eq!(update.attr.as_path.to_string(), "65001");
+            assert!(update.attr.atomic_aggregate);
+        }
+
+        // Without a contributing route the aggregate is withdrawn.
+        let mut update = MessageUpdate::new();
+        update.withdrawn = vec!["10.1.0.0/16".parse().unwrap()];
+        a.send(Message::Update(update)).await.unwrap();
+        for conn in [&mut a, &mut b] {
+            let update = next_update(conn).await;
+            assert_eq!(update.withdrawn, vec!["10.0.0.0/8".parse().unwrap()]);
+        }
+
+        stop.send(()).unwrap();
+        task.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn network() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut bgpd = passive_bgpd(&listener, Duration::ZERO);
+        bgpd.configure_network("network 10.9.0.0/16").unwrap();
+        let handle = bgpd.handle();
+        let (mut a, mut b, stop, task) = serve_passive(bgpd, listener).await;
+
+        // Originated once the system RIB has the prefix, with import-check.
+        let prefix: Prefix = "10.9.0.0/16".parse().unwrap();
+        handle.add_system_route(prefix).await.unwrap();
+        for conn in [&mut a, &mut b] {
+            let update = next_update(conn).await;
+            assert_eq!(update.nlri, vec![prefix]);
+            assert_eq!(update.attr.as_path.to_string(), "65001");
+            assert_eq!(update.attr.next_hop, Some("127.0.0.1".parse().unwrap()));
+        }
+        handle.remove_system_route(prefix).await.unwrap();
+        for conn in [&mut a, &mut b] {
+            assert_eq!(next_update(conn).await.withdrawn, vec![prefix]);
+        }
+
+        // And without it right away.
+        handle
+            .configure_network("no bgp network import-check")
+            .await
+            .unwrap();
+        for conn in [&mut a, &mut b] {
+            assert_eq!(next_update(conn).await.nlri, vec![prefix]);
+        }
+
+        stop.send(()).unwrap();
+        task.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn dampening() {
+        let mut bgpd = Bgpd::new(BgpConfig::new(65001, "10.0.0.1".parse().unwrap()));
+        let addr: IpAddr = "192.0.2.2".parse().unwrap();
+        let mut neighbor = Neighbor::new(addr);
+        neighbor.connect_mode = ConnectMode::Passive;
+        bgpd.add_neighbor(neighbor);
+        assert!(bgpd.configure_dampening("bgp dampening 15 750").is_err());
+        bgpd.configure_dampening("bgp dampening").unwrap();
+        let mut attr = Attr::new();
+        attr.as_path = "65002".parse().unwrap();
+        let route = Route::new("10.0.0.0/8".parse().unwrap(), attr);
+        let prefix = route.prefix;
+
+        // Withdrawn three times the path is suppressed, re-advertised it is
+        // kept but not used.
+        for _ in 0..3 {
+            bgpd.routes_received(addr, vec![route.clone()], Vec::new());
+            assert!(bgpd.rib.best(&prefix).is_some());
+            bgpd.routes_received(addr, Vec::new(), vec![prefix]);
+        }
+        bgpd.routes_received(addr, vec![route.clone()], Vec::new());
+        assert!(bgpd.rib.best(&prefix).is_none());
+        assert_eq!(bgpd.rib.paths(&prefix).count(), 1);
+
+        // Reused once dampening is disabled.
+        bgpd.configure_dampening("no bgp dampening").unwrap();
+        assert!(bgpd.rib.best(&prefix).is_some());
+    }
+
+    #[tokio::test]
+    async fn rpki() {
+        let mut config = BgpConfig::new(65001, "10.0.0.1".parse().unwrap());
+        config.disallow_invalid = true;
+        let mut bgpd = Bgpd::new(config);
+        let addr: IpAddr = "192.0.2.2".parse().unwrap();
+        let mut neighbor = Neighbor::new(addr);
+        neighbor.connect_mode = ConnectMode::Passive;
+        bgpd.add_neighbor(neighbor);
+        let prefix: Prefix = "10.0.0.0/8".parse().unwrap();
+        let vrp = |asn: u32| Vrp::new(prefix, 8, asn);
+        bgpd.rpki.write().unwrap().vrps.insert(vrp(65003));
+        let mut attr = Attr::new();
+        attr.as_path = "65002".parse().unwrap();
+        bgpd.routes_received(addr, vec![Route::new(prefix, attr)], Vec::new());
+        let path = |bgpd: &Bgpd| bgpd.rib.paths(&prefix).next().unwrap().1.rpki;
+        assert_eq!(path(&bgpd), RpkiState::Invalid);
+        assert!(bgpd.rib.best(&prefix).is_none());
+
+        // Valid once the cache has the VRP.
+        bgpd.rpki.write().unwrap().vrps.insert(vrp(65002));
+        bgpd.revalidate();
+        assert_eq!(path(&bgpd), RpkiState::Valid);
+        assert!(bgpd.rib.best(&prefix).is_some());
+    }
+
+    #[tokio::test]
+    async fn aspa() {
+        let mut bgpd = Bgpd::new(BgpConfig::new(65001, "10.0.0.1".parse().unwrap()));
+        let (customer, provider): (IpAddr, IpAddr) =
+            ("192.0.2.2".parse().unwrap(), "192.0.2.4".parse().unwrap());
+        for (addr, role) in [(customer, Role::Provider), (provider, Role::Customer)] {
+            let mut neighbor = Neighbor::new(addr);
+            neighbor.connect_mode = ConnectMode::Passive;
+            neighbor.local_role = Some(role);
+            bgpd.add_neighbor(neighbor);
+        }
+        bgpd.aspas.insert(65003, &[65009]);
+        let prefix: Prefix = "10.0.0.0/8".parse().unwrap();
+        for (addr, path) in [(customer, "65002 65003"), (provider, "65004 65003")] {
+            let mut attr = Attr::new();
+            attr.as_path = path.parse().unwrap();
+            bgpd.routes_received(addr, vec![Route::new(prefix, attr)], Vec::new());
+        }
+        let path = |bgpd: &Bgpd, addr: IpAddr| {
+            let mut paths = bgpd.rib.paths(&prefix);
+            let source = RouteSource::Neighbor(addr);
+            paths.find(|(s, _)| **s == source).unwrap().1.aspa
+        };
+        // 65003 attests another provider, only a leak upstream.
+        assert_eq!(path(&bgpd, customer), AspaState::Invalid);
+        assert_eq!(path(&bgpd, provider), AspaState::Valid);
+
+        // ASPAs of a cache replace the file.
+        bgpd.rpki.write().unwrap().aspas.insert(65003, &[65002]);
+        bgpd.revalidate();
+        assert_eq!(path(&bgpd, customer), AspaState::Valid);
+    }
+
+    #[tokio::test]
+    async fn fib() {
+        let mut bgpd = Bgpd::new(BgpConfig::new(65001, "10.0.0.1".parse().unwrap()));
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        bgpd.fib = Some(tx);
+        let (unnumbered, numbered): (IpAddr, IpAddr) =
+            ("fe80::2".parse().unwrap(), "192.0.2.3".parse().unwrap());
+        for addr in [unnumbered, numbered] {
+            let mut neighbor = Neighbor::new(addr);
+            neighbor.connect_mode = ConnectMode::Passive;
+            if addr == unnumbered {
+                neighbor.interface = Some("lo".to_string());
+            }
+            bgpd.add_neighbor(neighbor);
+        }
+        let route = |path: &str| {
+            let mut attr = Attr::new();
+            attr.as_path = path.parse().unwrap();
+            Route::new("10.0.0.0/8".parse().unwrap(), attr)
+        };
+        let prefix = route("").prefix;
+        let nexthop = NextHop {
+            addr: unnumbered,
+            ifindex: bgpd.neighbors.get(&unnumbered).unwrap().ifindex(),
+        };
+
+        // Only the best path is installed, and only from unnumbered
+        // neighbors.
+        bgpd.routes_received(unnumbered, vec![route("65002 65010")], Vec::new());
+        assert_eq!(rx.try_recv(), Ok(FibChange::Install(prefix, nexthop)));
+        bgpd.routes_received(numbered, vec![route("65003")], Vec::new());
+        assert_eq!(rx.try_recv(), Ok(FibChange::Remove(prefix, nexthop)));
+        bgpd.routes_received(unnumbered, vec![route("65002")], Vec::new());
+        assert!(rx.try_recv().is_err());
+        bgpd.routes_received(numbered, Vec::new(), vec![prefix]);
+        assert_eq!(rx.try_recv(), Ok(FibChange::Install(prefix, nexthop)));
+        bgpd.routes_received(unnumbered, Vec::new(), vec![prefix]);
+        assert_eq!(rx.try_recv(), Ok(FibChange::Remove(prefix, nexthop)));
+        assert!(rx.try_recv().is_err());
+
+        // The IGP route of a backdoor network wins over eBGP.
+        bgpd.routes_received(unnumbered, vec![route("65002")], Vec::new());
+        assert_eq!(rx.try_recv(), Ok(FibChange::Install(prefix, nexthop)));
+        bgpd.configure_network("network 10.0.0.0/8 backdoor")
+            .unwrap();
+        assert_eq!(rx.try_recv(), Ok(FibChange::Remove(prefix, nexthop)));
+        bgpd.routes_received(unnumbered, vec![route("65002 65010")], Vec::new());
+        assert!(rx.try_recv().is_err());
+        bgpd.configure_network("no network 10.0.0.0/8").unwrap();
+        assert_eq!(rx.try_recv(), Ok(FibChange::Install(prefix, nexthop)));
+    }
+
+    #[tokio::test]
+    async fn memory() {
+        let mut bgpd = Bgpd::new(BgpConfig::new(65001, "10.0.0.1".parse().unwrap()));
+        let addrs: Vec<IpAddr> = vec!["192.0.2.2".parse().unwrap(), "192.0.2.3".parse().unwrap()];
+        for addr in &addrs {
+            let mut neighbor = Neighbor::new(*addr);
+            neighbor.connect_mode = ConnectMode::Passive;
+            bgpd.add_neighbor(neighbor);
+        }
+        let route = |prefix: &str| {
+            let mut attr = Attr::new();
+            attr.as_path = "65002 65010".parse().unwrap();
+            attr.next_hop = Some("192.0.2.2".parse().unwrap());
+            Route::new(prefix.parse().unwrap(), attr)
+        };
+
+        // Routes with the same attributes share them, from any neighbor.
+        for addr in &addrs {
+            let routes = vec![route("10.1.0.0/16"), route("10.2.0.0/16")];
+            bgpd.routes_received(*addr, routes, Vec::new());
+        }
+        let show = bgpd.show_memory();
+        assert!(show.starts_with("attribute sets         1 entries, 4 references"));
+        assert!(show.contains("as-paths               1 entries, 1 references"));
+
+        // The Adj-RIB-Out shares the attributes advertised.
+        let c = Neighbor::new("192.0.2.4".parse().unwrap());
+        let key = UpdateGroupKey::new(&c, &Peer::new(State::Established), None);
+        let best: Vec<Route> = bgpd.rib.loc_rib().cloned().collect();
+        bgpd.groups
+            .join(&bgpd.policy, &mut bgpd.attrs, &c, key, &best);
+        drop(best);
+        let show = bgpd.show_memory();
+        assert!(show.starts_with("attribute sets         2 entries, 6 references"));
+        assert!(show.contains("adj-rib-out            2 entries"));
+
+        // And are dropped with the last of them.
+        bgpd.routes_received(addrs[0], Vec::new(), vec!["10.1.0.0/16".parse().unwrap()]);
+        for addr in &addrs {
+            bgpd.rib_event(RibEvent::Down(*addr));
+        }
+        assert!(bgpd
+            .show_memory()
+            .starts_with("attribute sets         0 entries"));
+    }
+
+    #[tokio::test]
+    async fn unknown_neighbor() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut config = BgpConfig::new(65001, "10.0.0.1".parse().unwrap());
+        config.port = addr.port();
+        let mut bgpd = Bgpd::new(config);
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let (stream, from) = listener.accept().await.unwrap();
+        bgpd.accept(stream, from);
+
+        use tokio::io::AsyncReadExt;
+        let mut buf = [0u8; 1];
+        assert_eq!(client.read(&mut buf).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn configure() {
+        let la = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let lb = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let pa = la.local_addr().unwrap().port();
+        let pb = lb.local_addr().unwrap().port();
+        let addr: IpAddr = "127.0.0.1".parse().unwrap();
+        let cmd = |line: &str| NeighborCommand::parse(line).unwrap();
+
+        let mut a = bgpd(65001, "10.0.0.1", pa, pb);
+        let mut b = bgpd(65002, "10.0.0.2", pb, pa);
+        a.configure(addr, cmd("remote-as external")).unwrap();
+        b.configure(addr, cmd("remote-as 65001")).unwrap();
+        assert_eq!(
+            b.configure("10.0.0.9".parse().unwrap(), cmd("shutdown")),
+            Err(NeighborError::NotConfigured("10.0.0.9".parse().unwrap()))
+        );
+        assert_eq!(
+            b.configure(addr, cmd("local-as 65002")),
+            Err(NeighborError::LocalAsSame(65002))
+        );
+        let mut sa = a.state(&addr).unwrap();
+        let mut sb = b.state(&addr).unwrap();
+        let (ha, hb) = (a.handle(), b.handle());
+
+        let (stop_a, stopped_a) = oneshot::channel::<()>();
+        let (stop_b, stopped_b) = oneshot::channel::<()>();
+        let ta = tokio::spawn(async move {
+            a.serve(la, async {
+                let _ = stopped_a.await;
+            })
+            .await
+        });
+        let tb = tokio::spawn(async move {
+            b.serve(lb, async {
+                let _ = stopped_b.await;
+            })
+            .await
+        });
+        wait(&mut sa, |s| s == State::Established).await;
+        wait(&mut sb, |s| s == State::Established).await;
+
+        // Policy changes leave the session up.
+        sa.borrow_and_update();
+        ha.configure(addr, cmd("description peer b")).await.unwrap();
+        ha.configure(addr, cmd("route-map IN in")).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!sa.has_changed().unwrap());
+
+        // Shutdown keeps the session down until it is lifted.
+        ha.configure(addr, cmd("shutdown")).await.unwrap();
+        wait(&mut sa, |s| s == State::Idle).await;
+        wait(&mut sb, |s| s != State::Established).await;
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(*sa.borrow(), State::Idle);
+        ha.configure(addr, cmd("no shutdown")).await.unwrap();
+        wait(&mut sa, |s| s == State::Established).await;
+        wait(&mut sb, |s| s == State::Established).await;
+
+        // A remote AS which does not match keeps the session down.
+        hb.configure(addr, cmd("remote-as 65009")).await.unwrap();
+        wait(&mut sb, |s| s != State::Established).await;
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_ne!(*sb.borrow(), State::Established);
+        assert_eq!(
+            hb.configure(addr, cmd("timers 10 1")).await,
+            Err(NeighborError::HoldTime(1))
+        );
+
+        stop_a.send(()).unwrap();
+        stop_b.send(()).unwrap();
+        ta.await.unwrap();
+        tb.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn dynamic_neighbor() {
+        let la = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let lb = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let pa = la.local_addr().unwrap().port();
+        let pb = lb.local_addr().unwrap().port();
+        let addr: IpAddr = "127.0.0.1".parse().unwrap();
+        let cmd = |line: &str| NeighborCommand::parse(line).unwrap();
+        let listen = |line: &str| ListenCommand::parse(line).unwrap();
+
+        let mut config = BgpConfig::new(65001, "10.0.0.1".parse().unwrap());
+        config.port = pa;
+        let mut a = Bgpd::new(config);
+        assert_eq!(
+            a.configure_listen(listen("bgp listen range 127.0.0.0/8 peer-group TOR")),
+            Err(NeighborError::UnknownPeerGroup("TOR".to_string()))
+        );
+        a.configure_peer_group("TOR", cmd("remote-as external"))
+            .unwrap();
+        a.configure_listen(listen("bgp listen range 127.0.0.0/8 peer-group TOR"))
+            .unwrap();
+        a.configure_listen(listen("bgp listen limit 1")).unwrap();
+        let mut b = bgpd(65002, "10.0.0.2", pb, pa);
+        let mut sb = b.state(&addr).unwrap();
+
+        let (stop_a, stopped_a) = oneshot::channel::<()>();
+        let (stop_b, stopped_b) = oneshot::channel::<()>();
+        let ta = tokio::spawn(async move {
+            a.serve(la, async {
+                let _ = stopped_a.await;
+            })
+            .await;
+            a
+        });
+        let tb = tokio::spawn(async move {
+            b.serve(lb, async {
+                let _ = stopped_b.await;
+            })
+            .await
+        });
+        wait(&mut sb, |s| s == State::Established).await;
+
+        // Beyond the limit connections are closed.
+        let to_a = SocketAddr::new(addr, pa);
+        let source = "127.0.0.2".parse().unwrap();
+        let mut client = tcp::connect(to_a, Some(source), None, None).await.unwrap();
+        use tokio::io::AsyncReadExt;
+        let mut buf = [0u8; 1];
+        assert_eq!(client.read(&mut buf).await.unwrap(), 0);
+
+        // The neighbor is removed when its session goes down.
+        stop_b.send(()).unwrap();
+        tb.await.unwrap();
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        stop_a.send(()).unwrap();
+        let a = ta.await.unwrap();
+        assert!(!a.is_dynamic(&addr));
+        assert!(a.neighbors.get(&addr).is_none());
+        assert!(!a.peer_groups.contains(&addr));
+    }
+
+    #[tokio::test]
+    async fn peer_group() {
+        let cmd = |line: &str| NeighborCommand::parse(line).unwrap();
+        let mut bgpd = Bgpd::new(BgpConfig::new(65000, "10.0.0.1".parse().unwrap()));
+        bgpd.configure_template("BASE", cmd("shutdown")).unwrap();
+        bgpd.configure_peer_group("RS", cmd("inherit BASE"))
+            .unwrap();
+        bgpd.configure_peer_group("RS", cmd("remote-as external"))
+            .unwrap();
+
+        let (a, b): (IpAddr, IpAddr) = ("192.0.2.1".parse().unwrap(), "192.0.2.2".parse().unwrap());
+        bgpd.configure(a, cmd("peer-group RS")).unwrap();
+        bgpd.configure(b, cmd("remote-as 65002")).unwrap();
+        bgpd.configure(b, cmd("shutdown")).unwrap();
+        assert!(bgpd.neighbors.get(&a).unwrap().shutdown);
+        assert_eq!(*bgpd.state(&a).unwrap().borrow(), State::Idle);
+
+        // Only members are reconfigured.
+        bgpd.configure_peer_group("RS", cmd("description route server client"))
+            .unwrap();
+        let description = |addr| bgpd.neighbors.get(&addr).unwrap().description.clone();
+        assert_eq!(description(a).as_deref(), Some("route server client"));
+        assert_eq!(description(b), None);
+        assert!(bgpd
+            .show_neighbor(&a)
+            .unwrap()
+            .contains("shutdown                                 inherited from template BASE"));
+        bgpd.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn unnumbered() {
+        let cmd = |line: &str| NeighborCommand::parse(line).unwrap();
+        let mut bgpd = Bgpd::new(BgpConfig::new(65000, "10.0.0.1".parse().unwrap()));
+        assert_eq!(
+            bgpd.configure_interface("lo", Some("FABRIC")),
+            Err(NeighborError::UnknownPeerGroup("FABRIC".to_string()))
+        );
+        bgpd.configure_peer_group("FABRIC", cmd("remote-as external"))
+            .unwrap();
+        bgpd.configure_peer_group("FABRIC", cmd("shutdown"))
+            .unwrap();
+        bgpd.configure_interface("lo", Some("FABRIC")).unwrap();
+
+        // Router Advertisements need privileges, fake their reception.
+        let first: Ipv6Addr = "fe80::1".parse().unwrap();
+        bgpd.neighbor_discovered("lo".to_string(), first);
+        let neighbor = bgpd.neighbors.get(&IpAddr::V6(first)).unwrap();
+        assert_eq!(neighbor.interface.as_deref(), Some("lo"));
+        assert!(neighbor.extended_nexthop_enabled());
+        assert_eq!(
+            bgpd.peer_groups.peer_group_of(&IpAddr::V6(first)),
+            Some("FABRIC")
+        );
+
+        // A new address replaces the neighbor.
+        let second: Ipv6Addr = "fe80::2".parse().unwrap();
+        bgpd.neighbor_discovered("lo".to_string(), second);
+        assert!(bgpd.neighbors.get(&IpAddr::V6(first)).is_none());
+        assert!(bgpd.neighbors.get(&IpAddr::V6(second)).is_some());
+
+        bgpd.configure_interface("lo", None).unwrap();
+        assert!(bgpd.neighbors.get(&IpAddr::V6(second)).is_none());
+        assert!(bgpd.state(&IpAddr::V6(second)).is_none());
+        bgpd.shutdown().await;
+    }
+}
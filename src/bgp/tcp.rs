@@ -0,0 +1,183 @@
+#![allow(dead_code)]
+
+use super::Prefix;
+use std::ffi::CString;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::os::unix::io::{AsRawFd, RawFd};
+use tokio::net::{TcpSocket, TcpStream};
+
+/// `struct tcp_md5sig` of linux/tcp.h.
+#[cfg(target_os = "linux")]
+#[repr(C)]
+struct TcpMd5Sig {
+    addr: libc::sockaddr_storage,
+    flags: u8,
+    prefixlen: u8,
+    keylen: u16,
+    ifindex: libc::c_int,
+    key: [u8; libc::TCP_MD5SIG_MAXKEYLEN],
+}
+
+/// `TCP_MD5SIG_FLAG_PREFIX` of linux/tcp.h, the key applies to a prefix.
+#[cfg(target_os = "linux")]
+const TCP_MD5SIG_FLAG_PREFIX: u8 = 1;
+
+/// Set the TCP MD5 signature key (RFC 2385) for segments exchanged with
+/// `peer` on socket `fd`, `None` removes it. Set on a listening socket it
+/// applies to the connections accepted from `peer`.
+#[cfg(target_os = "linux")]
+pub fn set_md5sig(fd: RawFd, peer: IpAddr, key: Option<&str>) -> io::Result<()> {
+    md5sig(fd, peer, None, key)
+}
+
+/// Set the TCP MD5 signature key for all addresses of `prefix`, `None`
+/// removes it. A key set for a single address takes precedence.
+#[cfg(target_os = "linux")]
+pub fn set_md5sig_prefix(fd: RawFd, prefix: &Prefix, key: Option<&str>) -> io::Result<()> {
+    md5sig(fd, prefix.addr(), Some(prefix.prefixlen()), key)
+}
+
+#[cfg(target_os = "linux")]
+fn md5sig(fd: RawFd, peer: IpAddr, prefixlen: Option<u8>, key: Option<&str>) -> io::Result<()> {
+    let key = key.unwrap_or("").as_bytes();
+    if key.len() > libc::TCP_MD5SIG_MAXKEYLEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "TCP MD5 key too long",
+        ));
+    }
+    // Zeroed is a valid value of the plain C struct.
+    let mut sig: TcpMd5Sig = unsafe { std::mem::zeroed() };
+    match peer {
+        IpAddr::V4(addr) => {
+            let sin = &mut sig.addr as *mut _ as *mut libc::sockaddr_in;
+            unsafe {
+                (*sin).sin_family = libc::AF_INET as libc::sa_family_t;
+                (*sin).sin_addr.s_addr = u32::from(addr).to_be();
+            }
+        }
+        IpAddr::V6(addr) => {
+            let sin6 = &mut sig.addr as *mut _ as *mut libc::sockaddr_in6;
+            unsafe {
+                (*sin6).sin6_family = libc::AF_INET6 as libc::sa_family_t;
+                (*sin6).sin6_addr.s6_addr = addr.octets();
+            }
+        }
+    }
+    sig.keylen = key.len() as u16;
+    sig.key[..key.len()].copy_from_slice(key);
+    let opt = match prefixlen {
+        Some(len) => {
+            sig.flags = TCP_MD5SIG_FLAG_PREFIX;
+            sig.prefixlen = len;
+            libc::TCP_MD5SIG_EXT
+        }
+        None => libc::TCP_MD5SIG,
+    };
+    let ret = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::IPPROTO_TCP,
+            opt,
+            &sig as *const _ as *const libc::c_void,
+            std::mem::size_of::<TcpMd5Sig>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set_md5sig(_fd: RawFd, _peer: IpAddr, key: Option<&str>) -> io::Result<()> {
+    match key {
+        Some(_) => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "TCP MD5 signatures are not supported",
+        )),
+        None => Ok(()),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set_md5sig_prefix(fd: RawFd, prefix: &Prefix, key: Option<&str>) -> io::Result<()> {
+    set_md5sig(fd, prefix.addr(), key)
+}
+
+/// Index of the network interface `name`.
+pub fn ifindex(name: &str) -> io::Result<u32> {
+    let cname = CString::new(name).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    match unsafe { libc::if_nametoindex(cname.as_ptr()) } {
+        0 => Err(io::Error::last_os_error()),
+        index => Ok(index),
+    }
+}
+
+/// Connect to `peer`, from `source` when set, signing segments with
+/// `password`. A link-local `peer` is reached through `interface`.
+pub async fn connect(
+    peer: SocketAddr,
+    source: Option<IpAddr>,
+    password: Option<&str>,
+    interface: Option<&str>,
+) -> io::Result<TcpStream> {
+    let peer = match (peer, interface) {
+        (SocketAddr::V6(mut addr), Some(name)) => {
+            addr.set_scope_id(ifindex(name)?);
+            SocketAddr::V6(addr)
+        }
+        (peer, _) => peer,
+    };
+    let socket = if peer.is_ipv4() {
+        TcpSocket::new_v4()?
+    } else {
+        TcpSocket::new_v6()?
+    };
+    if let Some(source) = source {
+        socket.bind(SocketAddr::new(source, 0))?;
+    }
+    if password.is_some() {
+        set_md5sig(socket.as_raw_fd(), peer.ip(), password)?;
+    }
+    socket.connect(peer).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn update_source() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let source: IpAddr = "127.0.0.2".parse().unwrap();
+        let stream = connect(addr, Some(source), None, None).await.unwrap();
+        assert_eq!(stream.local_addr().unwrap().ip(), source);
+        let (_, from) = listener.accept().await.unwrap();
+        assert_eq!(from.ip(), source);
+    }
+
+    #[tokio::test]
+    async fn md5sig_prefix() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let prefix: Prefix = "127.0.0.0/8".parse().unwrap();
+        let fd = listener.as_raw_fd();
+        set_md5sig_prefix(fd, &prefix, Some("secret")).unwrap();
+        let source: IpAddr = "127.0.0.2".parse().unwrap();
+        let stream = connect(addr, Some(source), Some("secret"), None).await;
+        assert!(stream.is_ok());
+        let (_, from) = listener.accept().await.unwrap();
+        assert_eq!(from.ip(), source);
+        set_md5sig_prefix(fd, &prefix, None).unwrap();
+    }
+
+    #[test]
+    fn interface() {
+        assert!(ifindex("lo").unwrap() > 0);
+        assert!(ifindex("no-such-if0").is_err());
+    }
+}
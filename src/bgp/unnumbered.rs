@@ -0,0 +1,254 @@
+#![allow(dead_code)]
+
+use super::is_link_local;
+use super::tcp;
+use std::io;
+use std::mem;
+use std::net::{IpAddr, Ipv6Addr, SocketAddrV6};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::ptr;
+use std::time::Duration;
+use tokio::io::Interest;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+
+/// ICMPv6 type of Router Advertisements (RFC 4861 4.2).
+pub const ND_ROUTER_ADVERT: u8 = 134;
+/// Router Advertisement without options.
+const ROUTER_ADVERT_LEN: usize = 16;
+/// Neighbor Discovery packets are sent with, and only accepted with, a hop
+/// limit of 255 so that they can not come from off-link.
+const ND_HOP_LIMIT: libc::c_int = 255;
+/// Interval of the advertisements sent on unnumbered interfaces.
+pub const RA_INTERVAL: Duration = Duration::from_secs(10);
+
+const ALL_NODES: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
+
+/// Router Advertisement making our link-local address known to the
+/// neighbor. The router lifetime is 0, hosts on the link do not take us
+/// as their default router. The kernel fills in the checksum.
+pub fn router_advertisement() -> Vec<u8> {
+    let mut buf = vec![0u8; ROUTER_ADVERT_LEN];
+    buf[0] = ND_ROUTER_ADVERT;
+    // Cur Hop Limit.
+    buf[4] = 64;
+    buf
+}
+
+pub fn is_router_advertisement(buf: &[u8]) -> bool {
+    buf.len() >= ROUTER_ADVERT_LEN && buf[0] == ND_ROUTER_ADVERT && buf[1] == 0
+}
+
+/// The link-local address `buf` was advertised from, when it is a Router
+/// Advertisement received with hop limit 255 (RFC 4861 6.1.2).
+fn advertised_from(buf: &[u8], src: &IpAddr, hop_limit: Option<libc::c_int>) -> Option<Ipv6Addr> {
+    match src {
+        IpAddr::V6(addr)
+            if is_router_advertisement(buf)
+                && is_link_local(src)
+                && hop_limit == Some(ND_HOP_LIMIT) =>
+        {
+            Some(*addr)
+        }
+        _ => None,
+    }
+}
+
+fn setsockopt<T>(fd: RawFd, level: libc::c_int, name: libc::c_int, value: &T) -> io::Result<()> {
+    let ret = unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            name,
+            value as *const T as *const libc::c_void,
+            mem::size_of::<T>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Raw ICMPv6 socket sending and receiving Router Advertisements on one
+/// interface. Needs CAP_NET_RAW. Datagram sockets and raw ones share the
+/// `sendto()`/`recvfrom()` calls, so tokio's UdpSocket drives it.
+pub struct RaSocket {
+    socket: UdpSocket,
+    ifindex: u32,
+}
+
+impl RaSocket {
+    pub fn open(interface: &str) -> io::Result<RaSocket> {
+        let ifindex = tcp::ifindex(interface)?;
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_INET6,
+                libc::SOCK_RAW | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                libc::IPPROTO_ICMPV6,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let socket = unsafe { std::net::UdpSocket::from_raw_fd(fd) };
+        let ret = unsafe {
+            libc::setsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                libc::SO_BINDTODEVICE,
+                interface.as_ptr() as *const libc::c_void,
+                interface.len() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let hops = ND_HOP_LIMIT;
+        setsockopt(fd, libc::IPPROTO_IPV6, libc::IPV6_MULTICAST_HOPS, &hops)?;
+        setsockopt(fd, libc::IPPROTO_IPV6, libc::IPV6_UNICAST_HOPS, &hops)?;
+        setsockopt(
+            fd,
+            libc::IPPROTO_IPV6,
+            libc::IPV6_MULTICAST_IF,
+            &(ifindex as libc::c_int),
+        )?;
+        // Our own advertisements are not looped back.
+        setsockopt(
+            fd,
+            libc::IPPROTO_IPV6,
+            libc::IPV6_MULTICAST_LOOP,
+            &(0 as libc::c_int),
+        )?;
+        setsockopt(
+            fd,
+            libc::IPPROTO_IPV6,
+            libc::IPV6_RECVHOPLIMIT,
+            &(1 as libc::c_int),
+        )?;
+        Ok(RaSocket {
+            socket: UdpSocket::from_std(socket)?,
+            ifindex,
+        })
+    }
+
+    /// Send a Router Advertisement to all nodes on the link.
+    pub async fn advertise(&self) -> io::Result<()> {
+        let dst = SocketAddrV6::new(ALL_NODES, 0, 0, self.ifindex);
+        self.socket.send_to(&router_advertisement(), dst).await?;
+        Ok(())
+    }
+
+    /// Receive a packet into `buf`. Returns its length, source address and
+    /// the hop limit it arrived with.
+    fn recv_hop_limit(&self, buf: &mut [u8]) -> io::Result<(usize, IpAddr, Option<libc::c_int>)> {
+        let mut src: libc::sockaddr_in6 = unsafe { mem::zeroed() };
+        let mut iov = libc::iovec {
+            iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+            iov_len: buf.len(),
+        };
+        let mut control = [0u64; 8];
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_name = &mut src as *mut libc::sockaddr_in6 as *mut libc::c_void;
+        msg.msg_namelen = mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t;
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = mem::size_of_val(&control) as _;
+        let len = unsafe { libc::recvmsg(self.socket.as_raw_fd(), &mut msg, 0) };
+        if len < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut hop_limit = None;
+        let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+        while !cmsg.is_null() {
+            let hdr = unsafe { &*cmsg };
+            if hdr.cmsg_level == libc::IPPROTO_IPV6 && hdr.cmsg_type == libc::IPV6_HOPLIMIT {
+                let data = unsafe { libc::CMSG_DATA(cmsg) } as *const libc::c_int;
+                hop_limit = Some(unsafe { ptr::read_unaligned(data) });
+            }
+            cmsg = unsafe { libc::CMSG_NXTHDR(&msg, cmsg) };
+        }
+        let src = IpAddr::V6(Ipv6Addr::from(src.sin6_addr.s6_addr));
+        Ok((len as usize, src, hop_limit))
+    }
+
+    /// Wait for a Router Advertisement and return the link-local address
+    /// it came from.
+    pub async fn recv(&self) -> io::Result<Ipv6Addr> {
+        let mut buf = [0u8; 1500];
+        loop {
+            let (len, src, hop_limit) = self
+                .socket
+                .async_io(Interest::READABLE, || self.recv_hop_limit(&mut buf))
+                .await?;
+            if let Some(addr) = advertised_from(&buf[..len], &src, hop_limit) {
+                return Ok(addr);
+            }
+        }
+    }
+}
+
+/// Send Router Advertisements on `interface` every `interval` and report
+/// the link-local address of every advertisement heard there, or the
+/// errors. Runs until `tx` is closed or the socket fails.
+pub async fn discover(
+    interface: String,
+    interval: Duration,
+    tx: mpsc::UnboundedSender<(String, io::Result<Ipv6Addr>)>,
+) {
+    let socket = match RaSocket::open(&interface) {
+        Ok(socket) => socket,
+        Err(e) => {
+            let _ = tx.send((interface, Err(e)));
+            return;
+        }
+    };
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {
+                if let Err(e) = socket.advertise().await {
+                    if tx.send((interface.clone(), Err(e))).is_err() {
+                        return;
+                    }
+                }
+            }
+            res = socket.recv() => {
+                let failed = res.is_err();
+                if tx.send((interface.clone(), res)).is_err() || failed {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn advertisement() {
+        let buf = router_advertisement();
+        assert!(is_router_advertisement(&buf));
+        // Router lifetime 0.
+        assert_eq!(&buf[6..8], &[0, 0]);
+        // Router Solicitation.
+        let mut rs = buf.clone();
+        rs[0] = 133;
+        assert!(!is_router_advertisement(&rs));
+        assert!(!is_router_advertisement(&buf[..8]));
+
+        // Only from on-link, with hop limit 255.
+        let src: IpAddr = "fe80::1".parse().unwrap();
+        assert_eq!(
+            advertised_from(&buf, &src, Some(255)),
+            Some("fe80::1".parse().unwrap())
+        );
+        assert_eq!(advertised_from(&buf, &src, Some(254)), None);
+        assert_eq!(advertised_from(&buf, &src, None), None);
+        let global: IpAddr = "2001:db8::1".parse().unwrap();
+        assert_eq!(advertised_from(&buf, &global, Some(255)), None);
+    }
+}
@@ -0,0 +1,871 @@
+#![allow(dead_code)]
+
+use super::attr::{
+    ATTR_FLAG_OPTIONAL, ATTR_FLAG_TRANSITIVE, ATTR_TYPE_AGGREGATOR, ATTR_TYPE_AS_PATH,
+    ATTR_TYPE_ATOMIC_AGGREGATE, ATTR_TYPE_COMMUNITIES, ATTR_TYPE_EXT_COMMUNITIES,
+    ATTR_TYPE_IPV6_EXT_COMMUNITIES, ATTR_TYPE_LARGE_COMMUNITIES, ATTR_TYPE_LOCAL_PREF,
+    ATTR_TYPE_MED, ATTR_TYPE_MP_REACH_NLRI, ATTR_TYPE_MP_UNREACH_NLRI, ATTR_TYPE_NEXT_HOP,
+    ATTR_TYPE_ORIGIN, ATTR_TYPE_OTC,
+};
+use super::{AsPath, Attr, AttrHeader, Communities, ExtCommunities, Family, LargeCommunities};
+use super::{MessageNotification, Origin, Prefix, PrefixError};
+use super::{AFI_IP, AFI_IP6, SAFI_MULTICAST, SAFI_UNICAST};
+use super::{
+    NOTIFY_UPDATE_ATTR_FLAG_ERR, NOTIFY_UPDATE_ATTR_LENG_ERR, NOTIFY_UPDATE_ERR,
+    NOTIFY_UPDATE_INVAL_NETWORK, NOTIFY_UPDATE_INVAL_NEXT_HOP, NOTIFY_UPDATE_INVAL_ORIGIN,
+    NOTIFY_UPDATE_MAL_AS_PATH, NOTIFY_UPDATE_MAL_ATTR, NOTIFY_UPDATE_MISS_WELLKNOWN,
+    NOTIFY_UPDATE_OPT_ATTR_ERR, NOTIFY_UPDATE_UNREC_WELLKNOWN,
+};
+use byteorder::{NetworkEndian, ReadBytesExt, WriteBytesExt};
+use std::fmt;
+use std::io::Cursor;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// Approaches to a malformed UPDATE (RFC 7606 2), weakest first so the
+/// strongest of several errors wins.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ErrorHandling {
+    AttributeDiscard,
+    TreatAsWithdraw,
+    AfiSafiDisable,
+    SessionReset,
+}
+
+impl fmt::Display for ErrorHandling {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            ErrorHandling::AttributeDiscard => "attribute-discard",
+            ErrorHandling::TreatAsWithdraw => "treat-as-withdraw",
+            ErrorHandling::AfiSafiDisable => "afi-safi-disable",
+            ErrorHandling::SessionReset => "session-reset",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct UpdateError {
+    pub handling: ErrorHandling,
+    pub subcode: u8,
+    /// Attribute type code, 0 when the error is not in a path attribute.
+    pub typ: u8,
+    /// Family to disable for `ErrorHandling::AfiSafiDisable`.
+    pub family: Option<Family>,
+}
+
+impl UpdateError {
+    fn new(handling: ErrorHandling, subcode: u8, typ: u8) -> Self {
+        UpdateError {
+            handling,
+            subcode,
+            typ,
+            family: None,
+        }
+    }
+
+    /// NOTIFICATION to send when the error resets the session.
+    pub fn notification(&self) -> MessageNotification {
+        MessageNotification::new(NOTIFY_UPDATE_ERR, self.subcode, Vec::new())
+    }
+}
+
+impl fmt::Display for UpdateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.notification())?;
+        if self.typ != 0 {
+            write!(f, " in attribute {}", self.typ)?;
+        }
+        if let Some(family) = self.family {
+            write!(f, " for AFI {} SAFI {}", family.afi, family.safi)?;
+        }
+        write!(f, ", {}", self.handling)
+    }
+}
+
+impl std::error::Error for UpdateError {}
+
+/// Session properties which change how an UPDATE is decoded.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct UpdateContext {
+    /// Four-octet AS numbers were negotiated (RFC 6793).
+    pub as4: bool,
+    /// The neighbor is in another AS, LOCAL_PREF is ignored.
+    pub external: bool,
+    /// Extended Next Hop Encoding was negotiated, IPv4 NLRI may come with
+    /// an IPv6 next hop (RFC 8950).
+    pub extended_nexthop: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MpReach {
+    pub family: Family,
+    pub next_hop: IpAddr,
+    /// Link-local address following a global IPv6 next hop (RFC 2545 3).
+    pub link_local: Option<Ipv6Addr>,
+    pub nlri: Vec<Prefix>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MpUnreach {
+    pub family: Family,
+    pub withdrawn: Vec<Prefix>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MessageUpdate {
+    pub withdrawn: Vec<Prefix>,
+    pub attr: Attr,
+    pub nlri: Vec<Prefix>,
+    pub mp_reach: Option<MpReach>,
+    pub mp_unreach: Option<MpUnreach>,
+    /// Errors handled without resetting the session. Treat-as-withdraw has
+    /// already been applied, the NLRI were moved to the withdrawn routes.
+    pub errors: Vec<UpdateError>,
+}
+
+fn nlri_from_bytes(buf: &[u8], ipv6: bool) -> Result<Vec<Prefix>, PrefixError> {
+    let mut c = Cursor::new(buf);
+    let mut prefixes = Vec::new();
+    while (c.position() as usize) < buf.len() {
+        prefixes.push(Prefix::from_bytes(&mut c, ipv6)?);
+    }
+    Ok(prefixes)
+}
+
+/// Optional and transitive flags a known attribute must carry.
+fn attr_flags(typ: u8) -> Option<u8> {
+    match typ {
+        ATTR_TYPE_ORIGIN
+        | ATTR_TYPE_AS_PATH
+        | ATTR_TYPE_NEXT_HOP
+        | ATTR_TYPE_LOCAL_PREF
+        | ATTR_TYPE_ATOMIC_AGGREGATE => Some(ATTR_FLAG_TRANSITIVE),
+        ATTR_TYPE_MED | ATTR_TYPE_MP_REACH_NLRI | ATTR_TYPE_MP_UNREACH_NLRI => {
+            Some(ATTR_FLAG_OPTIONAL)
+        }
+        ATTR_TYPE_AGGREGATOR
+        | ATTR_TYPE_COMMUNITIES
+        | ATTR_TYPE_EXT_COMMUNITIES
+        | ATTR_TYPE_IPV6_EXT_COMMUNITIES
+        | ATTR_TYPE_LARGE_COMMUNITIES
+        | ATTR_TYPE_OTC => Some(ATTR_FLAG_OPTIONAL | ATTR_FLAG_TRANSITIVE),
+        _ => None,
+    }
+}
+
+fn attr_to_bytes(buf: &mut Vec<u8>, typ: u8, value: &[u8]) -> Result<usize, anyhow::Error> {
+    let flags = attr_flags(typ).unwrap_or(ATTR_FLAG_OPTIONAL);
+    let len = AttrHeader::new(flags, typ, value.len()).to_bytes(buf)?;
+    buf.extend_from_slice(value);
+    Ok(len + value.len())
+}
+
+/// Handling of a malformed attribute which does not affect NLRI parsing
+/// (RFC 7606 7). Attributes which only inform aggregation are discarded,
+/// everything else may influence route selection and withdraws the routes.
+fn attr_handling(typ: u8) -> ErrorHandling {
+    match typ {
+        ATTR_TYPE_ATOMIC_AGGREGATE | ATTR_TYPE_AGGREGATOR => ErrorHandling::AttributeDiscard,
+        _ => ErrorHandling::TreatAsWithdraw,
+    }
+}
+
+fn mp_family(value: &[u8]) -> Option<Family> {
+    if value.len() < 3 {
+        return None;
+    }
+    Some(Family {
+        afi: u16::from_be_bytes([value[0], value[1]]),
+        safi: value[2],
+    })
+}
+
+fn mp_supported(family: Family) -> Option<bool> {
+    match (family.afi, family.safi) {
+        (AFI_IP, SAFI_UNICAST) | (AFI_IP, SAFI_MULTICAST) => Some(false),
+        (AFI_IP6, SAFI_UNICAST) | (AFI_IP6, SAFI_MULTICAST) => Some(true),
+        _ => None,
+    }
+}
+
+impl MessageUpdate {
+    pub fn new() -> Self {
+        MessageUpdate::default()
+    }
+
+    /// Strongest error handling applied to this UPDATE.
+    pub fn handling(&self) -> Option<ErrorHandling> {
+        self.errors.iter().map(|e| e.handling).max()
+    }
+
+    fn error(&mut self, subcode: u8, typ: u8) {
+        self.errors
+            .push(UpdateError::new(attr_handling(typ), subcode, typ));
+    }
+
+    /// AFI/SAFI disable for the family of a malformed MP_REACH_NLRI or
+    /// MP_UNREACH_NLRI, session reset when not even the family is readable
+    /// (RFC 7606 5.3).
+    fn mp_error(&mut self, value: &[u8], subcode: u8, typ: u8) -> Result<(), UpdateError> {
+        match mp_family(value) {
+            Some(family) => {
+                let mut e = UpdateError::new(ErrorHandling::AfiSafiDisable, subcode, typ);
+                e.family = Some(family);
+                self.errors.push(e);
+                Ok(())
+            }
+            None => Err(UpdateError::new(ErrorHandling::SessionReset, subcode, typ)),
+        }
+    }
+
+    /// An MP_REACH_NLRI or MP_UNREACH_NLRI of a family we do not support
+    /// is discarded.
+    fn unsupported(&mut self, family: Family, typ: u8) -> Result<(), UpdateError> {
+        let mut e = UpdateError::new(
+            ErrorHandling::AttributeDiscard,
+            NOTIFY_UPDATE_OPT_ATTR_ERR,
+            typ,
+        );
+        e.family = Some(family);
+        self.errors.push(e);
+        Ok(())
+    }
+
+    fn mp_reach_from_bytes(
+        &mut self,
+        value: &[u8],
+        ctx: &UpdateContext,
+    ) -> Result<(), UpdateError> {
+        let typ = ATTR_TYPE_MP_REACH_NLRI;
+        let family = match mp_family(value) {
+            Some(family) if value.len() >= 5 => family,
+            _ => return self.mp_error(value, NOTIFY_UPDATE_ATTR_LENG_ERR, typ),
+        };
+        let ipv6 = match mp_supported(family) {
+            Some(ipv6) => ipv6,
+            None => return self.unsupported(family, typ),
+        };
+        let nh_len = value[3] as usize;
+        if 5 + nh_len > value.len() {
+            return self.mp_error(value, NOTIFY_UPDATE_ATTR_LENG_ERR, typ);
+        }
+        let nh = &value[4..4 + nh_len];
+        let ipv6_nh = |nh: &[u8]| {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&nh[..16]);
+            Ipv6Addr::from(octets)
+        };
+        let (next_hop, link_local) = match (ipv6 || ctx.extended_nexthop, nh_len) {
+            (_, 4) if !ipv6 => (IpAddr::V4(Ipv4Addr::new(nh[0], nh[1], nh[2], nh[3])), None),
+            // Global address, optionally followed by the link-local one. A
+            // neighbor without a global address sends only the link-local.
+            (true, 16) => (IpAddr::V6(ipv6_nh(nh)), None),
+            (true, 32) => (IpAddr::V6(ipv6_nh(nh)), Some(ipv6_nh(&nh[16..]))),
+            _ => return self.mp_error(value, NOTIFY_UPDATE_OPT_ATTR_ERR, typ),
+        };
+        let nlri = match nlri_from_bytes(&value[5 + nh_len..], ipv6) {
+            Ok(nlri) => nlri,
+            Err(_) => return self.mp_error(value, NOTIFY_UPDATE_OPT_ATTR_ERR, typ),
+        };
+        self.mp_reach = Some(MpReach {
+            family,
+            next_hop,
+            link_local,
+            nlri,
+        });
+        Ok(())
+    }
+
+    fn mp_unreach_from_bytes(&mut self, value: &[u8]) -> Result<(), UpdateError> {
+        let typ = ATTR_TYPE_MP_UNREACH_NLRI;
+        let family = match mp_family(value) {
+            Some(family) => family,
+            None => return self.mp_error(value, NOTIFY_UPDATE_ATTR_LENG_ERR, typ),
+        };
+        let ipv6 = match mp_supported(family) {
+            Some(ipv6) => ipv6,
+            None => return self.unsupported(family, typ),
+        };
+        let withdrawn = match nlri_from_bytes(&value[3..], ipv6) {
+            Ok(withdrawn) => withdrawn,
+            Err(_) => return self.mp_error(value, NOTIFY_UPDATE_OPT_ATTR_ERR, typ),
+        };
+        self.mp_unreach = Some(MpUnreach { family, withdrawn });
+        Ok(())
+    }
+
+    fn attr_from_bytes(
+        &mut self,
+        header: &AttrHeader,
+        value: &[u8],
+        ctx: &UpdateContext,
+    ) -> Result<(), UpdateError> {
+        let typ = header.typ;
+        let len = value.len();
+
+        // LOCAL_PREF from an external neighbor is ignored (RFC 4271 5.1.5).
+        if typ == ATTR_TYPE_LOCAL_PREF && ctx.external {
+            return Ok(());
+        }
+        if let Some(flags) = attr_flags(typ) {
+            if header.flags & (ATTR_FLAG_OPTIONAL | ATTR_FLAG_TRANSITIVE) != flags {
+                if typ == ATTR_TYPE_MP_REACH_NLRI || typ == ATTR_TYPE_MP_UNREACH_NLRI {
+                    return self.mp_error(value, NOTIFY_UPDATE_ATTR_FLAG_ERR, typ);
+                }
+                self.error(NOTIFY_UPDATE_ATTR_FLAG_ERR, typ);
+                return Ok(());
+            }
+        }
+
+        let mut c = Cursor::new(value);
+        match typ {
+            ATTR_TYPE_ORIGIN => {
+                if len != 1 {
+                    self.error(NOTIFY_UPDATE_ATTR_LENG_ERR, typ);
+                    return Ok(());
+                }
+                match Origin::from_u8(value[0]) {
+                    Some(origin) => self.attr.origin = origin,
+                    None => self.error(NOTIFY_UPDATE_INVAL_ORIGIN, typ),
+                }
+            }
+            ATTR_TYPE_AS_PATH => match AsPath::from_bytes(&mut c, len, ctx.as4) {
+                Ok(as_path) => self.attr.as_path = as_path,
+                Err(_) => self.error(NOTIFY_UPDATE_MAL_AS_PATH, typ),
+            },
+            ATTR_TYPE_NEXT_HOP => {
+                if len != 4 {
+                    self.error(NOTIFY_UPDATE_ATTR_LENG_ERR, typ);
+                    return Ok(());
+                }
+                let addr = Ipv4Addr::new(value[0], value[1], value[2], value[3]);
+                if addr.is_unspecified() || addr.is_multicast() || addr.is_broadcast() {
+                    self.error(NOTIFY_UPDATE_INVAL_NEXT_HOP, typ);
+                    return Ok(());
+                }
+                self.attr.next_hop = Some(IpAddr::V4(addr));
+            }
+            ATTR_TYPE_MED | ATTR_TYPE_LOCAL_PREF => {
+                let val = match c.read_u32::<NetworkEndian>() {
+                    Ok(val) if len == 4 => val,
+                    _ => {
+                        self.error(NOTIFY_UPDATE_ATTR_LENG_ERR, typ);
+                        return Ok(());
+                    }
+                };
+                if typ == ATTR_TYPE_MED {
+                    self.attr.med = Some(val);
+                } else {
+                    self.attr.local_pref = Some(val);
+                }
+            }
+            ATTR_TYPE_ATOMIC_AGGREGATE => {
+                if len != 0 {
+                    self.error(NOTIFY_UPDATE_ATTR_LENG_ERR, typ);
+                    return Ok(());
+                }
+                self.attr.atomic_aggregate = true;
+            }
+            ATTR_TYPE_AGGREGATOR => {
+                let asn = match len {
+                    8 if ctx.as4 => c.read_u32::<NetworkEndian>(),
+                    6 if !ctx.as4 => c.read_u16::<NetworkEndian>().map(|v| v as u32),
+                    _ => {
+                        self.error(NOTIFY_UPDATE_ATTR_LENG_ERR, typ);
+                        return Ok(());
+                    }
+                };
+                let addr = c.read_u32::<NetworkEndian>();
+                if let (Ok(asn), Ok(addr)) = (asn, addr) {
+                    self.attr.aggregator = Some((asn, Ipv4Addr::from(addr)));
+                }
+            }
+            ATTR_TYPE_COMMUNITIES => match Communities::from_bytes(&mut c, len) {
+                Ok(coms) => self.attr.communities = Some(coms),
+                Err(_) => self.error(NOTIFY_UPDATE_OPT_ATTR_ERR, typ),
+            },
+            ATTR_TYPE_EXT_COMMUNITIES | ATTR_TYPE_IPV6_EXT_COMMUNITIES => {
+                let ecoms = if typ == ATTR_TYPE_EXT_COMMUNITIES {
+                    ExtCommunities::from_bytes(&mut c, len)
+                } else {
+                    ExtCommunities::from_ipv6_bytes(&mut c, len)
+                };
+                match (ecoms, self.attr.ext_communities.as_mut()) {
+                    (Ok(ecoms), Some(current)) => current.merge(&ecoms),
+                    (Ok(ecoms), None) => self.attr.ext_communities = Some(ecoms),
+                    (Err(_), _) => self.error(NOTIFY_UPDATE_OPT_ATTR_ERR, typ),
+                }
+            }
+            ATTR_TYPE_LARGE_COMMUNITIES => match LargeCommunities::from_bytes(&mut c, len) {
+                Ok(lcoms) => self.attr.large_communities = Some(lcoms),
+                Err(_) => self.error(NOTIFY_UPDATE_OPT_ATTR_ERR, typ),
+            },
+            ATTR_TYPE_OTC => match c.read_u32::<NetworkEndian>() {
+                Ok(otc) if len == 4 => self.attr.otc = Some(otc),
+                _ => self.error(NOTIFY_UPDATE_ATTR_LENG_ERR, typ),
+            },
+            ATTR_TYPE_MP_REACH_NLRI => self.mp_reach_from_bytes(value, ctx)?,
+            ATTR_TYPE_MP_UNREACH_NLRI => self.mp_unreach_from_bytes(value)?,
+            _ => {
+                if header.flags & ATTR_FLAG_OPTIONAL == 0 {
+                    return Err(UpdateError::new(
+                        ErrorHandling::SessionReset,
+                        NOTIFY_UPDATE_UNREC_WELLKNOWN,
+                        typ,
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Decode the path attributes. Returns the attribute types which were
+    /// present.
+    fn attrs_from_bytes(
+        &mut self,
+        buf: &[u8],
+        ctx: &UpdateContext,
+    ) -> Result<[bool; 256], UpdateError> {
+        let mut seen = [false; 256];
+        let mut c = Cursor::new(buf);
+
+        while (c.position() as usize) < buf.len() {
+            // An attribute overrunning the attribute section leaves the
+            // remaining attributes unusable but the NLRI intact (RFC 7606 4).
+            let header = match AttrHeader::from_bytes(&mut c) {
+                Ok(header) => header,
+                Err(_) => {
+                    self.errors.push(UpdateError::new(
+                        ErrorHandling::TreatAsWithdraw,
+                        NOTIFY_UPDATE_MAL_ATTR,
+                        0,
+                    ));
+                    break;
+                }
+            };
+            let start = c.position() as usize;
+            let end = start + header.len;
+            if end > buf.len() {
+                self.errors.push(UpdateError::new(
+                    ErrorHandling::TreatAsWithdraw,
+                    NOTIFY_UPDATE_ATTR_LENG_ERR,
+                    header.typ,
+                ));
+                break;
+            }
+            c.set_position(end as u64);
+
+            // Only the first of repeated attributes is used, except that
+            // repeated MP_REACH_NLRI or MP_UNREACH_NLRI leave it unclear
+            // which routes were meant (RFC 7606 3.g).
+            if seen[header.typ as usize] {
+                if header.typ == ATTR_TYPE_MP_REACH_NLRI || header.typ == ATTR_TYPE_MP_UNREACH_NLRI
+                {
+                    return Err(UpdateError::new(
+                        ErrorHandling::SessionReset,
+                        NOTIFY_UPDATE_MAL_ATTR,
+                        header.typ,
+                    ));
+                }
+                continue;
+            }
+            seen[header.typ as usize] = true;
+            self.attr_from_bytes(&header, &buf[start..end], ctx)?;
+        }
+        Ok(seen)
+    }
+
+    /// Move every advertised prefix to the withdrawn routes.
+    fn treat_as_withdraw(&mut self) {
+        self.withdrawn.append(&mut self.nlri);
+        if let Some(reach) = self.mp_reach.take() {
+            match self.mp_unreach.as_mut() {
+                Some(unreach) if unreach.family == reach.family => {
+                    unreach.withdrawn.extend(reach.nlri)
+                }
+                _ => {
+                    self.mp_unreach = Some(MpUnreach {
+                        family: reach.family,
+                        withdrawn: reach.nlri,
+                    })
+                }
+            }
+        }
+        self.attr = Attr::new();
+    }
+
+    /// Encode the UPDATE message body. The attributes are only sent along
+    /// with NLRI, NEXT_HOP only along with IPv4 NLRI outside MP_REACH_NLRI.
+    pub fn to_bytes(&self, buf: &mut Vec<u8>, ctx: &UpdateContext) -> Result<usize, anyhow::Error> {
+        let start = buf.len();
+        buf.write_u16::<NetworkEndian>(0)?;
+        for prefix in &self.withdrawn {
+            prefix.to_bytes(buf)?;
+        }
+        let len = (buf.len() - start - 2) as u16;
+        buf[start..start + 2].copy_from_slice(&len.to_be_bytes());
+
+        let attr_start = buf.len();
+        buf.write_u16::<NetworkEndian>(0)?;
+        if let Some(unreach) = &self.mp_unreach {
+            let mut value = Vec::new();
+            value.write_u16::<NetworkEndian>(unreach.family.afi)?;
+            value.write_u8(unreach.family.safi)?;
+            for prefix in &unreach.withdrawn {
+                prefix.to_bytes(&mut value)?;
+            }
+            attr_to_bytes(buf, ATTR_TYPE_MP_UNREACH_NLRI, &value)?;
+        }
+        if !self.nlri.is_empty() || self.mp_reach.is_some() {
+            self.attr.to_bytes(buf, ctx)?;
+        }
+        if let (false, Some(IpAddr::V4(next_hop))) = (self.nlri.is_empty(), self.attr.next_hop) {
+            attr_to_bytes(buf, ATTR_TYPE_NEXT_HOP, &next_hop.octets())?;
+        }
+        if let Some(reach) = &self.mp_reach {
+            let mut value = Vec::new();
+            value.write_u16::<NetworkEndian>(reach.family.afi)?;
+            value.write_u8(reach.family.safi)?;
+            let mut next_hop = match reach.next_hop {
+                IpAddr::V4(addr) => addr.octets().to_vec(),
+                IpAddr::V6(addr) => addr.octets().to_vec(),
+            };
+            if let Some(link_local) = reach.link_local {
+                next_hop.extend_from_slice(&link_local.octets());
+            }
+            value.write_u8(next_hop.len() as u8)?;
+            value.extend_from_slice(&next_hop);
+            // Reserved.
+            value.write_u8(0)?;
+            for prefix in &reach.nlri {
+                prefix.to_bytes(&mut value)?;
+            }
+            attr_to_bytes(buf, ATTR_TYPE_MP_REACH_NLRI, &value)?;
+        }
+        let len = (buf.len() - attr_start - 2) as u16;
+        buf[attr_start..attr_start + 2].copy_from_slice(&len.to_be_bytes());
+
+        for prefix in &self.nlri {
+            prefix.to_bytes(buf)?;
+        }
+        Ok(buf.len() - start)
+    }
+
+    /// Decode an UPDATE message body. Malformed attributes are handled as
+    /// RFC 7606 describes and recorded in `errors`. `Err` is returned only
+    /// when the session has to be reset, that is when the message can not
+    /// be framed into withdrawn routes, attributes and NLRI.
+    pub fn from_bytes(buf: &[u8], ctx: &UpdateContext) -> Result<MessageUpdate, UpdateError> {
+        let malformed = || UpdateError::new(ErrorHandling::SessionReset, NOTIFY_UPDATE_MAL_ATTR, 0);
+        let invalid_network =
+            || UpdateError::new(ErrorHandling::SessionReset, NOTIFY_UPDATE_INVAL_NETWORK, 0);
+
+        let mut c = Cursor::new(buf);
+        let withdrawn_len = c.read_u16::<NetworkEndian>().map_err(|_| malformed())? as usize;
+        if 2 + withdrawn_len + 2 > buf.len() {
+            return Err(malformed());
+        }
+        let withdrawn = &buf[2..2 + withdrawn_len];
+        c.set_position((2 + withdrawn_len) as u64);
+        let attr_len = c.read_u16::<NetworkEndian>().map_err(|_| malformed())? as usize;
+        let attr_start = 4 + withdrawn_len;
+        if attr_start + attr_len > buf.len() {
+            return Err(malformed());
+        }
+
+        let mut update = MessageUpdate::new();
+        update.withdrawn = nlri_from_bytes(withdrawn, false).map_err(|_| invalid_network())?;
+        update.nlri =
+            nlri_from_bytes(&buf[attr_start + attr_len..], false).map_err(|_| invalid_network())?;
+        let seen = update.attrs_from_bytes(&buf[attr_start..attr_start + attr_len], ctx)?;
+
+        // Missing well-known mandatory attributes (RFC 7606 3.d).
+        if !update.nlri.is_empty() || update.mp_reach.is_some() {
+            let mut mandatory = vec![ATTR_TYPE_ORIGIN, ATTR_TYPE_AS_PATH];
+            if !update.nlri.is_empty() {
+                mandatory.push(ATTR_TYPE_NEXT_HOP);
+            }
+            for typ in mandatory {
+                if !seen[typ as usize] {
+                    update.errors.push(UpdateError::new(
+                        ErrorHandling::TreatAsWithdraw,
+                        NOTIFY_UPDATE_MISS_WELLKNOWN,
+                        typ,
+                    ));
+                }
+            }
+        }
+
+        if update
+            .errors
+            .iter()
+            .any(|e| e.handling == ErrorHandling::TreatAsWithdraw)
+        {
+            update.treat_as_withdraw();
+        }
+        Ok(update)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // ORIGIN IGP, AS_PATH 100 200 and NEXT_HOP 192.168.0.1 with two octet
+    // ASNs.
+    const ATTRS: [u8; 20] = [
+        0x40, 1, 1, 0, 0x40, 2, 6, 2, 2, 0, 100, 0, 200, 0x40, 3, 4, 192, 168, 0, 1,
+    ];
+
+    fn update(attrs: &[u8], nlri: &[u8]) -> Vec<u8> {
+        let mut buf = vec![0, 0];
+        buf.extend_from_slice(&(attrs.len() as u16).to_be_bytes());
+        buf.extend_from_slice(attrs);
+        buf.extend_from_slice(nlri);
+        buf
+    }
+
+    #[test]
+    fn from_bytes() {
+        let ctx = UpdateContext::default();
+        let buf = update(&ATTRS[..], &[8, 10, 24, 192, 168, 1]);
+        let m = MessageUpdate::from_bytes(&buf, &ctx).unwrap();
+        assert_eq!(m.handling(), None);
+        assert_eq!(m.attr.as_path.to_string(), "100 200");
+        assert_eq!(m.attr.next_hop, Some("192.168.0.1".parse().unwrap()));
+        assert_eq!(
+            m.nlri,
+            vec![
+                "10.0.0.0/8".parse().unwrap(),
+                "192.168.1.0/24".parse().unwrap()
+            ]
+        );
+
+        let mut attrs = ATTRS[..].to_vec();
+        attrs.extend_from_slice(&[0xc0, ATTR_TYPE_OTC, 4, 0, 0, 0xfd, 0xe9]);
+        let m = MessageUpdate::from_bytes(&update(&attrs, &[8, 10]), &ctx).unwrap();
+        assert_eq!(m.attr.otc, Some(65001));
+
+        // End-of-RIB.
+        let m = MessageUpdate::from_bytes(&[0, 0, 0, 0], &ctx).unwrap();
+        assert_eq!(m, MessageUpdate::new());
+    }
+
+    #[test]
+    fn treat_as_withdraw() {
+        let ctx = UpdateContext::default();
+
+        // Invalid ORIGIN value.
+        let mut attrs = ATTRS[..].to_vec();
+        attrs[3] = 5;
+        let m = MessageUpdate::from_bytes(&update(&attrs, &[8, 10]), &ctx).unwrap();
+        assert_eq!(m.handling(), Some(ErrorHandling::TreatAsWithdraw));
+        assert_eq!(m.errors[0].subcode, NOTIFY_UPDATE_INVAL_ORIGIN);
+        assert!(m.nlri.is_empty());
+        assert_eq!(m.withdrawn, vec!["10.0.0.0/8".parse().unwrap()]);
+
+        // Malformed AS_PATH segment.
+        let mut attrs = ATTRS[..].to_vec();
+        attrs[8] = 3;
+        let m = MessageUpdate::from_bytes(&update(&attrs, &[8, 10]), &ctx).unwrap();
+        assert_eq!(m.errors[0].subcode, NOTIFY_UPDATE_MAL_AS_PATH);
+        assert_eq!(m.withdrawn.len(), 1);
+
+        // Missing NEXT_HOP.
+        let m = MessageUpdate::from_bytes(&update(&ATTRS[..13], &[8, 10]), &ctx).unwrap();
+        assert_eq!(m.errors[0].subcode, NOTIFY_UPDATE_MISS_WELLKNOWN);
+        assert_eq!(m.errors[0].typ, ATTR_TYPE_NEXT_HOP);
+        assert_eq!(m.withdrawn.len(), 1);
+
+        // Attribute length overruns the attribute section.
+        let mut attrs = ATTRS[..].to_vec();
+        attrs[15] = 9;
+        let m = MessageUpdate::from_bytes(&update(&attrs, &[8, 10]), &ctx).unwrap();
+        assert_eq!(m.errors[0].subcode, NOTIFY_UPDATE_ATTR_LENG_ERR);
+        assert_eq!(m.withdrawn.len(), 1);
+
+        // Malformed COMMUNITIES.
+        let mut attrs = ATTRS[..].to_vec();
+        attrs.extend_from_slice(&[0xc0, 8, 3, 0, 0, 0]);
+        let m = MessageUpdate::from_bytes(&update(&attrs, &[8, 10]), &ctx).unwrap();
+        assert_eq!(m.errors[0].subcode, NOTIFY_UPDATE_OPT_ATTR_ERR);
+        assert_eq!(m.withdrawn.len(), 1);
+        // OTC of the wrong length.
+        let mut attrs = ATTRS[..].to_vec();
+        attrs.extend_from_slice(&[0xc0, ATTR_TYPE_OTC, 2, 0, 1]);
+        let m = MessageUpdate::from_bytes(&update(&attrs, &[8, 10]), &ctx).unwrap();
+        assert_eq!(m.errors[0].typ, ATTR_TYPE_OTC);
+        assert_eq!(m.withdrawn.len(), 1);
+    }
+
+    #[test]
+    fn attribute_discard() {
+        let ctx = UpdateContext {
+            as4: false,
+            external: true,
+            extended_nexthop: false,
+        };
+        let mut attrs = ATTRS[..].to_vec();
+        // ATOMIC_AGGREGATE with a value, LOCAL_PREF and a repeated ORIGIN.
+        attrs.extend_from_slice(&[0x40, 6, 1, 0]);
+        attrs.extend_from_slice(&[0x40, 5, 4, 0, 0, 0, 200]);
+        attrs.extend_from_slice(&[0x40, 1, 1, 2]);
+        let m = MessageUpdate::from_bytes(&update(&attrs, &[8, 10]), &ctx).unwrap();
+        assert_eq!(m.handling(), Some(ErrorHandling::AttributeDiscard));
+        assert!(!m.attr.atomic_aggregate);
+        assert_eq!(m.attr.local_pref, None);
+        assert_eq!(m.attr.origin, Origin::Igp);
+        assert_eq!(m.nlri.len(), 1);
+
+        // MP_UNREACH_NLRI of a family we do not support.
+        let mut attrs = ATTRS[..].to_vec();
+        attrs.extend_from_slice(&[0x80, 15, 3, 0, 25, 70]);
+        let m = MessageUpdate::from_bytes(&update(&attrs, &[8, 10]), &ctx).unwrap();
+        assert_eq!(m.handling(), Some(ErrorHandling::AttributeDiscard));
+        assert_eq!(m.errors[0].family, Some(Family { afi: 25, safi: 70 }));
+        assert!(m.mp_unreach.is_none());
+        assert_eq!(m.nlri.len(), 1);
+    }
+
+    #[test]
+    fn afi_safi_disable() {
+        let ctx = UpdateContext::default();
+        let mut attrs = ATTRS[..13].to_vec();
+        // IPv6 unicast MP_REACH_NLRI with a 4 octet next hop.
+        attrs.extend_from_slice(&[0x80, 14, 11, 0, 2, 1, 4, 10, 0, 0, 1, 0, 32, 0x20]);
+        let m = MessageUpdate::from_bytes(&update(&attrs, &[]), &ctx).unwrap();
+        assert_eq!(m.handling(), Some(ErrorHandling::AfiSafiDisable));
+        assert_eq!(
+            m.errors[0].family,
+            Some(Family {
+                afi: AFI_IP6,
+                safi: SAFI_UNICAST
+            })
+        );
+        assert!(m.mp_reach.is_none());
+
+        // Well formed.
+        let mut attrs = ATTRS[..13].to_vec();
+        attrs.extend_from_slice(&[0x80, 14, 26, 0, 2, 1, 16]);
+        attrs.extend_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        attrs.extend_from_slice(&[0, 32, 0x20, 0x01, 0x0d, 0xb8]);
+        let m = MessageUpdate::from_bytes(&update(&attrs, &[]), &ctx).unwrap();
+        assert_eq!(m.handling(), None);
+        let reach = m.mp_reach.unwrap();
+        assert_eq!(reach.next_hop, "2001:db8::1".parse::<IpAddr>().unwrap());
+        assert_eq!(reach.nlri, vec!["2001:db8::/32".parse().unwrap()]);
+    }
+
+    #[test]
+    fn extended_nexthop() {
+        // IPv4 unicast MP_REACH_NLRI with global and link-local IPv6 next
+        // hops.
+        let mut attrs = ATTRS[..13].to_vec();
+        attrs.extend_from_slice(&[0x80, 14, 41, 0, 1, 1, 32]);
+        attrs.extend_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        attrs.extend_from_slice(&"fe80::1".parse::<Ipv6Addr>().unwrap().octets());
+        attrs.extend_from_slice(&[0, 24, 10, 0, 1]);
+
+        // Only accepted once Extended Next Hop Encoding is negotiated.
+        let mut ctx = UpdateContext::default();
+        let m = MessageUpdate::from_bytes(&update(&attrs, &[]), &ctx).unwrap();
+        assert_eq!(m.handling(), Some(ErrorHandling::AfiSafiDisable));
+
+        ctx.extended_nexthop = true;
+        let m = MessageUpdate::from_bytes(&update(&attrs, &[]), &ctx).unwrap();
+        assert_eq!(m.handling(), None);
+        let reach = m.mp_reach.unwrap();
+        assert_eq!(reach.next_hop, "2001:db8::1".parse::<IpAddr>().unwrap());
+        assert_eq!(reach.link_local, Some("fe80::1".parse().unwrap()));
+        assert_eq!(reach.nlri, vec!["10.0.1.0/24".parse().unwrap()]);
+
+        // An IPv4 next hop is still accepted.
+        let mut attrs = ATTRS[..13].to_vec();
+        attrs.extend_from_slice(&[0x80, 14, 13, 0, 1, 1, 4, 192, 168, 0, 1, 0, 24, 10, 0, 1]);
+        let m = MessageUpdate::from_bytes(&update(&attrs, &[]), &ctx).unwrap();
+        assert_eq!(
+            m.mp_reach.unwrap().next_hop,
+            "192.168.0.1".parse::<IpAddr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn to_bytes() {
+        let ctx = UpdateContext {
+            as4: true,
+            ..Default::default()
+        };
+        let mut m = MessageUpdate::new();
+        m.withdrawn = vec!["172.16.0.0/12".parse().unwrap()];
+        m.attr.as_path = "65001 4200000000".parse().unwrap();
+        m.attr.next_hop = Some("192.168.0.1".parse().unwrap());
+        m.attr.med = Some(10);
+        m.attr.aggregator = Some((4200000000, "10.0.0.1".parse().unwrap()));
+        m.attr.communities = Communities::from_str("no-export");
+        m.nlri = vec!["10.0.0.0/8".parse().unwrap()];
+        m.mp_reach = Some(MpReach {
+            family: Family {
+                afi: AFI_IP6,
+                safi: SAFI_UNICAST,
+            },
+            next_hop: "2001:db8::1".parse().unwrap(),
+            link_local: Some("fe80::1".parse().unwrap()),
+            nlri: vec!["2001:db8:1::/48".parse().unwrap()],
+        });
+        m.mp_unreach = Some(MpUnreach {
+            family: Family {
+                afi: AFI_IP6,
+                safi: SAFI_UNICAST,
+            },
+            withdrawn: vec!["2001:db8:2::/48".parse().unwrap()],
+        });
+        let mut buf = Vec::new();
+        let len = m.to_bytes(&mut buf, &ctx).unwrap();
+        assert_eq!(len, buf.len());
+        assert_eq!(MessageUpdate::from_bytes(&buf, &ctx).unwrap(), m);
+
+        // Two octet ASNs, LOCAL_PREF only to internal neighbors.
+        m.attr.local_pref = Some(200);
+        let mut buf = Vec::new();
+        m.to_bytes(&mut buf, &UpdateContext::default()).unwrap();
+        let decoded = MessageUpdate::from_bytes(&buf, &UpdateContext::default()).unwrap();
+        assert_eq!(decoded.attr.as_path.to_string(), "65001 23456");
+        assert_eq!(decoded.attr.local_pref, Some(200));
+        let external = UpdateContext {
+            external: true,
+            ..Default::default()
+        };
+        let mut buf = Vec::new();
+        m.to_bytes(&mut buf, &external).unwrap();
+        let decoded = MessageUpdate::from_bytes(&buf, &UpdateContext::default()).unwrap();
+        assert_eq!(decoded.attr.local_pref, None);
+    }
+
+    #[test]
+    fn session_reset() {
+        let ctx = UpdateContext::default();
+
+        // Withdrawn routes length overruns the message.
+        let e = MessageUpdate::from_bytes(&[0, 9, 0, 0], &ctx).unwrap_err();
+        assert_eq!(e.handling, ErrorHandling::SessionReset);
+
+        // NLRI prefix length out of range.
+        let e = MessageUpdate::from_bytes(&update(&ATTRS[..], &[33, 10]), &ctx).unwrap_err();
+        assert_eq!(e.subcode, NOTIFY_UPDATE_INVAL_NETWORK);
+
+        // Unrecognized well-known attribute.
+        let mut attrs = ATTRS[..].to_vec();
+        attrs.extend_from_slice(&[0x40, 99, 0]);
+        let e = MessageUpdate::from_bytes(&update(&attrs, &[8, 10]), &ctx).unwrap_err();
+        assert_eq!(e.subcode, NOTIFY_UPDATE_UNREC_WELLKNOWN);
+        assert_eq!(
+            e.to_string(),
+            "UPDATE Message Error/Unrecognized Well-known Attribute in attribute 99, session-reset"
+        );
+
+        // Unknown optional attributes are fine.
+        let mut attrs = ATTRS[..].to_vec();
+        attrs.extend_from_slice(&[0xc0, 99, 0]);
+        assert!(MessageUpdate::from_bytes(&update(&attrs, &[8, 10]), &ctx).is_ok());
+    }
+}
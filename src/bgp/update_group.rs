@@ -0,0 +1,683 @@
+#![allow(dead_code)]
+
+use super::PeerType;
+use super::{Action, AdvertiseMap, AttrStore, ConditionalAdvertisement, Family, Neighbor, Peer};
+use super::{LocalAs, Policy, Prefix, Role, Route, SendCommunity, AFI_IP, AFI_IP6, SAFI_UNICAST};
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::IpAddr;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Everything outbound UPDATEs to a neighbor depend on. Neighbors with
+/// equal keys receive the same UPDATEs and share an update group.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UpdateGroupKey {
+    pub peer_type: PeerType,
+    pub local_as: Option<LocalAs>,
+    pub route_map_out: Option<String>,
+    pub prefix_list_out: Option<String>,
+    pub advertise_map: Option<AdvertiseMap>,
+    pub next_hop_self: bool,
+    pub send_community: SendCommunity,
+    pub local_role: Option<Role>,
+    /// Families routes are advertised in.
+    pub families: BTreeSet<Family>,
+    pub as4: bool,
+    pub extended_nexthop: bool,
+    /// Our address on the session, the next hop of locally originated routes
+    /// and of all routes with next-hop-self or to external neighbors.
+    pub local: Option<IpAddr>,
+}
+
+impl UpdateGroupKey {
+    /// Key of `neighbor` with the capabilities negotiated on its session
+    /// `peer`. `local` is our address on the session.
+    pub fn new(neighbor: &Neighbor, peer: &Peer, local: Option<IpAddr>) -> Self {
+        UpdateGroupKey {
+            peer_type: peer.peer_type,
+            local_as: neighbor.local_as,
+            route_map_out: neighbor.route_map_out.clone(),
+            prefix_list_out: neighbor.prefix_list_out.clone(),
+            advertise_map: neighbor.advertise_map.clone(),
+            next_hop_self: neighbor.next_hop_self,
+            send_community: neighbor.send_community,
+            local_role: neighbor.local_role,
+            families: neighbor
+                .activate
+                .difference(&peer.disabled)
+                .copied()
+                .collect(),
+            as4: peer.as4,
+            extended_nexthop: peer.extended_nexthop,
+            local,
+        }
+    }
+}
+
+/// A change of the Adj-RIB-Out.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AdjOut {
+    Announce(Box<Route>),
+    Withdraw(Prefix),
+}
+
+/// A change built once for an update group and sent to `members`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GroupUpdate {
+    pub group: u32,
+    pub members: Vec<IpAddr>,
+    pub change: AdjOut,
+}
+
+/// Neighbors sharing an update group key, with the routes advertised to
+/// them.
+#[derive(Clone, Debug)]
+pub struct UpdateGroup {
+    pub id: u32,
+    pub key: UpdateGroupKey,
+    /// Configuration outbound policy is applied with, that of the member
+    /// which created the group. A route-map matching on the peer address
+    /// sees that member's.
+    neighbor: Neighbor,
+    members: BTreeSet<IpAddr>,
+    adj_out: BTreeMap<Prefix, Route>,
+    /// State of the advertise-map of the key.
+    conditional: Option<ConditionalAdvertisement>,
+    /// Routes outbound policy was applied to.
+    pub evaluations: u64,
+}
+
+impl UpdateGroup {
+    fn new(id: u32, key: UpdateGroupKey, neighbor: &Neighbor, interval: Duration) -> Self {
+        let conditional = key
+            .advertise_map
+            .clone()
+            .map(|map| ConditionalAdvertisement::new(map, interval));
+        UpdateGroup {
+            id,
+            key,
+            neighbor: neighbor.clone(),
+            members: BTreeSet::new(),
+            adj_out: BTreeMap::new(),
+            conditional,
+            evaluations: 0,
+        }
+    }
+
+    pub fn members(&self) -> &BTreeSet<IpAddr> {
+        &self.members
+    }
+
+    pub fn adj_out(&self) -> &BTreeMap<Prefix, Route> {
+        &self.adj_out
+    }
+
+    /// The route advertised to the group for the best path `route`, or
+    /// `None` when it is not advertised. `asn` is the instance's AS.
+    fn route_out(&mut self, policy: &Policy, asn: u32, route: &Route) -> Option<Route> {
+        let family = Family {
+            afi: if route.prefix.is_ipv4() {
+                AFI_IP
+            } else {
+                AFI_IP6
+            },
+            safi: SAFI_UNICAST,
+        };
+        if !self.key.families.contains(&family) {
+            return None;
+        }
+        // No route reflection, routes from internal neighbors are not sent
+        // to internal neighbors (RFC 4271 9.2).
+        if route.ibgp && self.key.peer_type == PeerType::Internal {
+            return None;
+        }
+        if !self.neighbor.advertise_allowed(route, self.key.peer_type) {
+            return None;
+        }
+        if let Some(conditional) = &self.conditional {
+            if !conditional.permits(policy, route) {
+                return None;
+            }
+        }
+        self.evaluations += 1;
+        let mut route = route.clone();
+        self.neighbor.attr_reset_out(&mut route, self.key.peer_type);
+        if self.neighbor.policy_out(policy, &mut route, self.key.local) == Action::Deny {
+            return None;
+        }
+        if self
+            .neighbor
+            .otc_out(&mut route, self.neighbor.local_asn(asn))
+            == Action::Deny
+        {
+            return None;
+        }
+        self.neighbor
+            .attr_out(&mut route, asn, self.key.peer_type, self.key.local);
+        Some(route)
+    }
+
+    /// Apply outbound policy to the best path `best` to `prefix` and update
+    /// the Adj-RIB-Out, with its attributes interned in `attrs`, pushing
+    /// the changes to `updates`.
+    fn update(
+        &mut self,
+        policy: &Policy,
+        attrs: &mut AttrStore,
+        asn: u32,
+        prefix: Prefix,
+        best: Option<&Route>,
+        updates: &mut Vec<GroupUpdate>,
+    ) {
+        let new = best.and_then(|route| self.route_out(policy, asn, route));
+        let old = self.adj_out.get(&prefix);
+        if new.as_ref() == old {
+            return;
+        }
+        let (id, old_from) = (self.id, old.and_then(|route| route.peer));
+        let mut update = |members: Vec<IpAddr>, change: AdjOut| {
+            if !members.is_empty() {
+                updates.push(GroupUpdate {
+                    group: id,
+                    members,
+                    change,
+                });
+            }
+        };
+        match new {
+            Some(mut new) => {
+                new.attr = attrs.intern_set(new.attr);
+                let from = new.peer;
+                // The neighbor the new path is from had the old one.
+                if let (Some(from), Some(_)) = (from, old) {
+                    if old_from != Some(from) && self.members.contains(&from) {
+                        update(vec![from], AdjOut::Withdraw(prefix));
+                    }
+                }
+                update(
+                    self.members_except(from),
+                    AdjOut::Announce(Box::new(new.clone())),
+                );
+                self.adj_out.insert(prefix, new);
+            }
+            None => {
+                update(self.members_except(old_from), AdjOut::Withdraw(prefix));
+                self.adj_out.remove(&prefix);
+            }
+        }
+    }
+
+    /// Members other than the neighbor `route` was learned from, which is
+    /// not sent its own routes back.
+    fn members_except(&self, from: Option<IpAddr>) -> Vec<IpAddr> {
+        self.members
+            .iter()
+            .filter(|addr| Some(**addr) != from)
+            .copied()
+            .collect()
+    }
+}
+
+/// The update groups of a BGP instance. Outbound policy is applied once per
+/// group and the result replicated to its members. A neighbor whose key
+/// changes moves to the group of its new key, splitting from its old group
+/// or merging into an existing one.
+#[derive(Clone, Debug)]
+pub struct UpdateGroups {
+    asn: u32,
+    /// Interval advertise-map conditions are evaluated at.
+    conditional_timer: Duration,
+    groups: BTreeMap<u32, UpdateGroup>,
+    peers: BTreeMap<IpAddr, u32>,
+    next_id: u32,
+}
+
+impl UpdateGroups {
+    pub fn new(asn: u32, conditional_timer: Duration) -> Self {
+        UpdateGroups {
+            asn,
+            conditional_timer,
+            groups: BTreeMap::new(),
+            peers: BTreeMap::new(),
+            next_id: 1,
+        }
+    }
+
+    pub fn get(&self, id: u32) -> Option<&UpdateGroup> {
+        self.groups.get(&id)
+    }
+
+    pub fn groups(&self) -> impl Iterator<Item = &UpdateGroup> {
+        self.groups.values()
+    }
+
+    /// Group of the neighbor at `addr`.
+    pub fn group_of(&self, addr: &IpAddr) -> Option<u32> {
+        self.peers.get(addr).copied()
+    }
+
+    /// Put `neighbor`, established with `key`, in the group of its key,
+    /// after a change of its configuration as well. A new group is filled
+    /// from the best paths `best`. Returns the changes to send the
+    /// neighbor, its whole Adj-RIB-Out when it was in no group.
+    pub fn join<'a>(
+        &mut self,
+        policy: &Policy,
+        attrs: &mut AttrStore,
+        neighbor: &Neighbor,
+        key: UpdateGroupKey,
+        best: impl IntoIterator<Item = &'a Route>,
+    ) -> Vec<AdjOut> {
+        let addr = neighbor.ipaddr;
+        let old = match self.peers.get(&addr) {
+            Some(id) if self.groups[id].key == key => return Vec::new(),
+            Some(_) => self.leave(&addr),
+            None => BTreeMap::new(),
+        };
+        let id = match self.groups.values().find(|group| group.key == key) {
+            Some(group) => group.id,
+            None => {
+                let id = self.next_id;
+                self.next_id += 1;
+                let mut group = UpdateGroup::new(id, key, neighbor, self.conditional_timer);
+                let best: Vec<&Route> = best.into_iter().collect();
+                if let Some(conditional) = &mut group.conditional {
+                    conditional.evaluate(Instant::now(), policy, &best);
+                }
+                for route in best {
+                    if let Some(mut out) = group.route_out(policy, self.asn, route) {
+                        out.attr = attrs.intern_set(out.attr);
+                        group.adj_out.insert(out.prefix, out);
+                    }
+                }
+                self.groups.insert(id, group);
+                id
+            }
+        };
+        let group = self.groups.get_mut(&id).unwrap();
+        group.members.insert(addr);
+        self.peers.insert(addr, id);
+
+        let sent = |route: &Route| route.peer != Some(addr);
+        let mut changes: Vec<AdjOut> = old
+            .iter()
+            .filter(|(prefix, route)| sent(route) && !group.adj_out.contains_key(prefix))
+            .map(|(prefix, _)| AdjOut::Withdraw(*prefix))
+            .collect();
+        for (prefix, route) in &group.adj_out {
+            match old.get(prefix) {
+                Some(old) if old == route => {}
+                Some(old) if sent(old) && !sent(route) => changes.push(AdjOut::Withdraw(*prefix)),
+                _ if sent(route) => changes.push(AdjOut::Announce(Box::new(route.clone()))),
+                _ => {}
+            }
+        }
+        changes
+    }
+
+    /// Remove the neighbor at `addr` from its group, when its session goes
+    /// down. Returns the routes advertised to it. An empty group is
+    /// deleted.
+    pub fn leave(&mut self, addr: &IpAddr) -> BTreeMap<Prefix, Route> {
+        let id = match self.peers.remove(addr) {
+            Some(id) => id,
+            None => return BTreeMap::new(),
+        };
+        let group = self.groups.get_mut(&id).unwrap();
+        group.members.remove(addr);
+        if group.members.is_empty() {
+            self.groups.remove(&id).unwrap().adj_out
+        } else {
+            group.adj_out.clone()
+        }
+    }
+
+    /// The best path to `prefix` changed to `best`, `None` when there is
+    /// none left. Every group evaluates it once, the changes of its
+    /// Adj-RIB-Out are returned with the members to send them to.
+    pub fn best_changed(
+        &mut self,
+        policy: &Policy,
+        attrs: &mut AttrStore,
+        prefix: Prefix,
+        best: Option<&Route>,
+    ) -> Vec<GroupUpdate> {
+        let asn = self.asn;
+        let mut updates = Vec::new();
+        for group in self.groups.values_mut() {
+            group.update(policy, attrs, asn, prefix, best, &mut updates);
+        }
+        updates
+    }
+
+    /// Routes in the Adj-RIB-Out of every group.
+    pub fn adj_out_len(&self) -> usize {
+        self.groups.values().map(|group| group.adj_out.len()).sum()
+    }
+
+    /// When the next advertise-map condition is due, `None` when no group
+    /// has one.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.groups
+            .values()
+            .filter_map(|group| group.conditional.as_ref())
+            .map(|conditional| conditional.deadline())
+            .min()
+    }
+
+    /// Evaluate the advertise-map conditions due at `now` against the best
+    /// paths `best`. The routes of a condition which changed are evaluated
+    /// again like best path changes.
+    pub fn evaluate(
+        &mut self,
+        policy: &Policy,
+        attrs: &mut AttrStore,
+        best: &[&Route],
+        now: Instant,
+    ) -> Vec<GroupUpdate> {
+        let mut changed = Vec::new();
+        for group in self.groups.values_mut() {
+            if let Some(conditional) = &mut group.conditional {
+                if conditional.deadline() <= now && conditional.evaluate(now, policy, best) {
+                    changed.push(group.id);
+                }
+            }
+        }
+        self.readvertise(policy, attrs, &changed, best)
+    }
+
+    /// Track the best path change of `prefix` in the advertise-map
+    /// conditions. Returns the groups whose condition changed, see
+    /// `readvertise()`.
+    pub fn track(&mut self, policy: &Policy, prefix: Prefix, best: Option<&Route>) -> Vec<u32> {
+        let mut changed = Vec::new();
+        for group in self.groups.values_mut() {
+            if let Some(conditional) = &mut group.conditional {
+                if conditional.track(policy, prefix, best) {
+                    changed.push(group.id);
+                }
+            }
+        }
+        changed
+    }
+
+    /// Evaluate the routes of the best paths `best` the advertise-map of
+    /// the groups `ids` matches again, after their condition changed.
+    pub fn readvertise(
+        &mut self,
+        policy: &Policy,
+        attrs: &mut AttrStore,
+        ids: &[u32],
+        best: &[&Route],
+    ) -> Vec<GroupUpdate> {
+        let asn = self.asn;
+        let mut updates = Vec::new();
+        for id in ids {
+            let group = match self.groups.get_mut(id) {
+                Some(group) => group,
+                None => continue,
+            };
+            let map = match &group.conditional {
+                Some(conditional) => conditional.map.clone(),
+                None => continue,
+            };
+            for route in best.iter().filter(|route| map.matches(policy, route)) {
+                group.update(policy, attrs, asn, route.prefix, Some(route), &mut updates);
+            }
+        }
+        updates
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bgp::policy::fixture::permit;
+    use crate::bgp::{AdvertiseCondition, Attr, Communities, State};
+    use crate::bgp::{UpdateContext, UpdatePacker, BGP_MAX_LEN};
+    use crate::bgp::{CONDITIONAL_ADVERTISEMENT_TIMER_DEFAULT, LOCAL_PREF_DEFAULT};
+    use std::sync::Arc;
+
+    fn neighbor(addr: &str) -> Neighbor {
+        Neighbor::new(addr.parse().unwrap())
+    }
+
+    fn key(neighbor: &Neighbor) -> UpdateGroupKey {
+        let mut peer = Peer::new(State::Established);
+        peer.as4 = true;
+        UpdateGroupKey::new(neighbor, &peer, Some("192.0.2.254".parse().unwrap()))
+    }
+
+    fn route(prefix: &str, from: &str) -> Route {
+        let mut route = Route::new(prefix.parse().unwrap(), Attr::new());
+        route.peer = Some(from.parse().unwrap());
+        route
+    }
+
+    #[test]
+    fn replicate() {
+        let policy = Policy::new();
+        let mut groups = UpdateGroups::new(65000, CONDITIONAL_ADVERTISEMENT_TIMER_DEFAULT);
+        let mut attrs = AttrStore::new();
+        let (a, b, c) = (
+            neighbor("192.0.2.1"),
+            neighbor("192.0.2.2"),
+            neighbor("192.0.2.3"),
+        );
+        for n in &[&a, &b, &c] {
+            assert!(groups.join(&policy, &mut attrs, n, key(n), &[]).is_empty());
+        }
+        assert_eq!(groups.groups().count(), 1);
+
+        // Evaluated once, not sent back to the neighbor it is from.
+        let r = route("10.0.0.0/8", "192.0.2.1");
+        let updates = groups.best_changed(&policy, &mut attrs, r.prefix, Some(&r));
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].members, vec![b.ipaddr, c.ipaddr]);
+        match &updates[0].change {
+            AdjOut::Announce(out) => assert_eq!(out.attr.as_path.origin_as(), Some(65000)),
+            change => panic!("unexpected {:?}", change),
+        }
+        let group = groups.get(updates[0].group).unwrap();
+        assert_eq!(group.evaluations, 1);
+
+        // The new path is from a neighbor which had the old one.
+        let r2 = route("10.0.0.0/8", "192.0.2.2");
+        let updates = groups.best_changed(&policy, &mut attrs, r2.prefix, Some(&r2));
+        assert_eq!(updates.len(), 2);
+        assert_eq!(updates[0].members, vec![b.ipaddr]);
+        assert_eq!(updates[0].change, AdjOut::Withdraw(r2.prefix));
+        assert_eq!(updates[1].members, vec![a.ipaddr, c.ipaddr]);
+
+        // Unchanged routes are not sent again.
+        assert!(groups
+            .best_changed(&policy, &mut attrs, r2.prefix, Some(&r2))
+            .is_empty());
+        let updates = groups.best_changed(&policy, &mut attrs, r2.prefix, None);
+        assert_eq!(updates[0].members, vec![a.ipaddr, c.ipaddr]);
+        assert_eq!(updates[0].change, AdjOut::Withdraw(r2.prefix));
+    }
+
+    #[test]
+    fn ibgp() {
+        let policy = Policy::new();
+        let mut groups = UpdateGroups::new(65000, CONDITIONAL_ADVERTISEMENT_TIMER_DEFAULT);
+        let mut attrs = AttrStore::new();
+        let (a, b) = (neighbor("192.0.2.1"), neighbor("192.0.2.2"));
+        let mut peer = Peer::new(State::Established);
+        peer.peer_type = PeerType::Internal;
+        groups.join(
+            &policy,
+            &mut attrs,
+            &a,
+            UpdateGroupKey::new(&a, &peer, None),
+            &[],
+        );
+        groups.join(&policy, &mut attrs, &b, key(&b), &[]);
+        let announced = |updates: Vec<GroupUpdate>| -> Vec<(Vec<IpAddr>, Route)> {
+            updates
+                .into_iter()
+                .filter_map(|update| match update.change {
+                    AdjOut::Announce(route) => Some((update.members, *route)),
+                    AdjOut::Withdraw(_) => None,
+                })
+                .collect()
+        };
+
+        // Learned over iBGP, only sent to the external neighbor, without
+        // the MED.
+        let mut r = route("10.0.0.0/8", "192.0.2.9");
+        r.ibgp = true;
+        r.attr_mut().med = Some(10);
+        let updates = announced(groups.best_changed(&policy, &mut attrs, r.prefix, Some(&r)));
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].0, vec![b.ipaddr]);
+        assert_eq!(updates[0].1.attr.med, None);
+
+        // Learned over eBGP, internal neighbors get a LOCAL_PREF.
+        let r = route("172.16.0.0/12", "192.0.2.9");
+        let updates = announced(groups.best_changed(&policy, &mut attrs, r.prefix, Some(&r)));
+        assert_eq!(updates.len(), 2);
+        assert_eq!(updates[0].0, vec![a.ipaddr]);
+        assert_eq!(updates[0].1.attr.local_pref, Some(LOCAL_PREF_DEFAULT));
+
+        // NO_EXPORT stays within the AS.
+        let mut r = route("192.168.0.0/16", "192.0.2.9");
+        r.attr_mut().communities = Communities::from_str("no-export").map(Arc::new);
+        let updates = announced(groups.best_changed(&policy, &mut attrs, r.prefix, Some(&r)));
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].0, vec![a.ipaddr]);
+    }
+
+    #[test]
+    fn local_next_hop() {
+        let policy = Policy::new();
+        let mut groups = UpdateGroups::new(65000, CONDITIONAL_ADVERTISEMENT_TIMER_DEFAULT);
+        let mut attrs = AttrStore::new();
+        let a = neighbor("192.0.2.1");
+        let mut peer = Peer::new(State::Established);
+        peer.as4 = true;
+        peer.peer_type = PeerType::Internal;
+        let local: IpAddr = "192.0.2.254".parse().unwrap();
+        groups.join(
+            &policy,
+            &mut attrs,
+            &a,
+            UpdateGroupKey::new(&a, &peer, Some(local)),
+            &[],
+        );
+
+        // A network route has no next hop of its own, internal neighbors
+        // without next-hop-self still get our address.
+        let r = Route::new("10.0.0.0/8".parse().unwrap(), Attr::new());
+        let updates = groups.best_changed(&policy, &mut attrs, r.prefix, Some(&r));
+        let mut packer = UpdatePacker::new(Duration::ZERO);
+        match &updates[0].change {
+            AdjOut::Announce(out) => packer.announce(out),
+            change => panic!("unexpected {:?}", change),
+        }
+        let ctx = UpdateContext {
+            as4: true,
+            ..Default::default()
+        };
+        let (updates, errors) = packer.flush(Instant::now(), &ctx, BGP_MAX_LEN);
+        assert!(errors.is_empty());
+        assert_eq!(updates[0].nlri, vec![r.prefix]);
+        assert_eq!(updates[0].attr.next_hop, Some(local));
+    }
+
+    #[test]
+    fn split_merge() {
+        let mut policy = Policy::new();
+        permit(&mut policy, "TEN", "10.0.0.0/8");
+
+        let mut groups = UpdateGroups::new(65000, CONDITIONAL_ADVERTISEMENT_TIMER_DEFAULT);
+        let mut attrs = AttrStore::new();
+        let (a, mut b) = (neighbor("192.0.2.1"), neighbor("192.0.2.2"));
+        groups.join(&policy, &mut attrs, &a, key(&a), &[]);
+        groups.join(&policy, &mut attrs, &b, key(&b), &[]);
+        let best = vec![
+            route("10.0.0.0/8", "192.0.2.9"),
+            route("172.16.0.0/12", "192.0.2.9"),
+        ];
+        for r in &best {
+            groups.best_changed(&policy, &mut attrs, r.prefix, Some(r));
+        }
+
+        // A diverging neighbor splits into its own group.
+        b.prefix_list_out = Some("TEN".to_string());
+        let changes = groups.join(&policy, &mut attrs, &b, key(&b), &best);
+        assert_eq!(changes, vec![AdjOut::Withdraw(best[1].prefix)]);
+        assert_eq!(groups.groups().count(), 2);
+        assert_ne!(groups.group_of(&a.ipaddr), groups.group_of(&b.ipaddr));
+        let updates = groups.best_changed(&policy, &mut attrs, best[1].prefix, None);
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].members, vec![a.ipaddr]);
+
+        // And merges back once it matches again.
+        b.prefix_list_out = None;
+        assert!(groups
+            .join(&policy, &mut attrs, &b, key(&b), &best)
+            .is_empty());
+        assert_eq!(groups.groups().count(), 1);
+        assert_eq!(groups.group_of(&a.ipaddr), groups.group_of(&b.ipaddr));
+
+        // The last member leaving deletes the group.
+        groups.leave(&a.ipaddr);
+        assert_eq!(groups.leave(&b.ipaddr).len(), 1);
+        assert_eq!(groups.groups().count(), 0);
+    }
+
+    #[test]
+    fn conditional() {
+        // 192.0.2.0/24 is advertised while the default route is gone.
+        let mut policy = Policy::new();
+        permit(&mut policy, "OURS", "192.0.2.0/24");
+        permit(&mut policy, "PRIMARY", "0.0.0.0/0");
+        let timer = CONDITIONAL_ADVERTISEMENT_TIMER_DEFAULT;
+        let mut groups = UpdateGroups::new(65000, timer);
+        let mut attrs = AttrStore::new();
+        let mut a = neighbor("192.0.2.1");
+        a.advertise_map = Some(AdvertiseMap::new(
+            "OURS",
+            AdvertiseCondition::NonExist("PRIMARY".to_string()),
+        ));
+        let ours = route("192.0.2.0/24", "192.0.2.9");
+        let primary = route("0.0.0.0/0", "192.0.2.9");
+        let changes = groups.join(
+            &policy,
+            &mut attrs,
+            &a,
+            key(&a),
+            &[ours.clone(), primary.clone()],
+        );
+        assert_eq!(changes.len(), 1);
+        assert!(matches!(&changes[0], AdjOut::Announce(r) if r.prefix == primary.prefix));
+
+        // Tracked once the Loc-RIB changed, the routes of the advertise-map
+        // go through outbound policy again.
+        let now = Instant::now();
+        groups.best_changed(&policy, &mut attrs, primary.prefix, None);
+        assert!(groups.track(&policy, ours.prefix, Some(&ours)).is_empty());
+        let ids = groups.track(&policy, primary.prefix, None);
+        assert_eq!(ids.len(), 1);
+        let updates = groups.readvertise(&policy, &mut attrs, &ids, &[&ours]);
+        assert_eq!(updates.len(), 1);
+        match &updates[0].change {
+            AdjOut::Announce(out) => assert_eq!(out.attr.as_path.origin_as(), Some(65000)),
+            change => panic!("unexpected {:?}", change),
+        }
+
+        // Or when its timer expires.
+        let best = [&ours, &primary];
+        groups.best_changed(&policy, &mut attrs, primary.prefix, Some(&primary));
+        let updates = groups.evaluate(&policy, &mut attrs, &best, now + timer);
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].change, AdjOut::Withdraw(ours.prefix));
+        assert!(groups
+            .get(updates[0].group)
+            .unwrap()
+            .adj_out()
+            .contains_key(&primary.prefix));
+    }
+}
@@ -0,0 +1,476 @@
+#![allow(dead_code)]
+
+use super::{AttrSet, Family, MessageUpdate, MpReach, MpUnreach, PeerType, Prefix, Route};
+use super::{UpdateContext, AFI_IP, AFI_IP6, BGP_HEADER_LEN, SAFI_UNICAST};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// MinRouteAdvertisementIntervalTimer suggested by RFC 4271 10.
+pub const MRAI_EBGP_DEFAULT: Duration = Duration::from_secs(30);
+pub const MRAI_IBGP_DEFAULT: Duration = Duration::from_secs(5);
+
+/// MinRouteAdvertisementInterval of each kind of neighbor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Mrai {
+    pub ebgp: Duration,
+    pub ibgp: Duration,
+}
+
+impl Mrai {
+    /// Interval for `peer_type`, confederation neighbors are external.
+    pub fn interval(&self, peer_type: PeerType) -> Duration {
+        match peer_type {
+            PeerType::Internal => self.ibgp,
+            PeerType::ConfedExternal | PeerType::External => self.ebgp,
+        }
+    }
+}
+
+impl Default for Mrai {
+    fn default() -> Self {
+        Mrai {
+            ebgp: MRAI_EBGP_DEFAULT,
+            ibgp: MRAI_IBGP_DEFAULT,
+        }
+    }
+}
+
+/// Routes `UpdatePacker::flush()` could not send, they are left pending.
+#[derive(thiserror::Error, Debug)]
+pub enum PackError {
+    #[error("{} routes without a next hop", .0.len())]
+    NoNextHop(Vec<Prefix>),
+    #[error("{} IPv4 routes with next hop {1}, extended next hop encoding not negotiated", .0.len())]
+    ExtendedNextHop(Vec<Prefix>, IpAddr),
+    #[error("{} routes with attributes not encoded, {1}", .0.len())]
+    Attr(Vec<Prefix>, anyhow::Error),
+}
+
+/// Encoded length of `prefix` in NLRI.
+fn nlri_len(prefix: &Prefix) -> usize {
+    1 + (prefix.prefixlen() as usize).div_ceil(8)
+}
+
+/// Message header, withdrawn routes length and attributes length.
+const UPDATE_FIXED_LEN: usize = BGP_HEADER_LEN + 4;
+/// MP_REACH_NLRI or MP_UNREACH_NLRI header with the extended length, AFI
+/// and SAFI.
+const MP_FIXED_LEN: usize = 4 + 3;
+
+fn family(prefix: &Prefix) -> Family {
+    Family {
+        afi: if prefix.is_ipv4() { AFI_IP } else { AFI_IP6 },
+        safi: SAFI_UNICAST,
+    }
+}
+
+/// Fill as few UPDATEs as possible with `prefixes`, each starting from
+/// `base` of length `base_len`. `add` puts a prefix into an UPDATE.
+fn pack(
+    prefixes: &[Prefix],
+    base: &MessageUpdate,
+    base_len: usize,
+    max_len: usize,
+    add: impl Fn(&mut MessageUpdate, Prefix),
+) -> Vec<MessageUpdate> {
+    let mut updates = Vec::new();
+    let mut update = base.clone();
+    let mut len = base_len;
+    let mut empty = true;
+    for prefix in prefixes {
+        if !empty && len + nlri_len(prefix) > max_len {
+            updates.push(std::mem::replace(&mut update, base.clone()));
+            len = base_len;
+        }
+        add(&mut update, *prefix);
+        len += nlri_len(prefix);
+        empty = false;
+    }
+    if !empty {
+        updates.push(update);
+    }
+    updates
+}
+
+/// Outbound routes of one neighbor waiting to be sent. Prefixes announced
+/// with the same path attributes are packed into the same UPDATEs.
+/// Announcements are sent at most once per MinRouteAdvertisementInterval,
+/// withdrawals right away (RFC 4271 9.2.1.1).
+#[derive(Clone, Debug)]
+pub struct UpdatePacker {
+    mrai: Duration,
+    /// The latest change of each prefix, `None` withdraws it.
+    pending: BTreeMap<Prefix, Option<Arc<AttrSet>>>,
+    /// Pending announcements which could not be sent, they wait for the
+    /// next change of their prefix.
+    held: BTreeSet<Prefix>,
+    /// When announcements were last sent.
+    last: Option<Instant>,
+}
+
+impl UpdatePacker {
+    pub fn new(mrai: Duration) -> Self {
+        UpdatePacker {
+            mrai,
+            pending: BTreeMap::new(),
+            held: BTreeSet::new(),
+            last: None,
+        }
+    }
+
+    pub fn set_mrai(&mut self, mrai: Duration) {
+        self.mrai = mrai;
+    }
+
+    /// Queue `route`, replacing what is pending for its prefix.
+    pub fn announce(&mut self, route: &Route) {
+        self.held.remove(&route.prefix);
+        self.pending.insert(route.prefix, Some(route.attr.clone()));
+    }
+
+    pub fn withdraw(&mut self, prefix: Prefix) {
+        self.held.remove(&prefix);
+        self.pending.insert(prefix, None);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// When the pending changes may be sent, `None` when there are none
+    /// besides those held.
+    pub fn deadline(&self) -> Option<Instant> {
+        let mut pending = self
+            .pending
+            .iter()
+            .filter(|(prefix, _)| !self.held.contains(prefix))
+            .peekable();
+        pending.peek()?;
+        match self.last {
+            Some(last) if pending.all(|(_, attr)| attr.is_some()) => Some(last + self.mrai),
+            _ => Some(Instant::now()),
+        }
+    }
+
+    /// Leave the announcements of `prefixes` with `attr` pending.
+    fn hold(&mut self, prefixes: &[Prefix], attr: &Arc<AttrSet>) {
+        for prefix in prefixes {
+            self.pending.insert(*prefix, Some(attr.clone()));
+            self.held.insert(*prefix);
+        }
+    }
+
+    /// Take the changes which may be sent at `now` and pack them into
+    /// UPDATEs of at most `max_len` octets for a session with properties
+    /// `ctx`. IPv4 routes with an IPv6 next hop are only sent when
+    /// Extended Next Hop Encoding was negotiated, IPv6 routes with an IPv4
+    /// next hop get it IPv4-mapped. Routes which cannot be sent are held
+    /// pending and returned as errors.
+    pub fn flush(
+        &mut self,
+        now: Instant,
+        ctx: &UpdateContext,
+        max_len: usize,
+    ) -> (Vec<MessageUpdate>, Vec<PackError>) {
+        let announce = match self.last {
+            Some(last) => now >= last + self.mrai,
+            None => true,
+        };
+        let pending = std::mem::take(&mut self.pending);
+        let mut withdrawn = Vec::new();
+        // Attribute sets in the order they were first seen, the map only
+        // indexes them by their hash.
+        let mut sets: Vec<(Arc<AttrSet>, Vec<Prefix>)> = Vec::new();
+        let mut index: HashMap<Arc<AttrSet>, usize> = HashMap::new();
+        for (prefix, attr) in pending {
+            match attr {
+                None => withdrawn.push(prefix),
+                Some(attr) if !announce => {
+                    self.pending.insert(prefix, Some(attr));
+                }
+                Some(attr) => match index.get(&attr) {
+                    Some(i) => sets[*i].1.push(prefix),
+                    None => {
+                        index.insert(attr.clone(), sets.len());
+                        sets.push((attr, vec![prefix]));
+                    }
+                },
+            }
+        }
+
+        let mut updates = Vec::new();
+        let (v4, v6): (Vec<Prefix>, Vec<Prefix>) = withdrawn.iter().partition(|p| p.is_ipv4());
+        let base = MessageUpdate::new();
+        updates.extend(pack(&v4, &base, UPDATE_FIXED_LEN, max_len, |m, p| {
+            m.withdrawn.push(p)
+        }));
+        if let Some(first) = v6.first() {
+            let mut base = MessageUpdate::new();
+            base.mp_unreach = Some(MpUnreach {
+                family: family(first),
+                withdrawn: Vec::new(),
+            });
+            let base_len = UPDATE_FIXED_LEN + MP_FIXED_LEN;
+            updates.extend(pack(&v6, &base, base_len, max_len, |m, p| {
+                m.mp_unreach.as_mut().unwrap().withdrawn.push(p)
+            }));
+        }
+
+        let mut errors = Vec::new();
+        for (set, prefixes) in sets {
+            let attr = set.attr();
+            let mut attr_buf = Vec::new();
+            let attr_len = match attr.to_bytes(&mut attr_buf, ctx) {
+                Ok(len) => len,
+                Err(e) => {
+                    self.hold(&prefixes, &set);
+                    errors.push(PackError::Attr(prefixes, e));
+                    continue;
+                }
+            };
+            let (v4, v6): (Vec<Prefix>, Vec<Prefix>) = prefixes.iter().partition(|p| p.is_ipv4());
+            let mut base = MessageUpdate::new();
+            base.attr = attr;
+            if !v4.is_empty() {
+                match set.next_hop {
+                    // NEXT_HOP attribute.
+                    Some(IpAddr::V4(_)) => {
+                        let base_len = UPDATE_FIXED_LEN + attr_len + 7;
+                        updates.extend(pack(&v4, &base, base_len, max_len, |m, p| m.nlri.push(p)));
+                    }
+                    Some(next_hop @ IpAddr::V6(_)) if ctx.extended_nexthop => {
+                        updates.extend(mp_pack(&v4, &base, next_hop, attr_len, max_len));
+                    }
+                    Some(next_hop @ IpAddr::V6(_)) => {
+                        self.hold(&v4, &set);
+                        errors.push(PackError::ExtendedNextHop(v4, next_hop));
+                    }
+                    None => {
+                        self.hold(&v4, &set);
+                        errors.push(PackError::NoNextHop(v4));
+                    }
+                }
+            }
+            if !v6.is_empty() {
+                match set.next_hop {
+                    Some(next_hop @ IpAddr::V6(_)) => {
+                        updates.extend(mp_pack(&v6, &base, next_hop, attr_len, max_len));
+                    }
+                    Some(IpAddr::V4(next_hop)) => {
+                        let next_hop = IpAddr::V6(next_hop.to_ipv6_mapped());
+                        updates.extend(mp_pack(&v6, &base, next_hop, attr_len, max_len));
+                    }
+                    None => {
+                        self.hold(&v6, &set);
+                        errors.push(PackError::NoNextHop(v6));
+                    }
+                }
+            }
+        }
+        if updates
+            .iter()
+            .any(|m| !m.nlri.is_empty() || m.mp_reach.is_some())
+        {
+            self.last = Some(now);
+        }
+        (updates, errors)
+    }
+}
+
+/// UPDATEs with `prefixes` in MP_REACH_NLRI.
+fn mp_pack(
+    prefixes: &[Prefix],
+    base: &MessageUpdate,
+    next_hop: IpAddr,
+    attr_len: usize,
+    max_len: usize,
+) -> Vec<MessageUpdate> {
+    let first = match prefixes.first() {
+        Some(first) => first,
+        None => return Vec::new(),
+    };
+    // The next hop is carried in MP_REACH_NLRI only.
+    let mut base = base.clone();
+    base.attr.next_hop = None;
+    base.mp_reach = Some(MpReach {
+        family: family(first),
+        next_hop,
+        link_local: None,
+        nlri: Vec::new(),
+    });
+    // Next hop length, the IPv6 next hop and the reserved octet.
+    let base_len = UPDATE_FIXED_LEN + attr_len + MP_FIXED_LEN + 1 + 16 + 1;
+    pack(prefixes, &base, base_len, max_len, |m, p| {
+        m.mp_reach.as_mut().unwrap().nlri.push(p)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bgp::{Attr, Communities, Message, BGP_MAX_LEN};
+
+    fn route(prefix: Prefix, med: u32) -> Route {
+        let mut attr = Attr::new();
+        attr.as_path = "65001".parse().unwrap();
+        attr.next_hop = Some("192.0.2.1".parse().unwrap());
+        attr.med = Some(med);
+        Route::new(prefix, attr)
+    }
+
+    fn prefix(i: u32) -> Prefix {
+        Prefix::new(IpAddr::V4((0x0a000000 + (i << 8)).into()), 24).unwrap()
+    }
+
+    fn index(prefix: &Prefix) -> u32 {
+        match prefix.addr() {
+            IpAddr::V4(addr) => (u32::from(addr) - 0x0a000000) >> 8,
+            IpAddr::V6(_) => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn pack_attributes() {
+        let ctx = UpdateContext {
+            as4: true,
+            ..Default::default()
+        };
+        let mut packer = UpdatePacker::new(Duration::from_secs(5));
+        for i in 0..4000 {
+            packer.announce(&route(prefix(i), i % 2));
+        }
+        packer.withdraw("172.16.0.0/12".parse().unwrap());
+        packer.withdraw("2001:db8::/32".parse().unwrap());
+        let now = Instant::now();
+        let (updates, _) = packer.flush(now, &ctx, BGP_MAX_LEN);
+
+        // One UPDATE per family of withdrawals, then two attribute sets of
+        // 2000 prefixes of 4 octets each.
+        assert_eq!(updates.len(), 2 + 2 * 2);
+        assert_eq!(updates[0].withdrawn.len(), 1);
+        assert!(updates[1].mp_unreach.is_some());
+        let mut announced = 0;
+        for m in &updates[2..] {
+            let len = Message::Update(m.clone())
+                .encode(false, &ctx)
+                .unwrap()
+                .len();
+            assert!(len <= BGP_MAX_LEN);
+            assert!(m.nlri.iter().all(|p| Some(index(p) % 2) == m.attr.med));
+            announced += m.nlri.len();
+        }
+        assert_eq!(announced, 4000);
+        assert!(packer.is_empty());
+
+        // Announcements wait for the MRAI, withdrawals do not.
+        packer.announce(&route(prefix(1), 1));
+        packer.withdraw(prefix(2));
+        let (updates, _) = packer.flush(now, &ctx, BGP_MAX_LEN);
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].withdrawn, vec![prefix(2)]);
+        assert_eq!(packer.deadline(), Some(now + Duration::from_secs(5)));
+        let (updates, _) = packer.flush(now + Duration::from_secs(5), &ctx, BGP_MAX_LEN);
+        assert_eq!(updates[0].nlri, vec![prefix(1)]);
+        assert_eq!(packer.deadline(), None);
+
+        // Extended messages hold everything in one UPDATE per set.
+        for i in 0..2000 {
+            packer.announce(&route(prefix(i), i % 2));
+        }
+        let later = now + Duration::from_secs(10);
+        let (updates, _) = packer.flush(later, &ctx, crate::bgp::BGP_EXTENDED_MAX_LEN);
+        assert_eq!(updates.len(), 2);
+    }
+
+    #[test]
+    fn ipv6_next_hop() {
+        let mut ctx = UpdateContext::default();
+        let mut packer = UpdatePacker::new(Duration::from_secs(0));
+        let mut r = route("10.0.0.0/8".parse().unwrap(), 0);
+        r.attr_mut().next_hop = Some("2001:db8::1".parse().unwrap());
+        let mut r6 = r.clone();
+        r6.prefix = "2001:db8:1::/48".parse().unwrap();
+        packer.announce(&r);
+        packer.announce(&r6);
+
+        // IPv4 routes need Extended Next Hop Encoding, they stay pending.
+        let (updates, errors) = packer.flush(Instant::now(), &ctx, BGP_MAX_LEN);
+        assert_eq!(updates.len(), 1);
+        let reach = updates[0].mp_reach.as_ref().unwrap();
+        assert_eq!(reach.family.afi, AFI_IP6);
+        assert_eq!(reach.nlri, vec![r6.prefix]);
+        assert!(matches!(&errors[..], [PackError::ExtendedNextHop(p, _)] if p == &[r.prefix]));
+        assert!(!packer.is_empty());
+        assert_eq!(packer.deadline(), None);
+
+        ctx.extended_nexthop = true;
+        packer.announce(&r);
+        let (updates, errors) = packer.flush(Instant::now(), &ctx, BGP_MAX_LEN);
+        assert!(errors.is_empty());
+        let reach = updates[0].mp_reach.as_ref().unwrap();
+        assert_eq!(reach.family.afi, AFI_IP);
+        assert_eq!(reach.nlri, vec![r.prefix]);
+        let buf = Message::Update(updates[0].clone())
+            .encode(false, &ctx)
+            .unwrap();
+        let decoded = MessageUpdate::from_bytes(&buf[BGP_HEADER_LEN..], &ctx).unwrap();
+        assert_eq!(decoded, updates[0]);
+    }
+
+    #[test]
+    fn next_hops() {
+        let ctx = UpdateContext::default();
+        let mut packer = UpdatePacker::new(Duration::from_secs(0));
+        let mut r6 = route("2001:db8:1::/48".parse().unwrap(), 0);
+        packer.announce(&r6);
+
+        // IPv6 routes carry an IPv4 next hop IPv4-mapped.
+        let (updates, errors) = packer.flush(Instant::now(), &ctx, BGP_MAX_LEN);
+        assert!(errors.is_empty());
+        let reach = updates[0].mp_reach.as_ref().unwrap();
+        assert_eq!(
+            reach.next_hop,
+            "::ffff:192.0.2.1".parse::<IpAddr>().unwrap()
+        );
+
+        // Routes without a next hop, or whose attributes do not encode, are
+        // held until their prefix changes again.
+        r6.attr_mut().next_hop = None;
+        let mut r = route("10.0.0.0/8".parse().unwrap(), 0);
+        r.attr_mut().next_hop = None;
+        let mut big = route("10.1.0.0/16".parse().unwrap(), 0);
+        let mut coms = Communities::new();
+        for i in 0..20000 {
+            coms.push(i);
+        }
+        big.attr_mut().communities = Some(Arc::new(coms));
+        for route in [&r, &r6, &big] {
+            packer.announce(route);
+        }
+        let (updates, errors) = packer.flush(Instant::now(), &ctx, BGP_MAX_LEN);
+        assert!(updates.is_empty());
+        assert_eq!(errors.len(), 3);
+        assert!(errors
+            .iter()
+            .any(|e| matches!(e, PackError::Attr(p, _) if p == &[big.prefix])));
+        assert_eq!(packer.deadline(), None);
+
+        packer.withdraw(r.prefix);
+        packer.announce(&route(big.prefix, 0));
+        let (updates, errors) = packer.flush(Instant::now(), &ctx, BGP_MAX_LEN);
+        assert_eq!(updates.len(), 2);
+        assert_eq!(updates[0].withdrawn, vec![r.prefix]);
+        assert_eq!(updates[1].nlri, vec![big.prefix]);
+        assert!(matches!(&errors[..], [PackError::NoNextHop(p)] if p == &[r6.prefix]));
+    }
+
+    #[test]
+    fn mrai() {
+        let mrai = Mrai::default();
+        assert_eq!(mrai.interval(PeerType::External), MRAI_EBGP_DEFAULT);
+        assert_eq!(mrai.interval(PeerType::Internal), MRAI_IBGP_DEFAULT);
+    }
+}